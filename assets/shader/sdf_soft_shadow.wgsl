@@ -0,0 +1,43 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::utils
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import mesh2sdf::sdf_material
+
+struct SoftShadowMaterial {
+    color: vec4<f32>,
+    // towards the light
+    light_direction: vec3<f32>,
+    // larger values give narrower penumbras
+    hardness: f32,
+    // shadow rays stop after this distance
+    max_distance: f32,
+    // 0 to skip the march and only apply ambient occlusion
+    shadows_enabled: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: SoftShadowMaterial;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    let light = normalize(material.light_direction);
+
+    let diffuse = max(dot(normal, light), 0.0);
+    var shadow = 1.0;
+    if (material.shadows_enabled != 0u && diffuse > 0.0) {
+        // start off the surface so the receiver doesn't shadow itself
+        let origin = in.world_position.xyz + normal * 0.05;
+        shadow = sdf_soft_shadow(origin, light, material.hardness, material.max_distance);
+    }
+    let ambient = sdf_ambient_occlusion(in.world_position, normal) * 0.25;
+
+    return vec4<f32>(material.color.rgb * (diffuse * shadow + ambient), material.color.a);
+}
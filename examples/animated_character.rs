@@ -0,0 +1,254 @@
+//! a ring of animated foxes between pillars, for checking ambient occlusion on and from skinned
+//! characters.
+//!
+//! keys:
+//! - space: pause / resume the animations
+//! - n: add another fox to the ring
+//! - u: toggle sparse updates of skinned sdfs (`SdfGlobalSettings::sparse_skinned_updates`)
+//! - 1 / 2 / 3: low / medium / high quality tier
+//! - p: toggle a high generation priority on the first fox
+//! - o: toggle the debug raymarch view of the sdfs
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    prelude::*,
+};
+
+const RING_RADIUS: f32 = 150.0;
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(400),
+        buffer_size: 15.0,
+        unit_size: 5.0,
+        ambient_distance: 15.0,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(SdfRenderPlugin)
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .insert_resource(FoxCount(0))
+        .add_startup_system(setup)
+        .add_system(finalise_foxes)
+        .add_system(toggle)
+        .add_system(toggle_debug_view)
+        .run();
+}
+
+struct Animations(Vec<Handle<AnimationClip>>);
+
+struct FoxCount(usize);
+
+// the root of each fox scene, in spawn order
+#[derive(Component)]
+struct Fox(usize);
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut fox_count: ResMut<FoxCount>,
+) {
+    // ground plane
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 600.0 })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+
+    // pillars around the ring, close enough to occlude the passing foxes
+    let pillar = meshes.add(Mesh::from(shape::Box::new(30.0, 120.0, 30.0)));
+    let pillar_material = materials.add(StandardMaterial {
+        base_color: Color::ANTIQUE_WHITE,
+        perceptual_roughness: 1.0,
+        ..default()
+    });
+    for i in 0..8 {
+        let angle = i as f32 * std::f32::consts::TAU / 8.0;
+        let position = Vec3::new(angle.cos(), 0.0, angle.sin()) * (RING_RADIUS + 40.0);
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: pillar.clone(),
+                material: pillar_material.clone(),
+                transform: Transform::from_translation(position + Vec3::Y * 60.0),
+                ..default()
+            })
+            .insert(Sdf::new_scaled(1.0));
+    }
+
+    commands.insert_resource(Animations(vec![
+        asset_server.load("gltf/Fox.glb#Animation2")
+    ]));
+    for _ in 0..4 {
+        spawn_fox(&mut commands, &asset_server, &mut fox_count);
+    }
+
+    // ambient only, so all shading comes from the sdf occlusion
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    });
+
+    // camera
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 250.0, 400.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController { walk_speed: 250.0, ..Default::default() });
+}
+
+// foxes are placed at fixed steps around the ring, facing along it
+fn spawn_fox(commands: &mut Commands, asset_server: &AssetServer, fox_count: &mut FoxCount) {
+    let angle = fox_count.0 as f32 * 0.9;
+    let position = Vec3::new(angle.cos(), 0.0, angle.sin()) * RING_RADIUS;
+    commands
+        .spawn_bundle(SceneBundle {
+            scene: asset_server.load("gltf/Fox.glb#Scene0"),
+            transform: Transform::from_translation(position)
+                .with_rotation(Quat::from_rotation_y(-angle)),
+            ..default()
+        })
+        .insert(Fox(fox_count.0));
+    fox_count.0 += 1;
+}
+
+// add sdfs to the fox meshes once their scenes have spawned, and start any new animation players
+fn finalise_foxes(
+    mut commands: Commands,
+    meshes: Query<Entity, (With<Handle<Mesh>>, Without<Sdf>, Without<SdfRender>)>,
+    animations: Res<Animations>,
+    mut players: Query<(Entity, &mut AnimationPlayer), Without<Playing>>,
+) {
+    for ent in meshes.iter() {
+        commands.entity(ent).insert(Sdf::new_scaled(1.0));
+    }
+
+    for (ent, mut player) in players.iter_mut() {
+        player.play(animations.0[0].clone_weak()).repeat();
+        commands.entity(ent).insert(Playing);
+    }
+}
+
+#[derive(Component)]
+struct Playing;
+
+fn toggle(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut fox_count: ResMut<FoxCount>,
+    mut settings: ResMut<SdfGlobalSettings>,
+    mut tier: ResMut<SdfQualityTier>,
+    mut players: Query<&mut AnimationPlayer>,
+    foxes: Query<(&Fox, &Children)>,
+    priorities: Query<&SdfPriority>,
+    children: Query<&Children>,
+    sdfs: Query<Entity, With<Sdf>>,
+) {
+    if input.just_pressed(KeyCode::Space) {
+        for mut player in players.iter_mut() {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+    }
+
+    if input.just_pressed(KeyCode::N) {
+        spawn_fox(&mut commands, &asset_server, &mut fox_count);
+        info!("{} foxes", fox_count.0);
+    }
+
+    if input.just_pressed(KeyCode::U) {
+        settings.sparse_skinned_updates = !settings.sparse_skinned_updates;
+        info!("sparse skinned updates: {}", settings.sparse_skinned_updates);
+    }
+
+    for (key, new_tier, name) in [
+        (KeyCode::Key1, SdfQualityTier::low(), "low"),
+        (KeyCode::Key2, SdfQualityTier::medium(), "medium"),
+        (KeyCode::Key3, SdfQualityTier::high(), "high"),
+    ] {
+        if input.just_pressed(key) {
+            *tier = new_tier;
+            info!("quality tier: {}", name);
+        }
+    }
+
+    if input.just_pressed(KeyCode::P) {
+        // the sdfs are on the meshes somewhere below the scene root
+        let mut stack = foxes
+            .iter()
+            .filter(|(fox, _)| fox.0 == 0)
+            .flat_map(|(_, root_children)| root_children.iter().copied())
+            .collect::<Vec<_>>();
+        let mut prioritized = false;
+        while let Some(ent) = stack.pop() {
+            if sdfs.contains(ent) {
+                if priorities.contains(ent) {
+                    commands.entity(ent).remove::<SdfPriority>();
+                } else {
+                    commands.entity(ent).insert(SdfPriority(1000.0));
+                    prioritized = true;
+                }
+            }
+            if let Ok(grandchildren) = children.get(ent) {
+                stack.extend(grandchildren.iter().copied());
+            }
+        }
+        info!("first fox prioritized: {}", prioritized);
+    }
+}
+
+fn toggle_debug_view(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    sdfs: Query<Entity, With<Sdf>>,
+    renders: Query<Entity, With<SdfRender>>,
+) {
+    if !input.just_pressed(KeyCode::O) {
+        return;
+    }
+
+    if !renders.is_empty() {
+        for ent in renders.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        return;
+    }
+
+    for ent in sdfs.iter() {
+        commands.entity(ent).with_children(|p| {
+            p.spawn_bundle(SpatialBundle::default()).insert(SdfRender {
+                entity: ent,
+                base_color: Color::rgba_linear(0.0, 0.0, 0.0, 1.0),
+                hit_color: Color::rgba_linear(1.0, 0.0, 0.0, 0.0),
+                step_color: Color::rgba_linear(0.0, 1.0, 0.0, 0.0),
+                distance_color: Color::rgba_linear(0.0, 0.0, 1.0, 0.0),
+                min_step_size: 0.1,
+                hit_threshold: 0.1,
+                max_step_count: 50,
+            });
+        });
+    }
+}
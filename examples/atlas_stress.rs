@@ -0,0 +1,205 @@
+//! 500 objects sharing the atlas, for checking generation throughput, the compute budget and
+//! atlas packing.
+//!
+//! keys:
+//! - b: cycle the per frame compute budget (unlimited, 512 blocks, 4096 blocks)
+//! - h: toggle deduplication of identical meshes (each object otherwise has its own mesh asset)
+//! - v: hide / show every other object, so entries are evicted and regenerated
+//! - r: purge the atlas and regenerate everything
+//! - i: log a summary of the sdf statuses
+//! - 1 / 2 / 3: low / medium / high quality tier
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+    utils::HashMap,
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    prelude::*,
+};
+
+const OBJECT_COUNT: usize = 500;
+const BUDGETS: [Option<u32>; 3] = [None, Some(512), Some(4096)];
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(400),
+        buffer_size: 0.5,
+        unit_size: 0.1,
+        ambient_distance: 0.5,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .insert_resource(BudgetIndex(0))
+        .add_startup_system(setup)
+        .add_system(toggle)
+        .run();
+}
+
+struct BudgetIndex(usize);
+
+// a small deterministic generator, so runs are comparable
+struct Lcg(u32);
+
+impl Lcg {
+    // uniform in 0..1
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next()
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut rng = Lcg(12345);
+
+    // ground plane, large enough to hold everything
+    let side = (OBJECT_COUNT as f32).sqrt().ceil() * 2.0;
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: side + 4.0 })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(0.25));
+
+    let colors = [Color::PINK, Color::LIME_GREEN, Color::ORANGE, Color::ANTIQUE_WHITE];
+    let materials = colors.map(|color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            perceptual_roughness: 1.0,
+            ..default()
+        })
+    });
+
+    let columns = (OBJECT_COUNT as f32).sqrt().ceil() as usize;
+    for i in 0..OBJECT_COUNT {
+        // every object gets its own mesh asset, so they are only shared with deduplication on
+        let size = rng.range(0.4, 1.2);
+        let mesh = match i % 4 {
+            0 => Mesh::from(shape::Cube { size }),
+            1 => Mesh::from(shape::UVSphere {
+                radius: size * 0.5,
+                ..default()
+            }),
+            2 => Mesh::from(shape::Box::new(size * 0.3, size * 1.5, size)),
+            _ => Mesh::from(shape::Torus {
+                radius: size * 0.4,
+                ring_radius: size * 0.1,
+                ..default()
+            }),
+        };
+
+        let position = Vec3::new(
+            (i % columns) as f32 * 2.0 - side * 0.5 + rng.range(-0.3, 0.3),
+            size * 0.75,
+            (i / columns) as f32 * 2.0 - side * 0.5 + rng.range(-0.3, 0.3),
+        );
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(mesh),
+                material: materials[i % 4].clone(),
+                transform: Transform::from_translation(position)
+                    .with_rotation(Quat::from_rotation_y(rng.range(0.0, std::f32::consts::TAU))),
+                ..default()
+            })
+            .insert(Sdf::new_scaled(1.0));
+    }
+
+    // ambient only, so all shading comes from the sdf occlusion
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    });
+
+    // camera, looking over the whole field
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, side * 0.6, side * 0.8).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController { walk_speed: 20.0, ..Default::default() });
+}
+
+fn toggle(
+    input: Res<Input<KeyCode>>,
+    mut budget_index: ResMut<BudgetIndex>,
+    mut budget: ResMut<SdfComputeBudget>,
+    mut settings: ResMut<SdfGlobalSettings>,
+    mut tier: ResMut<SdfQualityTier>,
+    mut atlas: ResMut<SdfAtlas>,
+    mut objects: Query<(Entity, &mut Visibility), With<Sdf>>,
+    statuses: Query<Option<&SdfStatus>, With<Sdf>>,
+) {
+    if input.just_pressed(KeyCode::B) {
+        budget_index.0 = (budget_index.0 + 1) % BUDGETS.len();
+        budget.max_blocks_per_frame = BUDGETS[budget_index.0];
+        // the tier's budget overrides the global one
+        tier.max_blocks_per_frame = None;
+        info!("max blocks per frame: {:?}", budget.max_blocks_per_frame);
+    }
+
+    if input.just_pressed(KeyCode::H) {
+        settings.dedupe_meshes = !settings.dedupe_meshes;
+        atlas.purge_all();
+        info!("dedupe meshes: {}", settings.dedupe_meshes);
+    }
+
+    if input.just_pressed(KeyCode::V) {
+        for (ent, mut visibility) in objects.iter_mut() {
+            if ent.id() % 2 == 0 {
+                visibility.is_visible = !visibility.is_visible;
+            }
+        }
+    }
+
+    if input.just_pressed(KeyCode::R) {
+        atlas.purge_all();
+        info!("atlas purged");
+    }
+
+    if input.just_pressed(KeyCode::I) {
+        let mut counts = HashMap::<String, usize>::default();
+        for status in statuses.iter() {
+            let name = match status {
+                Some(status) => format!("{:?}", status),
+                None => String::from("Unqueued"),
+            };
+            *counts.entry(name).or_default() += 1;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort();
+        info!("{} sdfs: {:?}", statuses.iter().count(), counts);
+    }
+
+    for (key, new_tier, name) in [
+        (KeyCode::Key1, SdfQualityTier::low(), "low"),
+        (KeyCode::Key2, SdfQualityTier::medium(), "medium"),
+        (KeyCode::Key3, SdfQualityTier::high(), "high"),
+    ] {
+        if input.just_pressed(key) {
+            *tier = new_tier;
+            info!("quality tier: {}", name);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! generating sdfs on the cpu, for platforms where the compute path isn't available. each mesh is
+//! baked with `cpu::create_sdf_from_mesh_cpu` (one per frame, to keep the app responsive) and the
+//! result used as a precomputed image.
+//!
+//! keys:
+//! - c: switch between cpu baked and gpu generated sdfs, to compare the results
+//! - r: discard the cpu bakes and bake again
+//! - i: log the status of each sdf
+//! - o: toggle the debug raymarch view of the sdfs
+#![feature(let_else)]
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+    render::primitives::Aabb,
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    cpu::create_sdf_from_mesh_cpu,
+    prelude::*,
+};
+
+const BUFFER_SIZE: f32 = 0.5;
+const UNIT_SIZE: f32 = 0.1;
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(300),
+        buffer_size: BUFFER_SIZE,
+        unit_size: UNIT_SIZE,
+        ambient_distance: 0.5,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(SdfRenderPlugin)
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .insert_resource(UseCpu(true))
+        .add_startup_system(setup)
+        .add_system(bake_next)
+        .add_system(toggle)
+        .add_system(toggle_debug_view)
+        .run();
+}
+
+// whether sdfs use their cpu bakes
+struct UseCpu(bool);
+
+// the cpu bake of an entity's mesh
+#[derive(Component)]
+struct CpuBake(Handle<Image>);
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let mut spawn = |mesh: Handle<Mesh>, color: Color, transform: Transform| {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh,
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    perceptual_roughness: 1.0,
+                    ..default()
+                }),
+                transform,
+                ..default()
+            })
+            .insert(Sdf::new_scaled(1.0));
+    };
+
+    spawn(
+        meshes.add(Mesh::from(shape::Plane { size: 10.0 })),
+        Color::WHITE,
+        Transform::default(),
+    );
+    spawn(
+        meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        Color::PINK,
+        Transform::from_xyz(-1.5, 0.5, 0.0),
+    );
+    spawn(
+        meshes.add(Mesh::from(shape::UVSphere {
+            radius: 0.5,
+            ..default()
+        })),
+        Color::LIME_GREEN,
+        Transform::from_xyz(0.0, 0.5, 0.0),
+    );
+    spawn(
+        asset_server.load("gltf/monkey.glb#Mesh0/Primitive0"),
+        Color::ORANGE,
+        Transform::from_xyz(1.5, 0.7, 0.0),
+    );
+
+    // ambient only, so all shading comes from the sdf occlusion
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    });
+
+    // camera
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController::default());
+}
+
+// bake one loaded mesh per frame. precomputed images span the mesh's aabb plus the buffer
+fn bake_next(
+    mut commands: Commands,
+    use_cpu: Res<UseCpu>,
+    meshes: Res<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut unbaked: Query<(Entity, &Handle<Mesh>, &mut Sdf), Without<CpuBake>>,
+) {
+    if !use_cpu.0 {
+        return;
+    }
+
+    let Some((ent, mesh, mut sdf)) = unbaked.iter_mut().find(|(_, mesh, _)| meshes.contains(mesh)) else { return };
+    let mesh = meshes.get(mesh).unwrap();
+    let Some(mesh_aabb) = mesh.compute_aabb() else { return };
+
+    let aabb = Aabb {
+        center: mesh_aabb.center,
+        half_extents: mesh_aabb.half_extents + BUFFER_SIZE,
+    };
+    let dimension = (aabb.half_extents * 2.0 / UNIT_SIZE).ceil().as_uvec3() + 1;
+    let image = images.add(create_sdf_from_mesh_cpu(mesh, &aabb, dimension, &sdf.options, None));
+
+    sdf.mode = SdfGenMode::Precomputed(image.clone());
+    commands.entity(ent).insert(CpuBake(image));
+}
+
+fn toggle(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mut use_cpu: ResMut<UseCpu>,
+    mut sdfs: Query<(Entity, &mut Sdf, Option<&CpuBake>, Option<&SdfStatus>)>,
+) {
+    if input.just_pressed(KeyCode::C) {
+        use_cpu.0 = !use_cpu.0;
+        info!("cpu bakes: {}", use_cpu.0);
+        for (_, mut sdf, maybe_bake, _) in sdfs.iter_mut() {
+            sdf.mode = match (use_cpu.0, maybe_bake) {
+                (true, Some(bake)) => SdfGenMode::Precomputed(bake.0.clone()),
+                _ => SdfGenMode::FromPrimaryMesh,
+            };
+        }
+    }
+
+    if input.just_pressed(KeyCode::R) {
+        for (ent, mut sdf, maybe_bake, _) in sdfs.iter_mut() {
+            if maybe_bake.is_some() {
+                commands.entity(ent).remove::<CpuBake>();
+                sdf.mode = SdfGenMode::FromPrimaryMesh;
+            }
+        }
+    }
+
+    if input.just_pressed(KeyCode::I) {
+        for (ent, sdf, _, maybe_status) in sdfs.iter() {
+            let source = match sdf.mode {
+                SdfGenMode::Precomputed(_) => "cpu",
+                _ => "gpu",
+            };
+            info!("{:?}: {} {:?}", ent, source, maybe_status);
+        }
+    }
+}
+
+fn toggle_debug_view(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    sdfs: Query<Entity, With<Sdf>>,
+    renders: Query<Entity, With<SdfRender>>,
+) {
+    if !input.just_pressed(KeyCode::O) {
+        return;
+    }
+
+    if !renders.is_empty() {
+        for ent in renders.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        return;
+    }
+
+    for ent in sdfs.iter() {
+        commands.entity(ent).with_children(|p| {
+            p.spawn_bundle(SdfDebugBundle::for_entity(ent));
+        });
+    }
+}
@@ -8,10 +8,8 @@ use bevy::{
 };
 use mesh2sdf::{
     controller::{CameraController, ControllerPlugin},
-    debug_render::{SdfMaterial, SdfRenderPlugin},
-    Sdf, SdfAtlas,
+    prelude::*,
 };
-use mesh2sdf::{debug_render::SdfRender, SdfGenMode, SdfGlobalSettings, SdfPlugin};
 
 fn main() {
     let mut app = App::new();
@@ -45,9 +43,11 @@ fn main() {
         unit_size,
         atlas_page_size: UVec3::splat(400),
         ambient_distance: 1.0,
+        ..Default::default()
     });
     app.add_plugin(SdfPlugin);
     app.add_plugin(SdfRenderPlugin);
+    app.add_plugin(SdfShadowCatcherPlugin);
     app.add_plugin(ControllerPlugin);
     app.insert_resource(AmbientLight {
         color: Color::WHITE,
@@ -64,9 +64,25 @@ fn main() {
 
 struct Animations(Vec<Handle<AnimationClip>>);
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut catchers: ResMut<Assets<SdfShadowCatcherMaterial>>,
+) {
     let filename = std::env::args().nth(1).unwrap_or("teapot".into());
 
+    // a ground plane showing only the grounding shadows of the models
+    commands.spawn_bundle(SdfShadowCatcherBundle {
+        mesh: meshes.add(Mesh::from(shape::Plane { size: 20.0 })),
+        material: catchers.add(SdfShadowCatcherMaterial {
+            light_direction: Vec3::new(0.3, 1.0, 0.2),
+            ..Default::default()
+        }),
+        transform: Transform::from_xyz(1.0, 1.5, 3.0),
+        ..Default::default()
+    });
+
     // always load a teapot in the sky
     let scene = asset_server.load("gltf/teapot.glb#Scene0");
     commands
@@ -112,7 +128,14 @@ fn setup_scene_once_loaded(
 
 fn system(
     mut commands: Commands,
-    mesh_ents: Query<(Entity, &Handle<Mesh>, &Aabb), (Without<SdfRender>, Without<Sdf>)>,
+    mesh_ents: Query<
+        (Entity, &Handle<Mesh>, &Aabb),
+        (
+            Without<SdfRender>,
+            Without<Sdf>,
+            Without<Handle<SdfShadowCatcherMaterial>>,
+        ),
+    >,
     meshes: Res<Assets<Mesh>>,
     mut cam_added: Local<bool>,
 ) {
@@ -191,7 +214,7 @@ fn toggle(
     }
 
     if key_input.just_pressed(KeyCode::L) {
-        atlas.page.purge_all();
+        atlas.purge_all();
     }
 
     if key_input.just_pressed(KeyCode::K) {
@@ -0,0 +1,194 @@
+//! using sdf data prepared ahead of time instead of generating from the mesh at runtime: geometry
+//! preprocessed into a `.sdfmesh` asset, and fully baked precomputed images.
+//!
+//! run with `--features serialize`. keys:
+//! - x: preprocess the teapot and export it to `assets/gltf/teapot.sdfmesh`
+//! - l: load the exported `.sdfmesh` and generate from it (`SdfGenMode::FromPreprocessed`)
+//! - b: bake an image on the cpu and use it directly (`SdfGenMode::Precomputed`)
+//! - g: go back to generating from the mesh (`SdfGenMode::FromPrimaryMesh`)
+//! - i: log the mode and status of the teapot's sdf
+//! - o: toggle the debug raymarch view of the sdf
+#![feature(let_else)]
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+    render::primitives::Aabb,
+    tasks::ComputeTaskPool,
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    cpu::bake_all,
+    preprocessed::PreprocessedMesh,
+    prelude::*,
+};
+
+const BUFFER_SIZE: f32 = 0.5;
+const UNIT_SIZE: f32 = 0.05;
+const EXPORT_PATH: &str = "assets/gltf/teapot.sdfmesh";
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(300),
+        buffer_size: BUFFER_SIZE,
+        unit_size: UNIT_SIZE,
+        ambient_distance: 0.5,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(SdfRenderPlugin)
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .add_startup_system(setup)
+        .add_system(toggle)
+        .add_system(toggle_debug_view)
+        .run();
+}
+
+#[derive(Component)]
+struct Teapot;
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    // ground plane
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 10.0 })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(0.5));
+
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: asset_server.load("gltf/teapot.glb#Mesh0/Primitive0"),
+            material: materials.add(StandardMaterial {
+                base_color: Color::ORANGE,
+                perceptual_roughness: 0.5,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0))
+        .insert(Teapot);
+
+    // ambient only, so all shading comes from the sdf occlusion
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    });
+
+    // camera
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController::default());
+}
+
+fn toggle(
+    input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    meshes: Res<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut teapot: Query<(&Handle<Mesh>, &mut Sdf, Option<&SdfStatus>), With<Teapot>>,
+) {
+    let Ok((mesh_handle, mut sdf, maybe_status)) = teapot.get_single_mut() else { return };
+
+    if input.just_pressed(KeyCode::X) {
+        match meshes.get(mesh_handle) {
+            Some(mesh) => {
+                let bytes = PreprocessedMesh::from_mesh(mesh, &sdf.options).to_bytes();
+                match std::fs::write(EXPORT_PATH, bytes) {
+                    Ok(()) => info!("exported {}", EXPORT_PATH),
+                    Err(e) => warn!("failed to write {}: {}", EXPORT_PATH, e),
+                }
+            }
+            None => warn!("teapot not loaded yet"),
+        }
+    }
+
+    if input.just_pressed(KeyCode::L) {
+        // the asset path is relative to the assets folder
+        sdf.mode = SdfGenMode::FromPreprocessed(asset_server.load("gltf/teapot.sdfmesh"));
+        info!("generating from the preprocessed asset");
+    }
+
+    if input.just_pressed(KeyCode::B) {
+        let Some(mesh_aabb) = meshes.get(mesh_handle).and_then(|mesh| mesh.compute_aabb()) else {
+            warn!("teapot not loaded yet");
+            return;
+        };
+
+        // precomputed images span the aabb plus the buffer
+        let aabb = Aabb {
+            center: mesh_aabb.center,
+            half_extents: mesh_aabb.half_extents + BUFFER_SIZE,
+        };
+        let dimension = (aabb.half_extents * 2.0 / UNIT_SIZE).ceil().as_uvec3() + 1;
+        let baked = bake_all(
+            &meshes,
+            [(mesh_handle.clone_weak(), aabb, dimension)],
+            &sdf.options,
+            ComputeTaskPool::get(),
+            |_, _| (),
+        );
+        if let Some(Some(image)) = baked.into_iter().next() {
+            sdf.mode = SdfGenMode::Precomputed(images.add(image));
+            info!("using a {} cpu bake", dimension);
+        }
+    }
+
+    if input.just_pressed(KeyCode::G) {
+        sdf.mode = SdfGenMode::FromPrimaryMesh;
+        info!("generating from the mesh");
+    }
+
+    if input.just_pressed(KeyCode::I) {
+        let mode = match sdf.mode {
+            SdfGenMode::FromPreprocessed(_) => "preprocessed",
+            SdfGenMode::Precomputed(_) => "precomputed",
+            _ => "mesh",
+        };
+        info!("{}: {:?}", mode, maybe_status);
+    }
+}
+
+fn toggle_debug_view(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    sdfs: Query<Entity, With<Teapot>>,
+    renders: Query<Entity, With<SdfRender>>,
+) {
+    if !input.just_pressed(KeyCode::O) {
+        return;
+    }
+
+    if !renders.is_empty() {
+        for ent in renders.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        return;
+    }
+
+    for ent in sdfs.iter() {
+        commands.entity(ent).with_children(|p| {
+            p.spawn_bundle(SdfDebugBundle::for_entity(ent));
+        });
+    }
+}
@@ -4,8 +4,7 @@ use bevy::{
 };
 use mesh2sdf::{
     controller::{CameraController, ControllerPlugin},
-    debug_render::{SdfRender, SdfRenderPlugin},
-    Sdf, SdfAtlas, SdfGlobalSettings, SdfPlugin,
+    prelude::*,
 };
 
 #[allow(unused_imports)]
@@ -29,6 +28,7 @@ fn main() {
         buffer_size: 15.0,
         unit_size: 5.0,
         ambient_distance: 15.0,
+        ..Default::default()
     });
 
     SdfPlugin::add_view_bindings(&mut app);
@@ -220,7 +220,7 @@ fn toggle(
             });
         }
 
-        atlas.page.purge_all();
+        atlas.purge_all();
     }
 }
 
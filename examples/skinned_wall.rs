@@ -0,0 +1,127 @@
+//! an animated fox running in place next to a wall, to check that ambient occlusion on skinned
+//! receivers follows the animated pose (and stays stable from frame to frame)
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    prelude::*,
+};
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(400),
+        buffer_size: 15.0,
+        unit_size: 5.0,
+        ambient_distance: 15.0,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .add_startup_system(setup)
+        .add_system(finalise_scene)
+        .add_system(toggle)
+        .run();
+}
+
+struct Animations(Vec<Handle<AnimationClip>>);
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    // ground plane
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 500.0 })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+
+    // wall, close enough to the fox's flank to occlude it
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(250.0, 150.0, 20.0))),
+            material: materials.add(StandardMaterial {
+                base_color: Color::ANTIQUE_WHITE,
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            transform: Transform::from_xyz(0.0, 75.0, -40.0),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+
+    // fox, running in place alongside the wall
+    commands.spawn_bundle(SceneBundle {
+        scene: asset_server.load("gltf/Fox.glb#Scene0"),
+        transform: Transform::from_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)),
+        ..default()
+    });
+    commands.insert_resource(Animations(vec![
+        asset_server.load("gltf/Fox.glb#Animation2")
+    ]));
+
+    // ambient only, so all shading comes from the sdf occlusion
+    commands.insert_resource(AmbientLight {
+        color: Color::WHITE,
+        brightness: 1.0,
+    });
+
+    // camera
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 80.0, 200.0).looking_at(Vec3::new(0.0, 40.0, 0.0), Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController { walk_speed: 250.0, ..Default::default() });
+}
+
+// add sdfs to the fox meshes once the scene has spawned, and start the animation
+fn finalise_scene(
+    mut commands: Commands,
+    q: Query<Entity, (With<Handle<Mesh>>, Without<Sdf>)>,
+    animations: Res<Animations>,
+    mut player: Query<&mut AnimationPlayer>,
+    mut done: Local<bool>,
+) {
+    for ent in q.iter() {
+        commands.entity(ent).insert(Sdf::new_scaled(1.0));
+    }
+
+    if !*done {
+        if let Ok(mut player) = player.get_single_mut() {
+            player.play(animations.0[0].clone_weak()).repeat();
+            *done = true;
+        }
+    }
+}
+
+// space to pause, so the occlusion can be compared against the moving pose
+fn toggle(input: Res<Input<KeyCode>>, mut player: Query<&mut AnimationPlayer>) {
+    if input.just_pressed(KeyCode::Space) {
+        for mut player in player.iter_mut() {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+    }
+}
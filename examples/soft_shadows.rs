@@ -0,0 +1,213 @@
+//! sdf soft shadows from a custom material. the material's fragment shader imports
+//! `mesh2sdf::sdf_material` to march the sdf view bindings towards a directional light, alongside
+//! the plugin's ambient occlusion. the material needs no sdf bindings of its own.
+//!
+//! keys:
+//! - left / right: rotate the light
+//! - up / down: harder / softer penumbras
+//! - space: toggle the shadow march, leaving only ambient occlusion
+//! - 1 / 2 / 3: low / medium / high quality tier, which sets the shadow step count
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{AsBindGroup, ShaderRef},
+};
+use mesh2sdf::{
+    controller::{CameraController, ControllerPlugin},
+    prelude::*,
+};
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(SdfGlobalSettings {
+        atlas_page_size: UVec3::splat(300),
+        // as far as the shadow rays march, so occluders are found before they're reached
+        buffer_size: 2.0,
+        unit_size: 0.1,
+        ambient_distance: 1.0,
+        ..Default::default()
+    });
+
+    SdfPlugin::add_view_bindings(&mut app);
+    app.add_plugin(LogDiagnosticsPlugin::default());
+    app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(SdfPlugin)
+        .add_plugin(MaterialPlugin::<SoftShadowMaterial>::default())
+        .add_plugin(ControllerPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.7, 0.7, 1.0)))
+        .insert_resource(ShadowSettings {
+            light_angle: 0.8,
+            hardness: 8.0,
+            enabled: true,
+        })
+        .add_startup_system(setup)
+        .add_system(toggle)
+        .add_system(update_materials)
+        .run();
+}
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "0c4f0b5e-29f4-4c3b-8d6e-6a8a3c1f4e27"]
+struct SoftShadowMaterial {
+    #[uniform(0)]
+    color: Color,
+    #[uniform(0)]
+    light_direction: Vec3,
+    #[uniform(0)]
+    hardness: f32,
+    #[uniform(0)]
+    max_distance: f32,
+    #[uniform(0)]
+    shadows_enabled: u32,
+}
+
+impl Material for SoftShadowMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shader/sdf_soft_shadow.wgsl".into()
+    }
+}
+
+struct ShadowSettings {
+    // elevation of the light above the horizon, in radians
+    light_angle: f32,
+    hardness: f32,
+    enabled: bool,
+}
+
+impl ShadowSettings {
+    fn light_direction(&self) -> Vec3 {
+        Vec3::new(self.light_angle.cos(), self.light_angle.sin(), 0.3).normalize()
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<SoftShadowMaterial>>,
+    settings: Res<ShadowSettings>,
+) {
+    let mut material = |color| {
+        materials.add(SoftShadowMaterial {
+            color,
+            light_direction: settings.light_direction(),
+            hardness: settings.hardness,
+            max_distance: 2.0,
+            shadows_enabled: 1,
+        })
+    };
+
+    // ground plane
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 20.0 })),
+            material: material(Color::WHITE),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+
+    // a row of thin posts, whose shadows show the penumbra widening with distance
+    let post = meshes.add(Mesh::from(shape::Box::new(0.1, 1.5, 0.1)));
+    let post_material = material(Color::ANTIQUE_WHITE);
+    for i in 0..6 {
+        commands
+            .spawn_bundle(MaterialMeshBundle {
+                mesh: post.clone(),
+                material: post_material.clone(),
+                transform: Transform::from_xyz(-3.0 + i as f32, 0.75, -1.5),
+                ..default()
+            })
+            .insert(Sdf::new_scaled(2.0));
+    }
+
+    // larger occluders
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+            material: material(Color::PINK),
+            transform: Transform::from_xyz(-1.5, 0.5, 1.0),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::UVSphere {
+                radius: 0.6,
+                ..default()
+            })),
+            material: material(Color::LIME_GREEN),
+            transform: Transform::from_xyz(1.5, 0.8, 1.0),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(1.0));
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::Torus {
+                radius: 0.6,
+                ring_radius: 0.15,
+                ..default()
+            })),
+            material: material(Color::ORANGE),
+            transform: Transform::from_xyz(0.0, 1.2, 2.5)
+                .with_rotation(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2)),
+            ..default()
+        })
+        .insert(Sdf::new_scaled(2.0));
+
+    // camera
+    commands
+        .spawn_bundle(Camera3dBundle {
+            transform: Transform::from_xyz(0.0, 4.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        })
+        .insert(CameraController::default());
+}
+
+fn toggle(
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut settings: ResMut<ShadowSettings>,
+    mut tier: ResMut<SdfQualityTier>,
+) {
+    if input.pressed(KeyCode::Left) {
+        settings.light_angle = (settings.light_angle + time.delta_seconds()).min(3.0);
+    }
+    if input.pressed(KeyCode::Right) {
+        settings.light_angle = (settings.light_angle - time.delta_seconds()).max(0.15);
+    }
+    if input.pressed(KeyCode::Up) {
+        settings.hardness = (settings.hardness * 1.02).min(64.0);
+    }
+    if input.pressed(KeyCode::Down) {
+        settings.hardness = (settings.hardness / 1.02).max(1.0);
+    }
+    if input.just_pressed(KeyCode::Space) {
+        settings.enabled = !settings.enabled;
+        info!("shadows: {}", settings.enabled);
+    }
+
+    for (key, new_tier, name) in [
+        (KeyCode::Key1, SdfQualityTier::low(), "low"),
+        (KeyCode::Key2, SdfQualityTier::medium(), "medium"),
+        (KeyCode::Key3, SdfQualityTier::high(), "high"),
+    ] {
+        if input.just_pressed(key) {
+            info!("quality tier: {} ({} shadow steps)", name, new_tier.shadow_steps);
+            *tier = new_tier;
+        }
+    }
+}
+
+fn update_materials(settings: Res<ShadowSettings>, mut materials: ResMut<Assets<SoftShadowMaterial>>) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (_, material) in materials.iter_mut() {
+        material.light_direction = settings.light_direction();
+        material.hardness = settings.hardness;
+        material.shadows_enabled = settings.enabled as u32;
+    }
+}
@@ -10,6 +10,8 @@ use bevy::{
     },
 };
 
+use crate::utils::JointIndexes;
+
 /// generate an aabb for the current animation state of the mesh
 /// example usage:
 ///
@@ -42,7 +44,7 @@ impl<'w, 's> AnimatedAabbBuilder<'w, 's> {
         let poses = self.inverse_bindposes.get(&skin.inverse_bindposes)?;
         let VertexAttributeValues::Float32x3(values) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)? else {return None};
         let VertexAttributeValues::Float32x4(joint_weights) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT)? else {return None};
-        let VertexAttributeValues::Uint16x4(joint_indexes) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX)? else {return None};
+        let joint_indexes = JointIndexes::new(mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX)?)?;
 
         let joints = skin
             .joints
@@ -54,12 +56,12 @@ impl<'w, 's> AnimatedAabbBuilder<'w, 's> {
             .collect::<Vec<_>>();
 
         let weight = |v: Vec3, index: usize| -> Vec3 {
-            let indexes = joint_indexes[index];
+            let indexes = joint_indexes.get(index);
             let weights = joint_weights[index];
-            let mat = joints[indexes[0] as usize] * weights[0]
-                + joints[indexes[1] as usize] * weights[1]
-                + joints[indexes[2] as usize] * weights[2]
-                + joints[indexes[3] as usize] * weights[3];
+            let mat = joints[indexes[0]] * weights[0]
+                + joints[indexes[1]] * weights[1]
+                + joints[indexes[2]] * weights[2]
+                + joints[indexes[3]] * weights[3];
             let res = mat * v.extend(1.0);
             res.truncate() / res.w
         };
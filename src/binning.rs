@@ -0,0 +1,317 @@
+use bevy::{math::Vec3A, prelude::*, utils::HashMap};
+
+use crate::{
+    bvh::FeatureBvh,
+    compute::WORKGROUP_SIZE,
+    cpu::compute_distance_bvh,
+    utils::PreprocessedMeshData,
+    SdfOptions,
+};
+
+// meshes with fewer features are cheaper to iterate directly than to bin
+pub(crate) const MIN_BINNED_FEATURES: usize = 64;
+
+// u32s per block header: (start, count) into the index list for vertices, edges then triangles.
+// must match compute_sdf.wgsl
+pub(crate) const BIN_HEADER_SIZE: u32 = 6;
+
+// an axis aligned box
+#[derive(Clone, Copy)]
+pub(crate) struct Bounds {
+    pub min: Vec3A,
+    pub max: Vec3A,
+}
+
+impl Bounds {
+    pub fn from_points(points: &[Vec3A]) -> Self {
+        let mut bounds = Bounds {
+            min: points[0],
+            max: points[0],
+        };
+        for p in points[1..].iter() {
+            bounds.min = bounds.min.min(*p);
+            bounds.max = bounds.max.max(*p);
+        }
+        bounds
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    // no point in the two boxes is closer than this
+    pub fn min_distance(&self, other: &Bounds) -> f32 {
+        (self.min - other.max)
+            .max(other.min - self.max)
+            .max(Vec3A::ZERO)
+            .length()
+    }
+
+    // squared distance from the point to the nearest point in the box
+    pub fn distance_sq(&self, point: Vec3A) -> f32 {
+        (self.min - point)
+            .max(point - self.max)
+            .max(Vec3A::ZERO)
+            .length_squared()
+    }
+
+    // every point in the box is within this distance of the point
+    pub fn max_distance(&self, point: Vec3A) -> f32 {
+        (point - self.min).abs().max((point - self.max).abs()).length()
+    }
+}
+
+// bounds of the vertex, edge and triangle features
+pub(crate) fn feature_bounds(data: &PreprocessedMeshData) -> [Vec<Bounds>; 3] {
+    [
+        data.vertices
+            .iter()
+            .map(|(v, _)| Bounds::from_points(&[*v]))
+            .collect(),
+        data.edges
+            .iter()
+            .map(|((v0, v1), _)| Bounds::from_points(&[*v0, *v1]))
+            .collect(),
+        data.triangles
+            .iter()
+            .map(|tri| Bounds::from_points(&[tri.a, tri.b, tri.c]))
+            .collect(),
+    ]
+}
+
+/// for each compute block, the features that could be nearest to any of its voxels. any voxel is
+/// at most its distance to the surface point nearest the block's center from the surface, so
+/// features further than that from the whole block can be skipped. that reach is found per block
+/// through a bvh, then each feature is binned into only the blocks within the largest reach of its
+/// bounds, rather than testing every feature against every block.
+///
+/// `margin` grows each block's bounds, for voxels sampled at points away from their centers.
+///
+/// the result starts with a `BIN_HEADER_SIZE` header per block in x-major order, followed by the
+/// index lists. header starts are relative to the result and indices relative to the instance's
+/// first feature of each kind.
+pub(crate) fn bin_features(
+    data: &PreprocessedMeshData,
+    aabb_min: Vec3A,
+    scale: Vec3A,
+    block_dimensions: UVec3,
+    margin: Vec3A,
+) -> Vec<u32> {
+    let block_bounds = |block: UVec3| {
+        let first_voxel = block * WORKGROUP_SIZE;
+        Bounds {
+            min: aabb_min + first_voxel.as_vec3a() * scale - margin,
+            max: aabb_min + (first_voxel + WORKGROUP_SIZE - 1).as_vec3a() * scale + margin,
+        }
+    };
+
+    let bvh = FeatureBvh::new(data);
+    let mut reaches = Vec::new();
+    for z in 0..block_dimensions.z {
+        for y in 0..block_dimensions.y {
+            for x in 0..block_dimensions.x {
+                let block = block_bounds(UVec3::new(x, y, z));
+                let nearest = bvh.nearest(data, (block.min + block.max) * 0.5).nearest;
+                // allow for float noise between the cpu and the shader
+                reaches.push(block.max_distance(nearest) * 1.001 + 1e-5);
+            }
+        }
+    }
+    let max_reach = reaches.iter().copied().fold(0.0, f32::max);
+
+    // blocks whose bounds may be within `reach` of the box, one block wider either side for float
+    // noise. the exact test against each block's own reach follows
+    let block_size = scale * WORKGROUP_SIZE as f32;
+    let max_block = block_dimensions.as_vec3a() - 1.0;
+    let block_range = |bounds: &Bounds| {
+        let block_extent = scale * (WORKGROUP_SIZE - 1) as f32;
+        let lo = (bounds.min - max_reach - margin - block_extent - aabb_min) / block_size;
+        let hi = (bounds.max + max_reach + margin - aabb_min) / block_size;
+        let lo = (lo.floor() - 1.0).clamp(Vec3A::ZERO, max_block).as_uvec3();
+        let hi = (hi.floor() + 1.0).clamp(Vec3A::ZERO, max_block).as_uvec3();
+        (lo, hi)
+    };
+
+    let block_count = (block_dimensions.x * block_dimensions.y * block_dimensions.z) as usize;
+    let mut bins = vec![[Vec::new(), Vec::new(), Vec::new()]; block_count];
+    for (kind, bounds) in feature_bounds(data).iter().enumerate() {
+        for (index, bounds) in bounds.iter().enumerate() {
+            let (lo, hi) = block_range(bounds);
+            for z in lo.z..=hi.z {
+                for y in lo.y..=hi.y {
+                    for x in lo.x..=hi.x {
+                        let block = (x + (y + z * block_dimensions.y) * block_dimensions.x) as usize;
+                        if block_bounds(UVec3::new(x, y, z)).min_distance(bounds) <= reaches[block] {
+                            bins[block][kind].push(index as u32);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let header_len = block_count as u32 * BIN_HEADER_SIZE;
+    let mut headers = Vec::with_capacity(block_count * BIN_HEADER_SIZE as usize);
+    let mut indices = Vec::new();
+    for block in bins {
+        for kind in block {
+            headers.push(header_len + indices.len() as u32);
+            headers.push(kind.len() as u32);
+            indices.extend(kind);
+        }
+    }
+
+    headers.extend(indices);
+    headers
+}
+
+// the blocks of an entry stored as bricks which need a brick, and the distance from each block to
+// the surface
+pub(crate) struct BrickLayout {
+    // x-major block indices
+    pub occupied: Vec<u32>,
+    // per block, the distance at its center for occupied blocks and a lower bound over the whole
+    // block for the rest
+    pub distances: Vec<f32>,
+}
+
+/// classify the blocks of an entry stored as bricks. as in `create_sdf_bricks_from_mesh_cpu`, a
+/// block can only contain surface if its center is closer than its half diagonal, allowing for
+/// thickened thin features. empty blocks keep their center distance shrunk by that reach, so
+/// nothing marching through them steps past the surface.
+///
+/// center distances come from a bvh over the features rather than testing every feature, as this
+/// runs for every block of what are usually the largest entries
+pub(crate) fn brick_layout(
+    data: &PreprocessedMeshData,
+    options: &SdfOptions,
+    aabb_min: Vec3A,
+    scale: Vec3A,
+    block_dimensions: UVec3,
+) -> BrickLayout {
+    let reach = (scale * WORKGROUP_SIZE as f32).length() * 0.5
+        + options.min_thickness * scale.max_element() * 2.0;
+
+    let bvh = FeatureBvh::new(data);
+    let mut layout = BrickLayout {
+        occupied: Vec::new(),
+        distances: Vec::new(),
+    };
+    let mut index = 0;
+    for z in 0..block_dimensions.z {
+        for y in 0..block_dimensions.y {
+            for x in 0..block_dimensions.x {
+                let first_voxel = UVec3::new(x, y, z) * WORKGROUP_SIZE;
+                let center =
+                    aabb_min + (first_voxel.as_vec3a() + (WORKGROUP_SIZE - 1) as f32 * 0.5) * scale;
+                let distance = compute_distance_bvh(data, &bvh, options, center);
+                if distance.abs() <= reach {
+                    layout.occupied.push(index);
+                    layout.distances.push(distance);
+                } else {
+                    layout.distances.push(distance - reach.copysign(distance));
+                }
+                index += 1;
+            }
+        }
+    }
+    layout
+}
+
+// a skinned vertex in the previous and current pose
+#[derive(Clone, Copy)]
+pub(crate) struct VertexMotion {
+    pub previous: Vec3A,
+    pub current: Vec3A,
+    // the most heavily weighted joint that moved, if any did
+    pub moved_joint: Option<u32>,
+}
+
+// moved features grouped by the joint moving them, so blocks far from a joint's whole region skip
+// its features
+struct JointRegion {
+    bounds: Bounds,
+    features: Vec<Bounds>,
+}
+
+/// blocks (as x-major indices) whose distances can differ between the previous and current pose.
+/// `sources` are the mesh vertices of each vertex, edge and triangle feature in turn, as laid out
+/// for gpu skinning. a block is unchanged when some static vertex is closer to all of it than any
+/// moved feature is in either pose, as its nearest feature is then static in both.
+pub(crate) fn dirty_blocks(
+    sources: &[u32],
+    feature_counts: [usize; 3],
+    motions: &[VertexMotion],
+    aabb_min: Vec3A,
+    scale: Vec3A,
+    block_dimensions: UVec3,
+) -> Vec<u32> {
+    let static_points = sources[..feature_counts[0]]
+        .iter()
+        .map(|i| motions[*i as usize])
+        .filter(|m| m.moved_joint.is_none())
+        .map(|m| m.current)
+        .collect::<Vec<_>>();
+
+    // bounds of each moved feature covering both poses, by the joint moving its first moved vertex
+    let mut regions = HashMap::<u32, JointRegion>::default();
+    let mut offset = 0;
+    for (kind, count) in feature_counts.iter().enumerate() {
+        for feature in sources[offset..offset + count * (kind + 1)].chunks_exact(kind + 1) {
+            let feature = feature.iter().map(|i| motions[*i as usize]).collect::<Vec<_>>();
+            let Some(joint) = feature.iter().find_map(|m| m.moved_joint) else { continue };
+            let points = feature
+                .iter()
+                .flat_map(|m| [m.previous, m.current])
+                .collect::<Vec<_>>();
+            let bounds = Bounds::from_points(&points);
+            let region = regions.entry(joint).or_insert(JointRegion {
+                bounds,
+                features: Vec::new(),
+            });
+            region.bounds = region.bounds.union(&bounds);
+            region.features.push(bounds);
+        }
+        offset += count * (kind + 1);
+    }
+
+    if regions.is_empty() {
+        return Vec::new();
+    }
+
+    let mut dirty = Vec::new();
+    let mut index = 0;
+    for z in 0..block_dimensions.z {
+        for y in 0..block_dimensions.y {
+            for x in 0..block_dimensions.x {
+                let first_voxel = UVec3::new(x, y, z) * WORKGROUP_SIZE;
+                let block = Bounds {
+                    min: aabb_min + first_voxel.as_vec3a() * scale,
+                    max: aabb_min + (first_voxel + WORKGROUP_SIZE - 1).as_vec3a() * scale,
+                };
+
+                let mut moved_distance = f32::MAX;
+                for region in regions.values() {
+                    if block.min_distance(&region.bounds) >= moved_distance {
+                        continue;
+                    }
+                    for bounds in region.features.iter() {
+                        moved_distance = moved_distance.min(block.min_distance(bounds));
+                    }
+                }
+
+                // with the same allowance for float noise as binning, the block is unchanged when
+                // a static vertex is closer to all of it than the nearest moved feature
+                let threshold = (moved_distance - 1e-5) / 1.001;
+                if !static_points.iter().any(|p| block.max_distance(*p) < threshold) {
+                    dirty.push(index);
+                }
+                index += 1;
+            }
+        }
+    }
+    dirty
+}
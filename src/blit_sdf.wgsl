@@ -0,0 +1,90 @@
+struct BlitParams {
+    write_position: vec3<u32>,
+    dimensions: vec3<u32>,
+    // voxel positions in the entity's space, for composite deltas
+    aabb_min: vec3<f32>,
+    scale: vec3<f32>,
+    delta_count: u32,
+};
+
+struct Delta {
+    shape: u32,
+    op: u32,
+    // sphere and box center or capsule start
+    a: vec3<f32>,
+    // box half extents or capsule end
+    b: vec3<f32>,
+    radius: f32,
+};
+
+struct Deltas {
+    data: array<Delta>,
+};
+
+let DELTA_SHAPE_SPHERE: u32 = 0u;
+let DELTA_SHAPE_BOX: u32 = 1u;
+let DELTA_SHAPE_CAPSULE: u32 = 2u;
+let DELTA_OP_UNION: u32 = 0u;
+let DELTA_OP_SUBTRACT: u32 = 1u;
+
+@group(0) @binding(0)
+var source: texture_3d<f32>;
+@group(0) @binding(1)
+#ifdef ATLAS_R16FLOAT
+var texture: texture_storage_3d<r16float, write>;
+#else
+var texture: texture_storage_3d<r32float, write>;
+#endif
+@group(0) @binding(2)
+var<uniform> params: BlitParams;
+@group(0) @binding(3)
+var<storage> deltas: Deltas;
+
+fn shape_distance(delta: Delta, p: vec3<f32>) -> f32 {
+    if (delta.shape == DELTA_SHAPE_SPHERE) {
+        return length(p - delta.a) - delta.radius;
+    }
+    if (delta.shape == DELTA_SHAPE_BOX) {
+        let q = abs(p - delta.a) - delta.b;
+        return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+    }
+    // capsule
+    let pa = p - delta.a;
+    let ba = delta.b - delta.a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-12), 0.0, 1.0);
+    return length(pa - ba * h) - delta.radius;
+}
+
+// copy a precomputed sdf into its atlas slot, trilinearly resampling when the sizes differ. both
+// volumes have voxels at the aabb corners, so voxel 0 maps to 0 and the last voxel to the last
+@compute
+@workgroup_size(8, 8, 8)
+fn blit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let source_max = vec3<i32>(textureDimensions(source)) - 1;
+    let coords = vec3<f32>(invocation_id) / vec3<f32>(max(params.dimensions - 1u, vec3<u32>(1u))) * vec3<f32>(source_max);
+
+    let base = vec3<i32>(floor(coords));
+    let t = coords - floor(coords);
+
+    var dist = 0.0;
+    for (var corner = 0; corner < 8; corner = corner + 1) {
+        let offset = vec3<i32>(corner & 1, (corner >> 1u) & 1, (corner >> 2u) & 1);
+        let weights = select(1.0 - t, t, offset == vec3<i32>(1));
+        let sample = textureLoad(source, min(base + offset, source_max), 0).r;
+        dist = dist + sample * weights.x * weights.y * weights.z;
+    }
+
+    // composite deltas, applied in order
+    let p = params.aabb_min + vec3<f32>(invocation_id) * params.scale;
+    for (var i = 0u; i < params.delta_count; i = i + 1u) {
+        let delta = deltas.data[i];
+        let shape = shape_distance(delta, p);
+        if (delta.op == DELTA_OP_UNION) {
+            dist = min(dist, shape);
+        } else {
+            dist = max(dist, -shape);
+        }
+    }
+
+    textureStore(texture, vec3<i32>(params.write_position + invocation_id), vec4<f32>(dist, 0.0, 0.0, 1.0));
+}
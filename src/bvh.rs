@@ -0,0 +1,175 @@
+use bevy::math::Vec3A;
+
+use crate::{
+    binning::{feature_bounds, Bounds},
+    cpu::{edge_nearest, triangle_nearest, vertex_nearest, NearestFeature},
+    utils::PreprocessedMeshData,
+};
+
+// meshes with at least this many features traverse a bvh instead of using per block bins
+pub(crate) const BVH_MIN_FEATURES: usize = 4096;
+
+// u32s per node: min and max bounds (as f32 bits), then either the first item and item count of a
+// leaf, or the right child and zero for an interior node. must match compute_sdf.wgsl
+pub(crate) const BVH_NODE_SIZE: usize = 8;
+
+const MAX_LEAF_ITEMS: usize = 4;
+
+// deepest leaf below the root. the shader's traversal stack holds at most one entry per level plus
+// the two children just pushed, so this must stay below `BVH_STACK_SIZE` in compute_sdf.wgsl.
+// median splits only reach it past 2^33 features, so in practice nothing is capped
+const MAX_DEPTH: u32 = 31;
+
+// items are feature indices tagged with their kind in the top bits
+const ITEM_KIND_SHIFT: u32 = 30;
+
+struct Item {
+    // kind << ITEM_KIND_SHIFT | index
+    tag: u32,
+    bounds: Bounds,
+    centroid: Vec3A,
+}
+
+// a bvh over the features of a mesh. nodes are (bounds, right child or first leaf item, leaf item
+// count), depth first so the left child of an interior node immediately follows it
+pub(crate) struct FeatureBvh {
+    nodes: Vec<(Bounds, u32, u32)>,
+    leaf_items: Vec<u32>,
+}
+
+impl FeatureBvh {
+    pub fn new(data: &PreprocessedMeshData) -> Self {
+        let mut items = Vec::new();
+        for (kind, bounds) in feature_bounds(data).into_iter().enumerate() {
+            items.extend(bounds.into_iter().enumerate().map(|(index, bounds)| Item {
+                tag: (kind as u32) << ITEM_KIND_SHIFT | index as u32,
+                centroid: (bounds.min + bounds.max) * 0.5,
+                bounds,
+            }));
+        }
+
+        let mut bvh = FeatureBvh {
+            nodes: Vec::new(),
+            leaf_items: Vec::new(),
+        };
+        if !items.is_empty() {
+            build_node(&mut items, 0, &mut bvh.nodes, &mut bvh.leaf_items);
+        }
+        bvh
+    }
+
+    // the nearest feature to the point, as `cpu::nearest_surface` finds by iterating them all.
+    // nodes further than the best so far are skipped, nearer child first
+    pub fn nearest(&self, data: &PreprocessedMeshData, point: Vec3A) -> NearestFeature {
+        let mut best = NearestFeature {
+            dist_sq: f32::MAX,
+            ..Default::default()
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let (bounds, first, count) = self.nodes[index as usize];
+            if bounds.distance_sq(point) > best.dist_sq {
+                continue;
+            }
+
+            if count == 0 {
+                let (left, right) = (index + 1, first);
+                let left_sq = self.nodes[left as usize].0.distance_sq(point);
+                let right_sq = self.nodes[right as usize].0.distance_sq(point);
+                match left_sq <= right_sq {
+                    true => stack.extend([right, left]),
+                    false => stack.extend([left, right]),
+                }
+                continue;
+            }
+
+            for &tag in self.leaf_items[first as usize..(first + count) as usize].iter() {
+                let index = (tag & ((1 << ITEM_KIND_SHIFT) - 1)) as usize;
+                let nearest = match tag >> ITEM_KIND_SHIFT {
+                    0 => {
+                        let (v, n) = data.vertices[index];
+                        vertex_nearest(v, n, point, best.dist_sq)
+                    }
+                    1 => {
+                        let ((v0, v1), n) = data.edges[index];
+                        edge_nearest(v0, v1, n, point, best.dist_sq)
+                    }
+                    _ => triangle_nearest(&data.triangles[index], point, best.dist_sq),
+                };
+                if let Some(nearest) = nearest {
+                    best = nearest;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// a flattened bvh over all the features of a mesh, for nearest feature queries in the calc pass.
+///
+/// nodes are laid out depth first, so the left child of an interior node immediately follows it,
+/// and are followed by the leaf item lists. item starts and right child indices are relative to
+/// the start of the result.
+pub(crate) fn build_feature_bvh(data: &PreprocessedMeshData) -> Vec<u32> {
+    let FeatureBvh { nodes, leaf_items } = FeatureBvh::new(data);
+
+    // item starts are only known relative to the item lists until the node count is final
+    let items_start = nodes.len() as u32;
+    let mut result = Vec::with_capacity(nodes.len() * BVH_NODE_SIZE + leaf_items.len());
+    for (bounds, first, count) in nodes {
+        result.extend(bounds.min.to_array().map(f32::to_bits));
+        result.extend(bounds.max.to_array().map(f32::to_bits));
+        match count {
+            // interior nodes keep their right child as a node index
+            0 => result.push(first),
+            _ => result.push(first + items_start * BVH_NODE_SIZE as u32),
+        }
+        result.push(count);
+    }
+    result.extend(leaf_items);
+    result
+}
+
+// returns the node's index. nodes are (bounds, right child or first leaf item, leaf item count).
+// nodes at `MAX_DEPTH` are leaves however many items they hold
+fn build_node(
+    items: &mut [Item],
+    depth: u32,
+    nodes: &mut Vec<(Bounds, u32, u32)>,
+    leaf_items: &mut Vec<u32>,
+) -> u32 {
+    let bounds = items[1..]
+        .iter()
+        .fold(items[0].bounds, |bounds, item| bounds.union(&item.bounds));
+
+    let index = nodes.len() as u32;
+    if items.len() <= MAX_LEAF_ITEMS || depth == MAX_DEPTH {
+        nodes.push((bounds, leaf_items.len() as u32, items.len() as u32));
+        leaf_items.extend(items.iter().map(|item| item.tag));
+        return index;
+    }
+
+    // median split along the longest axis of the centroids, which keeps the tree balanced
+    let centroid_bounds = Bounds::from_points(&items.iter().map(|item| item.centroid).collect::<Vec<_>>());
+    let extent = centroid_bounds.max - centroid_bounds.min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+
+    nodes.push((bounds, 0, 0));
+    let (left, right) = items.split_at_mut(mid);
+    build_node(left, depth + 1, nodes, leaf_items);
+    let right = build_node(right, depth + 1, nodes, leaf_items);
+    nodes[index as usize].1 = right;
+    index
+}
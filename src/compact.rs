@@ -0,0 +1,101 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    compute::SdfPipelineStatus, Sdf, SdfAtlas, SdfAtlasInsert, SdfAtlasKey, SdfGlobalSettings,
+};
+
+/// an atlas entry relocated by compaction this frame. the compute node copies the texels from
+/// the old slot to the new one before anything else is written, so the entry stays usable
+#[derive(Clone)]
+pub struct SdfAtlasMove {
+    pub key: SdfAtlasKey,
+    pub from: UVec3,
+    pub to: UVec3,
+    pub size: UVec3,
+}
+
+// entries already reallocated during the current compaction pass, so an entry the allocator
+// doesn't place any lower isn't moved back and forth
+#[derive(Default)]
+pub(crate) struct SdfCompactionPass(HashSet<SdfAtlasKey>);
+
+// once an entry has failed to fit, reallocate the resident entries from the far end of the atlas
+// a few at a time, letting the allocator pack them towards the origin and join up the free space
+// left behind by purges. the pass ends when every entry has been reallocated once
+pub(crate) fn compact_atlas(
+    settings: Res<SdfGlobalSettings>,
+    pipeline_status: Res<SdfPipelineStatus>,
+    cameras: Query<&Camera, With<Camera3d>>,
+    sdfs: Query<(Entity, &Sdf, Option<&Handle<Mesh>>)>,
+    mut pass: Local<SdfCompactionPass>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    atlas.moves.clear();
+
+    if !atlas.fragmented || settings.compaction_moves_per_frame == 0 {
+        return;
+    }
+
+    // the copies run in the compute node, which only runs for a 3d camera in the default graph,
+    // and the moved regions are downsampled by the mip pipeline
+    if !pipeline_status.is_ready(settings.compute_workgroup)
+        || (settings.require_3d_camera && !cameras.iter().any(|camera| camera.is_active))
+    {
+        return;
+    }
+
+    let atlas = &mut *atlas;
+    let queued = atlas
+        .need_computing
+        .iter()
+        .map(|(_, key, _)| key.clone())
+        .collect::<HashSet<_>>();
+
+    // entries being written this frame already have their new slot, and pinned entries stay put.
+    // bricks are all the same size, so entries stored as bricks never fragment the atlas
+    let mut candidates = sdfs
+        .iter()
+        .filter(|(_, sdf, _)| !sdf.skinned)
+        .filter_map(|(ent, sdf, maybe_mesh)| atlas.key(ent, sdf, maybe_mesh))
+        .filter(|key| {
+            !queued.contains(key)
+                && !atlas.is_pinned(key)
+                && !atlas.is_bricks(key)
+                && !pass.0.contains(key)
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter_map(|key| {
+            let slot = atlas.slot(&key)?;
+            Some((key, slot.position, slot.size))
+        })
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        pass.0.clear();
+        atlas.fragmented = false;
+        return;
+    }
+
+    // furthest from the origin first
+    candidates.sort_by_key(|(_, position, _)| std::cmp::Reverse((position.z, position.y, position.x)));
+
+    for (key, from, size) in candidates {
+        if atlas.moves.len() >= settings.compaction_moves_per_frame {
+            break;
+        }
+
+        pass.0.insert(key.clone());
+        // keeping any coarse or reduced state, the entry is the same
+        atlas.page.purge(&key);
+        let SdfAtlasInsert::New(slot) = atlas.insert(key.clone(), size) else {
+            // the freed slot always fits, if not the entry is regenerated next frame
+            warn!("failed to reallocate sdf atlas entry during compaction");
+            continue;
+        };
+        let to = slot.position;
+        if to != from {
+            atlas.moves.push(SdfAtlasMove { key, from, to, size });
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::num::NonZeroU32;
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::skinning::SkinnedMesh,
+        render_asset::RenderAssets,
+        render_resource::{Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, TextureAspect},
+        renderer::RenderQueue,
+        RenderApp, RenderStage,
+    },
+    utils::HashSet,
+};
+
+use crate::{
+    compact_atlas, queue_sdfs,
+    readback::{SdfReadback, SdfReadbackReady},
+    utils::encode_bc4,
+    Sdf, SdfAtlas, SdfAtlasInsert, SdfAtlasKey, SdfDeltas, SdfMorphTargets, SdfStatus,
+};
+
+/// moves static entries with `SdfOptions::compress` into the BC4 compressed atlas (see
+/// `SdfGlobalSettings::compressed_atlas_size`) once they're generated. each entry is read back,
+/// block compressed on the cpu and uploaded, and its slot in the atlas is freed. add after
+/// `SdfPlugin` and `SdfReadbackPlugin`.
+pub struct SdfCompressionPlugin;
+
+impl Plugin for SdfCompressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SdfCompressionRequests>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                compress_entries.after(queue_sdfs).before(compact_atlas),
+            );
+
+        app.sub_app_mut(RenderApp)
+            .add_system_to_stage(RenderStage::Queue, queue_compressed_uploads);
+    }
+}
+
+// an entry in the compressed atlas
+#[derive(Clone, Copy)]
+pub(crate) struct SdfCompressedSlot {
+    // first texel, with the layer in z
+    pub position: UVec3,
+    // voxels along each axis, layers in z
+    pub size: UVec3,
+    // distances are stored as a fraction of this
+    pub range: f32,
+}
+
+impl SdfAtlas {
+    /// whether the entry has been moved to the compressed atlas, see `SdfOptions::compress`. it
+    /// then has no slot in the atlas
+    pub fn is_compressed(&self, key: &SdfAtlasKey) -> bool {
+        self.compressed.contains_key(key)
+    }
+
+    // free an entry's compressed blocks, if it has any
+    pub(crate) fn purge_compressed(&mut self, key: &SdfAtlasKey) {
+        if self.compressed.remove(key).is_some() {
+            self.page.purge_compressed(key);
+        }
+    }
+}
+
+// entities waiting for the readback to compress, and entries which didn't fit in the compressed
+// atlas, left dense until they're regenerated
+#[derive(Default)]
+struct SdfCompressionRequests {
+    pending: HashSet<Entity>,
+    rejected: HashSet<SdfAtlasKey>,
+}
+
+#[allow(clippy::type_complexity)]
+fn compress_entries(
+    mut commands: Commands,
+    mut readbacks: EventReader<SdfReadbackReady>,
+    sdfs: Query<
+        (Entity, &Sdf, Option<&Handle<Mesh>>, &SdfStatus),
+        (
+            Without<SkinnedMesh>,
+            Without<SdfMorphTargets>,
+            Without<SdfDeltas>,
+        ),
+    >,
+    mut requests: ResMut<SdfCompressionRequests>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    atlas.compressed_uploads.clear();
+    let requests = &mut *requests;
+    requests.pending.retain(|ent| sdfs.get(*ent).is_ok());
+    requests.rejected.retain(|key| atlas.slot(key).is_some());
+
+    if !atlas.page.has_compressed() {
+        return;
+    }
+
+    for event in readbacks.iter() {
+        if !requests.pending.remove(&event.entity) {
+            continue;
+        }
+        let Ok((ent, sdf, maybe_mesh, _)) = sdfs.get(event.entity) else { continue };
+        let Some(key) = atlas.key(ent, sdf, maybe_mesh) else { continue };
+        // regenerated since the readback was requested
+        if atlas.is_compressed(&key)
+            || atlas.slot(&key).is_none()
+            || atlas.need_computing.iter().any(|(_, k, _)| *k == key)
+        {
+            continue;
+        }
+
+        let grid = &event.grid;
+        let position = match atlas.page.insert_compressed(key.clone(), grid.dimension) {
+            SdfAtlasInsert::New(slot) | SdfAtlasInsert::Existing(slot) => slot.position,
+            SdfAtlasInsert::NoFit => {
+                requests.rejected.insert(key);
+                continue;
+            }
+        };
+        // whole 4x4 blocks of each layer
+        let blocks = (grid.dimension + UVec3::new(3, 3, 0)) / UVec3::new(4, 4, 1);
+
+        let range = grid
+            .data
+            .iter()
+            .fold(1e-6, |range: f32, d| range.max(d.abs()));
+        let data = encode_bc4(&grid.data, grid.dimension, 1.0 / range);
+        atlas.compressed_uploads.push((position, blocks, data));
+        atlas.compressed.insert(
+            key.clone(),
+            SdfCompressedSlot {
+                position,
+                size: grid.dimension,
+                range,
+            },
+        );
+        // the views sample the compressed copy from now on
+        atlas.page.purge(&key);
+    }
+
+    for (ent, sdf, maybe_mesh, status) in sdfs.iter() {
+        if !sdf.options.compress
+            || !matches!(status, SdfStatus::Full | SdfStatus::Reduced)
+            || requests.pending.contains(&ent)
+        {
+            continue;
+        }
+        let Some(key) = atlas.key(ent, sdf, maybe_mesh) else { continue };
+        if atlas.is_compressed(&key)
+            || atlas.is_bricks(&key)
+            || atlas.slot(&key).is_none()
+            || requests.rejected.contains(&key)
+        {
+            continue;
+        }
+        commands.entity(ent).insert(SdfReadback);
+        requests.pending.insert(ent);
+    }
+}
+
+// write the blocks of entries compressed this frame
+fn queue_compressed_uploads(
+    atlas: Res<SdfAtlas>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_queue: Res<RenderQueue>,
+) {
+    if atlas.compressed_uploads.is_empty() {
+        return;
+    }
+    let Some(gpu_image) = gpu_images.get(&atlas.compressed_image) else { return };
+
+    for (position, blocks, data) in atlas.compressed_uploads.iter() {
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            data,
+            // 8 bytes per 4x4 block
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(blocks.x * 8),
+                rows_per_image: NonZeroU32::new(blocks.y),
+            },
+            Extent3d {
+                width: blocks.x * 4,
+                height: blocks.y * 4,
+                depth_or_array_layers: blocks.z,
+            },
+        );
+    }
+}
@@ -1,45 +1,378 @@
 use bevy::{
+    asset::load_internal_asset,
     core_pipeline::core_3d,
+    math::Vec3A,
     prelude::*,
+    reflect::TypeUuid,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+        primitives::Aabb,
         render_asset::RenderAssets,
         render_graph::{self, RenderGraph},
         render_resource::{encase::private::WriteInto, *},
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
         RenderApp, RenderStage,
     },
+    tasks::ComputeTaskPool,
+    transform::TransformSystem,
+    utils::{FloatOrd, HashMap, HashSet},
+};
+use std::{
+    borrow::Cow,
+    num::{NonZeroU32, NonZeroU64},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
-use std::borrow::Cow;
 
-use crate::{utils::preprocess_mesh_for_sdf, Sdf, SdfAtlas};
+use crate::{
+    apply_failure_policy,
+    binning::{bin_features, brick_layout, dirty_blocks, VertexMotion, MIN_BINNED_FEATURES},
+    bvh::{build_feature_bvh, BVH_MIN_FEATURES},
+    hierarchy::SdfHierarchy,
+    preprocessed::PreprocessedMesh,
+    sdf_buffer_size,
+    utils::{
+        preprocess_mesh_for_sdf, preprocess_meshes_for_sdf, preprocess_rest_pose_for_sdf,
+        preprocess_topology_for_sdf, skin_vertices, MeshTopology, PreprocessedMeshData,
+    },
+    Sdf, SdfAtlas, SdfAtlasKey, SdfAutoBufferSize, SdfBackFaces, SdfComputeWorkgroup, SdfDeltaOp,
+    SdfDeltas, SdfFailReason, SdfGlobalSettings, SdfMethod, SdfMetric, SdfMorphTargets, SdfOptions,
+    SdfShape, SdfSign, SdfStatus,
+};
 
 pub const WORKGROUP_SIZE: u32 = 8;
 
+pub const COMPUTE_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 9260670219764286020);
+pub const BLIT_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 993477912855787260);
+pub const JFA_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 11616267270053840746);
+pub const DISPATCH_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 11767318553802719066);
+pub const MIP_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 4385067205219936731);
+
+// instance flags, must match compute_sdf.wgsl
+// read by the jump flood passes, the calc pass is specialized on it instead
+const INSTANCE_FLAG_IGNORE_BACK_FACES: u32 = 1;
+const INSTANCE_FLAG_INVERT: u32 = 2;
+const INSTANCE_FLAG_SKINNED: u32 = 4;
+// terminates the instance list, the buffers are oversized so their length can't be used
+const INSTANCE_FLAG_END: u32 = 8;
+// only the lower half along the axis is dispatched and written to both halves
+const INSTANCE_FLAG_MIRROR_X: u32 = 16;
+// voxels the surface may pass through are inside, see `SdfOptions::surface_mask`
+const INSTANCE_FLAG_SURFACE_MASK: u32 = 128;
+// `SdfMetric::Chebyshev` and `SdfMetric::Manhattan`, euclidean without either
+const INSTANCE_FLAG_CHEBYSHEV: u32 = 256;
+const INSTANCE_FLAG_MANHATTAN: u32 = 512;
+// chunks of an instance split across dispatches, see `SdfBufferLimits::split`: merge with the
+// partial distances of the chunks before, and store the merged partials for the chunks after
+// rather than writing the atlas
+const INSTANCE_FLAG_CHUNK_READ: u32 = 1024;
+const INSTANCE_FLAG_CHUNK_WRITE: u32 = 2048;
+// bin_offset of instances that iterate all their features
+const NO_BINS: u32 = u32::MAX;
+// block_list_offset of instances that compute every block
+const NO_BLOCK_LIST: u32 = u32::MAX;
+// bvh_offset of instances without a bvh
+const NO_BVH: u32 = u32::MAX;
+// brick_offset of instances written to their atlas slot
+const NO_BRICKS: u32 = u32::MAX;
+// group of the end marker
+const NO_GROUP: u32 = u32::MAX;
+// partial_offset of instances computed in a single dispatch
+const NO_PARTIALS: u32 = u32::MAX;
+// bytes per voxel of the partials buffer, the nearest distance squared and metric length, the
+// sign from the nearest feature and the winding number so far
+const PARTIAL_SIZE: u64 = 16;
+
+// threads per workgroup for the skinning and jump flood seeding entry points
+const SKIN_WORKGROUP_SIZE: u32 = 64;
+const JFA_SEED_WORKGROUP_SIZE: u32 = 64;
+// edge length of the mip downsampling workgroups
+const MIP_WORKGROUP_SIZE: u32 = 4;
+// workgroups per dispatch dimension guaranteed by every device, must match the shaders
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// limits the compute work queued each frame, so many entities becoming visible at once don't
+/// stall the gpu for a whole frame. entries over the budget are deferred to later frames, and
+/// existing atlas contents (coarse entries, the previous pose of animated entries) stay in use
+/// until their replacement is generated. insert before adding the plugin, or modify at runtime.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdfComputeBudget {
+    // maximum 8x8x8 blocks dispatched per frame, none for unlimited. an entry larger than the
+    // whole budget is still generated, on a frame of its own
+    pub max_blocks_per_frame: Option<u32>,
+}
+
+/// where the sdf nodes are added to the render graph. insert before adding the plugin to use an
+/// app without the core 3d graph (a custom render graph, or a headless render world). nodes added
+/// to the top level graph run once per frame rather than once per 3d camera, so also clear
+/// `SdfGlobalSettings::require_3d_camera` to generate without one.
+#[derive(Clone)]
+pub struct SdfComputeGraphConfig {
+    // sub graph to add the nodes to, none for the top level render graph
+    pub sub_graph: Option<&'static str>,
+    // node of that graph which samples the atlas, the sdf nodes run before it. none if nothing
+    // in the graph depends on them
+    pub run_before: Option<&'static str>,
+}
+
+impl Default for SdfComputeGraphConfig {
+    fn default() -> Self {
+        Self {
+            sub_graph: Some(core_3d::graph::NAME),
+            run_before: Some(core_3d::graph::node::MAIN_PASS),
+        }
+    }
+}
+
+impl SdfComputeGraphConfig {
+    // the graph holding the sdf nodes, none if the configured sub graph doesn't exist
+    pub(crate) fn graph<'a>(&self, render_graph: &'a mut RenderGraph) -> Option<&'a mut RenderGraph> {
+        match self.sub_graph {
+            Some(name) => render_graph.get_sub_graph_mut(name),
+            None => Some(render_graph),
+        }
+    }
+
+    // order a node before `run_before`, if it's set and exists
+    pub(crate) fn add_run_before(&self, graph: &mut RenderGraph, node: &'static str) {
+        let Some(run_before) = self.run_before else { return };
+        if graph.add_node_edge(node, run_before).is_err() {
+            warn!("sdf render graph node `{}` not found, atlas writes may land after it samples the atlas", run_before);
+        }
+    }
+}
+
+// blocks queued so far this frame against the budget
+pub(crate) struct BlockBudget {
+    remaining: Option<u32>,
+    queued: bool,
+}
+
+impl BlockBudget {
+    pub fn new(max_blocks_per_frame: Option<u32>) -> Self {
+        Self {
+            remaining: max_blocks_per_frame,
+            queued: false,
+        }
+    }
+
+    // whether an entry with this atlas size can be queued
+    pub fn fits(&self, size: UVec3) -> bool {
+        // always allow one entry so oversized entries still make progress
+        match self.remaining {
+            Some(remaining) => !self.queued || block_count(size) <= remaining,
+            None => true,
+        }
+    }
+
+    // reserve the blocks for an entry, returns false if it doesn't fit
+    pub fn take(&mut self, size: UVec3) -> bool {
+        if !self.fits(size) {
+            return false;
+        }
+        self.queued = true;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining = remaining.saturating_sub(block_count(size));
+        }
+        true
+    }
+}
+
+// workgroups covering `count` threads in rows of at most `MAX_WORKGROUPS` workgroups, for 1d
+// dispatches that can outgrow a single dimension. shaders recover the thread index as
+// `id.x + id.y * num_workgroups.x * workgroup_size`
+fn linear_workgroups(count: u32, workgroup_size: u32) -> UVec3 {
+    let workgroups = (count + workgroup_size - 1) / workgroup_size;
+    let x = workgroups.min(MAX_WORKGROUPS).max(1);
+    UVec3::new(x, (workgroups + x - 1) / x, 1)
+}
+
+// the atlas size whose blocks match the compute dispatched for an entry, which is halved along
+// the mirror axis of symmetric entries
+pub(crate) fn dispatch_size(size: UVec3, options: &SdfOptions, animated: bool) -> UVec3 {
+    let mut size = size;
+    if let Some(axis) = options.mirror_axis(animated) {
+        let blocks = (size[axis] - 1) / WORKGROUP_SIZE;
+        size[axis] = (blocks + 1) / 2 * WORKGROUP_SIZE + 1;
+    }
+    size
+}
+
+// compute blocks needed for an atlas entry of the given size
+fn block_count(size: UVec3) -> u32 {
+    let block_dimensions = (size - 1) / WORKGROUP_SIZE;
+    block_dimensions.x * block_dimensions.y * block_dimensions.z
+}
+
+/// options the calc pass is specialized on. runs of instances with different keys are
+/// dispatched with their own pipelines in the same frame
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct SdfComputePipelineKey {
+    // distances are all positive (`SdfBackFaces::Ignore`), so the sign test is compiled out
+    pub unsigned: bool,
+    // the sign comes from the winding number rather than the nearest feature's normal
+    pub winding_number: bool,
+    pub workgroup: SdfComputeWorkgroup,
+    // the atlas is R16Float rather than R32Float, see `SdfAtlas::format`
+    pub half_precision: bool,
+    // also write the direction away from the surface into `SdfAtlas::gradient_image`
+    pub gradient: bool,
+}
+
+impl SdfComputePipelineKey {
+    pub fn new(
+        options: &SdfOptions,
+        settings: &SdfGlobalSettings,
+        atlas: &SdfAtlas,
+        bricks: bool,
+    ) -> Self {
+        let unsigned = options.back_faces == SdfBackFaces::Ignore;
+        Self {
+            unsigned,
+            winding_number: !unsigned && options.sign == SdfSign::WindingNumber,
+            workgroup: settings.compute_workgroup,
+            half_precision: atlas.format == TextureFormat::R16Float,
+            gradient: options.gradient
+                && atlas.gradients
+                && !bricks
+                && options.method == SdfMethod::BruteForce,
+        }
+    }
+}
+
+/// sent once, on the frame after sdfs are first dispatched to the gpu. until the compute
+/// pipelines have compiled, sdfs stay queued (without `SdfStatus::Full` etc)
+pub struct SdfComputeStarted;
+
+// compute pipeline readiness and the first dispatch, shared between the render world and the
+// main world
+#[derive(Clone, Default)]
+pub(crate) struct SdfPipelineStatus {
+    // the workgroup shape whose pipelines have all compiled
+    ready: Arc<Mutex<Option<SdfComputeWorkgroup>>>,
+    dispatched: Arc<AtomicBool>,
+    // entities whose entries the node couldn't write because a pipeline was missing, to requeue
+    skipped: Arc<Mutex<HashSet<Entity>>>,
+    // entities whose entries the node last copied or generated
+    written: Arc<Mutex<Vec<Entity>>>,
+}
+
+impl SdfPipelineStatus {
+    // whether sdfs queued this frame can be dispatched
+    pub fn is_ready(&self, workgroup: SdfComputeWorkgroup) -> bool {
+        *self.ready.lock().unwrap() == Some(workgroup)
+    }
+
+    pub fn was_skipped(&self, ent: Entity) -> bool {
+        self.skipped.lock().unwrap().contains(&ent)
+    }
+
+    // entities skipped by the node since the last call
+    pub fn take_skipped(&self) -> HashSet<Entity> {
+        std::mem::take(&mut *self.skipped.lock().unwrap())
+    }
+
+    // entities the node wrote the last time it ran with any entries
+    pub fn written(&self) -> Vec<Entity> {
+        self.written.lock().unwrap().clone()
+    }
+}
+
+// set by the first run of the compute node each frame, and cleared in prepare. the node is added
+// to a sub graph run once per 3d camera by default, but its writes must be applied exactly once
+// per frame: replaying the compaction moves copies entries over slots already reused
+#[derive(Default)]
+pub(crate) struct SdfComputeFrame {
+    ran: AtomicBool,
+}
+
+impl SdfComputeFrame {
+    // whether the compute node has run this frame
+    pub(crate) fn has_run(&self) -> bool {
+        self.ran.load(Ordering::Acquire)
+    }
+}
+
+fn prepare_compute_frame(mut frame: ResMut<SdfComputeFrame>) {
+    *frame.ran.get_mut() = false;
+}
+
 pub struct SdfComputePlugin;
 
 impl Plugin for SdfComputePlugin {
     fn build(&self, app: &mut App) {
+        let status = SdfPipelineStatus::default();
+
+        load_internal_asset!(app, COMPUTE_SDF_SHADER_HANDLE, "compute_sdf.wgsl", Shader::from_wgsl);
+        load_internal_asset!(app, BLIT_SDF_SHADER_HANDLE, "blit_sdf.wgsl", Shader::from_wgsl);
+        load_internal_asset!(app, JFA_SDF_SHADER_HANDLE, "jfa_sdf.wgsl", Shader::from_wgsl);
+        load_internal_asset!(app, DISPATCH_SDF_SHADER_HANDLE, "dispatch_sdf.wgsl", Shader::from_wgsl);
+        load_internal_asset!(app, MIP_SDF_SHADER_HANDLE, "mip_sdf.wgsl", Shader::from_wgsl);
+
+        // skinned entries read the propagated joint transforms, so they match the pose the mesh
+        // pipeline skins the receivers with this frame
         app.add_system_to_stage(
             CoreStage::PostUpdate,
-            preprocess_sdfs.label("preprocess sdfs"),
+            preprocess_sdfs
+                .label("preprocess sdfs")
+                .after(TransformSystem::TransformPropagate),
         )
         .add_plugin(ExtractResourcePlugin::<SdfData>::default())
-        .init_resource::<SdfData>();
+        .add_plugin(ExtractResourcePlugin::<SdfSkinData>::default())
+        .init_resource::<SdfComputeBudget>()
+        .init_resource::<SdfData>()
+        .init_resource::<SdfSkinData>()
+        .init_resource::<SdfBufferLimits>()
+        .init_resource::<PreprocessedMeshCache>()
+        .insert_resource(status.clone())
+        .add_event::<SdfComputeStarted>()
+        .add_system_to_stage(CoreStage::PreUpdate, report_compute_started);
+        #[cfg(feature = "shader_hot_reload")]
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            regenerate_on_shader_reload.before(crate::queue_sdfs),
+        );
+        let atlas_format = app.world.resource::<SdfAtlas>().format;
+        let graph_config = app
+            .world
+            .get_resource_or_insert_with(SdfComputeGraphConfig::default)
+            .clone();
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .insert_resource(status)
+            .insert_resource(SdfAtlasFormat(atlas_format))
             .init_resource::<SdfComputePipeline>()
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group);
+            .init_resource::<SpecializedComputePipelines<SdfComputePipeline>>()
+            .init_resource::<SdfGpuBuffers>()
+            .init_resource::<SdfComputeFrame>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_compute_frame)
+            .add_system_to_stage(RenderStage::Queue, queue_atlas_mips)
+            .add_system_to_stage(RenderStage::Queue, queue_atlas_moves)
+            .add_system_to_stage(RenderStage::Queue, queue_brick_indirections)
+            .add_system_to_stage(RenderStage::Queue, queue_bind_group.after(queue_atlas_mips))
+            .add_system_to_stage(RenderStage::Queue, queue_jfa_bind_group.after(queue_bind_group))
+            .add_system_to_stage(RenderStage::Queue, check_pipelines.after(queue_bind_group));
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        let graph_3d = render_graph
-            .get_sub_graph_mut(core_3d::graph::NAME)
-            .unwrap();
-        graph_3d.add_node("sdf_compute", SdfComputeNode::default());
-        graph_3d
-            .add_node_edge("sdf_compute", core_3d::graph::node::MAIN_PASS)
-            .unwrap();
+        let Some(graph) = graph_config.graph(&mut render_graph) else {
+            warn!(
+                "render graph `{}` not found, sdfs won't be generated. insert an `SdfComputeGraphConfig` naming the graph to use",
+                graph_config.sub_graph.unwrap_or_default()
+            );
+            return;
+        };
+        graph.add_node("sdf_compute", SdfComputeNode::default());
+        graph_config.add_run_before(graph, "sdf_compute");
     }
 }
 
@@ -51,14 +384,49 @@ struct SdfInstanceData {
     block_dimensions: UVec3,
     counts: UVec3,
     block_count: u32,
+    flags: u32,
+    weld_margin: f32,
+    // distance samples averaged per voxel
+    samples: u32,
+    // in mesh units, 0 when thin features aren't thickened
+    min_thickness: f32,
+    // skinned instances only: start of the feature source indices, of the mesh's vertices in the
+    // skin vertex buffer, and of the instance's joint matrices
+    skin_sources_offset: u32,
+    skin_vertex_offset: u32,
+    joint_offset: u32,
+    // start of the per block feature lists in the bins buffer, or NO_BINS
+    bin_offset: u32,
+    // start of the blocks to compute in the bins buffer for sparse updates, or NO_BLOCK_LIST
+    block_list_offset: u32,
+    // start of the feature bvh in the bins buffer, or NO_BVH
+    bvh_offset: u32,
+    // entries stored as bricks: start of the first texel of each computed block's brick in the
+    // bins buffer, 3 u32s per entry of the block list. otherwise NO_BRICKS
+    brick_offset: u32,
+    // split instances: start of the partial distances of their blocks' voxels in the partials
+    // buffer, shared by every chunk. otherwise NO_PARTIALS
+    partial_offset: u32,
+    // index of the instance's run in `SdfData::groups`
+    group: u32,
+    // written by the dispatch pass: the instance's first block
+    block_start: u32,
+    // the instance's first vertex, edge and triangle in the feature buffers
+    feature_start: UVec3,
 }
 
 #[derive(ShaderType, Clone, Default)]
 struct SdfInstancesData {
+    // written by the dispatch pass
+    instance_count: u32,
+    block_count: u32,
     #[size(runtime)]
     data: Vec<SdfInstanceData>,
 }
 
+// arguments for an indirect calc dispatch, one set per group
+const DISPATCH_ARGS_SIZE: u64 = 12;
+
 #[derive(ShaderType, Clone, Default)]
 struct SdfVerticesData {
     #[size(runtime)]
@@ -77,7 +445,7 @@ struct SdfTrisData {
     data: Vec<SdfTriData>,
 }
 
-#[derive(ShaderType, Clone)]
+#[derive(ShaderType, Clone, Default)]
 struct SdfTriData {
     a: Vec3,
     b: Vec3,
@@ -86,287 +454,2791 @@ struct SdfTriData {
     inv_area: f32,
 }
 
-#[derive(Component, Clone, ExtractResource, Default)]
-struct SdfData {
-    bind_group: Option<BindGroup>,
+#[derive(ShaderType, Clone, Default)]
+struct SdfSkinSourcesData {
+    #[size(runtime)]
+    data: Vec<u32>,
+}
+
+#[derive(ShaderType, Clone, Default)]
+struct SdfBinsData {
+    #[size(runtime)]
+    data: Vec<u32>,
+}
+
+#[derive(ShaderType, Clone, Default)]
+struct SdfJointsData {
+    #[size(runtime)]
+    data: Vec<Mat4>,
+}
+
+#[derive(ShaderType, Clone)]
+struct SdfSkinVertex {
+    position: Vec3,
+    joints: UVec4,
+    weights: Vec4,
+}
+
+#[derive(ShaderType, Clone, Default)]
+struct SdfSkinVerticesData {
+    #[size(runtime)]
+    data: Vec<SdfSkinVertex>,
+}
+
+// a precomputed image to copy into the atlas, with composite deltas to apply over it
+#[derive(Clone)]
+struct SdfBlit {
+    entity: Entity,
+    image: Handle<Image>,
+    write_position: UVec3,
+    dimensions: UVec3,
+    aabb_min: Vec3,
+    scale: Vec3,
+    deltas: SdfDeltasData,
+}
+
+#[derive(ShaderType)]
+struct SdfBlitParams {
+    write_position: UVec3,
+    dimensions: UVec3,
+    aabb_min: Vec3,
+    scale: Vec3,
+    delta_count: u32,
+}
+
+// per dispatch parameters for mip_sdf.wgsl, the region of the target level to write
+#[derive(ShaderType, Clone)]
+struct SdfMipParams {
+    position: UVec3,
+    size: UVec3,
+}
+
+// delta shapes and ops, must match blit_sdf.wgsl
+const DELTA_SHAPE_SPHERE: u32 = 0;
+const DELTA_SHAPE_BOX: u32 = 1;
+const DELTA_SHAPE_CAPSULE: u32 = 2;
+const DELTA_OP_UNION: u32 = 0;
+const DELTA_OP_SUBTRACT: u32 = 1;
+
+#[derive(ShaderType, Clone)]
+struct SdfDeltaData {
+    shape: u32,
+    op: u32,
+    // sphere and box center or capsule start
+    a: Vec3,
+    // box half extents or capsule end
+    b: Vec3,
+    radius: f32,
+}
+
+#[derive(ShaderType, Clone, Default)]
+struct SdfDeltasData {
+    #[size(runtime)]
+    data: Vec<SdfDeltaData>,
+}
+
+impl SdfDeltasData {
+    fn new(deltas: &SdfDeltas) -> Self {
+        Self {
+            data: deltas
+                .0
+                .iter()
+                .map(|delta| {
+                    let (shape, a, b, radius) = match delta.shape {
+                        SdfShape::Sphere { center, radius } => (DELTA_SHAPE_SPHERE, center, Vec3::ZERO, radius),
+                        SdfShape::Box { center, half_extents } => (DELTA_SHAPE_BOX, center, half_extents, 0.0),
+                        SdfShape::Capsule { start, end, radius } => (DELTA_SHAPE_CAPSULE, start, end, radius),
+                    };
+                    SdfDeltaData {
+                        shape,
+                        op: match delta.op {
+                            SdfDeltaOp::Union => DELTA_OP_UNION,
+                            SdfDeltaOp::Subtract => DELTA_OP_SUBTRACT,
+                        },
+                        a,
+                        b,
+                        radius,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// an instance generated with jump flooding rather than by the calc pass
+#[derive(Clone)]
+struct SdfJfaInstance {
+    write_position: UVec3,
+    aabb_min: Vec3,
+    scale: Vec3,
+    dimensions: UVec3,
+    // the instance's triangles, from the start of its batch in the shared triangle buffer
+    batch: u32,
+    tri_start: u32,
+    tri_count: u32,
+    // start of the instance's voxels in the seed buffers
+    voxel_offset: u32,
+    flags: u32,
+    weld_margin: f32,
+}
+
+// per dispatch parameters for jfa_sdf.wgsl
+#[derive(ShaderType, Clone)]
+struct SdfJfaParams {
+    write_position: UVec3,
+    tri_start: u32,
+    aabb_min: Vec3,
+    tri_count: u32,
+    scale: Vec3,
+    voxel_offset: u32,
+    dimensions: UVec3,
+    flags: u32,
+    weld_margin: f32,
+    step: u32,
+    flip: u32,
+}
+
+// a run of consecutive instances sharing a calc pipeline and feature batch
+#[derive(Clone)]
+struct SdfCalcGroup {
+    key: SdfComputePipelineKey,
+    batch: u32,
+    first_instance: u32,
+    end_instance: u32,
     block_count: u32,
+}
+
+// a run of consecutive instances whose features fit in a single storage binding of each feature
+// buffer. usually everything is one batch, but a frame with a lot of dense geometry is split so
+// each pass binds only its batch's slice of the buffers
+#[derive(Clone)]
+struct SdfFeatureBatch {
+    first_instance: u32,
+    end_instance: u32,
+    // first vertex, edge and triangle of the batch, aligned for use as a binding offset
+    feature_start: UVec3,
+    // one past the last
+    feature_end: UVec3,
+    // features written by the skin pass
+    skin_feature_count: u32,
+}
+
+// per group parameters for the calc and skin passes
+#[derive(ShaderType, Clone)]
+struct SdfCalcGroupParams {
+    first_instance: u32,
+    end_instance: u32,
+    // start of the bound slice of each feature buffer
+    feature_base: UVec3,
+}
+
+// bytes per vertex, edge and triangle in the feature buffers
+fn feature_strides() -> UVec3 {
+    UVec3::new(
+        <[Vec3; 2] as encase::ShaderSize>::SHADER_SIZE.get() as u32,
+        <[Vec3; 3] as encase::ShaderSize>::SHADER_SIZE.get() as u32,
+        <SdfTriData as encase::ShaderSize>::SHADER_SIZE.get() as u32,
+    )
+}
+
+/// storage binding limits of the render device, so preprocessing can split the feature buffers
+/// into batches that can each be bound
+#[derive(Clone, Copy)]
+pub(crate) struct SdfBufferLimits {
+    pub max_binding_size: u64,
+    pub offset_alignment: u64,
+}
+
+impl FromWorld for SdfBufferLimits {
+    fn from_world(world: &mut World) -> Self {
+        let limits = world.resource::<RenderDevice>().limits();
+        Self {
+            max_binding_size: limits.max_storage_buffer_binding_size as u64,
+            offset_alignment: limits.min_storage_buffer_offset_alignment as u64,
+        }
+    }
+}
+
+impl SdfBufferLimits {
+    // whether the features fit in one binding
+    fn fits(&self, counts: UVec3) -> bool {
+        let strides = feature_strides();
+        (0..3).all(|axis| counts[axis] as u64 * strides[axis] as u64 <= self.max_binding_size)
+    }
+
+    // the first element at or after `index` that can start a binding
+    fn align(&self, index: u32, stride: u32) -> u32 {
+        let mut index = index;
+        while index as u64 * stride as u64 % self.offset_alignment != 0 {
+            index += 1;
+        }
+        index
+    }
+
+    // features too dense to bind in one batch split into even chunks that each fit, or None if
+    // they fit. each chunk takes the same share of the vertices, edges and triangles
+    fn split(&self, data: &PreprocessedMeshData) -> Option<Vec<PreprocessedMeshData>> {
+        let counts = UVec3::new(
+            data.vertices.len() as u32,
+            data.edges.len() as u32,
+            data.triangles.len() as u32,
+        );
+        if self.fits(counts) {
+            return None;
+        }
+
+        let strides = feature_strides();
+        let chunk_count = (0..3)
+            .map(|axis| {
+                let capacity = (self.max_binding_size / strides[axis] as u64).max(1);
+                (counts[axis] as u64 + capacity - 1) / capacity
+            })
+            .max()
+            .unwrap() as usize;
+        let range = |len: usize, chunk: usize| {
+            let per_chunk = (len + chunk_count - 1) / chunk_count;
+            (chunk * per_chunk).min(len)..((chunk + 1) * per_chunk).min(len)
+        };
+        Some(
+            (0..chunk_count)
+                .map(|chunk| PreprocessedMeshData {
+                    vertices: data.vertices[range(data.vertices.len(), chunk)].to_vec(),
+                    edges: data.edges[range(data.edges.len(), chunk)].to_vec(),
+                    triangles: data.triangles[range(data.triangles.len(), chunk)].to_vec(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Component, Clone, ExtractResource, Default)]
+pub(crate) struct SdfData {
+    pub(crate) block_count: u32,
     instances: SdfInstancesData,
+    groups: Vec<SdfCalcGroup>,
     vertices: SdfVerticesData,
     edges: SdfEdgesData,
     tris: SdfTrisData,
+    bins: SdfBinsData,
+    batches: Vec<SdfFeatureBatch>,
+    // features of skinned instances are written by the skin pass from the persistent rest pose
+    // data in `SdfSkinData`, so their (start, count) runs in the feature buffers aren't uploaded
+    skin_features: Vec<(UVec3, UVec3)>,
+    joints: SdfJointsData,
+    blits: Vec<SdfBlit>,
+    jfa: Vec<SdfJfaInstance>,
+    // total voxels over the jump flood instances
+    jfa_voxel_count: u32,
+    // total voxels over the split instances, see `SdfBufferLimits::split`
+    partial_count: u32,
+    // atlas regions (position, size) written this frame, to downsample into the mip levels
+    mip_regions: Vec<(UVec3, UVec3)>,
+    // indirection regions (position, size) of entries stored as bricks generated this frame, with
+    // their texels in x-major order
+    indirections: Vec<(UVec3, UVec3, Vec<[f32; 4]>)>,
+    // entities whose entries are written this frame, by copy or generation
+    pub(crate) entities: Vec<Entity>,
+}
+
+// bind pose vertices of skinned meshes and the feature sources of their rest poses, only modified
+// when a new mesh is first skinned so the buffers are uploaded once rather than every frame
+#[derive(Clone, ExtractResource, Default)]
+struct SdfSkinData {
+    vertices: SdfSkinVerticesData,
+    offsets: HashMap<Handle<Mesh>, u32>,
+    // per rest pose: the skin vertex of each vertex, edge end and triangle corner, followed by the
+    // rest normals of the vertices then edges as f32 bits
+    sources: SdfSkinSourcesData,
+    source_offsets: HashMap<(Handle<Mesh>, FloatOrd), u32>,
+    // bumped whenever meshes are added or the data is rebuilt. the render world uploads only then,
+    // rather than whenever change detection sees the resource touched
+    generation: u32,
+}
+
+/// cached topology for skinned meshes, so per-frame preprocessing only re-applies the joint
+/// transforms, and rest pose features for meshes skinned on the gpu
+#[derive(Default)]
+pub struct PreprocessedMeshCache {
+    topologies: HashMap<Handle<Mesh>, MeshTopology>,
+    // keyed by min triangle area, which controls which rest pose triangles are kept
+    rest_poses: HashMap<(Handle<Mesh>, FloatOrd), (PreprocessedMeshData, Vec<u32>)>,
+    // joint matrices of the pose last written for each gpu skinned entity
+    previous_joints: HashMap<Entity, Vec<Mat4>>,
+}
+
+// how the calc pass finds the features near each voxel
+enum FeatureIndex {
+    All,
+    Bins(Vec<u32>),
+    Bvh(Vec<u32>),
+}
+
+// source geometry for a queued entry
+enum JobGeometry<'a> {
+    // a single mesh, with joint matrices if skinned and morph targets if present
+    Mesh(&'a Mesh, Option<Vec<Mat4>>, Option<&'a SdfMorphTargets>),
+    // a skinned mesh using the cached topology for its handle
+    Skinned(Handle<Mesh>, &'a Mesh, Vec<Mat4>, Option<&'a SdfMorphTargets>),
+    // a skinned mesh without morph targets, skinned from its cached rest pose in the shader
+    GpuSkinned(Handle<Mesh>, &'a Mesh, Vec<Mat4>),
+    // several meshes with transforms into the sdf entity's space
+    Hierarchy(Vec<(&'a Mesh, Mat4)>),
+    // geometry preprocessed ahead of time
+    Preprocessed(&'a PreprocessedMesh),
+}
+
+impl JobGeometry<'_> {
+    // posed geometry isn't symmetric
+    fn is_animated(&self) -> bool {
+        match self {
+            JobGeometry::Mesh(_, joints, morph) => joints.is_some() || morph.is_some(),
+            JobGeometry::Skinned(..) | JobGeometry::GpuSkinned(..) => true,
+            JobGeometry::Hierarchy(_) | JobGeometry::Preprocessed(_) => false,
+        }
+    }
+}
+
+// everything needed to preprocess one queued entry off the main thread
+struct PreprocessJob<'a> {
+    entity: Entity,
+    key: &'a SdfAtlasKey,
+    pipeline_key: SdfComputePipelineKey,
+    // written over the previous pose in the same slot
+    sparse: bool,
+    // stored as bricks, `write_position` is then the slot in the indirection texture
+    bricks: bool,
+    geometry: JobGeometry<'a>,
+    options: SdfOptions,
+    write_position: UVec3,
+    dimensions: UVec3,
+    aabb: &'a Aabb,
 }
 
 fn preprocess_sdfs(
+    mut commands: Commands,
     meshes: Res<Assets<Mesh>>,
-    atlas: Res<SdfAtlas>,
-    sdfs: Query<(&Sdf, Option<&Handle<Mesh>>, Option<&SkinnedMesh>)>,
+    images: Res<Assets<Image>>,
+    mut atlas: ResMut<SdfAtlas>,
+    sdfs: Query<(
+        &Sdf,
+        Option<&Handle<Mesh>>,
+        Option<&SkinnedMesh>,
+        Option<&SdfStatus>,
+        Option<&SdfMorphTargets>,
+        Option<&SdfDeltas>,
+        Option<&SdfAutoBufferSize>,
+    )>,
     inverse_bindposes: Res<Assets<SkinnedMeshInverseBindposes>>,
     joint_transforms: Query<&GlobalTransform>,
+    hierarchy: SdfHierarchy,
+    settings: Res<SdfGlobalSettings>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    mut mesh_cache: ResMut<PreprocessedMeshCache>,
+    preprocessed_meshes: Res<Assets<PreprocessedMesh>>,
     mut sdf_data: ResMut<SdfData>,
+    mut skin_data: ResMut<SdfSkinData>,
+    limits: Res<SdfBufferLimits>,
 ) {
+    for event in mesh_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } | AssetEvent::Removed { handle } => {
+                mesh_cache.topologies.remove(handle);
+                mesh_cache.rest_poses.retain(|(h, _), _| h != handle);
+                // offsets of the other meshes would move, so rebuild them all as they are used
+                if skin_data.offsets.contains_key(handle) {
+                    skin_data.vertices.data.clear();
+                    skin_data.offsets.clear();
+                    skin_data.sources.data.clear();
+                    skin_data.source_offsets.clear();
+                    skin_data.generation += 1;
+                }
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+
     sdf_data.block_count = 0;
     sdf_data.instances.data.clear();
+    sdf_data.groups.clear();
     sdf_data.vertices.data.clear();
     sdf_data.edges.data.clear();
     sdf_data.tris.data.clear();
+    sdf_data.bins.data.clear();
+    sdf_data.batches.clear();
+    sdf_data.skin_features.clear();
+    sdf_data.joints.data.clear();
+    sdf_data.blits.clear();
+    sdf_data.jfa.clear();
+    sdf_data.jfa_voxel_count = 0;
+    sdf_data.partial_count = 0;
+    sdf_data.mip_regions.clear();
+    sdf_data.indirections.clear();
+    sdf_data.entities.clear();
+
+    let atlas = &mut *atlas;
+
+    // compaction only copies the top level of relocated entries, the rest are downsampled again
+    if atlas.mip_levels > 1 {
+        for atlas_move in atlas.moves.iter() {
+            sdf_data.mip_regions.push((atlas_move.to, atlas_move.size - 1));
+        }
+    }
 
+    // gather the world data for each entry on the main thread
+    let mut jobs = Vec::new();
     for (ent, key, aabb) in atlas.need_computing.iter() {
-        let Ok((sdf, maybe_mesh, maybe_skin)) = sdfs.get(*ent) else {
+        let Ok((sdf, maybe_mesh, maybe_skin, maybe_status, maybe_morph, maybe_deltas, maybe_auto_buffer)) = sdfs.get(*ent) else {
             warn!("can't get sdf");
             continue;
         };
 
-        let Some(mesh_handle) = (match sdf.mode {
-            crate::SdfGenMode::FromPrimaryMesh => maybe_mesh,
-            crate::SdfGenMode::Precomputed(_) => unimplemented!(),
-            crate::SdfGenMode::FromCustomMesh(ref h) => Some(h),
-        }) else {
-            warn!("failed to get mesh handle");
-            continue;
+        // entities restored from a snapshot copy their saved distances, whatever their mode
+        let restored = match sdf.mode {
+            crate::SdfGenMode::Precomputed(_) | crate::SdfGenMode::Composite(_) => None,
+            _ => atlas.restored.get(ent).cloned(),
         };
 
-        let Some(mesh) = meshes.get(mesh_handle) else {
-            warn!("failed to get mesh");
-            continue;
+        // release the slot so the entry is requeued next frame
+        let mut fail = |reason| {
+            atlas.page.purge(key);
+            let mut occluder = *aabb;
+            occluder.half_extents -= sdf_buffer_size(&sdf.options, maybe_auto_buffer, &settings);
+            apply_failure_policy(
+                &mut commands,
+                &mut atlas.fallbacks,
+                *ent,
+                sdf,
+                Some(occluder),
+                maybe_status,
+                reason,
+            );
         };
 
-        let Some(atlas_info) = atlas.page.get(key) else {
-            warn!("failed to get atlas info");
-            continue;
+        // precomputed images are copied straight into the atlas, composites apply their deltas
+        // during the copy
+        let image = match sdf.mode {
+            crate::SdfGenMode::Precomputed(ref h) | crate::SdfGenMode::Composite(ref h) => Some(h),
+            _ => restored.as_ref(),
         };
-        let dimensions = atlas_info.size - 1;
+        if let Some(h) = image {
+            if images.get(h).is_none() {
+                fail(SdfFailReason::ImageNotLoaded);
+                continue;
+            }
+
+            let Some(atlas_info) = atlas.page.get(key) else {
+                warn!("failed to get atlas info");
+                continue;
+            };
+
+            let dimensions = atlas_info.size - 1;
+            let deltas = match (&sdf.mode, maybe_deltas) {
+                (crate::SdfGenMode::Composite(_), Some(deltas)) => SdfDeltasData::new(deltas),
+                _ => SdfDeltasData::default(),
+            };
+            if atlas.mip_levels > 1 {
+                sdf_data.mip_regions.push((atlas_info.position, dimensions));
+            }
+            sdf_data.entities.push(*ent);
+            sdf_data.blits.push(SdfBlit {
+                entity: *ent,
+                image: h.clone_weak(),
+                write_position: atlas_info.position,
+                dimensions,
+                aabb_min: (aabb.center - aabb.half_extents).into(),
+                scale: (aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a()).into(),
+                deltas,
+            });
+            continue;
+        }
+
+        let geometry = if let crate::SdfGenMode::FromPreprocessed(ref h) = sdf.mode {
+            let Some(preprocessed) = preprocessed_meshes.get(h) else {
+                fail(SdfFailReason::MeshNotLoaded);
+                continue;
+            };
+
+            JobGeometry::Preprocessed(preprocessed)
+        } else if let crate::SdfGenMode::FromHierarchy = sdf.mode {
+            let mesh_transforms = hierarchy
+                .mesh_transforms(*ent)
+                .into_iter()
+                .filter_map(|(handle, transform)| Some((meshes.get(&handle)?, transform)))
+                .collect::<Vec<_>>();
+
+            if mesh_transforms.is_empty() {
+                fail(SdfFailReason::NoMesh);
+                continue;
+            }
+
+            JobGeometry::Hierarchy(mesh_transforms)
+        } else {
+            let Some(mesh_handle) = (match sdf.mode {
+                crate::SdfGenMode::FromPrimaryMesh => maybe_mesh,
+                crate::SdfGenMode::FromCustomMesh(ref h) => Some(h),
+                crate::SdfGenMode::Precomputed(_)
+                | crate::SdfGenMode::Composite(_)
+                | crate::SdfGenMode::FromHierarchy
+                | crate::SdfGenMode::FromPreprocessed(_) => unreachable!(),
+            }) else {
+                fail(SdfFailReason::NoMesh);
+                continue;
+            };
+
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                fail(SdfFailReason::MeshNotLoaded);
+                continue;
+            };
+
+            // a short target would index past its deltas, generate from the base pose instead
+            let maybe_morph = maybe_morph.filter(|morph| {
+                let fits = morph.fits(mesh.count_vertices());
+                if !fits {
+                    warn!("morph targets of {:?} don't cover every vertex of its mesh, ignoring them", ent);
+                }
+                fits
+            });
 
-        let preprocessed = match maybe_skin {
-            Some(skin) => {
+            let joints = maybe_skin.map(|skin| {
                 let Some(poses) = inverse_bindposes.get(&skin.inverse_bindposes) else {panic!("no bindposes")};
 
-                let joints = skin
-                    .joints
+                skin.joints
                     .iter()
                     .zip(poses.iter())
                     .map(|(joint_ent, pose)| {
                         joint_transforms.get(*joint_ent).unwrap().affine() * *pose
                     })
-                    .collect::<Vec<_>>();
-                preprocess_mesh_for_sdf(mesh, Some(&joints))
+                    .collect::<Vec<_>>()
+            });
+
+            match joints {
+                // decimation changes the topology, so can't use the cache
+                Some(joints) if sdf.options.decimation.is_none() && maybe_morph.is_none() => {
+                    JobGeometry::GpuSkinned(mesh_handle.clone_weak(), mesh, joints)
+                }
+                Some(joints) if sdf.options.decimation.is_none() => {
+                    JobGeometry::Skinned(mesh_handle.clone_weak(), mesh, joints, maybe_morph)
+                }
+                joints => JobGeometry::Mesh(mesh, joints, maybe_morph),
             }
-            _ => preprocess_mesh_for_sdf(mesh, None),
         };
 
-        let block_dimensions = dimensions / WORKGROUP_SIZE;
-        let block_count = block_dimensions.x * block_dimensions.y * block_dimensions.z;
-        sdf_data.block_count += block_count;
-        sdf_data.instances.data.push(SdfInstanceData {
-            block_count,
+        let Some(atlas_info) = atlas.page.get(key) else {
+            warn!("failed to get atlas info");
+            continue;
+        };
+
+        let dimensions = atlas_info.size - 1;
+        let voxel_size = (aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a()).min_element();
+        let options = sdf.options.for_voxel_size(voxel_size);
+
+        // bricks have no mip levels
+        let bricks = atlas.page.is_bricks(key);
+        if atlas.mip_levels > 1 && !bricks {
+            sdf_data.mip_regions.push((atlas_info.position, dimensions));
+        }
+        sdf_data.entities.push(*ent);
+        jobs.push(PreprocessJob {
+            entity: *ent,
+            key,
+            pipeline_key: SdfComputePipelineKey::new(&options, &settings, &atlas, bricks),
+            sparse: atlas.sparse.contains(ent),
+            bricks,
+            geometry,
+            options,
             write_position: atlas_info.position,
-            aabb_min: (aabb.center - aabb.half_extents).into(),
-            scale: (aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a()).into(),
-            block_dimensions,
-            counts: UVec3::new(
-                preprocessed.vertices.len() as u32,
-                preprocessed.edges.len() as u32,
-                preprocessed.triangles.len() as u32,
-            ),
+            dimensions,
+            aabb,
         });
-        sdf_data.vertices.data.extend(
-            preprocessed
-                .vertices
-                .into_iter()
-                .map(|(v, n)| [Vec3::from(v), Vec3::from(n)]),
-        );
-        sdf_data.edges.data.extend(
-            preprocessed
-                .edges
-                .into_iter()
-                .map(|((v0, v1), n)| [Vec3::from(v0), Vec3::from(v1), Vec3::from(n)]),
-        );
-        sdf_data
-            .tris
-            .data
-            .extend(preprocessed.triangles.into_iter().map(|tri| SdfTriData {
-                a: tri.a.into(),
-                b: tri.b.into(),
-                c: tri.c.into(),
-                plane: tri.plane.normal_d(),
-                inv_area: tri.inv_area,
-            }));
-
-        // println!("[{}] preprocess: {}", *frame, block_dimensions * 8);
     }
-}
-
-fn queue_bind_group(
-    atlas: Res<SdfAtlas>,
-    mut sdf_data: ResMut<SdfData>,
-    pipeline: Res<SdfComputePipeline>,
-    gpu_images: Res<RenderAssets<Image>>,
-    render_device: Res<RenderDevice>,
-) {
-    let Some(gpu_image) = gpu_images.get(&atlas.image) else {
-        warn!("can't find gpu sdf image");
-        sdf_data.bind_group = None;
-        return;
-    };
 
-    if sdf_data.block_count == 0 {
-        sdf_data.bind_group = None;
+    if jobs.is_empty() {
         return;
     }
 
-    fn storage_buffer<T: ShaderType + WriteInto>(
-        storage_data: &T,
-        label: &'static str,
-        render_device: &RenderDevice,
-    ) -> Buffer {
-        let byte_buffer = vec![0u8; T::min_size().get() as usize];
-        let mut buffer = encase::StorageBuffer::new(byte_buffer);
-        buffer.write(storage_data).unwrap();
+    // instances sharing a pipeline must be contiguous, otherwise keep the queue order
+    jobs.sort_by_key(|job| job.pipeline_key);
 
-        render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some(label),
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-            contents: buffer.as_ref(),
-        })
+    // build missing topologies in parallel first (e.g. many new skinned meshes on level load)
+    let mut missing = HashMap::default();
+    for job in jobs.iter() {
+        if let JobGeometry::Skinned(handle, mesh, ..) | JobGeometry::GpuSkinned(handle, mesh, _) =
+            &job.geometry
+        {
+            if !mesh_cache.topologies.contains_key(handle) {
+                missing.insert(handle.clone_weak(), *mesh);
+            }
+        }
     }
+    let built = ComputeTaskPool::get().scope(|s| {
+        for (handle, mesh) in missing.iter() {
+            s.spawn(async move { (handle.clone_weak(), MeshTopology::new(mesh)) });
+        }
+    });
+    mesh_cache.topologies.extend(built);
 
-    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    // then rest poses and bind pose vertices for meshes newly skinned on the gpu
+    let mut missing = HashMap::default();
+    for job in jobs.iter() {
+        if let JobGeometry::GpuSkinned(handle, mesh, _) = &job.geometry {
+            let key = (handle.clone_weak(), FloatOrd(job.options.min_triangle_area));
+            if !mesh_cache.rest_poses.contains_key(&key) {
+                missing.insert(key, (*mesh, &job.options));
+            }
+            if !skin_data.offsets.contains_key(handle) {
+                let offset = skin_data.vertices.data.len() as u32;
+                let Some(vertices) = skin_vertices(mesh) else {panic!("bad joint attributes!")};
+                skin_data.vertices.data.extend(vertices.into_iter().map(
+                    |(position, joints, weights)| SdfSkinVertex {
+                        position,
+                        joints: UVec4::from(joints),
+                        weights: Vec4::from(weights),
+                    },
+                ));
+                skin_data.offsets.insert(handle.clone_weak(), offset);
+                skin_data.generation += 1;
+            }
+        }
+    }
+    let topologies = &mesh_cache.topologies;
+    let built = ComputeTaskPool::get().scope(|s| {
+        for (key, (mesh, options)) in missing.iter() {
+            s.spawn(async move {
+                (
+                    key.clone(),
+                    preprocess_rest_pose_for_sdf(&topologies[&key.0], mesh, options),
+                )
+            });
+        }
+    });
+    mesh_cache.rest_poses.extend(built);
+
+    // the skin pass writes whole rest poses into one batch, so those too dense to bind are skinned
+    // on the cpu and split across dispatches like any other instance
+    for job in jobs.iter_mut() {
+        let JobGeometry::GpuSkinned(handle, mesh, joints) = &job.geometry else { continue };
+        let key = (handle.clone_weak(), FloatOrd(job.options.min_triangle_area));
+        let (rest_pose, _) = &mesh_cache.rest_poses[&key];
+        let counts = UVec3::new(
+            rest_pose.vertices.len() as u32,
+            rest_pose.edges.len() as u32,
+            rest_pose.triangles.len() as u32,
+        );
+        if !limits.fits(counts) {
+            job.geometry = JobGeometry::Skinned(handle.clone_weak(), *mesh, joints.clone(), None);
+        }
+    }
+    let mesh_cache = &mut *mesh_cache;
+    let topologies = &mesh_cache.topologies;
+    let rest_poses = &mesh_cache.rest_poses;
+
+    // and their sources and rest normals, which only the skin pass reads
+    for job in jobs.iter() {
+        let JobGeometry::GpuSkinned(handle, ..) = &job.geometry else { continue };
+        let key = (handle.clone_weak(), FloatOrd(job.options.min_triangle_area));
+        if skin_data.source_offsets.contains_key(&key) {
+            continue;
+        }
+        let (rest_pose, sources) = &rest_poses[&key];
+        let offset = skin_data.sources.data.len() as u32;
+        let normals = rest_pose
+            .vertices
+            .iter()
+            .map(|(_, n)| *n)
+            .chain(rest_pose.edges.iter().map(|(_, n)| *n));
+        skin_data.sources.data.extend(sources.iter());
+        skin_data
+            .sources
+            .data
+            .extend(normals.flat_map(|n| n.to_array().map(f32::to_bits)));
+        skin_data.source_offsets.insert(key, offset);
+        skin_data.generation += 1;
+    }
+
+    // entries are independent, so preprocess them in parallel
+    let preprocessed = ComputeTaskPool::get().scope(|s| {
+        for job in jobs.iter() {
+            s.spawn(async move {
+                match &job.geometry {
+                    JobGeometry::Mesh(mesh, joints, morph) => Some(preprocess_mesh_for_sdf(
+                        mesh,
+                        joints.as_deref(),
+                        *morph,
+                        &job.options,
+                    )),
+                    JobGeometry::Skinned(handle, mesh, joints, morph) => {
+                        Some(preprocess_topology_for_sdf(
+                            &topologies[handle],
+                            mesh,
+                            Some(joints),
+                            *morph,
+                            &job.options,
+                        ))
+                    }
+                    JobGeometry::Hierarchy(meshes) => {
+                        Some(preprocess_meshes_for_sdf(meshes, &job.options))
+                    }
+                    // already done
+                    JobGeometry::Preprocessed(_) | JobGeometry::GpuSkinned(..) => None,
+                }
+            });
+        }
+    });
+
+    let preprocessed = jobs
+        .iter()
+        .zip(preprocessed.iter())
+        .map(|(job, preprocessed)| match (&job.geometry, preprocessed) {
+            (JobGeometry::Preprocessed(asset), _) => &asset.data,
+            (JobGeometry::GpuSkinned(handle, ..), _) => {
+                &rest_poses[&(handle.clone_weak(), FloatOrd(job.options.min_triangle_area))].0
+            }
+            (_, Some(preprocessed)) => preprocessed,
+            (_, None) => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+
+    // instances too dense to bind however the frame is batched are split into chunks, each
+    // dispatched in turn and merged through the partials buffer
+    let chunks = preprocessed
+        .iter()
+        .map(|preprocessed| limits.split(preprocessed))
+        .collect::<Vec<_>>();
+    let parts = preprocessed
+        .iter()
+        .zip(chunks.iter())
+        .map(|(preprocessed, chunks)| match chunks {
+            Some(chunks) => chunks.iter().collect::<Vec<_>>(),
+            None => vec![*preprocessed],
+        })
+        .collect::<Vec<_>>();
+
+    // per block feature lists or a bvh for dense meshes, so the calc pass only tests features
+    // that can be nearest. gpu skinned features move after these are built so they always test
+    // everything. split instances get one per chunk, and are always computed by brute force
+    let feature_indices = ComputeTaskPool::get().scope(|s| {
+        for (job, parts) in jobs.iter().zip(parts.iter()) {
+            s.spawn(async move {
+                let method = match parts.len() {
+                    1 => job.options.method,
+                    _ => SdfMethod::BruteForce,
+                };
+                let index = |preprocessed: &PreprocessedMeshData| {
+                    let feature_count = preprocessed.vertices.len()
+                        + preprocessed.edges.len()
+                        + preprocessed.triangles.len();
+                    if method != SdfMethod::BruteForce
+                        || matches!(job.geometry, JobGeometry::GpuSkinned(..))
+                        || feature_count < MIN_BINNED_FEATURES
+                    {
+                        return FeatureIndex::All;
+                    }
+
+                    if feature_count >= BVH_MIN_FEATURES {
+                        return FeatureIndex::Bvh(build_feature_bvh(preprocessed));
+                    }
+
+                    let dimensions = job.dimensions;
+                    let aabb = job.aabb;
+                    let scale = aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a();
+                    // supersamples reach half a voxel past the block, and thin feature probes up
+                    // to three times the min thickness
+                    let margin = match job.options.samples() {
+                        1 => Vec3A::ZERO,
+                        _ => scale * 0.5,
+                    };
+                    let margin = margin.max(Vec3A::splat(job.options.min_thickness * scale.max_element() * 3.0));
+                    FeatureIndex::Bins(bin_features(
+                        preprocessed,
+                        aabb.center - aabb.half_extents,
+                        scale,
+                        dimensions / WORKGROUP_SIZE,
+                        margin,
+                    ))
+                };
+                parts.iter().map(|preprocessed| index(preprocessed)).collect::<Vec<_>>()
+            });
+        }
+    });
+
+    // the blocks of entries stored as bricks which need a brick
+    let brick_layouts = ComputeTaskPool::get().scope(|s| {
+        for (job, preprocessed) in jobs.iter().zip(preprocessed.iter()) {
+            s.spawn(async move {
+                if !job.bricks {
+                    return None;
+                }
+                let dimensions = job.dimensions;
+                let aabb = job.aabb;
+                Some(brick_layout(
+                    preprocessed,
+                    &job.options,
+                    aabb.center - aabb.half_extents,
+                    aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a(),
+                    dimensions / WORKGROUP_SIZE,
+                ))
+            });
+        }
+    });
+
+    // blocks near moved joints, for animated entries written over their previous pose. everything
+    // is recomputed when there's no previous pose to compare against
+    let skin_vertex_data = &skin_data.vertices.data;
+    let skin_offsets = &skin_data.offsets;
+    let previous_joints = &mesh_cache.previous_joints;
+    let block_lists = ComputeTaskPool::get().scope(|s| {
+        for job in jobs.iter() {
+            s.spawn(async move {
+                let JobGeometry::GpuSkinned(handle, mesh, joints) = &job.geometry else { return None };
+                // supersamples and thin feature probes can land on the far side of a block edge, so
+                // recompute everything
+                if !job.sparse
+                    || job.options.method != SdfMethod::BruteForce
+                    || job.options.samples() > 1
+                    || job.options.min_thickness > 0.0
+                {
+                    return None;
+                }
+                let previous = previous_joints
+                    .get(&job.entity)
+                    .filter(|previous| previous.len() == joints.len())?;
+
+                let moved = joints
+                    .iter()
+                    .zip(previous.iter())
+                    .map(|(joint, previous)| !joint.abs_diff_eq(*previous, 1e-5))
+                    .collect::<Vec<_>>();
+                // a still pose changes nothing
+                if !moved.contains(&true) {
+                    return Some(Vec::new());
+                }
+                let offset = skin_offsets[handle] as usize;
+                let motions = skin_vertex_data[offset..offset + mesh.count_vertices()]
+                    .iter()
+                    .map(|vertex| {
+                        let blend = |matrices: &[Mat4]| {
+                            (0..4).fold(Mat4::ZERO, |m, i| {
+                                m + matrices[vertex.joints[i] as usize] * vertex.weights[i]
+                            })
+                        };
+                        VertexMotion {
+                            previous: blend(previous).project_point3(vertex.position).into(),
+                            current: blend(joints).project_point3(vertex.position).into(),
+                            moved_joint: (0..4)
+                                .filter(|&i| {
+                                    vertex.weights[i] > 0.0 && moved[vertex.joints[i] as usize]
+                                })
+                                .max_by(|&a, &b| vertex.weights[a].total_cmp(&vertex.weights[b]))
+                                .map(|i| vertex.joints[i]),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                let (rest_pose, sources) =
+                    &rest_poses[&(handle.clone_weak(), FloatOrd(job.options.min_triangle_area))];
+                let dimensions = job.dimensions;
+                let aabb = job.aabb;
+                Some(dirty_blocks(
+                    sources,
+                    [
+                        rest_pose.vertices.len(),
+                        rest_pose.edges.len(),
+                        rest_pose.triangles.len(),
+                    ],
+                    &motions,
+                    aabb.center - aabb.half_extents,
+                    aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a(),
+                    dimensions / WORKGROUP_SIZE,
+                ))
+            });
+        }
+    });
+
+    // size the flat buffers up front
+    sdf_data.instances.data.reserve(jobs.len());
+    sdf_data.vertices.data.reserve(preprocessed.iter().map(|p| p.vertices.len()).sum());
+    sdf_data.edges.data.reserve(preprocessed.iter().map(|p| p.edges.len()).sum());
+    sdf_data.tris.data.reserve(preprocessed.iter().map(|p| p.triangles.len()).sum());
+
+    // and assemble them in order
+    for (((((job, parts), feature_indices), mut block_list), layout), chunks) in jobs
+        .iter()
+        .zip(parts.iter())
+        .zip(feature_indices.into_iter())
+        .zip(block_lists.into_iter())
+        .zip(brick_layouts.into_iter())
+        .zip(chunks.iter())
+    {
+        let mut flags = 0;
+        if job.options.back_faces == SdfBackFaces::Ignore {
+            flags |= INSTANCE_FLAG_IGNORE_BACK_FACES;
+        }
+        if job.options.invert {
+            flags |= INSTANCE_FLAG_INVERT;
+        }
+
+        if let Some(axis) = job.options.mirror_axis(job.geometry.is_animated()) {
+            flags |= INSTANCE_FLAG_MIRROR_X << axis;
+        }
+        if job.options.surface_mask {
+            flags |= INSTANCE_FLAG_SURFACE_MASK;
+        }
+        match job.options.metric {
+            SdfMetric::Euclidean => (),
+            SdfMetric::Chebyshev => flags |= INSTANCE_FLAG_CHEBYSHEV,
+            SdfMetric::Manhattan => flags |= INSTANCE_FLAG_MANHATTAN,
+        }
+
+        // split instances are computed by brute force, see `SdfBufferLimits::split`
+        let chunked = chunks.is_some();
+        let method = match chunked {
+            true => SdfMethod::BruteForce,
+            false => job.options.method,
+        };
+
+        let dimensions = job.dimensions;
+        let aabb = job.aabb;
+        let block_dimensions = dimensions / WORKGROUP_SIZE;
+        let aabb_min: Vec3 = (aabb.center - aabb.half_extents).into();
+        let scale: Vec3 = (aabb.half_extents * 2.0 / (dimensions - 1).as_vec3a()).into();
+
+        // jump flood instances are skipped by the calc pass but keep their features in the
+        // buffers, so skinning and the feature offsets work the same for both methods
+        let block_count = match (method, &layout, &block_list) {
+            (SdfMethod::JumpFlood, ..) => 0,
+            (SdfMethod::BruteForce, Some(layout), _) => layout.occupied.len() as u32,
+            (SdfMethod::BruteForce, None, Some(list)) => list.len() as u32,
+            (SdfMethod::BruteForce, None, None) => {
+                block_count(dispatch_size(dimensions + 1, &job.options, job.geometry.is_animated()))
+            }
+        };
+
+        // split instances keep the nearest distance so far of each voxel between their chunks
+        let partial_voxels = match chunked {
+            true => block_count * WORKGROUP_SIZE.pow(3),
+            false => 0,
+        };
+        let partials_fit = |voxels: u32| voxels as u64 * PARTIAL_SIZE <= limits.max_binding_size;
+        if chunked
+            && partials_fit(partial_voxels)
+            && !partials_fit(sdf_data.partial_count + partial_voxels)
+        {
+            // the split instances before it fill the binding, so leave it for a later frame
+            atlas.page.purge(job.key);
+            sdf_data.entities.retain(|ent| *ent != job.entity);
+            continue;
+        }
+
+        // too many voxels to split, or no room for the bricks. stale brick entries are evicted
+        // for them when the entry is next queued
+        let bricks = match (&layout, partials_fit(partial_voxels)) {
+            (_, false) => Err(SdfFailReason::TooManyFeatures),
+            (Some(layout), true) => {
+                let bricks = atlas.page.allocate_bricks(job.key, layout.occupied.len());
+                if bricks.is_none() {
+                    atlas.bricks_needed = atlas.bricks_needed.max(layout.occupied.len());
+                }
+                bricks.map(Some).ok_or(SdfFailReason::NoFit)
+            }
+            (None, true) => Ok(None),
+        };
+        let bricks = match bricks {
+            Ok(bricks) => bricks,
+            Err(reason) => {
+                if let Ok((sdf, _, _, maybe_status, _, _, maybe_auto_buffer)) = sdfs.get(job.entity) {
+                    atlas.page.purge(job.key);
+                    let mut occluder = *job.aabb;
+                    occluder.half_extents -= sdf_buffer_size(&sdf.options, maybe_auto_buffer, &settings);
+                    apply_failure_policy(
+                        &mut commands,
+                        &mut atlas.fallbacks,
+                        job.entity,
+                        sdf,
+                        Some(occluder),
+                        maybe_status,
+                        reason,
+                    );
+                }
+                sdf_data.entities.retain(|ent| *ent != job.entity);
+                continue;
+            }
+        };
+
+        // only the occupied blocks of entries stored as bricks are computed, each into its brick
+        let mut brick_list = None;
+        if let (Some(layout), Some(bricks)) = (layout, bricks) {
+            let mut texels = layout
+                .distances
+                .iter()
+                .map(|distance| [-1.0, 0.0, 0.0, *distance])
+                .collect::<Vec<_>>();
+            for (block, brick) in layout.occupied.iter().zip(bricks.iter()) {
+                let texel = &mut texels[*block as usize];
+                texel[..3].copy_from_slice(&brick.as_vec3().to_array());
+            }
+            sdf_data.indirections.push((job.write_position, job.dimensions / WORKGROUP_SIZE, texels));
+            brick_list = Some(bricks.iter().flat_map(|brick| brick.to_array()).collect::<Vec<_>>());
+            block_list = Some(layout.occupied);
+        }
+
+        // block lists and brick positions are shared by the chunks of split instances
+        let block_list_offset = append_bins(&mut sdf_data.bins, block_list, NO_BLOCK_LIST);
+        let brick_offset = append_bins(&mut sdf_data.bins, brick_list, NO_BRICKS);
+        let partial_offset = match chunked {
+            true => sdf_data.partial_count,
+            false => NO_PARTIALS,
+        };
+        sdf_data.partial_count += partial_voxels;
+
+        let last_chunk = parts.len() - 1;
+        let chunk_parts = parts.iter().zip(feature_indices);
+        for (chunk, (preprocessed, feature_index)) in chunk_parts.enumerate() {
+            let mut flags = flags;
+            if chunk > 0 {
+                flags |= INSTANCE_FLAG_CHUNK_READ;
+            }
+            if chunk < last_chunk {
+                flags |= INSTANCE_FLAG_CHUNK_WRITE;
+            }
+
+            let counts = UVec3::new(
+                preprocessed.vertices.len() as u32,
+                preprocessed.edges.len() as u32,
+                preprocessed.triangles.len() as u32,
+            );
+
+            let instance_index = sdf_data.instances.data.len() as u32;
+            let feature_end = UVec3::new(
+                sdf_data.vertices.data.len() as u32,
+                sdf_data.edges.data.len() as u32,
+                sdf_data.tris.data.len() as u32,
+            );
+            // start a new batch when the instance would overflow the current one, padding the
+            // buffers so the batch can be bound from its first features. the chunks of a split
+            // instance each fill most of a batch, so never share one
+            let fits = sdf_data
+                .batches
+                .last()
+                .map_or(false, |batch| limits.fits(feature_end - batch.feature_start + counts));
+            if !fits {
+                let strides = feature_strides();
+                let feature_start = UVec3::new(
+                    limits.align(feature_end.x, strides.x),
+                    limits.align(feature_end.y, strides.y),
+                    limits.align(feature_end.z, strides.z),
+                );
+                sdf_data.vertices.data.resize(feature_start.x as usize, Default::default());
+                sdf_data.edges.data.resize(feature_start.y as usize, Default::default());
+                sdf_data.tris.data.resize(feature_start.z as usize, Default::default());
+                sdf_data.batches.push(SdfFeatureBatch {
+                    first_instance: instance_index,
+                    end_instance: instance_index,
+                    feature_start,
+                    feature_end: feature_start,
+                    skin_feature_count: 0,
+                });
+            }
+            let batch_index = sdf_data.batches.len() as u32 - 1;
+            let batch_start = sdf_data.batches.last().unwrap().feature_start;
+            let feature_start = UVec3::new(
+                sdf_data.vertices.data.len() as u32,
+                sdf_data.edges.data.len() as u32,
+                sdf_data.tris.data.len() as u32,
+            );
+
+            let (mut skin_sources_offset, mut skin_vertex_offset, mut joint_offset) = (0, 0, 0);
+            let gpu_skinned = matches!(job.geometry, JobGeometry::GpuSkinned(..));
+            if let JobGeometry::GpuSkinned(handle, _, joints) = &job.geometry {
+                flags |= INSTANCE_FLAG_SKINNED;
+                let key = (handle.clone_weak(), FloatOrd(job.options.min_triangle_area));
+                skin_sources_offset = skin_data.source_offsets[&key];
+                skin_vertex_offset = skin_data.offsets[handle];
+                joint_offset = sdf_data.joints.data.len() as u32;
+                sdf_data.joints.data.extend(joints.iter());
+                sdf_data.skin_features.push((feature_start, counts));
+            }
+
+            if method == SdfMethod::JumpFlood {
+                sdf_data.jfa.push(SdfJfaInstance {
+                    write_position: job.write_position,
+                    aabb_min,
+                    scale,
+                    dimensions,
+                    batch: batch_index,
+                    tri_start: feature_start.z - batch_start.z,
+                    tri_count: preprocessed.triangles.len() as u32,
+                    voxel_offset: sdf_data.jfa_voxel_count,
+                    flags,
+                    weld_margin: job.options.weld_margin,
+                });
+                sdf_data.jfa_voxel_count += dimensions.x * dimensions.y * dimensions.z;
+            }
+            sdf_data.block_count += block_count;
+
+            match sdf_data.groups.last_mut() {
+                Some(group) if group.key == job.pipeline_key && group.batch == batch_index => {
+                    group.end_instance = instance_index + 1;
+                    group.block_count += block_count;
+                }
+                _ => sdf_data.groups.push(SdfCalcGroup {
+                    key: job.pipeline_key,
+                    batch: batch_index,
+                    first_instance: instance_index,
+                    end_instance: instance_index + 1,
+                    block_count,
+                }),
+            }
+            let group = sdf_data.groups.len() as u32 - 1;
+
+            // feature bins and bvhs share a buffer with the block lists and brick positions. the
+            // bvhs of split instances are large, so a chunk whose bvh would push the buffer past
+            // what can be bound tests every feature instead
+            let (bins, bvh) = match feature_index {
+                FeatureIndex::All => (None, None),
+                FeatureIndex::Bins(bins) => (Some(bins), None),
+                FeatureIndex::Bvh(bvh)
+                    if chunked
+                        && (sdf_data.bins.data.len() + bvh.len()) as u64 * 4
+                            > limits.max_binding_size =>
+                {
+                    (None, None)
+                }
+                FeatureIndex::Bvh(bvh) => (None, Some(bvh)),
+            };
+            let bin_offset = append_bins(&mut sdf_data.bins, bins, NO_BINS);
+            let bvh_offset = append_bins(&mut sdf_data.bins, bvh, NO_BVH);
+
+            sdf_data.instances.data.push(SdfInstanceData {
+                block_count,
+                write_position: job.write_position,
+                aabb_min,
+                scale,
+                block_dimensions,
+                counts,
+                flags,
+                weld_margin: job.options.weld_margin,
+                samples: job.options.samples(),
+                min_thickness: job.options.min_thickness * scale.max_element(),
+                skin_sources_offset,
+                skin_vertex_offset,
+                joint_offset,
+                bin_offset,
+                block_list_offset,
+                bvh_offset,
+                brick_offset,
+                partial_offset,
+                group,
+                block_start: 0,
+                feature_start,
+            });
+
+            // space for the skin pass to write into
+            if gpu_skinned {
+                sdf_data.vertices.data.resize((feature_start.x + counts.x) as usize, Default::default());
+                sdf_data.edges.data.resize((feature_start.y + counts.y) as usize, Default::default());
+                sdf_data.tris.data.resize((feature_start.z + counts.z) as usize, Default::default());
+            } else {
+                sdf_data.vertices.data.extend(
+                    preprocessed
+                        .vertices
+                        .iter()
+                        .map(|(v, n)| [Vec3::from(*v), Vec3::from(*n)]),
+                );
+                sdf_data.edges.data.extend(
+                    preprocessed
+                        .edges
+                        .iter()
+                        .map(|((v0, v1), n)| [Vec3::from(*v0), Vec3::from(*v1), Vec3::from(*n)]),
+                );
+                sdf_data
+                    .tris
+                    .data
+                    .extend(preprocessed.triangles.iter().map(|tri| SdfTriData {
+                        a: tri.a.into(),
+                        b: tri.b.into(),
+                        c: tri.c.into(),
+                        plane: tri.plane.normal_d(),
+                        inv_area: tri.inv_area,
+                    }));
+            }
+
+            let batch = sdf_data.batches.last_mut().unwrap();
+            batch.end_instance = instance_index + 1;
+            batch.feature_end = feature_start + counts;
+            if gpu_skinned {
+                batch.skin_feature_count += counts.x + counts.y + counts.z;
+            }
+        }
+
+        // println!("[{}] preprocess: {}", *frame, block_dimensions * 8);
+    }
+
+    sdf_data.instances.data.push(SdfInstanceData {
+        write_position: UVec3::ZERO,
+        aabb_min: Vec3::ZERO,
+        scale: Vec3::ZERO,
+        block_dimensions: UVec3::ZERO,
+        counts: UVec3::ZERO,
+        block_count: 0,
+        flags: INSTANCE_FLAG_END,
+        weld_margin: 0.0,
+        samples: 1,
+        min_thickness: 0.0,
+        skin_sources_offset: 0,
+        skin_vertex_offset: 0,
+        joint_offset: 0,
+        bin_offset: NO_BINS,
+        block_list_offset: NO_BLOCK_LIST,
+        bvh_offset: NO_BVH,
+        brick_offset: NO_BRICKS,
+        partial_offset: NO_PARTIALS,
+        group: NO_GROUP,
+        block_start: 0,
+        feature_start: UVec3::new(
+            sdf_data.vertices.data.len() as u32,
+            sdf_data.edges.data.len() as u32,
+            sdf_data.tris.data.len() as u32,
+        ),
+    });
+
+    // remember the poses written this frame
+    for job in jobs.iter() {
+        if let JobGeometry::GpuSkinned(_, _, joints) = &job.geometry {
+            mesh_cache.previous_joints.insert(job.entity, joints.clone());
+        }
+    }
+    mesh_cache.previous_joints.retain(|ent, _| sdfs.contains(*ent));
+}
+
+// append to the bins buffer, returning where the data starts or `none` without any
+fn append_bins(bins: &mut SdfBinsData, data: Option<Vec<u32>>, none: u32) -> u32 {
+    match data {
+        Some(data) => {
+            let offset = bins.data.len() as u32;
+            bins.data.extend(data);
+            offset
+        }
+        None => none,
+    }
+}
+
+// a persistent storage buffer, reallocated only when the data outgrows it
+#[derive(Default)]
+struct GpuStorageBuffer {
+    buffer: Option<Buffer>,
+    capacity: u64,
+    scratch: Vec<u8>,
+}
+
+impl GpuStorageBuffer {
+    // upload the data, returns true if the buffer had to be reallocated
+    fn write<T: ShaderType + WriteInto>(
+        &mut self,
+        data: &T,
+        label: &'static str,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> bool {
+        self.write_skipping(data, &[], label, render_device, render_queue)
+    }
+
+    // upload the data except for the given sorted byte ranges, which are written on the gpu
+    fn write_skipping<T: ShaderType + WriteInto>(
+        &mut self,
+        data: &T,
+        skip: &[Range<u64>],
+        label: &'static str,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+    ) -> bool {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        let mut buffer = encase::StorageBuffer::new(scratch);
+        buffer.write(data).unwrap();
+        self.scratch = buffer.into_inner();
+
+        let size = (self.scratch.len() as u64).max(T::min_size().get());
+        let reallocate = self.buffer.is_none() || size > self.capacity;
+        if reallocate {
+            self.capacity = size.next_power_of_two();
+            self.buffer = Some(render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: self.capacity,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+
+        let buffer = self.buffer.as_ref().unwrap();
+        let end = self.scratch.len() as u64;
+        let mut start = 0;
+        for range in skip.iter() {
+            if range.start > start {
+                render_queue.write_buffer(buffer, start, &self.scratch[start as usize..range.start as usize]);
+            }
+            start = start.max(range.end);
+        }
+        if end > start {
+            render_queue.write_buffer(buffer, start, &self.scratch[start as usize..]);
+        }
+        reallocate
+    }
+
+    fn binding(&self) -> BindingResource {
+        self.buffer.as_ref().unwrap().as_entire_binding()
+    }
+
+    // the elements from `start` up to `end`, of `stride` bytes each. an empty range binds a single
+    // element, bindings can't be empty
+    fn slice_binding(&self, start: u32, end: u32, stride: u32) -> BindingResource {
+        let (offset, size) = match end > start {
+            true => (start as u64 * stride as u64, (end - start) as u64 * stride as u64),
+            false => (0, stride as u64),
+        };
+        BindingResource::Buffer(BufferBinding {
+            buffer: self.buffer.as_ref().unwrap(),
+            offset,
+            size: NonZeroU64::new(size),
+        })
+    }
+}
+
+// render world buffers and bind group for the compute pass, reused across frames
+#[derive(Default)]
+struct SdfGpuBuffers {
+    instances: GpuStorageBuffer,
+    vertices: GpuStorageBuffer,
+    edges: GpuStorageBuffer,
+    tris: GpuStorageBuffer,
+    bins: GpuStorageBuffer,
+    skin_sources: GpuStorageBuffer,
+    skin_vertices: GpuStorageBuffer,
+    joints: GpuStorageBuffer,
+    // the `SdfSkinData::generation` the skin buffers hold
+    skin_generation: Option<u32>,
+    // one per feature batch, binding the batch's slice of the feature buffers
+    bind_groups: Vec<BindGroup>,
+    // the (start, end) features of the batches the bind groups were created for
+    bound_batches: Vec<(UVec3, UVec3)>,
+    // calc workgroup counts per group, written on the gpu from the instance data
+    dispatch_args: Option<Buffer>,
+    dispatch_args_capacity: u64,
+    dispatch_bind_group: Option<BindGroup>,
+    // partial distances of split instances, carried between the dispatches of their chunks
+    partials: Option<Buffer>,
+    partials_capacity: u64,
+    calc_params: DynamicUniformBuffer<SdfCalcGroupParams>,
+    // this frame's calc dispatches, one per group with blocks
+    calc_dispatches: Vec<CalcDispatch>,
+    // this frame's skin dispatches, one per batch with gpu skinned features
+    skin_dispatches: Vec<SkinDispatch>,
+    // the atlas view the bind group was created with
+    texture_view: Option<TextureViewId>,
+    // this frame's precomputed copies, with their dimensions
+    blits: Vec<(BindGroup, UVec3)>,
+    // ping-pong nearest triangle buffers for jump flooding
+    jfa_seeds: Option<[Buffer; 2]>,
+    jfa_seeds_capacity: u64,
+    jfa_params: DynamicUniformBuffer<SdfJfaParams>,
+    // one per feature batch, as `bind_groups`
+    jfa_bind_groups: Vec<BindGroup>,
+    // this frame's jump flood dispatches, in order
+    jfa_dispatches: Vec<JfaDispatch>,
+    // single level views of the atlas, storage bindings can't span levels
+    atlas_views: Vec<TextureView>,
+    // the atlas view the level views were created for
+    atlas_views_source: Option<TextureViewId>,
+    mip_params: DynamicUniformBuffer<SdfMipParams>,
+    // reading the level above each level from 1 up
+    mip_bind_groups: Vec<BindGroup>,
+    // this frame's downsampling dispatches, in level order
+    mip_dispatches: Vec<MipDispatch>,
+    // staging for entries relocated by compaction, as a texture level can't be copied within
+    // itself. grown to fit the largest move
+    move_scratch: Option<(Texture, UVec3)>,
+    // the same for their gradients, with the gradient atlas
+    gradient_move_scratch: Option<Texture>,
+}
+
+struct CalcDispatch {
+    pipeline: CachedComputePipelineId,
+    batch: usize,
+    params_offset: u32,
+    args_offset: u64,
+}
+
+struct SkinDispatch {
+    batch: usize,
+    params_offset: u32,
+    workgroups: UVec3,
+}
+
+// write the group params and specialize the calc pipeline for each group with blocks, and the
+// params of each batch with features to skin. returns true if the params buffer was reallocated
+fn queue_calc_dispatches(
+    sdf_data: &SdfData,
+    gpu_buffers: &mut SdfGpuBuffers,
+    pipeline: &SdfComputePipeline,
+    pipelines: &mut SpecializedComputePipelines<SdfComputePipeline>,
+    pipeline_cache: &mut PipelineCache,
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+) -> bool {
+    let previous_buffer = gpu_buffers.calc_params.buffer().map(|buffer| buffer.id());
+    gpu_buffers.calc_params.clear();
+    gpu_buffers.calc_dispatches.clear();
+    gpu_buffers.skin_dispatches.clear();
+
+    for (index, group) in sdf_data.groups.iter().enumerate() {
+        let params_offset = gpu_buffers.calc_params.push(SdfCalcGroupParams {
+            first_instance: group.first_instance,
+            end_instance: group.end_instance,
+            feature_base: sdf_data.batches[group.batch as usize].feature_start,
+        });
+        if group.block_count == 0 {
+            continue;
+        }
+        gpu_buffers.calc_dispatches.push(CalcDispatch {
+            pipeline: pipelines.specialize(pipeline_cache, pipeline, group.key),
+            batch: group.batch as usize,
+            params_offset,
+            args_offset: index as u64 * DISPATCH_ARGS_SIZE,
+        });
+    }
+
+    for (index, batch) in sdf_data.batches.iter().enumerate() {
+        if batch.skin_feature_count == 0 {
+            continue;
+        }
+        let params_offset = gpu_buffers.calc_params.push(SdfCalcGroupParams {
+            first_instance: batch.first_instance,
+            end_instance: batch.end_instance,
+            feature_base: batch.feature_start,
+        });
+        gpu_buffers.skin_dispatches.push(SkinDispatch {
+            batch: index,
+            params_offset,
+            workgroups: linear_workgroups(batch.skin_feature_count, SKIN_WORKGROUP_SIZE),
+        });
+    }
+
+    gpu_buffers.calc_params.write_buffer(render_device, render_queue);
+    gpu_buffers.calc_params.buffer().map(|buffer| buffer.id()) != previous_buffer
+}
+
+#[derive(Clone, Copy)]
+enum JfaPass {
+    Seed,
+    Flood,
+    Resolve,
+}
+
+struct JfaDispatch {
+    pass: JfaPass,
+    batch: usize,
+    params_offset: u32,
+    workgroups: UVec3,
+}
+
+struct MipDispatch {
+    level: u32,
+    params_offset: u32,
+    workgroups: UVec3,
+}
+
+// queue the seed, flood and resolve dispatches for each jump flood instance
+fn queue_jfa_dispatches(sdf_data: &SdfData, gpu_buffers: &mut SdfGpuBuffers) {
+    gpu_buffers.jfa_params.clear();
+    gpu_buffers.jfa_dispatches.clear();
+
+    for instance in sdf_data.jfa.iter() {
+        let params = |step: u32, flip: u32| SdfJfaParams {
+            write_position: instance.write_position,
+            tri_start: instance.tri_start,
+            aabb_min: instance.aabb_min,
+            tri_count: instance.tri_count,
+            scale: instance.scale,
+            voxel_offset: instance.voxel_offset,
+            dimensions: instance.dimensions,
+            flags: instance.flags,
+            weld_margin: instance.weld_margin,
+            step,
+            flip,
+        };
+        let voxel_workgroups = instance.dimensions / WORKGROUP_SIZE;
+
+        // seeds are written to the first buffer
+        gpu_buffers.jfa_dispatches.push(JfaDispatch {
+            pass: JfaPass::Seed,
+            batch: instance.batch as usize,
+            params_offset: gpu_buffers.jfa_params.push(params(0, 0)),
+            workgroups: linear_workgroups(instance.tri_count, JFA_SEED_WORKGROUP_SIZE),
+        });
+
+        // halving steps from half the largest dimension, with an extra final step of 1 to fix up
+        // most of the errors left by the larger steps
+        let mut steps = Vec::new();
+        let mut step = instance.dimensions.max_element().next_power_of_two() / 2;
+        while step > 0 {
+            steps.push(step);
+            step /= 2;
+        }
+        steps.push(1);
+
+        let mut flip = 0;
+        for step in steps {
+            gpu_buffers.jfa_dispatches.push(JfaDispatch {
+                pass: JfaPass::Flood,
+                batch: instance.batch as usize,
+                params_offset: gpu_buffers.jfa_params.push(params(step, flip)),
+                workgroups: voxel_workgroups,
+            });
+            flip = 1 - flip;
+        }
+
+        gpu_buffers.jfa_dispatches.push(JfaDispatch {
+            pass: JfaPass::Resolve,
+            batch: instance.batch as usize,
+            params_offset: gpu_buffers.jfa_params.push(params(0, flip)),
+            workgroups: voxel_workgroups,
+        });
+    }
+}
+
+// create single level views of the atlas when it is (re)created, and queue the downsampling of
+// this frame's written regions into each mip level. regions are rounded out to whole texels of
+// each level, so texels at the edges of an entry also take the minimum over their neighbours
+fn queue_atlas_mips(
+    atlas: Res<SdfAtlas>,
+    sdf_data: Res<SdfData>,
+    mut gpu_buffers: ResMut<SdfGpuBuffers>,
+    pipeline: Res<SdfComputePipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let gpu_buffers = &mut *gpu_buffers;
+    gpu_buffers.mip_dispatches.clear();
+
+    let Some(gpu_image) = gpu_images.get(&atlas.image) else {
+        gpu_buffers.atlas_views.clear();
+        gpu_buffers.atlas_views_source = None;
+        return;
+    };
+
+    let source = gpu_image.texture_view.id();
+    if gpu_buffers.atlas_views_source != Some(source) {
+        gpu_buffers.atlas_views = (0..atlas.mip_levels)
+            .map(|level| {
+                gpu_image.texture.create_view(&TextureViewDescriptor {
+                    label: Some("sdf atlas level"),
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        gpu_buffers.atlas_views_source = Some(source);
+    }
+
+    if sdf_data.mip_regions.is_empty() {
+        return;
+    }
+
+    gpu_buffers.mip_params.clear();
+    for level in 1..atlas.mip_levels {
+        for &(position, size) in sdf_data.mip_regions.iter() {
+            let min = position >> level;
+            let max = (position + size - 1) >> level;
+            let size = max - min + 1;
+            let params_offset = gpu_buffers.mip_params.push(SdfMipParams {
+                position: min,
+                size,
+            });
+            gpu_buffers.mip_dispatches.push(MipDispatch {
+                level,
+                params_offset,
+                workgroups: (size + MIP_WORKGROUP_SIZE - 1) / MIP_WORKGROUP_SIZE,
+            });
+        }
+    }
+    gpu_buffers.mip_params.write_buffer(&render_device, &render_queue);
+
+    let views = &gpu_buffers.atlas_views;
+    let mip_bind_groups = views
+        .iter()
+        .zip(views.iter().skip(1))
+        .map(|(source, target)| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.mip_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(target),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: gpu_buffers.mip_params.binding().unwrap(),
+                    },
+                ],
+            })
+        })
+        .collect();
+    gpu_buffers.mip_bind_groups = mip_bind_groups;
+}
+
+fn queue_atlas_moves(
+    atlas: Res<SdfAtlas>,
+    mut gpu_buffers: ResMut<SdfGpuBuffers>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(required) = atlas.moves.iter().map(|atlas_move| atlas_move.size).reduce(UVec3::max) else {
+        return;
+    };
+    let size = match gpu_buffers.move_scratch {
+        Some((_, size)) if size.cmpge(required).all() => return,
+        Some((_, size)) => size.max(required),
+        None => required,
+    };
+
+    let scratch = |label, format| {
+        render_device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format,
+            usage: TextureUsages::COPY_SRC | TextureUsages::COPY_DST,
+        })
+    };
+    gpu_buffers.move_scratch = Some((scratch("sdf atlas move scratch", atlas.format), size));
+    gpu_buffers.gradient_move_scratch = atlas
+        .gradients
+        .then(|| scratch("sdf gradient move scratch", TextureFormat::Rgba8Snorm));
+}
+
+// write the indirection texels of entries stored as bricks generated this frame. the views only
+// sample them once their bricks are written, see `SdfWrittenEntries`
+fn queue_brick_indirections(
+    atlas: Res<SdfAtlas>,
+    sdf_data: Res<SdfData>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_queue: Res<RenderQueue>,
+) {
+    if sdf_data.indirections.is_empty() {
+        return;
+    }
+    // uploaded along with the atlas image, without which nothing is generated either
+    let Some(gpu_image) = gpu_images.get(&atlas.indirection_image) else { return };
+
+    for (position, size, texels) in sdf_data.indirections.iter() {
+        let data = texels
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            &data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(size.x * 16),
+                rows_per_image: NonZeroU32::new(size.y),
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+    }
+}
+
+fn queue_bind_group(
+    atlas: Res<SdfAtlas>,
+    sdf_data: Res<SdfData>,
+    skin_data: Res<SdfSkinData>,
+    mut gpu_buffers: ResMut<SdfGpuBuffers>,
+    pipeline: Res<SdfComputePipeline>,
+    mut pipelines: ResMut<SpecializedComputePipelines<SdfComputePipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    status: Res<SdfPipelineStatus>,
+) {
+    let gpu_buffers = &mut *gpu_buffers;
+    let mut reallocated = false;
+
+    // bind pose vertices only change when a new mesh is skinned
+    if gpu_buffers.skin_generation != Some(skin_data.generation) {
+        reallocated |= gpu_buffers.skin_vertices.write(&skin_data.vertices, "sdf skin vertices", &render_device, &render_queue);
+        reallocated |= gpu_buffers.skin_sources.write(&skin_data.sources, "sdf skin sources", &render_device, &render_queue);
+        gpu_buffers.skin_generation = Some(skin_data.generation);
+    }
+
+    gpu_buffers.blits.clear();
+    if !sdf_data.blits.is_empty() {
+        if let Some(atlas_view) = gpu_buffers.atlas_views.first() {
+            for blit in sdf_data.blits.iter() {
+                // its slot is already allocated, so have the main world free it and requeue the
+                // entry rather than leave the slot unwritten
+                let Some(source) = gpu_images.get(&blit.image) else {
+                    status.skipped.lock().unwrap().insert(blit.entity);
+                    continue;
+                };
+
+                let mut params = encase::UniformBuffer::new(Vec::new());
+                params
+                    .write(&SdfBlitParams {
+                        write_position: blit.write_position,
+                        dimensions: blit.dimensions,
+                        aabb_min: blit.aabb_min,
+                        scale: blit.scale,
+                        delta_count: blit.deltas.data.len() as u32,
+                    })
+                    .unwrap();
+                let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("sdf blit params"),
+                    usage: BufferUsages::UNIFORM,
+                    contents: params.as_ref(),
+                });
+
+                // bindings can't be empty
+                let mut deltas = encase::StorageBuffer::new(Vec::new());
+                deltas.write(&blit.deltas).unwrap();
+                let mut deltas = deltas.into_inner();
+                deltas.resize(deltas.len().max(SdfDeltasData::min_size().get() as usize), 0);
+                let deltas = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("sdf blit deltas"),
+                    usage: BufferUsages::STORAGE,
+                    contents: &deltas,
+                });
+
+                let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+                    label: None,
+                    layout: &pipeline.blit_bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&source.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(atlas_view),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: params.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: deltas.as_entire_binding(),
+                        },
+                    ],
+                });
+                gpu_buffers.blits.push((bind_group, blit.dimensions));
+            }
+        } else {
+            let mut skipped = status.skipped.lock().unwrap();
+            skipped.extend(sdf_data.blits.iter().map(|blit| blit.entity));
+        }
+    }
+
+    // nothing to upload, keep what we have for later frames
+    if sdf_data.block_count == 0 && sdf_data.jfa.is_empty() {
+        if reallocated {
+            gpu_buffers.bind_groups.clear();
+        }
+        return;
+    }
+
+    let (Some(gpu_image), Some(gradient_image)) = (
+        gpu_images.get(&atlas.image),
+        gpu_images.get(&atlas.gradient_image),
+    ) else {
+        warn!("can't find gpu sdf image");
+        gpu_buffers.bind_groups.clear();
+        return;
+    };
+
+    reallocated |= gpu_buffers.instances.write(&sdf_data.instances, "sdf instances", &render_device, &render_queue);
+    // gpu skinned features are written by the skin pass, so leave their runs out of the upload
+    let strides = feature_strides();
+    let skinned = |axis: usize| {
+        let stride = strides[axis] as u64;
+        sdf_data
+            .skin_features
+            .iter()
+            .map(|(start, count)| start[axis] as u64 * stride..(start[axis] + count[axis]) as u64 * stride)
+            .collect::<Vec<_>>()
+    };
+    let vertices_skip = skinned(0);
+    let edges_skip = skinned(1);
+    let tris_skip = skinned(2);
+    reallocated |= gpu_buffers.vertices.write_skipping(&sdf_data.vertices, &vertices_skip, "sdf vertices", &render_device, &render_queue);
+    reallocated |= gpu_buffers.edges.write_skipping(&sdf_data.edges, &edges_skip, "sdf edges", &render_device, &render_queue);
+    reallocated |= gpu_buffers.tris.write_skipping(&sdf_data.tris, &tris_skip, "sdf triangles", &render_device, &render_queue);
+    reallocated |= gpu_buffers.bins.write(&sdf_data.bins, "sdf bins", &render_device, &render_queue);
+    reallocated |= gpu_buffers.joints.write(&sdf_data.joints, "sdf joints", &render_device, &render_queue);
+    reallocated |= queue_calc_dispatches(
+        &sdf_data,
+        gpu_buffers,
+        &pipeline,
+        &mut pipelines,
+        &mut pipeline_cache,
+        &render_device,
+        &render_queue,
+    );
+
+    let args_size = sdf_data.groups.len() as u64 * DISPATCH_ARGS_SIZE;
+    if gpu_buffers.dispatch_args.is_none() || args_size > gpu_buffers.dispatch_args_capacity {
+        gpu_buffers.dispatch_args_capacity = args_size.next_power_of_two();
+        gpu_buffers.dispatch_args = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("sdf dispatch args"),
+            size: gpu_buffers.dispatch_args_capacity,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        }));
+        reallocated = true;
+    }
+
+    // sized to the split instances of the frame, which fit in one binding
+    let partials_size = (sdf_data.partial_count as u64 * PARTIAL_SIZE).max(PARTIAL_SIZE);
+    if gpu_buffers.partials.is_none() || partials_size > gpu_buffers.partials_capacity {
+        let max_size = render_device.limits().max_storage_buffer_binding_size as u64;
+        gpu_buffers.partials_capacity = partials_size.next_power_of_two().min(max_size);
+        gpu_buffers.partials = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("sdf partials"),
+            size: gpu_buffers.partials_capacity,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        }));
+        reallocated = true;
+    }
+
+    let texture_view = gpu_image.texture_view.id();
+    let batches = sdf_data
+        .batches
+        .iter()
+        .map(|batch| (batch.feature_start, batch.feature_end))
+        .collect::<Vec<_>>();
+    if !reallocated
+        && !gpu_buffers.bind_groups.is_empty()
+        && gpu_buffers.texture_view == Some(texture_view)
+        && gpu_buffers.bound_batches == batches
+    {
+        return;
+    }
+
+    let partials = gpu_buffers.partials.as_ref().unwrap();
+    gpu_buffers.bind_groups = batches
+        .iter()
+        .map(|(start, end)| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: gpu_buffers.instances.binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: gpu_buffers.vertices.slice_binding(start.x, end.x, strides.x),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: gpu_buffers.edges.slice_binding(start.y, end.y, strides.y),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: gpu_buffers.tris.slice_binding(start.z, end.z, strides.z),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: BindingResource::TextureView(&gpu_buffers.atlas_views[0]),
+                    },
+                    BindGroupEntry {
+                        binding: 5,
+                        resource: gpu_buffers.skin_sources.binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 6,
+                        resource: gpu_buffers.skin_vertices.binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 7,
+                        resource: gpu_buffers.joints.binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 8,
+                        resource: gpu_buffers.bins.binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 9,
+                        resource: gpu_buffers.calc_params.binding().unwrap(),
+                    },
+                    BindGroupEntry {
+                        binding: 10,
+                        resource: BindingResource::TextureView(&gradient_image.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 11,
+                        resource: partials.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+        .collect();
+    gpu_buffers.bound_batches = batches;
+    gpu_buffers.texture_view = Some(texture_view);
+
+    let dispatch_args = gpu_buffers.dispatch_args.as_ref().unwrap();
+    gpu_buffers.dispatch_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
-        layout: &pipeline.bind_group_layout,
+        layout: &pipeline.dispatch_bind_group_layout,
         entries: &[
             BindGroupEntry {
                 binding: 0,
-                resource: storage_buffer(&sdf_data.instances, "sdf instances", &render_device)
-                    .as_entire_binding(),
+                resource: gpu_buffers.instances.binding(),
             },
             BindGroupEntry {
                 binding: 1,
-                resource: storage_buffer(&sdf_data.vertices, "sdf vertices", &render_device)
-                    .as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 2,
-                resource: storage_buffer(&sdf_data.edges, "sdf edges", &render_device)
-                    .as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 3,
-                resource: storage_buffer(&sdf_data.tris, "sdf triangles", &render_device)
-                    .as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 4,
-                resource: BindingResource::TextureView(&gpu_image.texture_view),
+                resource: dispatch_args.as_entire_binding(),
             },
         ],
-    });
-    sdf_data.bind_group = Some(bind_group);
-    // println!("[{}] render_queue {}", *frame, sdf_data.instances.data[0].block_dimensions * 8);
+    }));
+}
+
+fn queue_jfa_bind_group(
+    sdf_data: Res<SdfData>,
+    mut gpu_buffers: ResMut<SdfGpuBuffers>,
+    pipeline: Res<SdfComputePipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let gpu_buffers = &mut *gpu_buffers;
+    gpu_buffers.jfa_bind_groups.clear();
+    if sdf_data.jfa.is_empty() {
+        gpu_buffers.jfa_dispatches.clear();
+        return;
+    }
+
+    // the triangles are shared with the main pass
+    if gpu_buffers.tris.buffer.is_none() || gpu_buffers.atlas_views.is_empty() {
+        gpu_buffers.jfa_dispatches.clear();
+        return;
+    }
+
+    let size = sdf_data.jfa_voxel_count as u64 * 4;
+    if gpu_buffers.jfa_seeds.is_none() || size > gpu_buffers.jfa_seeds_capacity {
+        gpu_buffers.jfa_seeds_capacity = size.next_power_of_two();
+        let create = || {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some("sdf jfa seeds"),
+                size: gpu_buffers.jfa_seeds_capacity,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        gpu_buffers.jfa_seeds = Some([create(), create()]);
+    }
+
+    queue_jfa_dispatches(&sdf_data, gpu_buffers);
+    gpu_buffers.jfa_params.write_buffer(&render_device, &render_queue);
+
+    // the jfa instances index triangles relative to their batch
+    let seeds = gpu_buffers.jfa_seeds.as_ref().unwrap();
+    let tri_stride = feature_strides().z;
+    gpu_buffers.jfa_bind_groups = sdf_data
+        .batches
+        .iter()
+        .map(|batch| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.jfa_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: gpu_buffers.tris.slice_binding(
+                            batch.feature_start.z,
+                            batch.feature_end.z,
+                            tri_stride,
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: seeds[0].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: seeds[1].as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&gpu_buffers.atlas_views[0]),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: gpu_buffers.jfa_params.binding().unwrap(),
+                    },
+                ],
+            })
+        })
+        .collect();
 }
 
 pub struct SdfComputePipeline {
     bind_group_layout: BindGroupLayout,
-    pipeline: CachedComputePipelineId,
+    dispatch_bind_group_layout: BindGroupLayout,
+    dispatch_pipeline: CachedComputePipelineId,
+    skin_pipeline: CachedComputePipelineId,
+    blit_bind_group_layout: BindGroupLayout,
+    blit_pipeline: CachedComputePipelineId,
+    jfa_bind_group_layout: BindGroupLayout,
+    jfa_seed_pipeline: CachedComputePipelineId,
+    jfa_flood_pipeline: CachedComputePipelineId,
+    jfa_resolve_pipeline: CachedComputePipelineId,
+    mip_bind_group_layout: BindGroupLayout,
+    mip_pipeline: CachedComputePipelineId,
 }
 
-impl FromWorld for SdfComputePipeline {
-    fn from_world(world: &mut World) -> Self {
-        let bind_group_layout =
+// storage format of the atlas, for the render world pipelines which are created before the atlas
+// is first extracted
+pub(crate) struct SdfAtlasFormat(pub TextureFormat);
+
+impl FromWorld for SdfComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let atlas_format = world.resource::<SdfAtlasFormat>().0;
+        // the storage format the passes other than calc write the atlas with, calc has it in its
+        // key
+        let atlas_shader_defs = match atlas_format {
+            TextureFormat::R32Float => vec![],
+            TextureFormat::R16Float => vec![String::from("ATLAS_R16FLOAT")],
+            format => panic!("unsupported sdf atlas format {:?}", format),
+        };
+
+        let bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        // sdf header
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfInstancesData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // vertices, skinned in place
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfVerticesData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // edges, skinned in place
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfEdgesData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // tris, skinned in place
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfTrisData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // output
+                        BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: atlas_format,
+                                view_dimension: TextureViewDimension::D3,
+                            },
+                            count: None,
+                        },
+                        // skin source indices
+                        BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfSkinSourcesData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // skin vertices
+                        BindGroupLayoutEntry {
+                            binding: 6,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfSkinVerticesData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // joint matrices
+                        BindGroupLayoutEntry {
+                            binding: 7,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfJointsData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // per block feature lists
+                        BindGroupLayoutEntry {
+                            binding: 8,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfBinsData::min_size()),
+                            },
+                            count: None,
+                        },
+                        // per group params
+                        BindGroupLayoutEntry {
+                            binding: 9,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: Some(SdfCalcGroupParams::min_size()),
+                            },
+                            count: None,
+                        },
+                        // gradient output, only written by pipelines specialized for it
+                        BindGroupLayoutEntry {
+                            binding: 10,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: TextureFormat::Rgba8Snorm,
+                                view_dimension: TextureViewDimension::D3,
+                            },
+                            count: None,
+                        },
+                        // partial distances of split instances between their chunks
+                        BindGroupLayoutEntry {
+                            binding: 11,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: NonZeroU64::new(PARTIAL_SIZE),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+        let blit_bind_group_layout =
             world
                 .resource::<RenderDevice>()
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: None,
                     entries: &[
-                        // sdf header
+                        // source, loaded rather than sampled as R32Float isn't filterable everywhere
                         BindGroupLayoutEntry {
                             binding: 0,
                             visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: false },
+                                view_dimension: TextureViewDimension::D3,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        // output
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: atlas_format,
+                                view_dimension: TextureViewDimension::D3,
+                            },
+                            count: None,
+                        },
+                        // params
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SdfBlitParams::min_size()),
+                            },
+                            count: None,
+                        },
+                        // composite deltas
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
                             ty: BindingType::Buffer {
                                 ty: BufferBindingType::Storage { read_only: true },
                                 has_dynamic_offset: false,
+                                min_binding_size: Some(SdfDeltasData::min_size()),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+        let dispatch_bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        // sdf header, block and feature starts are written
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
                                 min_binding_size: Some(SdfInstancesData::min_size()),
                             },
                             count: None,
                         },
-                        // vertices
+                        // indirect dispatch args
                         BindGroupLayoutEntry {
                             binding: 1,
                             visibility: ShaderStages::COMPUTE,
                             ty: BindingType::Buffer {
-                                ty: BufferBindingType::Storage { read_only: true },
+                                ty: BufferBindingType::Storage { read_only: false },
                                 has_dynamic_offset: false,
-                                min_binding_size: Some(SdfVerticesData::min_size()),
+                                min_binding_size: BufferSize::new(DISPATCH_ARGS_SIZE),
                             },
                             count: None,
                         },
-                        // edges
+                    ],
+                });
+
+        let jfa_bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        // tris, shared with the main pass
                         BindGroupLayoutEntry {
-                            binding: 2,
+                            binding: 0,
                             visibility: ShaderStages::COMPUTE,
                             ty: BindingType::Buffer {
                                 ty: BufferBindingType::Storage { read_only: true },
                                 has_dynamic_offset: false,
-                                min_binding_size: Some(SdfEdgesData::min_size()),
+                                min_binding_size: Some(SdfTrisData::min_size()),
                             },
                             count: None,
                         },
-                        // tris
+                        // seeds a
                         BindGroupLayoutEntry {
-                            binding: 3,
+                            binding: 1,
                             visibility: ShaderStages::COMPUTE,
                             ty: BindingType::Buffer {
-                                ty: BufferBindingType::Storage { read_only: true },
+                                ty: BufferBindingType::Storage { read_only: false },
                                 has_dynamic_offset: false,
-                                min_binding_size: Some(SdfTrisData::min_size()),
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        // seeds b
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
                             },
                             count: None,
                         },
                         // output
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: atlas_format,
+                                view_dimension: TextureViewDimension::D3,
+                            },
+                            count: None,
+                        },
+                        // per dispatch params
                         BindGroupLayoutEntry {
                             binding: 4,
                             visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: Some(SdfJfaParams::min_size()),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+        let mip_bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        // the level above
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: false },
+                                view_dimension: TextureViewDimension::D3,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        // output level
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
                             ty: BindingType::StorageTexture {
                                 access: StorageTextureAccess::WriteOnly,
-                                format: TextureFormat::R32Float,
+                                format: atlas_format,
                                 view_dimension: TextureViewDimension::D3,
                             },
                             count: None,
                         },
+                        // per dispatch params
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: Some(SdfMipParams::min_size()),
+                            },
+                            count: None,
+                        },
                     ],
                 });
 
-        let shader = world
-            .resource::<AssetServer>()
-            .load("shader/compute_sdf.wgsl");
+        let dispatch_shader = DISPATCH_SDF_SHADER_HANDLE.typed::<Shader>();
+        let jfa_shader = JFA_SDF_SHADER_HANDLE.typed::<Shader>();
+        let blit_shader = BLIT_SDF_SHADER_HANDLE.typed::<Shader>();
+        let shader = COMPUTE_SDF_SHADER_HANDLE.typed::<Shader>();
         let mut pipeline_cache = world.resource_mut::<PipelineCache>();
-        let pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        let dispatch_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: Some(vec![dispatch_bind_group_layout.clone()]),
+            shader: dispatch_shader,
+            shader_defs: atlas_shader_defs.clone(),
+            entry_point: Cow::from("prepare"),
+        });
+        let skin_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
             label: None,
             layout: Some(vec![bind_group_layout.clone()]),
             shader,
-            shader_defs: vec![],
-            entry_point: Cow::from("calc"),
+            shader_defs: atlas_shader_defs.clone(),
+            entry_point: Cow::from("skin"),
+        });
+        let blit_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: Some(vec![blit_bind_group_layout.clone()]),
+            shader: blit_shader,
+            shader_defs: atlas_shader_defs.clone(),
+            entry_point: Cow::from("blit"),
+        });
+        let [jfa_seed_pipeline, jfa_flood_pipeline, jfa_resolve_pipeline] =
+            ["seed", "flood", "resolve"].map(|entry_point| {
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: None,
+                    layout: Some(vec![jfa_bind_group_layout.clone()]),
+                    shader: jfa_shader.clone(),
+                    shader_defs: atlas_shader_defs.clone(),
+                    entry_point: Cow::from(entry_point),
+                })
+            });
+        let mip_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: None,
+            layout: Some(vec![mip_bind_group_layout.clone()]),
+            shader: MIP_SDF_SHADER_HANDLE.typed::<Shader>(),
+            shader_defs: atlas_shader_defs.clone(),
+            entry_point: Cow::from("downsample"),
         });
 
         SdfComputePipeline {
             bind_group_layout,
-            pipeline,
+            dispatch_bind_group_layout,
+            dispatch_pipeline,
+            skin_pipeline,
+            blit_bind_group_layout,
+            blit_pipeline,
+            jfa_bind_group_layout,
+            jfa_seed_pipeline,
+            jfa_flood_pipeline,
+            jfa_resolve_pipeline,
+            mip_bind_group_layout,
+            mip_pipeline,
+        }
+    }
+}
+
+impl SpecializedComputePipeline for SdfComputePipeline {
+    type Key = SdfComputePipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        // the layout is made for the atlas format, which the key always matches
+        let mut shader_defs = Vec::new();
+        if key.half_precision {
+            shader_defs.push(String::from("ATLAS_R16FLOAT"));
+        }
+        if key.gradient {
+            shader_defs.push(String::from("GRADIENT_OUTPUT"));
+        }
+        if key.unsigned {
+            shader_defs.push(String::from("UNSIGNED_DISTANCE"));
+        }
+        if key.winding_number {
+            shader_defs.push(String::from("WINDING_NUMBER_SIGN"));
+        }
+        match key.workgroup {
+            SdfComputeWorkgroup::Threads512 => (),
+            SdfComputeWorkgroup::Threads256 => shader_defs.push(String::from("WORKGROUP_THREADS_256")),
+            SdfComputeWorkgroup::Threads128 => shader_defs.push(String::from("WORKGROUP_THREADS_128")),
+        }
+
+        ComputePipelineDescriptor {
+            label: None,
+            layout: Some(vec![self.bind_group_layout.clone()]),
+            shader: COMPUTE_SDF_SHADER_HANDLE.typed::<Shader>(),
+            shader_defs,
+            entry_point: Cow::from("calc"),
         }
     }
 }
 
+// record whether every pipeline the node could use this frame has compiled, so the main world
+// only queues sdfs that will be dispatched. all calc variants for the current workgroup shape are
+// requested up front, since the main world can't tell which will be needed
+fn check_pipelines(
+    status: Res<SdfPipelineStatus>,
+    settings: Res<SdfGlobalSettings>,
+    atlas: Res<SdfAtlas>,
+    pipeline: Res<SdfComputePipeline>,
+    mut pipelines: ResMut<SpecializedComputePipelines<SdfComputePipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+) {
+    // the winding number only matters for signed distances, and gradients need the gradient atlas
+    let gradients: &[bool] = match atlas.gradients {
+        true => &[false, true],
+        false => &[false],
+    };
+    let calc = gradients
+        .iter()
+        .flat_map(|gradient| {
+            [(false, false), (true, false), (false, true)].map(|(unsigned, winding_number)| {
+                SdfComputePipelineKey {
+                    unsigned,
+                    winding_number,
+                    workgroup: settings.compute_workgroup,
+                    half_precision: atlas.format == TextureFormat::R16Float,
+                    gradient: *gradient,
+                }
+            })
+        })
+        .map(|key| pipelines.specialize(&mut pipeline_cache, &pipeline, key))
+        .collect::<Vec<_>>();
+
+    let ready = [
+        pipeline.dispatch_pipeline,
+        pipeline.skin_pipeline,
+        pipeline.blit_pipeline,
+        pipeline.jfa_seed_pipeline,
+        pipeline.jfa_flood_pipeline,
+        pipeline.jfa_resolve_pipeline,
+        pipeline.mip_pipeline,
+    ]
+    .into_iter()
+    .chain(calc)
+    .all(|id| pipeline_cache.get_compute_pipeline(id).is_some());
+
+    *status.ready.lock().unwrap() = ready.then_some(settings.compute_workgroup);
+}
+
+// with `shader_hot_reload` the internal shaders are loaded through bevy's debug asset server,
+// which reloads them from the source tree when they are saved. the pipeline cache recompiles the
+// pipelines using a modified shader by itself (and `check_pipelines` holds queueing until it's
+// done), so just clear the atlas to regenerate every entry with the new code
+#[cfg(feature = "shader_hot_reload")]
+fn regenerate_on_shader_reload(
+    mut events: EventReader<AssetEvent<Shader>>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    let shaders = [
+        COMPUTE_SDF_SHADER_HANDLE,
+        BLIT_SDF_SHADER_HANDLE,
+        JFA_SDF_SHADER_HANDLE,
+        DISPATCH_SDF_SHADER_HANDLE,
+        MIP_SDF_SHADER_HANDLE,
+    ]
+    .map(|handle| handle.id);
+    let reloaded = events.iter().any(|event| {
+        matches!(event, AssetEvent::Modified { handle } if shaders.contains(&handle.id))
+    });
+    if !reloaded {
+        return;
+    }
+
+    info!("sdf compute shader reloaded, regenerating all sdfs");
+    atlas.purge_all();
+}
+
+fn report_compute_started(
+    status: Res<SdfPipelineStatus>,
+    mut events: EventWriter<SdfComputeStarted>,
+    mut reported: Local<bool>,
+) {
+    if !*reported && status.dispatched.load(Ordering::Acquire) {
+        info!("sdf compute pipelines ready, first sdfs dispatched");
+        events.send(SdfComputeStarted);
+        *reported = true;
+    }
+}
+
 #[derive(Default)]
 struct SdfComputeNode;
 
-impl render_graph::Node for SdfComputeNode {
-    fn run(
-        &self,
-        _graph: &mut render_graph::RenderGraphContext,
-        render_context: &mut RenderContext,
-        world: &World,
-    ) -> Result<(), render_graph::NodeRunError> {
+impl SdfComputeNode {
+    // copy entries relocated by compaction to their new slots through the scratch texture. moves
+    // are applied in the order they were made, so a slot vacated by one move and reused by a
+    // later one is read before it is overwritten
+    fn relocate(&self, render_context: &mut RenderContext, world: &World) {
+        let atlas = world.resource::<SdfAtlas>();
+        let gpu_buffers = world.resource::<SdfGpuBuffers>();
+        let (Some(gpu_image), Some((scratch, _))) = (
+            world.resource::<RenderAssets<Image>>().get(&atlas.image),
+            gpu_buffers.move_scratch.as_ref(),
+        ) else { return };
+
+        let atlas_texel = |position: UVec3| ImageCopyTexture {
+            texture: &gpu_image.texture,
+            mip_level: 0,
+            origin: Origin3d {
+                x: position.x,
+                y: position.y,
+                z: position.z,
+            },
+            aspect: TextureAspect::All,
+        };
+        let scratch_origin = || ImageCopyTexture {
+            texture: scratch,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        };
+
+        // gradients move with their entries
+        let gradients = match (
+            world.resource::<RenderAssets<Image>>().get(&atlas.gradient_image),
+            gpu_buffers.gradient_move_scratch.as_ref(),
+        ) {
+            (Some(gradient_image), Some(gradient_scratch)) if atlas.gradients => {
+                Some((&gradient_image.texture, gradient_scratch))
+            }
+            _ => None,
+        };
+
+        let encoder = &mut render_context.command_encoder;
+        for atlas_move in atlas.moves.iter() {
+            let extent = Extent3d {
+                width: atlas_move.size.x,
+                height: atlas_move.size.y,
+                depth_or_array_layers: atlas_move.size.z,
+            };
+            encoder.copy_texture_to_texture(atlas_texel(atlas_move.from), scratch_origin(), extent);
+            encoder.copy_texture_to_texture(scratch_origin(), atlas_texel(atlas_move.to), extent);
+
+            let Some((texture, scratch)) = gradients else { continue };
+            let texel = |position: UVec3| ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                },
+                aspect: TextureAspect::All,
+            };
+            let scratch_origin = || ImageCopyTexture {
+                texture: scratch,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            };
+            encoder.copy_texture_to_texture(texel(atlas_move.from), scratch_origin(), extent);
+            encoder.copy_texture_to_texture(scratch_origin(), texel(atlas_move.to), extent);
+        }
+    }
+
+    // copy precomputed images into their slots. false if the pipeline isn't available
+    fn copy(&self, render_context: &mut RenderContext, world: &World) -> bool {
+        let gpu_buffers = world.resource::<SdfGpuBuffers>();
+        if gpu_buffers.blits.is_empty() {
+            return true;
+        }
+
+        let pipeline = world.resource::<SdfComputePipeline>();
+        let Some(blit_pipeline) = world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(pipeline.blit_pipeline) else { return false };
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(blit_pipeline);
+        for (bind_group, dimensions) in gpu_buffers.blits.iter() {
+            pass.set_bind_group(0, bind_group, &[]);
+            let workgroups = *dimensions / WORKGROUP_SIZE;
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        world
+            .resource::<SdfPipelineStatus>()
+            .dispatched
+            .store(true, Ordering::Release);
+        true
+    }
+
+    // downsample the regions written this frame into the mip levels, each level from the one
+    // above. false if the pipeline isn't available
+    fn downsample(&self, render_context: &mut RenderContext, world: &World) -> bool {
+        let gpu_buffers = world.resource::<SdfGpuBuffers>();
+        if gpu_buffers.mip_dispatches.is_empty() {
+            return true;
+        }
+
+        let pipeline = world.resource::<SdfComputePipeline>();
+        let Some(mip_pipeline) = world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(pipeline.mip_pipeline) else { return false };
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(mip_pipeline);
+        for dispatch in gpu_buffers.mip_dispatches.iter() {
+            let bind_group = &gpu_buffers.mip_bind_groups[dispatch.level as usize - 1];
+            pass.set_bind_group(0, bind_group, &[dispatch.params_offset]);
+            let workgroups = dispatch.workgroups;
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        true
+    }
+
+    // a pipeline needed for this frame's entries isn't available (e.g. it's recompiling after the
+    // workgroup shape changed), have the main world requeue them rather than leave them unwritten
+    fn skip(&self, world: &World) {
+        let sdf_data = world.resource::<SdfData>();
+        let status = world.resource::<SdfPipelineStatus>();
+        warn!("sdf compute pipelines not ready, requeueing {} entries", sdf_data.entities.len());
+        status.skipped.lock().unwrap().extend(sdf_data.entities.iter().copied());
+    }
+
+    // calc and jump flood passes for this frame's instances. false if they weren't dispatched
+    fn generate(&self, render_context: &mut RenderContext, world: &World) -> bool {
         let sdf_data = world.resource::<SdfData>();
-        let Some(bind_group) = sdf_data.bind_group.as_ref() else { return Ok(()) };
+        let gpu_buffers = world.resource::<SdfGpuBuffers>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<SdfComputePipeline>();
+        let status = world.resource::<SdfPipelineStatus>();
+        let get = |id| pipeline_cache.get_compute_pipeline(id);
+
+        if sdf_data.block_count == 0 && sdf_data.jfa.is_empty() {
+            return true;
+        }
+        let bind_groups = &gpu_buffers.bind_groups;
+        let (false, Some(dispatch_bind_group), Some(dispatch_args)) = (
+            bind_groups.is_empty(),
+            gpu_buffers.dispatch_bind_group.as_ref(),
+            gpu_buffers.dispatch_args.as_ref(),
+        ) else { return false };
+
+        // resolve everything up front so nothing is half dispatched
+        let (Some(dispatch_pipeline), Some(skin_pipeline)) = (
+            get(pipeline.dispatch_pipeline),
+            get(pipeline.skin_pipeline),
+        ) else {
+            self.skip(world);
+            return false;
+        };
+        let Some(calc_pipelines) = gpu_buffers
+            .calc_dispatches
+            .iter()
+            .map(|dispatch| get(dispatch.pipeline))
+            .collect::<Option<Vec<_>>>() else {
+            self.skip(world);
+            return false;
+        };
+        let Some(jfa_pipelines) = gpu_buffers
+            .jfa_dispatches
+            .iter()
+            .map(|dispatch| {
+                get(match dispatch.pass {
+                    JfaPass::Seed => pipeline.jfa_seed_pipeline,
+                    JfaPass::Flood => pipeline.jfa_flood_pipeline,
+                    JfaPass::Resolve => pipeline.jfa_resolve_pipeline,
+                })
+            })
+            .collect::<Option<Vec<_>>>() else {
+            self.skip(world);
+            return false;
+        };
 
         // println!("running {} blocks", sdf_data.block_count);
         // let block_counts = sdf_data.instances.data.iter().map(|d| d.block_count).collect::<Vec<_>>();
@@ -379,17 +3251,110 @@ impl render_graph::Node for SdfComputeNode {
             .command_encoder
             .begin_compute_pass(&ComputePassDescriptor::default());
 
-        pass.set_bind_group(0, bind_group, &[]);
+        // lay out the instances' blocks and size the calc dispatch on the gpu
+        pass.set_pipeline(dispatch_pipeline);
+        pass.set_bind_group(0, dispatch_bind_group, &[]);
+        pass.dispatch_workgroups(1, 1, 1);
 
-        pass.set_pipeline(
-            pipeline_cache
-                .get_compute_pipeline(pipeline.pipeline)
-                .unwrap(),
-        );
-        pass.dispatch_workgroups(sdf_data.block_count, 1, 1);
+        // skin the rest pose features in place before they are used, a batch at a time
+        if !gpu_buffers.skin_dispatches.is_empty() {
+            pass.set_pipeline(skin_pipeline);
+        }
+        for dispatch in gpu_buffers.skin_dispatches.iter() {
+            pass.set_bind_group(0, &bind_groups[dispatch.batch], &[dispatch.params_offset]);
+            let workgroups = dispatch.workgroups;
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        for (dispatch, calc_pipeline) in gpu_buffers.calc_dispatches.iter().zip(calc_pipelines) {
+            pass.set_pipeline(calc_pipeline);
+            pass.set_bind_group(0, &bind_groups[dispatch.batch], &[dispatch.params_offset]);
+            pass.dispatch_workgroups_indirect(dispatch_args, dispatch.args_offset);
+        }
+        drop(pass);
+        status.dispatched.store(true, Ordering::Release);
 
         // println!("dispatch: {}", sdf_data.instances.data[0].block_dimensions * 8);
 
+        let jfa_bind_groups = &gpu_buffers.jfa_bind_groups;
+        if jfa_bind_groups.is_empty() {
+            return true;
+        }
+
+        // seeds start unset, the flood passes overwrite every voxel of the second buffer
+        let seeds = gpu_buffers.jfa_seeds.as_ref().unwrap();
+        render_context.command_encoder.clear_buffer(&seeds[0], 0, None);
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        for (dispatch, jfa_pipeline) in gpu_buffers.jfa_dispatches.iter().zip(jfa_pipelines) {
+            pass.set_pipeline(jfa_pipeline);
+            pass.set_bind_group(0, &jfa_bind_groups[dispatch.batch], &[dispatch.params_offset]);
+            let workgroups = dispatch.workgroups;
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        true
+    }
+
+    // publish the entities whose entries were actually written: copies whose source image was
+    // ready, and generated entries if the calc passes were dispatched
+    fn record_written(&self, world: &World, generated: bool) {
+        let sdf_data = world.resource::<SdfData>();
+        if sdf_data.entities.is_empty() {
+            return;
+        }
+        let status = world.resource::<SdfPipelineStatus>();
+        let skipped = status.skipped.lock().unwrap();
+        let blits = sdf_data
+            .blits
+            .iter()
+            .map(|blit| blit.entity)
+            .collect::<HashSet<_>>();
+        *status.written.lock().unwrap() = sdf_data
+            .entities
+            .iter()
+            .filter(|ent| !skipped.contains(*ent) && (generated || blits.contains(*ent)))
+            .copied()
+            .collect();
+    }
+}
+
+impl render_graph::Node for SdfComputeNode {
+    // all atlas writes for the frame happen here, in order: compaction moves, precomputed copies,
+    // then generation, then downsampling of every written region. each step records its own
+    // passes after the previous one, and wgpu inserts the barriers between dispatches writing and
+    // reading the atlas, so a slot copied into and then modified (or downsampled) the same frame
+    // sees the earlier writes.
+    // none of this is idempotent, so the node must do its work once per frame. it's run once per
+    // 3d camera in the default graph, and only the first run records anything
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if world.resource::<SdfComputeFrame>().ran.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // the main world only queues sdfs once `check_pipelines` has seen every pipeline compiled,
+        // but skip and requeue rather than panic if one is still missing (e.g. the workgroup
+        // shape changed)
+        self.relocate(render_context, world);
+        if !self.copy(render_context, world) {
+            self.skip(world);
+            self.record_written(world, false);
+            return Ok(());
+        }
+
+        let generated = self.generate(render_context, world);
+
+        if !self.downsample(render_context, world) {
+            self.skip(world);
+        }
+
+        self.record_written(world, generated);
         Ok(())
     }
 }
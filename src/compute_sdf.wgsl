@@ -0,0 +1,716 @@
+struct VertexData {
+    v: vec3<f32>,
+    n: vec3<f32>,
+};
+
+struct Vertices {
+    data: array<VertexData>,
+};
+
+struct EdgeData {
+    a: vec3<f32>,
+    b: vec3<f32>,
+    n: vec3<f32>,
+};
+
+struct Edges {
+    data: array<EdgeData>,
+};
+
+struct TriData {
+    a: vec3<f32>,
+    b: vec3<f32>,
+    c: vec3<f32>,
+    plane: vec4<f32>,
+    inv_area: f32,
+};
+
+struct Tris {
+    data: array<TriData>,
+};
+
+struct InstanceData {
+    write_position: vec3<u32>,
+    aabb_min: vec3<f32>,
+    scale: vec3<f32>,
+    block_dimensions: vec3<u32>,
+    counts: vec3<u32>,
+    block_count: u32,
+    flags: u32,
+    // distance the surface is dilated by
+    weld_margin: f32,
+    // distance samples averaged per voxel
+    samples: u32,
+    // 0 when thin features aren't thickened
+    min_thickness: f32,
+    // skinned instances only
+    skin_sources_offset: u32,
+    skin_vertex_offset: u32,
+    joint_offset: u32,
+    // start of the per block feature lists, or NO_BINS to test every feature
+    bin_offset: u32,
+    // start of the blocks to compute for sparse updates, or NO_BLOCK_LIST for every block
+    block_list_offset: u32,
+    // start of the feature bvh, or NO_BVH
+    bvh_offset: u32,
+    // start of the brick each listed block is written to, or NO_BRICKS to write to the slot
+    brick_offset: u32,
+    // start of the partials of split instances' voxels, or NO_PARTIALS
+    partial_offset: u32,
+    // run of instances sharing a pipeline
+    group: u32,
+    // written by the dispatch pass
+    block_start: u32,
+    // in the whole feature buffers, subtract the group's `feature_base` for the bound batch
+    feature_start: vec3<u32>,
+};
+
+let INSTANCE_FLAG_IGNORE_BACK_FACES: u32 = 1u;
+let INSTANCE_FLAG_INVERT: u32 = 2u;
+let INSTANCE_FLAG_SKINNED: u32 = 4u;
+let INSTANCE_FLAG_END: u32 = 8u;
+let INSTANCE_FLAG_MIRROR_X: u32 = 16u;
+let INSTANCE_FLAG_MIRROR_Y: u32 = 32u;
+let INSTANCE_FLAG_MIRROR_Z: u32 = 64u;
+let INSTANCE_FLAG_SURFACE_MASK: u32 = 128u;
+let INSTANCE_FLAG_CHEBYSHEV: u32 = 256u;
+let INSTANCE_FLAG_MANHATTAN: u32 = 512u;
+let INSTANCE_FLAG_CHUNK_READ: u32 = 1024u;
+let INSTANCE_FLAG_CHUNK_WRITE: u32 = 2048u;
+let NO_BINS: u32 = 0xffffffffu;
+let NO_BLOCK_LIST: u32 = 0xffffffffu;
+let NO_BVH: u32 = 0xffffffffu;
+let NO_BRICKS: u32 = 0xffffffffu;
+let NO_PARTIALS: u32 = 0xffffffffu;
+
+// bvh nodes are 8 u32s: min and max bounds as f32 bits, then the first item and item count of a
+// leaf, or the right child and zero for an interior node (whose left child follows it). items are
+// feature indices tagged with their kind in the top 2 bits
+let BVH_NODE_SIZE: u32 = 8u;
+// bvhs are built no deeper than this less one (`MAX_DEPTH` in bvh.rs), so the traversal stack
+// can't overflow
+let BVH_STACK_SIZE: u32 = 32u;
+
+struct Instances {
+    // written by the dispatch pass
+    instance_count: u32,
+    block_count: u32,
+    data: array<InstanceData>,
+};
+
+// the run of instances a calc dispatch covers, the end instance is excluded
+struct CalcGroup {
+    first_instance: u32,
+    end_instance: u32,
+    // start of the batch the feature buffers are bound at
+    feature_base: vec3<u32>,
+};
+
+// calc threads along z per block, each computing 8 / WORKGROUP_DEPTH voxels
+#ifdef WORKGROUP_THREADS_128
+let WORKGROUP_DEPTH: u32 = 2u;
+#else
+#ifdef WORKGROUP_THREADS_256
+let WORKGROUP_DEPTH: u32 = 4u;
+#else
+let WORKGROUP_DEPTH: u32 = 8u;
+#endif
+#endif
+
+struct SkinSources {
+    data: array<u32>,
+};
+
+struct SkinVertex {
+    position: vec3<f32>,
+    joints: vec4<u32>,
+    weights: vec4<f32>,
+};
+
+struct SkinVertices {
+    data: array<SkinVertex>,
+};
+
+struct Joints {
+    data: array<mat4x4<f32>>,
+};
+
+// per voxel of split instances: the nearest distance squared and metric length so far, the sign
+// from the nearest feature's normal and the winding number summed over the chunks so far
+struct Partials {
+    data: array<vec4<f32>>,
+};
+
+// per block (start, count) headers for vertices, edges and triangles, then the index lists. also
+// holds the block lists of sparse updates and feature bvhs
+struct Bins {
+    data: array<u32>,
+};
+
+@group(0) @binding(0)
+var<storage> instances: Instances;
+@group(0) @binding(1)
+var<storage, read_write> vertices: Vertices;
+@group(0) @binding(2)
+var<storage, read_write> edges: Edges;
+@group(0) @binding(3)
+var<storage, read_write> tris: Tris;
+@group(0) @binding(4)
+#ifdef ATLAS_R16FLOAT
+var texture: texture_storage_3d<r16float, write>;
+#else
+var texture: texture_storage_3d<r32float, write>;
+#endif
+@group(0) @binding(5)
+var<storage> skin_sources: SkinSources;
+@group(0) @binding(6)
+var<storage> skin_vertices: SkinVertices;
+@group(0) @binding(7)
+var<storage> joints: Joints;
+@group(0) @binding(8)
+var<storage> bins: Bins;
+@group(0) @binding(9)
+var<uniform> calc_group: CalcGroup;
+#ifdef GRADIENT_OUTPUT
+@group(0) @binding(10)
+var gradients: texture_storage_3d<rgba8snorm, write>;
+#endif
+@group(0) @binding(11)
+var<storage, read_write> partials: Partials;
+
+// nearest feature found so far for the current voxel
+var<private> target_point: vec3<f32>;
+var<private> best_dist_sq: f32;
+var<private> best_norm: vec3<f32>;
+var<private> best_nearest: vec3<f32>;
+// 1.0 or -1.0, the sign of the last `point_distance`
+var<private> best_outside: f32;
+
+fn distance_squared(x: vec3<f32>, y: vec3<f32>) -> f32 {
+    let v = y - x;
+    return dot(v, v);
+}
+
+fn skin_matrix(instance: InstanceData, source_index: u32) -> mat4x4<f32> {
+    let vertex = skin_vertices.data[instance.skin_vertex_offset + skin_sources.data[source_index]];
+    let j = instance.joint_offset + vertex.joints;
+    return joints.data[j.x] * vertex.weights.x
+        + joints.data[j.y] * vertex.weights.y
+        + joints.data[j.z] * vertex.weights.z
+        + joints.data[j.w] * vertex.weights.w;
+}
+
+fn skin_position(instance: InstanceData, source_index: u32) -> vec3<f32> {
+    let vertex = skin_vertices.data[instance.skin_vertex_offset + skin_sources.data[source_index]];
+    let res = skin_matrix(instance, source_index) * vec4<f32>(vertex.position, 1.0);
+    return res.xyz / res.w;
+}
+
+// rest normals of the vertices then edges follow the instance's sources, as f32 bits
+fn rest_normal(instance: InstanceData, normal_index: u32) -> vec3<f32> {
+    let counts = instance.counts;
+    let index = instance.skin_sources_offset + counts.x + counts.y * 2u + counts.z * 3u + normal_index * 3u;
+    return bitcast<vec3<f32>>(vec3<u32>(skin_sources.data[index], skin_sources.data[index + 1u], skin_sources.data[index + 2u]));
+}
+
+fn skin_normal(m: mat4x4<f32>, n: vec3<f32>) -> vec3<f32> {
+    let skinned = (m * vec4<f32>(n, 0.0)).xyz;
+    // zero (non-manifold) normals stay zero
+    if (dot(skinned, skinned) == 0.0) {
+        return skinned;
+    }
+    return normalize(skinned);
+}
+
+// write the posed features of skinned instances from their rest poses, into the space left for
+// them in the feature buffers. dispatched per batch, with one thread per feature counted over the
+// vertices, edges then triangles of each skinned instance of the batch in turn
+@compute
+@workgroup_size(64, 1, 1)
+fn skin(
+    @builtin(global_invocation_id) invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    // features may be spread over y, see `linear_workgroups` in compute.rs
+    var feature_id = invocation_id.x + invocation_id.y * num_workgroups.x * 64u;
+    var instance_index = calc_group.first_instance;
+
+    var instance = instances.data[instance_index];
+    loop {
+        if (instance_index >= calc_group.end_instance) {
+            return;
+        }
+        if ((instance.flags & INSTANCE_FLAG_SKINNED) != 0u) {
+            let feature_count = instance.counts.x + instance.counts.y + instance.counts.z;
+            if (feature_id < feature_count) {
+                break;
+            }
+            feature_id = feature_id - feature_count;
+        }
+        instance_index = instance_index + 1u;
+        instance = instances.data[instance_index];
+    }
+
+    let start = instance.feature_start - calc_group.feature_base;
+    let sources = instance.skin_sources_offset;
+
+    if (feature_id < instance.counts.x) {
+        let index = start.x + feature_id;
+        let source = sources + feature_id;
+        vertices.data[index].v = skin_position(instance, source);
+        vertices.data[index].n = skin_normal(skin_matrix(instance, source), rest_normal(instance, feature_id));
+        return;
+    }
+    feature_id = feature_id - instance.counts.x;
+
+    if (feature_id < instance.counts.y) {
+        let index = start.y + feature_id;
+        let source = sources + instance.counts.x + feature_id * 2u;
+        edges.data[index].a = skin_position(instance, source);
+        edges.data[index].b = skin_position(instance, source + 1u);
+        let m = (skin_matrix(instance, source) + skin_matrix(instance, source + 1u)) * 0.5;
+        edges.data[index].n = skin_normal(m, rest_normal(instance, instance.counts.x + feature_id));
+        return;
+    }
+    feature_id = feature_id - instance.counts.y;
+
+    let index = start.z + feature_id;
+    let source = sources + instance.counts.x + instance.counts.y * 2u + feature_id * 3u;
+    let a = skin_position(instance, source);
+    let b = skin_position(instance, source + 1u);
+    let c = skin_position(instance, source + 2u);
+    tris.data[index].a = a;
+    tris.data[index].b = b;
+    tris.data[index].c = c;
+
+    let cross_ab_bc = cross(b - a, c - b);
+    let double_area = length(cross_ab_bc);
+    if (double_area <= 0.0) {
+        // collapsed by the pose: a plane no point can be close to, so the triangle is skipped
+        tris.data[index].plane = vec4<f32>(0.0, 0.0, 0.0, 1e18);
+        tris.data[index].inv_area = 0.0;
+        return;
+    }
+    let n = cross_ab_bc / double_area;
+    tris.data[index].plane = vec4<f32>(n, -dot(a, n));
+    tris.data[index].inv_area = 1.0 / dot(cross(b - a, c - a), n);
+}
+
+fn test_vertex(i: u32) {
+    let data = vertices.data[i];
+    let dist_sq = distance_squared(target_point, data.v);
+    if (dist_sq < best_dist_sq) {
+        best_dist_sq = dist_sq;
+        best_norm = data.n;
+        best_nearest = data.v;
+    }
+}
+
+fn test_edge(i: u32) {
+    let data = edges.data[i];
+
+    let edge = data.b - data.a;
+    let edge_len_sq = dot(edge, edge);
+    let intercept = clamp(dot(target_point - data.a, edge), 0.0, edge_len_sq);
+    if (intercept < 0.001 || intercept > edge_len_sq * 0.999) {
+        return;
+    }
+
+    let nearest = data.a + edge * (intercept / edge_len_sq);
+    let dist_sq = distance_squared(target_point, nearest);
+    if (dist_sq < best_dist_sq) {
+        best_dist_sq = dist_sq;
+        best_norm = data.n;
+        best_nearest = nearest;
+    }
+}
+
+fn test_tri(i: u32) {
+    let tri = tris.data[i];
+
+    let distance_to_plane = dot(tri.plane, vec4<f32>(target_point, 1.0));
+    let distance_to_plane_sq = distance_to_plane * distance_to_plane;
+    if (distance_to_plane_sq > best_dist_sq) {
+        return;
+    }
+
+    let n = tri.plane.xyz;
+    let point_on_plane = target_point - distance_to_plane * n;
+    // barycentric coords
+    let u = dot(
+                cross(tri.c - tri.b, point_on_plane - tri.b),
+                n
+            ) * tri.inv_area;
+    let v = dot(
+                cross(tri.a - tri.c, point_on_plane - tri.c),
+                n
+            ) * tri.inv_area;
+    let w = 1.0 - u - v;
+
+    if (u >= 0.0 && v >= 0.0 && w >= 0.0) {
+        best_dist_sq = distance_to_plane_sq;
+        best_norm = tri.plane.xyz;
+        best_nearest = point_on_plane;
+    }
+}
+
+fn bvh_node_distance_sq(node: u32) -> f32 {
+    let node_min = vec3<f32>(bitcast<f32>(bins.data[node]), bitcast<f32>(bins.data[node + 1u]), bitcast<f32>(bins.data[node + 2u]));
+    let node_max = vec3<f32>(bitcast<f32>(bins.data[node + 3u]), bitcast<f32>(bins.data[node + 4u]), bitcast<f32>(bins.data[node + 5u]));
+    let outside = max(max(node_min - target_point, target_point - node_max), vec3<f32>(0.0));
+    return dot(outside, outside);
+}
+
+// nearest first traversal, skipping nodes further than the best feature found so far
+fn traverse_bvh(bvh_offset: u32, start: vec3<u32>) {
+    var stack: array<u32, 32>;
+    stack[0] = 0u;
+    var stack_size = 1u;
+
+    loop {
+        if (stack_size == 0u) {
+            break;
+        }
+        stack_size = stack_size - 1u;
+        let node_index = stack[stack_size];
+        let node = bvh_offset + node_index * BVH_NODE_SIZE;
+        if (bvh_node_distance_sq(node) > best_dist_sq) {
+            continue;
+        }
+
+        let count = bins.data[node + 7u];
+        if (count > 0u) {
+            let first = bvh_offset + bins.data[node + 6u];
+            for (var j = 0u; j < count; j = j + 1u) {
+                let item = bins.data[first + j];
+                let kind = item >> 30u;
+                let index = item & 0x3fffffffu;
+                if (kind == 0u) {
+                    test_vertex(start.x + index);
+                } else if (kind == 1u) {
+                    test_edge(start.y + index);
+                } else {
+                    test_tri(start.z + index);
+                }
+            }
+            continue;
+        }
+
+        // push the further child first so the nearer one is visited next
+        let left = node_index + 1u;
+        let right = bins.data[node + 6u];
+        let left_dist_sq = bvh_node_distance_sq(bvh_offset + left * BVH_NODE_SIZE);
+        let right_dist_sq = bvh_node_distance_sq(bvh_offset + right * BVH_NODE_SIZE);
+        if (left_dist_sq < right_dist_sq) {
+            stack[stack_size] = right;
+            stack[stack_size + 1u] = left;
+        } else {
+            stack[stack_size] = left;
+            stack[stack_size + 1u] = right;
+        }
+        stack_size = stack_size + 2u;
+    }
+}
+
+// generalized winding number of the instance's triangles around the target point: about 1 inside
+// a closed surface, 0 outside, and in between behind holes. each triangle adds its signed solid
+// angle (van oosterom and strackee), so every triangle is visited regardless of bins
+fn winding_number(instance: InstanceData) -> f32 {
+    let start = instance.feature_start.z - calc_group.feature_base.z;
+    var total = 0.0;
+    for (var i = start; i < start + instance.counts.z; i = i + 1u) {
+        let tri = tris.data[i];
+        let a = tri.a - target_point;
+        let b = tri.b - target_point;
+        let c = tri.c - target_point;
+        let la = length(a);
+        let lb = length(b);
+        let lc = length(c);
+        let numerator = dot(a, cross(b, c));
+        let denominator = la * lb * lc + dot(a, b) * lc + dot(b, c) * la + dot(c, a) * lb;
+        total = total + 2.0 * atan2(numerator, denominator);
+    }
+    return total / (4.0 * 3.14159265);
+}
+
+// low discrepancy offsets within a voxel (in voxels) for supersampling, the first at the center.
+// the r3 sequence, must match `supersample_offset` in cpu.rs
+fn supersample_offset(index: u32) -> vec3<f32> {
+    return fract(vec3<f32>(0.5) + vec3<f32>(0.8191725, 0.6710436, 0.5497005) * f32(index)) - 0.5;
+}
+
+// length of the offset to the nearest point under the instance's metric, must match
+// `SdfMetric::apply` in lib.rs
+fn metric_length(flags: u32, offset: vec3<f32>, dist_sq: f32) -> f32 {
+    let a = abs(offset);
+    if ((flags & INSTANCE_FLAG_CHEBYSHEV) != 0u) {
+        return max(a.x, max(a.y, a.z));
+    }
+    if ((flags & INSTANCE_FLAG_MANHATTAN) != 0u) {
+        return a.x + a.y + a.z;
+    }
+    return sqrt(dist_sq);
+}
+
+// the nearest of the instance's features that can be nearest in the block to the target point
+fn nearest_features(instance: InstanceData, block_id: u32) {
+    let start = instance.feature_start - calc_group.feature_base;
+    best_dist_sq = 999999.0;
+
+    if (instance.bvh_offset != NO_BVH) {
+        traverse_bvh(instance.bvh_offset, start);
+    } else if (instance.bin_offset == NO_BINS) {
+        for (var i = start.x; i < start.x + instance.counts.x; i = i + 1u) {
+            test_vertex(i);
+        }
+        for (var i = start.y; i < start.y + instance.counts.y; i = i + 1u) {
+            test_edge(i);
+        }
+        for (var i = start.z; i < start.z + instance.counts.z; i = i + 1u) {
+            test_tri(i);
+        }
+    } else {
+        let header = instance.bin_offset + block_id * 6u;
+        for (var j = 0u; j < bins.data[header + 1u]; j = j + 1u) {
+            test_vertex(start.x + bins.data[instance.bin_offset + bins.data[header] + j]);
+        }
+        for (var j = 0u; j < bins.data[header + 3u]; j = j + 1u) {
+            test_edge(start.y + bins.data[instance.bin_offset + bins.data[header + 2u] + j]);
+        }
+        for (var j = 0u; j < bins.data[header + 5u]; j = j + 1u) {
+            test_tri(start.z + bins.data[instance.bin_offset + bins.data[header + 4u] + j]);
+        }
+    }
+}
+
+// signed distance from the target point to the instance's surface, before the weld margin
+fn point_distance(instance: InstanceData, block_id: u32) -> f32 {
+    nearest_features(instance, block_id);
+
+#ifdef UNSIGNED_DISTANCE
+    var outside = 1.0;
+#else
+#ifdef WINDING_NUMBER_SIGN
+    var outside = select(-1.0, 1.0, winding_number(instance) < 0.5);
+#else
+    let direction = target_point - best_nearest;
+    // non-manifold edges have a zero normal and are treated as outside
+    var outside = select(-1.0, 1.0, dot(direction, best_norm) >= 0.0);
+#endif
+#endif
+    if ((instance.flags & INSTANCE_FLAG_INVERT) != 0u) {
+        outside = -outside;
+    }
+    best_outside = outside;
+    return metric_length(instance.flags, target_point - best_nearest, best_dist_sq) * outside;
+}
+
+// unit direction away from the surface at the target point, after `point_distance`
+fn surface_gradient() -> vec3<f32> {
+    let offset = target_point - best_nearest;
+    var direction = best_norm;
+    if (dot(offset, offset) > 0.0) {
+        direction = normalize(offset);
+    }
+    return direction * best_outside;
+}
+
+// whether the surface may pass through the cell around the target point, after
+// `nearest_features`. conservative, as any point within half a voxel diagonal of the nearest
+// feature is taken to be in the cell
+fn in_surface_mask(instance: InstanceData, dist_sq: f32) -> bool {
+    let half_size = instance.scale * 0.5;
+    return (instance.flags & INSTANCE_FLAG_SURFACE_MASK) != 0u && dist_sq <= dot(half_size, half_size);
+}
+
+// the distance with thin features thickened to the instance's min thickness, must match
+// `thickened_distance` in cpu.rs. outside points near a surface probe the min thickness behind it,
+// and if that isn't inside either the feature is thin, so the distance is taken to a shell of the
+// min thickness around it instead, fading back to the true distance over the following min
+// thickness
+fn thicken(instance: InstanceData, block_id: u32, dist: f32) -> f32 {
+    let thickness = instance.min_thickness;
+    if (thickness <= 0.0 || dist <= 0.0 || dist >= thickness * 2.0) {
+        return dist;
+    }
+
+    let offset = target_point - best_nearest;
+    var normal = best_norm;
+    if (dot(offset, offset) > 0.0) {
+        normal = normalize(offset);
+    }
+    target_point = best_nearest - normal * thickness;
+    if (point_distance(instance, block_id) <= 0.0) {
+        return dist;
+    }
+
+    return dist - thickness * 0.5 * clamp(2.0 - dist / thickness, 0.0, 1.0);
+}
+
+// compute and store one voxel of a block at the given texel, averaging over the instance's samples
+fn calc_voxel(instance: InstanceData, block_id: u32, mirror: vec3<bool>, target_offset: vec3<u32>, write_position: vec3<u32>) {
+    let center = instance.aabb_min + vec3<f32>(target_offset) * instance.scale;
+    var total = 0.0;
+    var gradient = vec3<f32>(0.0);
+    for (var i = 0u; i < instance.samples; i = i + 1u) {
+        target_point = center + supersample_offset(i) * instance.scale;
+        var dist = point_distance(instance, block_id);
+#ifdef GRADIENT_OUTPUT
+        // before thickening moves the target point
+        gradient = gradient + surface_gradient();
+#endif
+        // masked samples are inside whichever side of the surface they're on, and so aren't
+        // thickened either
+        if (in_surface_mask(instance, best_dist_sq)) {
+            dist = -abs(dist);
+        }
+        total = total + thicken(instance, block_id, dist);
+    }
+    let dist = total / f32(instance.samples) - instance.weld_margin;
+
+    textureStore(texture, vec3<i32>(write_position), vec4<f32>(dist, 0.0, 0.0, 1.0));
+#ifdef GRADIENT_OUTPUT
+    if (dot(gradient, gradient) > 0.0) {
+        gradient = normalize(gradient);
+    }
+    textureStore(gradients, vec3<i32>(write_position), vec4<f32>(gradient, 0.0));
+#endif
+    if (any(mirror)) {
+        let mirrored = select(target_offset, instance.block_dimensions * 8u - 1u - target_offset, mirror);
+        textureStore(texture, vec3<i32>(instance.write_position + mirrored), vec4<f32>(dist, 0.0, 0.0, 1.0));
+#ifdef GRADIENT_OUTPUT
+        textureStore(gradients, vec3<i32>(instance.write_position + mirrored), vec4<f32>(select(gradient, -gradient, mirror), 0.0));
+#endif
+    }
+}
+
+// one voxel of a chunk of a split instance, see `SdfBufferLimits::split` in compute.rs. the
+// nearest feature and winding number of the chunks so far are carried in the partials buffer, and
+// only the last chunk writes the texture. split instances take a single sample and aren't
+// thickened
+fn calc_chunk_voxel(instance: InstanceData, block_id: u32, mirror: vec3<bool>, target_offset: vec3<u32>, write_position: vec3<u32>, partial_index: u32) {
+    target_point = instance.aabb_min + vec3<f32>(target_offset) * instance.scale;
+    nearest_features(instance, block_id);
+    let offset = target_point - best_nearest;
+    var partial = vec4<f32>(best_dist_sq, metric_length(instance.flags, offset, best_dist_sq), 1.0, 0.0);
+#ifdef WINDING_NUMBER_SIGN
+    partial.w = winding_number(instance);
+#else
+    // non-manifold edges have a zero normal and are treated as outside
+    partial.z = select(-1.0, 1.0, dot(offset, best_norm) >= 0.0);
+#endif
+
+    if ((instance.flags & INSTANCE_FLAG_CHUNK_READ) != 0u) {
+        let previous = partials.data[partial_index];
+        if (previous.x < partial.x) {
+            partial = vec4<f32>(previous.xyz, partial.w);
+        }
+        partial.w = partial.w + previous.w;
+    }
+    if ((instance.flags & INSTANCE_FLAG_CHUNK_WRITE) != 0u) {
+        partials.data[partial_index] = partial;
+        return;
+    }
+
+#ifdef UNSIGNED_DISTANCE
+    var outside = 1.0;
+#else
+#ifdef WINDING_NUMBER_SIGN
+    var outside = select(-1.0, 1.0, partial.w < 0.5);
+#else
+    var outside = partial.z;
+#endif
+#endif
+    if ((instance.flags & INSTANCE_FLAG_INVERT) != 0u) {
+        outside = -outside;
+    }
+    if (in_surface_mask(instance, partial.x)) {
+        outside = -1.0;
+    }
+    let dist = partial.y * outside - instance.weld_margin;
+
+    textureStore(texture, vec3<i32>(write_position), vec4<f32>(dist, 0.0, 0.0, 1.0));
+    if (any(mirror)) {
+        let mirrored = select(target_offset, instance.block_dimensions * 8u - 1u - target_offset, mirror);
+        textureStore(texture, vec3<i32>(instance.write_position + mirrored), vec4<f32>(dist, 0.0, 0.0, 1.0));
+    }
+}
+
+@compute
+#ifdef WORKGROUP_THREADS_128
+@workgroup_size(8, 8, 2)
+#else
+#ifdef WORKGROUP_THREADS_256
+@workgroup_size(8, 8, 4)
+#else
+@workgroup_size(8, 8, 8)
+#endif
+#endif
+fn calc(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+) {
+    // blocks may be spread over y, see dispatch_sdf.wgsl. the dispatch only covers this group's
+    // blocks
+    var block_id = workgroup_id.x + workgroup_id.y * num_workgroups.x
+        + instances.data[calc_group.first_instance].block_start;
+    if (block_id >= instances.data[calc_group.end_instance].block_start) {
+        return;
+    }
+
+    // the last instance of the group starting at or before the block. instances without blocks
+    // share their start with the next one so are never chosen
+    var lo = calc_group.first_instance;
+    var hi = calc_group.end_instance;
+    loop {
+        if (lo >= hi) {
+            break;
+        }
+        let mid = (lo + hi) / 2u;
+        if (instances.data[mid].block_start <= block_id) {
+            lo = mid + 1u;
+        } else {
+            hi = mid;
+        }
+    }
+    let instance = instances.data[lo - 1u];
+    block_id = block_id - instance.block_start;
+    let list_index = block_id;
+
+    if (instance.block_list_offset != NO_BLOCK_LIST) {
+        block_id = bins.data[instance.block_list_offset + block_id];
+    }
+
+    // mirrored instances only dispatch the lower half of the blocks along the mirror axis
+    let mirror = vec3<bool>(
+        (instance.flags & INSTANCE_FLAG_MIRROR_X) != 0u,
+        (instance.flags & INSTANCE_FLAG_MIRROR_Y) != 0u,
+        (instance.flags & INSTANCE_FLAG_MIRROR_Z) != 0u,
+    );
+    let block_dimensions = select(instance.block_dimensions, (instance.block_dimensions + 1u) / 2u, mirror);
+
+    let block_z = block_id / (block_dimensions.x * block_dimensions.y);
+    let block_y = (block_id - block_z * (block_dimensions.x * block_dimensions.y)) / block_dimensions.x;
+    let block_x = (block_id - block_z * (block_dimensions.x * block_dimensions.y) - block_y * block_dimensions.x);
+    block_id = block_x + (block_y + block_z * instance.block_dimensions.y) * instance.block_dimensions.x;
+
+    let block_offset = vec3<u32>(block_x, block_y, block_z) * 8u;
+
+    // entries stored as bricks write each block to its own brick rather than their slot
+    var write_origin = instance.write_position + block_offset;
+    if (instance.brick_offset != NO_BRICKS) {
+        let brick = instance.brick_offset + list_index * 3u;
+        write_origin = vec3<u32>(bins.data[brick], bins.data[brick + 1u], bins.data[brick + 2u]);
+    }
+
+    for (var z = local_id.z; z < 8u; z = z + WORKGROUP_DEPTH) {
+        let local_offset = vec3<u32>(local_id.xy, z);
+        if (instance.partial_offset != NO_PARTIALS) {
+            let partial_index = instance.partial_offset + list_index * 512u + local_offset.x + local_offset.y * 8u + z * 64u;
+            calc_chunk_voxel(instance, block_id, mirror, block_offset + local_offset, write_origin + local_offset, partial_index);
+        } else {
+            calc_voxel(instance, block_id, mirror, block_offset + local_offset, write_origin + local_offset);
+        }
+    }
+}
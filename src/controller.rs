@@ -1,4 +1,6 @@
-use bevy::{input::mouse::MouseMotion, prelude::*};
+use bevy::{input::mouse::MouseMotion, prelude::*, transform::TransformSystem};
+
+use crate::query::{update_query_meshes, SdfQuery};
 
 pub struct ControllerPlugin;
 
@@ -8,6 +10,58 @@ impl Plugin for ControllerPlugin {
     }
 }
 
+/// third person camera booms kept out of geometry with sdf queries. requires `SdfPlugin`
+pub struct SdfCameraBoomPlugin;
+
+impl Plugin for SdfCameraBoomPlugin {
+    fn build(&self, app: &mut App) {
+        // after the query meshes are updated for this frame, and before the camera's transform is
+        // propagated so it's used this frame
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            camera_boom
+                .after(update_query_meshes)
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// places the camera behind a target entity along the camera's backward axis, shortening the
+/// boom where it would pass through sdf geometry (other than the target's own). the camera's
+/// rotation (e.g. from the mouse look of a `CameraController`) swings the boom around the target,
+/// any translation from the controller is overridden.
+#[derive(Component)]
+pub struct SdfCameraBoom {
+    // entity the camera follows
+    pub target: Entity,
+    // offset from the target's translation to the boom pivot, e.g. up to the shoulders
+    pub offset: Vec3,
+    // boom length when unobstructed
+    pub length: f32,
+    // clearance kept between the camera and geometry
+    pub radius: f32,
+    // the boom is never shortened below this, even when the pivot itself is close to geometry
+    pub min_length: f32,
+    // rate the boom lengthens at once an obstruction clears, in units per second. shortening is
+    // immediate so the camera never clips
+    pub recover_speed: f32,
+    current_length: Option<f32>,
+}
+
+impl SdfCameraBoom {
+    pub fn new(target: Entity, length: f32) -> Self {
+        Self {
+            target,
+            offset: Vec3::ZERO,
+            length,
+            radius: 0.2,
+            min_length: 0.0,
+            recover_speed: 4.0,
+            current_length: None,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct CameraController {
     pub enabled: bool,
@@ -144,3 +198,64 @@ fn camera_controller(
         }
     }
 }
+
+// distance along the ray from `origin` before a sphere of `radius` would touch geometry (other
+// than the filtered out entities), up to `max_length`
+fn clear_length(
+    query: &SdfQuery,
+    filter: impl Fn(Entity) -> bool + Copy,
+    origin: Vec3,
+    direction: Vec3,
+    max_length: f32,
+    radius: f32,
+) -> f32 {
+    // smallest march step, so grazing rays don't stall
+    let min_step = radius.max(0.01) * 0.1;
+    let mut t = 0.0;
+    while t < max_length {
+        let distance =
+            query.distance_filtered(origin + direction * t, max_length - t + radius, filter);
+        if distance <= radius {
+            return t;
+        }
+        t += (distance - radius).max(min_step);
+    }
+    max_length
+}
+
+fn camera_boom(
+    time: Res<Time>,
+    query: SdfQuery,
+    targets: Query<&GlobalTransform>,
+    parents: Query<&Parent>,
+    mut cameras: Query<(&mut Transform, &mut SdfCameraBoom), With<Camera>>,
+) {
+    for (mut transform, mut boom) in cameras.iter_mut() {
+        let Ok(target) = targets.get(boom.target) else { continue };
+        let pivot = target.translation() + boom.offset;
+        let direction = transform.back();
+
+        // the pivot is usually inside the target, so ignore it and its descendants
+        let target_ent = boom.target;
+        let filter = |mut ent: Entity| loop {
+            if ent == target_ent {
+                return false;
+            }
+            match parents.get(ent) {
+                Ok(parent) => ent = parent.get(),
+                Err(_) => return true,
+            }
+        };
+        let clear = clear_length(&query, filter, pivot, direction, boom.length, boom.radius)
+            .max(boom.min_length);
+        let length = match boom.current_length {
+            Some(current) if clear > current => {
+                (current + boom.recover_speed * time.delta_seconds()).min(clear)
+            }
+            _ => clear,
+        };
+        boom.current_length = Some(length);
+
+        transform.translation = pivot + direction * length;
+    }
+}
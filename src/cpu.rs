@@ -2,125 +2,261 @@ use bevy::{
     math::Vec3A,
     prelude::*,
     render::{
-        mesh::PrimitiveTopology,
+        mesh::{Indices, PrimitiveTopology},
         primitives::Aabb,
         render_resource::{AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension},
         texture::ImageSampler,
     },
+    tasks::TaskPool,
+    utils::{FloatOrd, HashMap},
 };
 
-use crate::utils::preprocess_mesh_for_sdf;
+use crate::{
+    bvh::FeatureBvh,
+    utils::{preprocess_mesh_for_sdf, PreprocessedMeshData, TriData},
+    SdfBackFaces, SdfDecimation, SdfOptions,
+};
 
-pub fn create_sdf_from_mesh_cpu(
-    mesh: &Mesh,
-    aabb: &Aabb,
-    dimension: UVec3,
-    debug: Option<UVec3>,
-) -> Image {
-    let start = std::time::Instant::now();
-    assert!(
-        matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList),
-        "`sdf generation can only work on `TriangleList`s"
-    );
+// edge length (in voxels) of a brick in a `SdfBrickMap`
+pub const BRICK_SIZE: u32 = 8;
 
-    let preprocessed = preprocess_mesh_for_sdf(mesh, None);
+pub(crate) fn compute_distance(
+    preprocessed: &PreprocessedMeshData,
+    options: &SdfOptions,
+    point: Vec3A,
+    debug: bool,
+) -> f32 {
+    let (distance, nearest, _) = nearest_surface(preprocessed, options, point, debug);
+    options.metric.apply(distance, point - nearest) - options.weld_margin
+}
 
-    let compute_distance = |point: Vec3A, debug: bool| -> f32 {
-        if debug {
-            println!("point: {}", point);
-        }
+// as `compute_distance`, finding the nearest feature through a bvh
+pub(crate) fn compute_distance_bvh(
+    preprocessed: &PreprocessedMeshData,
+    bvh: &FeatureBvh,
+    options: &SdfOptions,
+    point: Vec3A,
+) -> f32 {
+    let (distance, nearest, _) = bvh.nearest(preprocessed, point).signed(options, point);
+    options.metric.apply(distance, point - nearest) - options.weld_margin
+}
 
-        #[derive(Default, Debug)]
-        struct Res {
-            dist_sq: f32,
-            norm: Vec3A,
-            nearest: Vec3A,
-        }
+// signed distance to the surface before the weld margin, with the nearest point and its normal
+fn nearest_surface(
+    preprocessed: &PreprocessedMeshData,
+    options: &SdfOptions,
+    point: Vec3A,
+    debug: bool,
+) -> (f32, Vec3A, Vec3A) {
+    if debug {
+        println!("point: {}", point);
+    }
 
-        let mut best = Res {
-            dist_sq: f32::MAX,
-            ..Default::default()
-        };
+    let mut best = NearestFeature {
+        dist_sq: f32::MAX,
+        ..Default::default()
+    };
 
-        for &(v, n) in preprocessed.vertices.iter() {
-            let dist_sq = point.distance_squared(v);
-            if dist_sq < best.dist_sq {
-                best.dist_sq = dist_sq;
-                best.norm = n;
-                best.nearest = v;
-                if debug {
-                    println!("vertex -- {}\n{:?}", v, best);
-                }
+    for &(v, n) in preprocessed.vertices.iter() {
+        if let Some(nearest) = vertex_nearest(v, n, point, best.dist_sq) {
+            best = nearest;
+            if debug {
+                println!("vertex -- {}\n{:?}", v, best);
             }
         }
+    }
 
-        for &((v0, v1), n) in preprocessed.edges.iter() {
-            let line = v1 - v0;
-            let line_len_sq = line.length_squared();
-            let intercept = f32::clamp((point - v0).dot(line), 0.0, line_len_sq);
-            if intercept < 0.001 || intercept > line_len_sq * 0.999 {
-                continue;
-            }
-
-            let nearest = v0 + line * (intercept / line_len_sq);
-            let dist_sq = point.distance_squared(nearest);
-            if dist_sq < best.dist_sq {
-                best.dist_sq = dist_sq;
-                best.norm = n;
-                best.nearest = nearest;
-                if debug {
-                    println!("edge -- {}-{}\n{:?}", v0, v1, best);
-                }
+    for &((v0, v1), n) in preprocessed.edges.iter() {
+        if let Some(nearest) = edge_nearest(v0, v1, n, point, best.dist_sq) {
+            best = nearest;
+            if debug {
+                println!("edge -- {}-{}\n{:?}", v0, v1, best);
             }
         }
+    }
 
-        for tri in preprocessed.triangles.iter() {
-            let distance_to_plane = tri.plane.normal_d().dot(point.extend(1.0));
-            let distance_to_plane_sq = distance_to_plane * distance_to_plane;
-            if distance_to_plane_sq > best.dist_sq {
-                continue;
-            }
-
-            let point_on_plane = point - distance_to_plane * tri.plane.normal();
-            // barycentric coords
-            let u = (tri.c - tri.b)
-                .cross(point_on_plane - tri.b)
-                .dot(tri.plane.normal())
-                * tri.inv_area;
-            let v = (tri.a - tri.c)
-                .cross(point_on_plane - tri.c)
-                .dot(tri.plane.normal())
-                * tri.inv_area;
-            let w = 1.0 - u - v;
-
-            if u.is_sign_positive() && v.is_sign_positive() && w.is_sign_positive() {
-                best.dist_sq = distance_to_plane_sq;
-                best.norm = tri.plane.normal();
-                best.nearest = point_on_plane;
-                if debug {
-                    println!("tri -- {:?}\n{:?}", tri, best);
-                }
+    for tri in preprocessed.triangles.iter() {
+        if let Some(nearest) = triangle_nearest(tri, point, best.dist_sq) {
+            best = nearest;
+            if debug {
+                println!("tri -- {:?}\n{:?}", tri, best);
             }
         }
+    }
 
+    if debug {
         let direction = point - best.nearest;
-        let outside = direction.dot(best.norm) >= 0.0;
+        println!(
+            "dist {}",
+            best.dist_sq.sqrt() * direction.dot(best.norm).signum()
+        );
+    }
 
-        if debug {
-            println!(
-                "dist {}",
-                best.dist_sq.sqrt() * direction.dot(best.norm).signum()
-            );
-        }
+    best.signed(options, point)
+}
+
+// the nearest point on a feature
+#[derive(Default, Debug, Clone, Copy)]
+pub(crate) struct NearestFeature {
+    pub dist_sq: f32,
+    pub norm: Vec3A,
+    pub nearest: Vec3A,
+}
 
-        if outside {
-            best.dist_sq.sqrt()
+impl NearestFeature {
+    // signed distance before the weld margin, with the nearest point and its normal
+    pub fn signed(&self, options: &SdfOptions, point: Vec3A) -> (f32, Vec3A, Vec3A) {
+        let direction = point - self.nearest;
+        let outside = match options.back_faces {
+            // non-manifold edges have a zero normal and are treated as outside
+            SdfBackFaces::TwoSided => direction.dot(self.norm) >= 0.0,
+            SdfBackFaces::Ignore => true,
+        };
+
+        let dist = if outside {
+            self.dist_sq.sqrt()
         } else {
-            -best.dist_sq.sqrt()
-        }
+            -self.dist_sq.sqrt()
+        };
+
+        let dist = if options.invert { -dist } else { dist };
+        (dist, self.nearest, self.norm)
+    }
+}
+
+// the vertex, if it's closer than `best_sq`
+pub(crate) fn vertex_nearest(v: Vec3A, n: Vec3A, point: Vec3A, best_sq: f32) -> Option<NearestFeature> {
+    let dist_sq = point.distance_squared(v);
+    (dist_sq < best_sq).then_some(NearestFeature {
+        dist_sq,
+        norm: n,
+        nearest: v,
+    })
+}
+
+// the nearest point on the edge, if it's closer than `best_sq` and not at an end, where the vertex
+// is nearest instead
+pub(crate) fn edge_nearest(
+    v0: Vec3A,
+    v1: Vec3A,
+    n: Vec3A,
+    point: Vec3A,
+    best_sq: f32,
+) -> Option<NearestFeature> {
+    let line = v1 - v0;
+    let line_len_sq = line.length_squared();
+    let intercept = f32::clamp((point - v0).dot(line), 0.0, line_len_sq);
+    if intercept < 0.001 || intercept > line_len_sq * 0.999 {
+        return None;
+    }
+
+    let nearest = v0 + line * (intercept / line_len_sq);
+    let dist_sq = point.distance_squared(nearest);
+    (dist_sq < best_sq).then_some(NearestFeature {
+        dist_sq,
+        norm: n,
+        nearest,
+    })
+}
+
+// the nearest point on the triangle's face, if it's no further than `best_sq` and the point
+// projects inside the triangle, where an edge or vertex is nearest otherwise
+pub(crate) fn triangle_nearest(tri: &TriData, point: Vec3A, best_sq: f32) -> Option<NearestFeature> {
+    let distance_to_plane = tri.plane.normal_d().dot(point.extend(1.0));
+    let distance_to_plane_sq = distance_to_plane * distance_to_plane;
+    if distance_to_plane_sq > best_sq {
+        return None;
+    }
+
+    let point_on_plane = point - distance_to_plane * tri.plane.normal();
+    // barycentric coords
+    let u = (tri.c - tri.b)
+        .cross(point_on_plane - tri.b)
+        .dot(tri.plane.normal())
+        * tri.inv_area;
+    let v = (tri.a - tri.c)
+        .cross(point_on_plane - tri.c)
+        .dot(tri.plane.normal())
+        * tri.inv_area;
+    let w = 1.0 - u - v;
+
+    (u.is_sign_positive() && v.is_sign_positive() && w.is_sign_positive()).then_some(NearestFeature {
+        dist_sq: distance_to_plane_sq,
+        norm: tri.plane.normal(),
+        nearest: point_on_plane,
+    })
+}
+
+// the distance with thin features thickened to `thickness`, must match `thicken` in
+// compute_sdf.wgsl. outside points near a surface probe `thickness` behind it, and if that isn't
+// inside either the feature is thin, so the distance is taken to a shell of that thickness around
+// it instead, fading back to the true distance over the following `thickness`
+fn thickened_distance(
+    preprocessed: &PreprocessedMeshData,
+    options: &SdfOptions,
+    point: Vec3A,
+    thickness: f32,
+) -> f32 {
+    let (dist, nearest, norm) = nearest_surface(preprocessed, options, point, false);
+    if thickness <= 0.0 || dist <= 0.0 || dist >= thickness * 2.0 {
+        return dist - options.weld_margin;
+    }
+
+    let offset = point - nearest;
+    let normal = match offset.length_squared() > 0.0 {
+        true => offset.normalize(),
+        false => norm,
     };
+    let (behind, ..) = nearest_surface(preprocessed, options, nearest - normal * thickness, false);
+    if behind <= 0.0 {
+        return dist - options.weld_margin;
+    }
+
+    dist - thickness * 0.5 * (2.0 - dist / thickness).clamp(0.0, 1.0) - options.weld_margin
+}
+
+// low discrepancy offsets within a voxel (in voxels) for supersampling, the first at the center.
+// the r3 sequence, must match compute_sdf.wgsl
+pub(crate) fn supersample_offset(index: u32) -> Vec3A {
+    let alpha = Vec3A::new(0.819_172_5, 0.671_043_6, 0.549_700_5);
+    (Vec3A::splat(0.5) + alpha * index as f32).fract() - 0.5
+}
+
+// the voxel's distance, averaged over `SdfOptions::supersample` jittered points within the voxel
+pub(crate) fn voxel_distance(
+    preprocessed: &PreprocessedMeshData,
+    options: &SdfOptions,
+    point: Vec3A,
+    scale: Vec3A,
+) -> f32 {
+    let thickness = options.min_thickness * scale.max_element();
+    let samples = options.samples();
+    if samples == 1 {
+        return thickened_distance(preprocessed, options, point, thickness);
+    }
+
+    (0..samples)
+        .map(|i| thickened_distance(preprocessed, options, point + supersample_offset(i) * scale, thickness))
+        .sum::<f32>()
+        / samples as f32
+}
+
+pub fn create_sdf_from_mesh_cpu(
+    mesh: &Mesh,
+    aabb: &Aabb,
+    dimension: UVec3,
+    options: &SdfOptions,
+    debug: Option<UVec3>,
+) -> Image {
+    let start = std::time::Instant::now();
+    assert!(
+        matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList),
+        "`sdf generation can only work on `TriangleList`s"
+    );
 
     let scale = aabb.half_extents * 2.0 / (dimension - 1).as_vec3a();
+    let options = &options.for_voxel_size(scale.min_element());
+    let preprocessed = preprocess_mesh_for_sdf(mesh, None, None, options);
 
     let mut data: Vec<u8> = Vec::new();
     data.resize((4 * dimension.x * dimension.y * dimension.z) as usize, 0);
@@ -134,10 +270,10 @@ pub fn create_sdf_from_mesh_cpu(
                 let point = aabb.min() + scale * UVec3::new(x, y, z).as_vec3a();
 
                 if Some(UVec3::new(x, y, z)) == debug {
-                    compute_distance(point, true);
+                    compute_distance(&preprocessed, options, point, true);
                 }
 
-                let dist = compute_distance(point, false);
+                let dist = voxel_distance(&preprocessed, options, point, scale);
 
                 let chunk = chunks.next().unwrap();
                 chunk.copy_from_slice(&dist.to_le_bytes());
@@ -147,6 +283,55 @@ pub fn create_sdf_from_mesh_cpu(
 
     let process = std::time::Instant::now();
 
+    let image = sdf_image(dimension, data);
+
+    let res = std::time::Instant::now();
+
+    // library callers (ffi, python) may own stdout, so only report when debugging
+    if debug.is_some() {
+        println!(
+            "prep: {:?}, proc: {:?}, res: {:?}, tot: {:?}",
+            prep - start,
+            process - prep,
+            res - process,
+            res - start
+        );
+    }
+
+    image
+}
+
+/// bake an indexed triangle list without a mesh asset, e.g. from ffi or scripting bindings. the
+/// volume spans the triangles' bounds expanded by `padding`, with voxels on both corners. returns
+/// the volume's aabb and its distances, x-fastest then y then z.
+/// indices must be in range, at least one triangle is required and `dimension` must be at least 2.
+pub fn create_sdf_from_triangles_cpu(
+    positions: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    dimension: UVec3,
+    padding: f32,
+    options: &SdfOptions,
+) -> (Aabb, Vec<f32>) {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    let mesh_aabb = mesh.compute_aabb().unwrap();
+    let aabb = Aabb {
+        center: mesh_aabb.center,
+        half_extents: mesh_aabb.half_extents + padding,
+    };
+
+    let image = create_sdf_from_mesh_cpu(&mesh, &aabb, dimension, options, None);
+    let distances = image
+        .data
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    (aabb, distances)
+}
+
+pub(crate) fn sdf_image(dimension: UVec3, data: Vec<u8>) -> Image {
     let mut image = Image::new(
         Extent3d {
             width: dimension.x,
@@ -168,15 +353,222 @@ pub fn create_sdf_from_mesh_cpu(
         ..Default::default()
     });
 
-    let res = std::time::Instant::now();
+    image
+}
 
-    println!(
-        "prep: {:?}, proc: {:?}, res: {:?}, tot: {:?}",
-        prep - start,
-        process - prep,
-        res - process,
-        res - start
+// evaluate a whole volume, splitting z-slices across the task pool
+fn bake_preprocessed(
+    preprocessed: &PreprocessedMeshData,
+    aabb: &Aabb,
+    dimension: UVec3,
+    options: &SdfOptions,
+    pool: &TaskPool,
+) -> Image {
+    let scale = aabb.half_extents * 2.0 / (dimension - 1).as_vec3a();
+    let min = aabb.min();
+
+    let slices = pool.scope(|s| {
+        for z in 0..dimension.z {
+            s.spawn(async move {
+                let mut slice = Vec::with_capacity((4 * dimension.x * dimension.y) as usize);
+                for y in 0..dimension.y {
+                    for x in 0..dimension.x {
+                        let point = min + scale * UVec3::new(x, y, z).as_vec3a();
+                        let dist = voxel_distance(preprocessed, options, point, scale);
+                        slice.extend_from_slice(&dist.to_le_bytes());
+                    }
+                }
+                slice
+            });
+        }
+    });
+
+    sdf_image(dimension, slices.concat())
+}
+
+/// bake many meshes at once. `items` are (mesh handle, aabb, dimension) triples, voxels are
+/// evaluated in parallel on the given task pool, and meshes which appear more than once are
+/// only preprocessed once (per voxel size with `SdfDecimation::UnitError`). `progress` is called
+/// with (completed, total) after each bake.
+/// returns an image per item in input order, `None` for items whose mesh is not loaded or is not
+/// a `TriangleList`. results aren't keyed by mesh handle, as the same mesh can be baked with
+/// several aabbs or dimensions.
+pub fn bake_all(
+    meshes: &Assets<Mesh>,
+    items: impl IntoIterator<Item = (Handle<Mesh>, Aabb, UVec3)>,
+    options: &SdfOptions,
+    pool: &TaskPool,
+    mut progress: impl FnMut(usize, usize),
+) -> Vec<Option<Image>> {
+    let items = items.into_iter().collect::<Vec<_>>();
+    let total = items.len();
+
+    // unit error decimation depends on the voxel size, so is preprocessed per voxel size
+    let mut preprocessed =
+        HashMap::<(Handle<Mesh>, Option<FloatOrd>), PreprocessedMeshData>::default();
+    let mut results = Vec::with_capacity(total);
+
+    for (completed, (handle, aabb, dimension)) in items.into_iter().enumerate() {
+        let Some(mesh) = meshes.get(&handle) else {
+            warn!("bake_all: mesh not loaded");
+            results.push(None);
+            progress(completed + 1, total);
+            continue;
+        };
+
+        if !matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList) {
+            warn!("bake_all: sdf generation can only work on `TriangleList`s");
+            results.push(None);
+            progress(completed + 1, total);
+            continue;
+        }
+
+        let voxel_size = (aabb.half_extents * 2.0 / (dimension - 1).as_vec3a()).min_element();
+        let unit_error = matches!(options.decimation, Some(SdfDecimation::UnitError(_)));
+        let options = &options.for_voxel_size(voxel_size);
+        let data = preprocessed
+            .entry((handle.clone_weak(), unit_error.then_some(FloatOrd(voxel_size))))
+            .or_insert_with(|| preprocess_mesh_for_sdf(mesh, None, None, options));
+
+        let image = bake_preprocessed(data, &aabb, dimension, options, pool);
+        results.push(Some(image));
+        progress(completed + 1, total);
+    }
+
+    results
+}
+
+/// sparse sdf representation: only bricks of `BRICK_SIZE`^3 voxels near the surface are stored
+/// in full, bricks in empty space store a single coarse distance (taken at the brick center).
+pub struct SdfBrickMap {
+    pub aabb: Aabb,
+    // voxel dimensions of the equivalent dense volume
+    pub dimension: UVec3,
+    // number of bricks along each axis
+    pub brick_dimensions: UVec3,
+    // per brick, index into `bricks` (None for empty bricks)
+    pub indirection: Vec<Option<u32>>,
+    // per brick, distance from the brick center to the surface
+    pub coarse: Vec<f32>,
+    // voxel data for occupied bricks, x-major
+    pub bricks: Vec<Vec<f32>>,
+}
+
+impl SdfBrickMap {
+    fn brick_index(&self, brick: UVec3) -> usize {
+        (brick.x
+            + brick.y * self.brick_dimensions.x
+            + brick.z * self.brick_dimensions.x * self.brick_dimensions.y) as usize
+    }
+
+    fn voxel_size(&self) -> Vec3A {
+        self.aabb.half_extents * 2.0 / (self.dimension - 1).as_vec3a()
+    }
+
+    /// number of bricks holding full voxel data
+    pub fn occupied_count(&self) -> usize {
+        self.bricks.len()
+    }
+
+    /// distance stored for the given voxel (clamped to the volume)
+    pub fn voxel(&self, voxel: UVec3) -> f32 {
+        let voxel = voxel.min(self.dimension - 1);
+        let brick = voxel / BRICK_SIZE;
+        let index = self.brick_index(brick);
+        match self.indirection[index] {
+            Some(brick_index) => {
+                let local = voxel - brick * BRICK_SIZE;
+                self.bricks[brick_index as usize]
+                    [(local.x + local.y * BRICK_SIZE + local.z * BRICK_SIZE * BRICK_SIZE) as usize]
+            }
+            None => self.coarse[index],
+        }
+    }
+
+    /// trilinearly filtered distance at a point in mesh space. points outside the aabb are
+    /// clamped to the boundary
+    pub fn sample(&self, point: Vec3A) -> f32 {
+        let coords = ((point - self.aabb.min()) / self.voxel_size())
+            .max(Vec3A::ZERO)
+            .min((self.dimension - 1).as_vec3a());
+        let base = coords.floor();
+        let t = coords - base;
+        let base = base.as_uvec3();
+
+        let weight = |offset: u32, t: f32| if offset == 1 { t } else { 1.0 - t };
+
+        let mut result = 0.0;
+        for corner in 0..8u32 {
+            let offset = UVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            result += self.voxel(base + offset)
+                * weight(offset.x, t.x)
+                * weight(offset.y, t.y)
+                * weight(offset.z, t.z);
+        }
+        result
+    }
+}
+
+pub fn create_sdf_bricks_from_mesh_cpu(
+    mesh: &Mesh,
+    aabb: &Aabb,
+    dimension: UVec3,
+    options: &SdfOptions,
+) -> SdfBrickMap {
+    assert!(
+        matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList),
+        "`sdf generation can only work on `TriangleList`s"
     );
 
-    image
+    let scale = aabb.half_extents * 2.0 / (dimension - 1).as_vec3a();
+    let options = &options.for_voxel_size(scale.min_element());
+    let preprocessed = preprocess_mesh_for_sdf(mesh, None, None, options);
+
+    let brick_dimensions = (dimension + BRICK_SIZE - 1) / BRICK_SIZE;
+    // a brick can only contain surface if the center is closer than the half-diagonal
+    let brick_radius = (scale * BRICK_SIZE as f32).length() * 0.5;
+
+    let mut indirection = Vec::new();
+    let mut coarse = Vec::new();
+    let mut bricks = Vec::new();
+
+    for bz in 0..brick_dimensions.z {
+        for by in 0..brick_dimensions.y {
+            for bx in 0..brick_dimensions.x {
+                let brick_min = UVec3::new(bx, by, bz) * BRICK_SIZE;
+                let center = aabb.min()
+                    + scale * (brick_min.as_vec3a() + (BRICK_SIZE - 1) as f32 * 0.5);
+                let center_dist = compute_distance(&preprocessed, options, center, false);
+                coarse.push(center_dist);
+
+                if center_dist.abs() > brick_radius {
+                    indirection.push(None);
+                    continue;
+                }
+
+                let mut brick = Vec::with_capacity((BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize);
+                for z in 0..BRICK_SIZE {
+                    for y in 0..BRICK_SIZE {
+                        for x in 0..BRICK_SIZE {
+                            let point =
+                                aabb.min() + scale * (brick_min + UVec3::new(x, y, z)).as_vec3a();
+                            brick.push(voxel_distance(&preprocessed, options, point, scale));
+                        }
+                    }
+                }
+
+                indirection.push(Some(bricks.len() as u32));
+                bricks.push(brick);
+            }
+        }
+    }
+
+    SdfBrickMap {
+        aabb: aabb.clone(),
+        dimension,
+        brick_dimensions,
+        indirection,
+        coarse,
+        bricks,
+    }
 }
@@ -1,11 +1,15 @@
-use crate::{queue_sdfs, Sdf, SdfAtlas, SdfAtlasKey};
+use crate::{queue_sdfs, Sdf, SdfAtlas};
 use bevy::{
+    asset::load_internal_asset,
     prelude::*,
     reflect::TypeUuid,
     render::render_resource::{AsBindGroup, ShaderRef},
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 
+pub const RENDER_SDF_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 13579746620665436718);
+
 pub struct SdfRenderPlugin;
 
 pub enum SdfRenderBounds {
@@ -16,11 +20,42 @@ pub enum SdfRenderBounds {
 
 impl Plugin for SdfRenderPlugin {
     fn build(&self, app: &mut App) {
+        load_internal_asset!(app, RENDER_SDF_SHADER_HANDLE, "render_sdf.wgsl", Shader::from_wgsl);
         app.add_plugin(MaterialPlugin::<SdfMaterial>::default());
+        let cube = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(shape::Cube { size: 1.0 }.into());
+        app.insert_resource(SdfRenderMesh(cube));
+        app.init_resource::<SdfDebugView>();
         app.add_system_to_stage(CoreStage::PostUpdate, update_sdf_render.after(queue_sdfs));
+        // after, so a render being set up isn't despawned before its components are inserted
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_debug_view.after(update_sdf_render),
+        );
     }
 }
 
+/// set `enabled` to ray march every sdf with an atlas entry at once, each over its own volume,
+/// and clear it to remove them again. sdfs added while enabled are picked up as they are
+/// generated
+#[derive(Default)]
+pub struct SdfDebugView {
+    pub enabled: bool,
+}
+
+// unit cube shared by every render, scaled over the sdf volume by the render's transform
+struct SdfRenderMesh(Handle<Mesh>);
+
+// render entities spawned by the `SdfDebugView`, as opposed to user spawned ones
+#[derive(Component)]
+struct SdfDebugViewRender;
+
+/// ray marches the sdf of `entity`, drawn over its atlas volume. the render entity is despawned
+/// when the target is despawned or loses its `Sdf`, and reuses its material as the target's
+/// atlas entry is regenerated. its `Transform` is overwritten to fit a shared cube mesh to the
+/// volume, so parent it to the target to follow it
 #[derive(Component)]
 pub struct SdfRender {
     pub entity: Entity,
@@ -33,6 +68,50 @@ pub struct SdfRender {
     pub max_step_count: u32,
 }
 
+impl SdfRender {
+    /// hits in red over black, with the default step settings
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            base_color: Color::rgba_linear(0.0, 0.0, 0.0, 1.0),
+            hit_color: Color::rgba_linear(1.0, 0.0, 0.0, 0.0),
+            step_color: Color::rgba_linear(0.0, 1.0, 0.0, 0.0),
+            distance_color: Color::rgba_linear(0.0, 0.0, 1.0, 0.0),
+            min_step_size: 0.01,
+            hit_threshold: 0.01,
+            max_step_count: 50,
+        }
+    }
+}
+
+/// an `SdfRender` with the spatial components it needs, best spawned as a child of the target so
+/// it's despawned along with it.
+///
+/// ```rust,no_run
+/// # use bevy::prelude::*;
+/// # use mesh2sdf::debug_render::SdfDebugBundle;
+/// # fn spawn(mut commands: Commands, ent: Entity) {
+/// commands.entity(ent).with_children(|p| {
+///     p.spawn_bundle(SdfDebugBundle::for_entity(ent));
+/// });
+/// # }
+/// ```
+#[derive(Bundle)]
+pub struct SdfDebugBundle {
+    pub render: SdfRender,
+    #[bundle]
+    pub spatial: SpatialBundle,
+}
+
+impl SdfDebugBundle {
+    pub fn for_entity(entity: Entity) -> Self {
+        Self {
+            render: SdfRender::new(entity),
+            spatial: SpatialBundle::default(),
+        }
+    }
+}
+
 #[derive(Clone, TypeUuid, AsBindGroup)]
 #[uuid = "8f83afc2-8543-40d9-b8ec-fbdb11051ebf"]
 pub struct SdfMaterial {
@@ -64,7 +143,7 @@ pub struct SdfMaterial {
 
 impl Material for SdfMaterial {
     fn fragment_shader() -> ShaderRef {
-        ShaderRef::Path("shader/render_sdf.wgsl".into())
+        RENDER_SDF_SHADER_HANDLE.typed().into()
     }
 
     fn alpha_mode(&self) -> AlphaMode {
@@ -82,14 +161,47 @@ impl Material for SdfMaterial {
     }
 }
 
+fn update_debug_view(
+    mut commands: Commands,
+    view: Res<SdfDebugView>,
+    atlas: Res<SdfAtlas>,
+    sdfs: Query<(Entity, &Sdf, Option<&Handle<Mesh>>)>,
+    renders: Query<(Entity, &SdfRender), With<SdfDebugViewRender>>,
+) {
+    if !view.enabled {
+        for (ent, _) in renders.iter() {
+            commands.entity(ent).despawn_recursive();
+        }
+        return;
+    }
+
+    let shown: HashSet<_> = renders.iter().map(|(_, render)| render.entity).collect();
+    for (ent, sdf, maybe_mesh) in sdfs.iter() {
+        if shown.contains(&ent) {
+            continue;
+        }
+        // the debug material samples dense entries only
+        let Some(key) = atlas.key(ent, sdf, maybe_mesh) else { continue };
+        if atlas.slot(&key).is_none() || atlas.is_bricks(&key) {
+            continue;
+        }
+
+        // as a child, so it follows the target and is despawned with it
+        commands.entity(ent).with_children(|p| {
+            p.spawn_bundle(SdfDebugBundle::for_entity(ent))
+                .insert(SdfDebugViewRender);
+        });
+    }
+}
+
 fn update_sdf_render(
     mut commands: Commands,
     atlas: Res<SdfAtlas>,
-    q: Query<(Entity, &SdfRender)>,
+    cube: Res<SdfRenderMesh>,
+    q: Query<(Entity, &SdfRender, Option<&Handle<SdfMaterial>>)>,
     sdf: Query<(&Sdf, Option<&Handle<Mesh>>, &GlobalTransform)>,
-    changed_scale: Query<(&Handle<SdfMaterial>, &GlobalTransform), Changed<GlobalTransform>>,
+    changed_scale: Query<&GlobalTransform, Changed<GlobalTransform>>,
     vis: Query<&ComputedVisibility>,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<SdfMaterial>>,
 ) {
     let lookup: HashMap<_, _> = atlas
@@ -98,62 +210,77 @@ fn update_sdf_render(
         .map(|(_ent, key, aabb)| ((key, aabb)))
         .collect();
 
-    for (ent, render) in q.iter() {
-        let Ok((sdf, maybe_mesh, g_trans)) = sdf.get(render.entity) else {continue};
-        let key = SdfAtlasKey::try_from_sdf(sdf, maybe_mesh).unwrap();
-
-        if let Some(&aabb) = lookup.get(&key) {
-            let min = aabb.min();
-            let max = aabb.max();
-            let mesh = shape::Box {
-                min_x: min.x,
-                max_x: max.x,
-                min_y: min.y,
-                max_y: max.y,
-                min_z: min.z,
-                max_z: max.z,
-            }
-            .into();
-            let mesh = meshes.add(mesh);
-            let atlas_info = atlas.page.get(&key).unwrap(); // we only add to the compute queue if we are in the atlas
-            println!(
-                "[{:?}] render: {} @ {}",
-                ent,
-                atlas_info.size - 1,
-                atlas_info.position
-            );
-
-            let material = SdfMaterial {
-                position: atlas_info.position.as_vec3() / atlas.page.dim.as_vec3(),
-                size: (atlas_info.size - 1).as_vec3() / atlas.page.dim.as_vec3(),
-                aabb_min: Vec3::from(min),
-                aabb_extents: Vec3::from(max - min),
-                base_color: render.base_color,
-                hit_color: render.hit_color,
-                step_color: render.step_color,
-                distance_color: render.distance_color,
-                min_step_size: render.min_step_size,
-                hit_threshold: render.hit_threshold,
-                max_step_count: render.max_step_count,
-                scale: g_trans.to_scale_rotation_translation().0.x,
-            };
-            let material = materials.add(material);
-
-            let computed_vis = vis.get(render.entity).cloned().unwrap_or_default();
-            commands.entity(ent).insert_bundle((
-                mesh,
-                material,
-                Transform::default(),
-                GlobalTransform::default(),
-                Visibility::default(),
-                computed_vis,
-            ));
+    for (ent, render, maybe_material) in q.iter() {
+        // the target is gone or no longer has an sdf
+        let Ok((sdf, maybe_mesh, g_trans)) = sdf.get(render.entity) else {
+            commands.entity(ent).despawn_recursive();
+            continue;
+        };
+        let Some(key) = atlas.key(render.entity, sdf, maybe_mesh) else { continue };
+        if atlas.is_bricks(&key) {
+            continue;
         }
-    }
 
-    for (mat_handle, g_trans) in changed_scale.iter() {
-        if let Some(mat) = materials.get_mut(mat_handle) {
-            mat.scale = g_trans.to_scale_rotation_translation().0.x;
+        // set up renders spawned after the entry was generated from its current volume
+        let aabb = match lookup.get(&key) {
+            Some(&aabb) => Some(aabb),
+            None if maybe_material.is_none() && atlas.slot(&key).is_some() => Some(&sdf.aabb),
+            None => None,
+        };
+        let Some(aabb) = aabb else {
+            if let (Ok(g_trans), Some(material)) = (changed_scale.get(render.entity), maybe_material) {
+                if let Some(material) = materials.get_mut(material) {
+                    material.scale = g_trans.to_scale_rotation_translation().0.x;
+                }
+            }
+            continue;
+        };
+
+        // entries are only queued if they are in the atlas, but may be purged if generation fails
+        let Some(atlas_info) = atlas.slot(&key) else { continue };
+        println!(
+            "[{:?}] render: {} @ {}",
+            ent,
+            atlas_info.size - 1,
+            atlas_info.position
+        );
+
+        let min = Vec3::from(aabb.min());
+        // flat meshes give flat aabbs, and the transform must stay invertible
+        let extents = Vec3::from(aabb.half_extents * 2.0).max(Vec3::splat(1e-6));
+        let material = SdfMaterial {
+            position: atlas_info.position.as_vec3() / atlas.dim().as_vec3(),
+            size: (atlas_info.size - 1).as_vec3() / atlas.dim().as_vec3(),
+            aabb_min: min,
+            aabb_extents: extents,
+            base_color: render.base_color,
+            hit_color: render.hit_color,
+            step_color: render.step_color,
+            distance_color: render.distance_color,
+            min_step_size: render.min_step_size,
+            hit_threshold: render.hit_threshold,
+            max_step_count: render.max_step_count,
+            scale: g_trans.to_scale_rotation_translation().0.x,
+        };
+        // the shared unit cube, stretched over the volume
+        let transform = Transform::from_translation(min + extents * 0.5).with_scale(extents);
+
+        // update the existing material, a new one each time the entry is regenerated would leak
+        // for as long as the render entity lives
+        if let Some(existing) = maybe_material.and_then(|handle| materials.get_mut(handle)) {
+            *existing = material;
+            commands.entity(ent).insert(transform);
+            continue;
         }
+
+        let computed_vis = vis.get(render.entity).cloned().unwrap_or_default();
+        commands.entity(ent).insert_bundle((
+            cube.0.clone(),
+            materials.add(material),
+            transform,
+            GlobalTransform::default(),
+            Visibility::default(),
+            computed_vis,
+        ));
     }
 }
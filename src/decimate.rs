@@ -0,0 +1,203 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{
+    math::{DMat4, DVec3, DVec4},
+    prelude::*,
+    utils::{FloatOrd, HashMap},
+};
+
+// symmetric 4x4 error quadric
+#[derive(Clone, Copy)]
+struct Quadric(DMat4);
+
+impl Quadric {
+    fn from_plane(plane: DVec4) -> Self {
+        Self(DMat4::from_cols(
+            plane * plane.x,
+            plane * plane.y,
+            plane * plane.z,
+            plane * plane.w,
+        ))
+    }
+
+    fn error(&self, point: DVec3) -> f64 {
+        let p = point.extend(1.0);
+        p.dot(self.0 * p).max(0.0)
+    }
+}
+
+impl std::ops::AddAssign for Quadric {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+struct Collapse {
+    cost: f64,
+    keep: usize,
+    remove: usize,
+    position: DVec3,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+impl Eq for Collapse {}
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        FloatOrd(self.cost as f32).cmp(&FloatOrd(other.cost as f32))
+    }
+}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn key(v: Vec3) -> [u32; 3] {
+    [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+}
+
+fn corner_normal(corners: [DVec3; 3]) -> DVec3 {
+    (corners[1] - corners[0])
+        .cross(corners[2] - corners[0])
+        .normalize_or_zero()
+}
+
+/// quadric error decimation of a triangle list. collapses edges in order of increasing error
+/// until at most `target_triangles` remain or the next collapse would move the surface by more
+/// than `max_error`.
+pub(crate) fn decimate(triangles: &[Vec3], target_triangles: usize, max_error: f32) -> Vec<Vec3> {
+    // weld coincident vertices
+    let mut lookup = HashMap::<[u32; 3], usize>::default();
+    let mut positions = Vec::<DVec3>::new();
+    let mut tris = Vec::<[usize; 3]>::new();
+    for tri in triangles.chunks_exact(3) {
+        let ix = [0, 1, 2].map(|i| {
+            *lookup.entry(key(tri[i])).or_insert_with(|| {
+                positions.push(tri[i].as_dvec3());
+                positions.len() - 1
+            })
+        });
+        if ix[0] != ix[1] && ix[1] != ix[2] && ix[0] != ix[2] {
+            tris.push(ix);
+        }
+    }
+
+    let mut quadrics = vec![Quadric(DMat4::ZERO); positions.len()];
+    let mut vertex_tris = vec![Vec::<usize>::new(); positions.len()];
+    for (tri_ix, tri) in tris.iter().enumerate() {
+        let n = corner_normal(tri.map(|i| positions[i]));
+        let q = Quadric::from_plane(n.extend(-n.dot(positions[tri[0]])));
+        for &v in tri {
+            quadrics[v] += q;
+            vertex_tris[v].push(tri_ix);
+        }
+    }
+
+    let mut versions = vec![0u32; positions.len()];
+    let mut alive_tris = vec![true; tris.len()];
+    let mut tri_count = tris.len();
+    let max_error_sq = (max_error as f64) * (max_error as f64);
+
+    let candidate = |positions: &[DVec3], quadrics: &[Quadric], versions: &[u32], a: usize, b: usize| {
+        let mut q = quadrics[a];
+        q += quadrics[b];
+        let (cost, position) = [positions[a], positions[b], (positions[a] + positions[b]) * 0.5]
+            .into_iter()
+            .map(|p| (q.error(p), p))
+            .min_by_key(|(cost, _)| FloatOrd(*cost as f32))
+            .unwrap();
+        Reverse(Collapse {
+            cost,
+            keep: a,
+            remove: b,
+            position,
+            versions: (versions[a], versions[b]),
+        })
+    };
+
+    let mut heap = BinaryHeap::new();
+    for tri in tris.iter() {
+        for (a, b) in [(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+            if a < b {
+                heap.push(candidate(&positions, &quadrics, &versions, a, b));
+            }
+        }
+    }
+
+    while tri_count > target_triangles {
+        let Some(Reverse(collapse)) = heap.pop() else { break };
+        if collapse.cost > max_error_sq {
+            break;
+        }
+        let (keep, remove) = (collapse.keep, collapse.remove);
+        if versions[keep] != collapse.versions.0 || versions[remove] != collapse.versions.1 {
+            // stale entry
+            continue;
+        }
+
+        // reject collapses which would flip a remaining triangle
+        let flips = [keep, remove].iter().any(|&v| {
+            vertex_tris[v].iter().any(|&t| {
+                if !alive_tris[t] || (tris[t].contains(&keep) && tris[t].contains(&remove)) {
+                    return false;
+                }
+                let corners = tris[t].map(|i| positions[i]);
+                let moved = tris[t].map(|i| match i == keep || i == remove {
+                    true => collapse.position,
+                    false => positions[i],
+                });
+                corner_normal(corners).dot(corner_normal(moved)) < 0.2
+            })
+        });
+        if flips {
+            continue;
+        }
+
+        positions[keep] = collapse.position;
+        let q = quadrics[remove];
+        quadrics[keep] += q;
+        versions[keep] += 1;
+        versions[remove] += 1;
+
+        let moved_tris = std::mem::take(&mut vertex_tris[remove]);
+        for t in moved_tris {
+            if !alive_tris[t] {
+                continue;
+            }
+            if tris[t].contains(&keep) {
+                alive_tris[t] = false;
+                tri_count -= 1;
+                continue;
+            }
+            for v in tris[t].iter_mut() {
+                if *v == remove {
+                    *v = keep;
+                }
+            }
+            vertex_tris[keep].push(t);
+        }
+        vertex_tris[keep].retain(|&t| alive_tris[t]);
+
+        let mut neighbours = vertex_tris[keep]
+            .iter()
+            .flat_map(|&t| tris[t])
+            .filter(|&v| v != keep)
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for n in neighbours {
+            heap.push(candidate(&positions, &quadrics, &versions, keep, n));
+        }
+    }
+
+    tris.iter()
+        .zip(alive_tris.iter())
+        .filter(|(_, alive)| **alive)
+        .flat_map(|(tri, _)| tri.map(|i| positions[i].as_vec3()))
+        .collect()
+}
@@ -0,0 +1,331 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+    render::{
+        render_graph::{self, RenderGraph},
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, Maintain, MapMode, WgpuFeatures},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage,
+    },
+};
+use wgpu::{QuerySet, QuerySetDescriptor, QueryType};
+
+use crate::{
+    compute::{SdfComputeFrame, SdfComputeGraphConfig, SdfData},
+    SdfAtlas,
+};
+
+// timings in flight at once, reads usually lag a couple of frames behind the dispatch
+const TIMER_SLOTS: usize = 4;
+// two u64 timestamps per slot
+const TIMER_SLOT_SIZE: u64 = 16;
+
+/// opt-in diagnostics for sdf generation: gpu time spent in the sdf compute node, the compute
+/// blocks dispatched each frame, and how full and fragmented the atlas is. view them with e.g.
+/// `LogDiagnosticsPlugin`.
+///
+/// the gpu time needs timestamp queries, request them when adding the default plugins with
+/// `WgpuSettings { features: WgpuFeatures::TIMESTAMP_QUERY, ..Default::default() }`. without
+/// them only the block counts are recorded. add after `SdfPlugin`.
+pub struct SdfDiagnosticsPlugin;
+
+impl SdfDiagnosticsPlugin {
+    /// gpu milliseconds spent in the sdf compute node (copies, generation and mip downsampling)
+    pub const COMPUTE_TIME: DiagnosticId =
+        DiagnosticId::from_u128(271935508519357301652840918730412906881);
+    /// compute blocks (of `WORKGROUP_SIZE` voxels per axis) dispatched for generation
+    pub const BLOCK_COUNT: DiagnosticId =
+        DiagnosticId::from_u128(97207135426359212407624117935651838540);
+    /// percentage of the atlas page covered by entries
+    pub const ATLAS_OCCUPANCY: DiagnosticId =
+        DiagnosticId::from_u128(180467703614125468120347796420551391093);
+    /// percentage of the atlas free space outside its largest free cube, see `SdfAtlasStats`
+    pub const ATLAS_FRAGMENTATION: DiagnosticId =
+        DiagnosticId::from_u128(41290612254788213306953384719660223719);
+    /// resident atlas entries
+    pub const ATLAS_ENTRIES: DiagnosticId =
+        DiagnosticId::from_u128(256023975049398113842806237081740521202);
+}
+
+impl Plugin for SdfDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let results = SdfTimingResults::default();
+
+        app.insert_resource(results.clone())
+            .add_startup_system(setup_diagnostics)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                measure_blocks.after("preprocess sdfs"),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, measure_atlas.after("preprocess sdfs"))
+            .add_system_to_stage(CoreStage::PreUpdate, publish_timings);
+
+        let graph_config = app.world.resource::<SdfComputeGraphConfig>().clone();
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(results)
+            .init_resource::<SdfGpuTimer>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_timer)
+            .add_system_to_stage(RenderStage::Cleanup, map_timer);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        // the compute plugin warns if the graph is missing
+        let Some(graph) = graph_config.graph(&mut render_graph) else { return };
+        graph.add_node("sdf_timer_begin", SdfTimestampNode { end: false });
+        graph.add_node("sdf_timer_end", SdfTimestampNode { end: true });
+        graph
+            .add_node_edge("sdf_timer_begin", "sdf_compute")
+            .unwrap();
+        graph
+            .add_node_edge("sdf_compute", "sdf_timer_end")
+            .unwrap();
+        graph_config.add_run_before(graph, "sdf_timer_end");
+    }
+}
+
+fn setup_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(
+        Diagnostic::new(SdfDiagnosticsPlugin::COMPUTE_TIME, "sdf_compute_time", 20)
+            .with_suffix("ms"),
+    );
+    diagnostics.add(Diagnostic::new(
+        SdfDiagnosticsPlugin::BLOCK_COUNT,
+        "sdf_block_count",
+        20,
+    ));
+    diagnostics.add(
+        Diagnostic::new(SdfDiagnosticsPlugin::ATLAS_OCCUPANCY, "sdf_atlas_occupancy", 20)
+            .with_suffix("%"),
+    );
+    diagnostics.add(
+        Diagnostic::new(SdfDiagnosticsPlugin::ATLAS_FRAGMENTATION, "sdf_atlas_fragmentation", 20)
+            .with_suffix("%"),
+    );
+    diagnostics.add(Diagnostic::new(
+        SdfDiagnosticsPlugin::ATLAS_ENTRIES,
+        "sdf_atlas_entries",
+        20,
+    ));
+}
+
+fn measure_blocks(mut diagnostics: ResMut<Diagnostics>, sdf_data: Res<SdfData>) {
+    diagnostics.add_measurement(SdfDiagnosticsPlugin::BLOCK_COUNT, || {
+        sdf_data.block_count as f64
+    });
+}
+
+// after preprocessing, which may release entries that failed
+fn measure_atlas(mut diagnostics: ResMut<Diagnostics>, atlas: Res<SdfAtlas>) {
+    let stats = atlas.stats();
+    diagnostics.add_measurement(SdfDiagnosticsPlugin::ATLAS_OCCUPANCY, || {
+        stats.occupancy as f64 * 100.0
+    });
+    diagnostics.add_measurement(SdfDiagnosticsPlugin::ATLAS_FRAGMENTATION, || {
+        stats.fragmentation as f64 * 100.0
+    });
+    diagnostics.add_measurement(SdfDiagnosticsPlugin::ATLAS_ENTRIES, || {
+        stats.entry_count as f64
+    });
+}
+
+// gpu milliseconds read back in the render world, waiting to be added to the diagnostics
+#[derive(Clone, Default)]
+struct SdfTimingResults(Arc<Mutex<Vec<f64>>>);
+
+fn publish_timings(mut diagnostics: ResMut<Diagnostics>, results: Res<SdfTimingResults>) {
+    for ms in results.0.lock().unwrap().drain(..) {
+        diagnostics.add_measurement(SdfDiagnosticsPlugin::COMPUTE_TIME, || ms);
+    }
+}
+
+enum TimerState {
+    Free,
+    // timestamps written by the nodes this frame
+    Recording,
+    // resolve submitted, waiting for the buffer to map
+    Mapping(Arc<AtomicBool>),
+}
+
+struct TimerSlot {
+    resolve: Buffer,
+    staging: Buffer,
+    state: TimerState,
+}
+
+struct SdfGpuTimer {
+    // none when the device doesn't support timestamp queries
+    query_set: Option<QuerySet>,
+    slots: Vec<TimerSlot>,
+    // slot the nodes write to this frame
+    current: Option<usize>,
+    // the end timestamp has been written this frame
+    ended: AtomicBool,
+}
+
+impl FromWorld for SdfGpuTimer {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        if !render_device
+            .features()
+            .contains(WgpuFeatures::TIMESTAMP_QUERY)
+        {
+            warn!("timestamp queries not enabled, sdf compute time won't be measured. request `WgpuFeatures::TIMESTAMP_QUERY` in the `WgpuSettings`");
+            return Self {
+                query_set: None,
+                slots: Vec::new(),
+                current: None,
+                ended: AtomicBool::new(false),
+            };
+        }
+
+        let query_set = render_device
+            .wgpu_device()
+            .create_query_set(&QuerySetDescriptor {
+                label: Some("sdf compute timestamps"),
+                ty: QueryType::Timestamp,
+                count: TIMER_SLOTS as u32 * 2,
+            });
+
+        let slots = (0..TIMER_SLOTS)
+            .map(|_| TimerSlot {
+                resolve: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("sdf timestamp resolve"),
+                    size: TIMER_SLOT_SIZE,
+                    usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                staging: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("sdf timestamp staging"),
+                    size: TIMER_SLOT_SIZE,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: TimerState::Free,
+            })
+            .collect();
+
+        Self {
+            query_set: Some(query_set),
+            slots,
+            current: None,
+            ended: AtomicBool::new(false),
+        }
+    }
+}
+
+fn prepare_timer(mut timer: ResMut<SdfGpuTimer>) {
+    if timer.query_set.is_none() {
+        return;
+    }
+    *timer.ended.get_mut() = false;
+
+    // if every slot is still mapping this frame goes unmeasured
+    timer.current = timer
+        .slots
+        .iter()
+        .position(|slot| matches!(slot.state, TimerState::Free));
+    if let Some(index) = timer.current {
+        timer.slots[index].state = TimerState::Recording;
+    }
+}
+
+struct SdfTimestampNode {
+    end: bool,
+}
+
+impl render_graph::Node for SdfTimestampNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let timer = world.resource::<SdfGpuTimer>();
+        let (Some(query_set), Some(index)) = (timer.query_set.as_ref(), timer.current) else {
+            return Ok(());
+        };
+
+        // the nodes run once per 3d camera, but the compute node only works on its first run.
+        // time that one: begin before it has run, end just after
+        let ran = world.resource::<SdfComputeFrame>().has_run();
+        let first_query = index as u32 * 2;
+        let encoder = &mut render_context.command_encoder;
+        if !self.end {
+            if !ran {
+                encoder.write_timestamp(query_set, first_query);
+            }
+            return Ok(());
+        }
+        if !ran || timer.ended.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let slot = &timer.slots[index];
+        encoder.write_timestamp(query_set, first_query + 1);
+        encoder.resolve_query_set(query_set, first_query..first_query + 2, &slot.resolve, 0);
+        encoder.copy_buffer_to_buffer(&slot.resolve, 0, &slot.staging, 0, TIMER_SLOT_SIZE);
+        Ok(())
+    }
+}
+
+fn map_timer(
+    mut timer: ResMut<SdfGpuTimer>,
+    results: Res<SdfTimingResults>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if timer.query_set.is_none() {
+        return;
+    }
+    timer.current = None;
+
+    // the nodes have been submitted by now
+    for slot in timer.slots.iter_mut() {
+        if matches!(slot.state, TimerState::Recording) {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let flag = mapped.clone();
+            slot.staging.slice(..).map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    flag.store(true, Ordering::Release);
+                }
+            });
+            slot.state = TimerState::Mapping(mapped);
+        }
+    }
+
+    render_device.wgpu_device().poll(Maintain::Poll);
+
+    // ns per timestamp tick
+    let period = render_queue.get_timestamp_period() as f64;
+    for slot in timer.slots.iter_mut() {
+        let TimerState::Mapping(ref mapped) = slot.state else { continue };
+        if !mapped.load(Ordering::Acquire) {
+            continue;
+        }
+
+        let (begin, end) = {
+            let bytes = slot.staging.slice(..).get_mapped_range();
+            let (begin, end) = bytes.split_at(8);
+            (
+                u64::from_le_bytes(begin.try_into().unwrap()),
+                u64::from_le_bytes(end.try_into().unwrap()),
+            )
+        };
+        slot.staging.unmap();
+
+        // zeroed so a frame where the nodes didn't run (no 3d camera) reads as empty rather than
+        // repeating the last sample
+        render_queue.write_buffer(&slot.staging, 0, &[0; TIMER_SLOT_SIZE as usize]);
+
+        if end > begin {
+            let ms = (end - begin) as f64 * period / 1_000_000.0;
+            results.0.lock().unwrap().push(ms);
+        }
+        slot.state = TimerState::Free;
+    }
+}
@@ -0,0 +1,90 @@
+struct InstanceData {
+    write_position: vec3<u32>,
+    aabb_min: vec3<f32>,
+    scale: vec3<f32>,
+    block_dimensions: vec3<u32>,
+    counts: vec3<u32>,
+    block_count: u32,
+    flags: u32,
+    weld_margin: f32,
+    samples: u32,
+    min_thickness: f32,
+    skin_sources_offset: u32,
+    skin_vertex_offset: u32,
+    joint_offset: u32,
+    bin_offset: u32,
+    block_list_offset: u32,
+    bvh_offset: u32,
+    brick_offset: u32,
+    partial_offset: u32,
+    group: u32,
+    block_start: u32,
+    feature_start: vec3<u32>,
+};
+
+struct Instances {
+    instance_count: u32,
+    block_count: u32,
+    data: array<InstanceData>,
+};
+
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+};
+
+// one set per group of instances sharing a calc pipeline
+struct DispatchArgsList {
+    data: array<DispatchArgs>,
+};
+
+let INSTANCE_FLAG_END: u32 = 8u;
+
+// workgroups per dispatch dimension, must match compute.rs
+let MAX_WORKGROUPS: u32 = 65535u;
+
+@group(0) @binding(0)
+var<storage, read_write> instances: Instances;
+@group(0) @binding(1)
+var<storage, read_write> args: DispatchArgsList;
+
+// size a group's calc dispatch
+fn write_args(group: u32, block_count: u32) {
+    // blocks are spread over y when there are too many for one dimension
+    let x = min(block_count, MAX_WORKGROUPS);
+    args.data[group].x = x;
+    args.data[group].y = select(0u, (block_count + x - 1u) / max(x, 1u), x > 0u);
+    args.data[group].z = 1u;
+}
+
+// prefix sum the instances' blocks so calc can find its instance directly, and size
+// the calc dispatch of each group. the instance list is short so a single thread is enough
+@compute
+@workgroup_size(1, 1, 1)
+fn prepare() {
+    var block_start = 0u;
+    var group_block_start = 0u;
+    var index = 0u;
+    loop {
+        let instance = instances.data[index];
+        // the end marker gets the total, which bounds the blocks of the last group. feature starts
+        // are set on the cpu, as the features are split into batches
+        instances.data[index].block_start = block_start;
+
+        // groups are contiguous and the end marker has a group of its own
+        if (index > 0u && instance.group != instances.data[index - 1u].group) {
+            write_args(instances.data[index - 1u].group, block_start - group_block_start);
+            group_block_start = block_start;
+        }
+
+        if ((instance.flags & INSTANCE_FLAG_END) != 0u) {
+            break;
+        }
+        block_start = block_start + instance.block_count;
+        index = index + 1u;
+    }
+
+    instances.instance_count = index;
+    instances.block_count = block_start;
+}
@@ -0,0 +1,215 @@
+use bevy::{math::Vec3A, prelude::*, tasks::ComputeTaskPool, utils::HashMap};
+
+use crate::{
+    queue_sdfs,
+    readback::{SdfGrid, SdfReadback, SdfReadbackReady},
+    Sdf, SdfStatus,
+};
+
+/// a low resolution world space grid of the distance to the nearest static sdf geometry, for
+/// systems making thousands of cheap queries a frame (ai spacing, procedural placement, crowds).
+/// each static entity's sdf is read back from the atlas once it's generated, and the grid is
+/// rebuilt from them on the frame after any of them change, move or are removed. lookups are
+/// constant time. add after `SdfPlugin` and `SdfReadbackPlugin`.
+pub struct SdfDistanceGridPlugin;
+
+impl Plugin for SdfDistanceGridPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SdfDistanceGridSettings>()
+            .init_resource::<SdfDistanceGrid>()
+            .init_resource::<SdfDistanceGridSources>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                request_grid_readbacks.after(queue_sdfs),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, collect_grid_readbacks)
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                rebuild_distance_grid.after(collect_grid_readbacks),
+            );
+    }
+}
+
+/// the volume covered by the `SdfDistanceGrid`. the grid is rebuilt when this changes
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
+pub struct SdfDistanceGridSettings {
+    // world space corner of the first cell
+    pub min: Vec3,
+    pub dimensions: UVec3,
+    pub cell_size: f32,
+    // distances are clamped to this, and returned outside the grid
+    pub max_distance: f32,
+}
+
+impl Default for SdfDistanceGridSettings {
+    fn default() -> Self {
+        Self {
+            min: Vec3::splat(-64.0),
+            dimensions: UVec3::splat(64),
+            cell_size: 2.0,
+            max_distance: 8.0,
+        }
+    }
+}
+
+/// distance to the nearest static sdf geometry at the center of each cell of the volume set by
+/// `SdfDistanceGridSettings`
+#[derive(Clone, Debug, Default)]
+pub struct SdfDistanceGrid {
+    // world space center of the first cell
+    pub origin: Vec3,
+    pub cell_size: f32,
+    pub dimensions: UVec3,
+    pub max_distance: f32,
+    // incremented each time the grid is rebuilt
+    pub generation: u64,
+    // x fastest, then y then z
+    distances: Vec<f32>,
+}
+
+impl SdfDistanceGrid {
+    /// signed distance stored for the cell containing the world space point, `max_distance`
+    /// outside the grid
+    pub fn distance(&self, point: Vec3) -> f32 {
+        match self.cell(point) {
+            Some(cell) => self.cell_distance(cell),
+            None => self.max_distance,
+        }
+    }
+
+    /// the cell containing the world space point, if it's inside the grid
+    pub fn cell(&self, point: Vec3) -> Option<UVec3> {
+        let local = ((point - self.origin) / self.cell_size + 0.5).floor();
+        if local.cmplt(Vec3::ZERO).any() || local.cmpge(self.dimensions.as_vec3()).any() {
+            return None;
+        }
+        Some(local.as_uvec3())
+    }
+
+    /// distance stored for the cell (clamped to the grid)
+    pub fn cell_distance(&self, cell: UVec3) -> f32 {
+        if self.distances.is_empty() {
+            return self.max_distance;
+        }
+        let cell = cell.min(self.dimensions - 1);
+        let d = self.dimensions;
+        self.distances[(cell.x + cell.y * d.x + cell.z * d.x * d.y) as usize]
+    }
+}
+
+// the read back sdfs of the static entities, and whether the grid needs rebuilding
+#[derive(Default)]
+struct SdfDistanceGridSources {
+    grids: HashMap<Entity, SdfGrid>,
+    dirty: bool,
+}
+
+// read back static entities whenever their sdf is (re)generated
+#[allow(clippy::type_complexity)]
+fn request_grid_readbacks(
+    mut commands: Commands,
+    sdfs: Query<(Entity, &Sdf, &SdfStatus), Or<(Changed<Sdf>, Changed<SdfStatus>)>>,
+) {
+    for (ent, sdf, status) in sdfs.iter() {
+        if !sdf.skinned && matches!(status, SdfStatus::Full | SdfStatus::Reduced) {
+            commands.entity(ent).insert(SdfReadback);
+        }
+    }
+}
+
+fn collect_grid_readbacks(
+    mut events: EventReader<SdfReadbackReady>,
+    sdfs: Query<&Sdf>,
+    moved: Query<(), (With<Sdf>, Changed<GlobalTransform>)>,
+    mut sources: ResMut<SdfDistanceGridSources>,
+) {
+    for event in events.iter() {
+        if sdfs.get(event.entity).map_or(false, |sdf| !sdf.skinned) {
+            sources.grids.insert(event.entity, event.grid.clone());
+            sources.dirty = true;
+        }
+    }
+
+    let count = sources.grids.len();
+    sources
+        .grids
+        .retain(|ent, _| sdfs.get(*ent).map_or(false, |sdf| !sdf.skinned));
+    if sources.grids.len() != count || sources.grids.keys().any(|ent| moved.get(*ent).is_ok()) {
+        sources.dirty = true;
+    }
+}
+
+fn rebuild_distance_grid(
+    settings: Res<SdfDistanceGridSettings>,
+    transforms: Query<&GlobalTransform>,
+    mut sources: ResMut<SdfDistanceGridSources>,
+    mut grid: ResMut<SdfDistanceGrid>,
+) {
+    if !sources.dirty && !settings.is_changed() {
+        return;
+    }
+    sources.dirty = false;
+
+    // entity space, with the world to entity transform and scale
+    let items = sources
+        .grids
+        .iter()
+        .filter_map(|(ent, sdf_grid)| {
+            let transform = transforms.get(*ent).ok()?;
+            let scale = transform.to_scale_rotation_translation().0.x;
+            Some((transform.affine().inverse(), scale, sdf_grid))
+        })
+        .collect::<Vec<_>>();
+
+    let dimensions = settings.dimensions.max(UVec3::ONE);
+    let cell_size = settings.cell_size;
+    let origin = settings.min + cell_size * 0.5;
+    let max_distance = settings.max_distance;
+    let cell_count = (dimensions.x * dimensions.y * dimensions.z) as usize;
+
+    let cell_distance = |index: usize| {
+        let index = index as u32;
+        let cell = UVec3::new(
+            index % dimensions.x,
+            (index / dimensions.x) % dimensions.y,
+            index / (dimensions.x * dimensions.y),
+        );
+        let point = origin + cell.as_vec3() * cell_size;
+        items
+            .iter()
+            .fold(max_distance, |best, (world_to_local, scale, sdf_grid)| {
+                let local = Vec3A::from(world_to_local.transform_point3(point));
+                // outside the entity's volume, add the distance to its boundary
+                let nearest = local.clamp(sdf_grid.aabb.min(), sdf_grid.aabb.max());
+                let outside = nearest.distance(local);
+                if outside * scale >= best {
+                    return best;
+                }
+                best.min((sdf_grid.sample(local) + outside) * scale)
+            })
+    };
+
+    let indices = (0..cell_count).collect::<Vec<_>>();
+    let chunk_size = (cell_count / ComputeTaskPool::get().thread_num()).max(64);
+    let distances = ComputeTaskPool::get()
+        .scope(|s| {
+            for chunk in indices.chunks(chunk_size) {
+                let cell_distance = &cell_distance;
+                s.spawn(async move { chunk.iter().map(|i| cell_distance(*i)).collect::<Vec<_>>() });
+            }
+        })
+        .into_iter()
+        .flatten()
+        .collect();
+
+    *grid = SdfDistanceGrid {
+        origin,
+        cell_size,
+        dimensions,
+        max_distance,
+        generation: grid.generation + 1,
+        distances,
+    };
+}
@@ -0,0 +1,87 @@
+//! c-compatible entry point to the cpu generator, for content pipelines outside of rust.
+//!
+//! build a shared library with `cargo rustc --release --features ffi --crate-type cdylib`, and
+//! declare:
+//!
+//! ```c
+//! int32_t mesh2sdf_generate(
+//!     const float *vertices, size_t vertex_count,
+//!     const uint32_t *indices, size_t index_count,
+//!     const uint32_t *dims, float padding,
+//!     float *out_ptr);
+//! ```
+use bevy::prelude::*;
+
+use crate::{cpu::create_sdf_from_triangles_cpu, SdfOptions};
+
+pub const MESH2SDF_OK: i32 = 0;
+pub const MESH2SDF_NULL_POINTER: i32 = -1;
+pub const MESH2SDF_BAD_INDICES: i32 = -2;
+pub const MESH2SDF_BAD_DIMS: i32 = -3;
+pub const MESH2SDF_PANIC: i32 = -4;
+pub const MESH2SDF_BAD_VERTEX_COUNT: i32 = -5;
+
+/// generate a signed distance volume from an indexed triangle list, with the default
+/// `SdfOptions` (so the same sign conventions as `Sdf::default()`).
+///
+/// - `vertices`: `vertex_count` xyz positions, `3 * vertex_count` floats. a count whose float
+///   count overflows `size_t` returns `MESH2SDF_BAD_VERTEX_COUNT`
+/// - `indices`: `index_count` vertex indices, three per triangle
+/// - `dims`: the x, y and z voxel counts, each at least 2
+/// - `padding`: distance added around the mesh bounds on every side
+/// - `out_ptr`: space for `dims[0] * dims[1] * dims[2]` floats, written x-fastest then y then z
+///
+/// the volume spans the mesh bounds expanded by `padding`, with voxels on both corners, so voxel
+/// `(x, y, z)` is at `min + (max - min) * (x, y, z) / (dims - 1)`. distances are negative inside.
+///
+/// returns `MESH2SDF_OK`, or one of the negative error codes with `out_ptr` untouched.
+///
+/// # Safety
+/// every pointer must be valid for the number of elements described above.
+#[no_mangle]
+pub unsafe extern "C" fn mesh2sdf_generate(
+    vertices: *const f32,
+    vertex_count: usize,
+    indices: *const u32,
+    index_count: usize,
+    dims: *const u32,
+    padding: f32,
+    out_ptr: *mut f32,
+) -> i32 {
+    if vertices.is_null() || indices.is_null() || dims.is_null() || out_ptr.is_null() {
+        return MESH2SDF_NULL_POINTER;
+    }
+
+    let Some(float_count) = vertex_count.checked_mul(3) else {
+        return MESH2SDF_BAD_VERTEX_COUNT;
+    };
+    let vertices = std::slice::from_raw_parts(vertices, float_count);
+    let indices = std::slice::from_raw_parts(indices, index_count);
+    let dims = UVec3::from_slice(std::slice::from_raw_parts(dims, 3));
+
+    if indices.is_empty()
+        || indices.len() % 3 != 0
+        || indices.iter().any(|&ix| ix as usize >= vertex_count)
+    {
+        return MESH2SDF_BAD_INDICES;
+    }
+    if dims.cmplt(UVec3::splat(2)).any() {
+        return MESH2SDF_BAD_DIMS;
+    }
+
+    let positions = vertices
+        .chunks_exact(3)
+        .map(|v| [v[0], v[1], v[2]])
+        .collect::<Vec<_>>();
+
+    // don't unwind across the ffi boundary
+    let result = std::panic::catch_unwind(|| {
+        create_sdf_from_triangles_cpu(positions, indices.to_vec(), dims, padding, &SdfOptions::default())
+    });
+    let Ok((_, values)) = result else {
+        return MESH2SDF_PANIC;
+    };
+
+    std::ptr::copy_nonoverlapping(values.as_ptr(), out_ptr, values.len());
+    MESH2SDF_OK
+}
@@ -0,0 +1,263 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::ComputeTaskPool,
+};
+
+use crate::query::SdfQuery;
+
+const OFFSETS: [IVec3; 3] = [IVec3::X, IVec3::Y, IVec3::Z];
+
+/// settings for building an `SdfFlowField`
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
+pub struct SdfFlowSettings {
+    // the undisturbed flow away from any geometry (e.g. the wind direction and speed)
+    pub wind: Vec3,
+    // distance from a surface within which the flow is bent along it
+    pub influence: f32,
+    // pressure relaxation passes used to reduce the divergence of the bent flow. more passes
+    // spread the deflection further upstream and speed the flow up around the sides of obstacles
+    pub iterations: u32,
+}
+
+impl Default for SdfFlowSettings {
+    fn default() -> Self {
+        Self {
+            wind: Vec3::X,
+            influence: 2.0,
+            iterations: 20,
+        }
+    }
+}
+
+/// a grid of flow velocities that pass around the sdf geometry, so particles, debris and foliage
+/// driven by it move around obstacles rather than through them. built on the cpu from `SdfQuery`,
+/// so only static sdf entities deflect the flow. sample with `velocity`, or upload with `to_image`
+/// for use in shaders.
+///
+/// example usage:
+///
+/// fn build_wind(mut commands: Commands, query: SdfQuery) {
+///     let settings = SdfFlowSettings { wind: Vec3::new(3.0, 0.0, 1.0), ..Default::default() };
+///     let field = SdfFlowField::build(&query, Vec3::splat(-32.0), UVec3::splat(64), 1.0, &settings);
+///     commands.insert_resource(field);
+/// }
+///
+#[derive(Clone, Debug)]
+pub struct SdfFlowField {
+    // world space center of the first cell
+    pub origin: Vec3,
+    pub cell_size: f32,
+    pub dimensions: UVec3,
+    // x fastest, then y then z
+    velocities: Vec<Vec3>,
+    // returned outside the grid
+    wind: Vec3,
+}
+
+impl SdfFlowField {
+    /// build the field over the world space box starting at `min`, with `dimensions` cells of
+    /// `cell_size` on each axis
+    pub fn build(
+        query: &SdfQuery,
+        min: Vec3,
+        dimensions: UVec3,
+        cell_size: f32,
+        settings: &SdfFlowSettings,
+    ) -> Self {
+        let dimensions = dimensions.max(UVec3::splat(2));
+        let mut field = Self {
+            origin: min + cell_size * 0.5,
+            cell_size,
+            dimensions,
+            velocities: vec![settings.wind; (dimensions.x * dimensions.y * dimensions.z) as usize],
+            wind: settings.wind,
+        };
+
+        let positions = (0..field.velocities.len())
+            .map(|index| field.origin + field.cell(index).as_vec3() * cell_size)
+            .collect::<Vec<_>>();
+        let max_distance = settings.influence + cell_size;
+        let chunk_size = (positions.len() / ComputeTaskPool::get().thread_num()).max(64);
+        let distances = ComputeTaskPool::get()
+            .scope(|s| {
+                for chunk in positions.chunks(chunk_size) {
+                    s.spawn(async move {
+                        chunk
+                            .iter()
+                            .map(|p| query.distance(*p, max_distance))
+                            .collect::<Vec<_>>()
+                    });
+                }
+            })
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        // remove the part of the wind heading into nearby surfaces, fading out over the influence
+        // distance, and stop it inside geometry
+        let influence = settings.influence.max(1e-5);
+        for index in 0..field.velocities.len() {
+            let distance = distances[index];
+            if distance <= 0.0 {
+                field.velocities[index] = Vec3::ZERO;
+                continue;
+            }
+            if distance >= influence {
+                continue;
+            }
+
+            let normal = field.gradient(&distances, field.cell(index)).normalize_or_zero();
+            let t = distance / influence;
+            let falloff = 1.0 - t * t * (3.0 - 2.0 * t);
+            let inward = settings.wind.dot(normal).min(0.0);
+            field.velocities[index] = settings.wind - normal * inward * falloff;
+        }
+
+        field.relax(&distances, settings.iterations);
+        field
+    }
+
+    /// flow velocity at the world space point, trilinearly interpolated. the undisturbed wind
+    /// outside the grid
+    pub fn velocity(&self, point: Vec3) -> Vec3 {
+        let local = (point - self.origin) / self.cell_size;
+        let max = (self.dimensions - 1).as_vec3();
+        if local.cmplt(Vec3::splat(-0.5)).any() || local.cmpgt(max + 0.5).any() {
+            return self.wind;
+        }
+
+        let local = local.clamp(Vec3::ZERO, max);
+        let base = local.floor().as_uvec3().min(self.dimensions - 2);
+        let f = local - base.as_vec3();
+        (0..8u32).fold(Vec3::ZERO, |velocity, corner| {
+            let offset = UVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+            let w = Vec3::select(offset.cmpeq(UVec3::ONE), f, 1.0 - f);
+            velocity + self.velocities[self.index(base + offset)] * w.x * w.y * w.z
+        })
+    }
+
+    /// the velocities as an `Rgba32Float` 3d image, one texel per cell with the velocity in rgb.
+    /// texel centers match the cell centers, so sample at
+    /// `((point - origin) / cell_size + 0.5) / dimensions`
+    pub fn to_image(&self) -> Image {
+        let data = self
+            .velocities
+            .iter()
+            .flat_map(|v| v.extend(0.0).to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        Image::new(
+            Extent3d {
+                width: self.dimensions.x,
+                height: self.dimensions.y,
+                depth_or_array_layers: self.dimensions.z,
+            },
+            TextureDimension::D3,
+            data,
+            TextureFormat::Rgba32Float,
+        )
+    }
+
+    fn cell(&self, index: usize) -> UVec3 {
+        let index = index as u32;
+        let d = self.dimensions;
+        UVec3::new(index % d.x, (index / d.x) % d.y, index / (d.x * d.y))
+    }
+
+    fn index(&self, cell: UVec3) -> usize {
+        let d = self.dimensions;
+        (cell.x + cell.y * d.x + cell.z * d.x * d.y) as usize
+    }
+
+    // the neighbouring cell, or none past the edge of the grid
+    fn neighbour(&self, cell: UVec3, offset: IVec3) -> Option<usize> {
+        let neighbour = cell.as_ivec3() + offset;
+        if neighbour.cmplt(IVec3::ZERO).any() || neighbour.cmpge(self.dimensions.as_ivec3()).any() {
+            return None;
+        }
+        Some(self.index(neighbour.as_uvec3()))
+    }
+
+    // central differences, one sided at the edges of the grid
+    fn gradient(&self, values: &[f32], cell: UVec3) -> Vec3 {
+        let center = values[self.index(cell)];
+        let axis = |offset: IVec3| {
+            let high = self.neighbour(cell, offset).map_or(center, |i| values[i]);
+            let low = self.neighbour(cell, -offset).map_or(center, |i| values[i]);
+            high - low
+        };
+        Vec3::new(axis(IVec3::X), axis(IVec3::Y), axis(IVec3::Z))
+    }
+
+    // jacobi iterations of a pressure projection over the open cells. solid cells keep zero
+    // velocity and mirror the pressure of their neighbours, the grid edges are left open
+    fn relax(&mut self, distances: &[f32], iterations: u32) {
+        if iterations == 0 {
+            return;
+        }
+
+        let h = self.cell_size;
+        let len = self.velocities.len();
+        let solid = |index: usize| distances[index] <= 0.0;
+
+        let divergence = (0..len)
+            .map(|index| {
+                if solid(index) {
+                    return 0.0;
+                }
+                let cell = self.cell(index);
+                OFFSETS
+                    .iter()
+                    .enumerate()
+                    .map(|(axis, offset)| {
+                        let velocity = |i: Option<usize>| self.velocities[i.unwrap_or(index)][axis];
+                        velocity(self.neighbour(cell, *offset)) - velocity(self.neighbour(cell, -*offset))
+                    })
+                    .sum::<f32>()
+                    / (2.0 * h)
+            })
+            .collect::<Vec<_>>();
+
+        let mut pressure = vec![0.0; len];
+        let mut next = vec![0.0; len];
+        for _ in 0..iterations {
+            for index in 0..len {
+                if solid(index) {
+                    continue;
+                }
+                let cell = self.cell(index);
+                let neighbours = OFFSETS
+                    .iter()
+                    .flat_map(|offset| [*offset, -*offset])
+                    .map(|offset| match self.neighbour(cell, offset) {
+                        Some(i) if solid(i) => pressure[index],
+                        Some(i) => pressure[i],
+                        None => 0.0,
+                    })
+                    .sum::<f32>();
+                next[index] = (neighbours - h * h * divergence[index]) / 6.0;
+            }
+            std::mem::swap(&mut pressure, &mut next);
+        }
+
+        for index in 0..len {
+            if solid(index) {
+                continue;
+            }
+            let cell = self.cell(index);
+            let sample = |offset: IVec3| match self.neighbour(cell, offset) {
+                Some(i) if !solid(i) => pressure[i],
+                _ => pressure[index],
+            };
+            let gradient = Vec3::new(
+                sample(IVec3::X) - sample(-IVec3::X),
+                sample(IVec3::Y) - sample(-IVec3::Y),
+                sample(IVec3::Z) - sample(-IVec3::Z),
+            );
+            self.velocities[index] -= gradient / (2.0 * h);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3A,
+    prelude::*,
+    render::primitives::Aabb,
+    scene::{SceneInstance, SceneSpawner},
+};
+
+use crate::{Sdf, SdfGenMode, SdfOptions};
+
+/// add to a glTF scene entity (e.g. alongside a `SceneBundle`) to give the whole scene a single
+/// aggregated sdf, baked in the root's space from every child mesh with its rigid transform.
+/// multi-primitive props then use one header and have no seams between their parts. don't add
+/// `Sdf` to the scene's children as well.
+#[derive(Component, Clone, Default)]
+pub struct SdfSceneRoot {
+    pub options: SdfOptions,
+}
+
+// give scene roots their hierarchy sdf once the scene has spawned and its meshes have bounds
+pub(crate) fn attach_scene_sdfs(
+    mut commands: Commands,
+    roots: Query<(Entity, &SdfSceneRoot, &SceneInstance), Without<Sdf>>,
+    scene_spawner: Res<SceneSpawner>,
+    hierarchy: SdfHierarchy,
+) {
+    for (ent, root, instance) in roots.iter() {
+        if !scene_spawner.instance_is_ready(**instance) || !hierarchy.meshes_ready(ent) {
+            continue;
+        }
+
+        commands.entity(ent).insert(Sdf {
+            mode: SdfGenMode::FromHierarchy,
+            options: root.options.clone(),
+            ..Default::default()
+        });
+    }
+}
+
+/// access to the descendant meshes of an entity, for sdfs generated with
+/// `SdfGenMode::FromHierarchy`
+#[derive(SystemParam)]
+pub struct SdfHierarchy<'w, 's> {
+    children: Query<'w, 's, &'static Children>,
+    meshes: Query<'w, 's, (&'static Handle<Mesh>, &'static GlobalTransform, Option<&'static Aabb>)>,
+    transforms: Query<'w, 's, &'static GlobalTransform>,
+}
+
+impl<'w, 's> SdfHierarchy<'w, 's> {
+    fn descendants(&self, root: Entity) -> Vec<Entity> {
+        let mut result = Vec::new();
+        let mut stack = vec![root];
+        while let Some(ent) = stack.pop() {
+            if let Ok(children) = self.children.get(ent) {
+                stack.extend(children.iter().copied());
+                result.extend(children.iter().copied());
+            }
+        }
+        result
+    }
+
+    /// true when `root` has descendant meshes and all of them have bounds (i.e. are loaded)
+    pub fn meshes_ready(&self, root: Entity) -> bool {
+        let mut any = false;
+        for ent in std::iter::once(root).chain(self.descendants(root)) {
+            if let Ok((_, _, maybe_aabb)) = self.meshes.get(ent) {
+                if maybe_aabb.is_none() {
+                    return false;
+                }
+                any = true;
+            }
+        }
+        any
+    }
+
+    /// meshes of all descendants of `root` (and root itself) with their transforms relative to
+    /// `root`
+    pub fn mesh_transforms(&self, root: Entity) -> Vec<(Handle<Mesh>, Mat4)> {
+        let Ok(root_transform) = self.transforms.get(root) else { return Vec::new() };
+        let root_inverse = root_transform.compute_matrix().inverse();
+
+        std::iter::once(root)
+            .chain(self.descendants(root))
+            .filter_map(|ent| self.meshes.get(ent).ok())
+            .map(|(handle, g_trans, _)| (handle.clone_weak(), root_inverse * g_trans.compute_matrix()))
+            .collect()
+    }
+
+    /// bounds of all descendant meshes in `root`'s space
+    pub fn aabb(&self, root: Entity) -> Option<Aabb> {
+        let root_inverse = self.transforms.get(root).ok()?.compute_matrix().inverse();
+
+        let (min, max) = std::iter::once(root)
+            .chain(self.descendants(root))
+            .filter_map(|ent| self.meshes.get(ent).ok())
+            .filter_map(|(_, g_trans, maybe_aabb)| Some((g_trans, maybe_aabb?)))
+            .flat_map(|(g_trans, aabb)| {
+                let transform = root_inverse * g_trans.compute_matrix();
+                (0..8).map(move |corner| {
+                    let sign = Vec3A::new(
+                        if corner & 1 == 0 { -1.0 } else { 1.0 },
+                        if corner & 2 == 0 { -1.0 } else { 1.0 },
+                        if corner & 4 == 0 { -1.0 } else { 1.0 },
+                    );
+                    transform.transform_point3(Vec3::from(aabb.center + aabb.half_extents * sign))
+                })
+            })
+            .fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(cur_min, cur_max), v| (cur_min.min(v), cur_max.max(v)),
+            );
+
+        if min.max_element() != f32::MAX && max.min_element() != f32::MIN {
+            return Some(Aabb::from_min_max(min, max));
+        }
+
+        None
+    }
+}
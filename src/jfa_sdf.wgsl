@@ -0,0 +1,283 @@
+struct TriData {
+    a: vec3<f32>,
+    b: vec3<f32>,
+    c: vec3<f32>,
+    plane: vec4<f32>,
+    inv_area: f32,
+};
+
+struct Tris {
+    data: array<TriData>,
+};
+
+// nearest triangle per voxel, as an index into the instance's triangles plus one. zero is unset.
+// the top bit marks voxels the surface passes through, and stays with the voxel through the flood
+struct Seeds {
+    data: array<u32>,
+};
+
+let SEED_SURFACE: u32 = 0x80000000u;
+let SEED_INDEX: u32 = 0x7fffffffu;
+
+struct JfaParams {
+    write_position: vec3<u32>,
+    tri_start: u32,
+    aabb_min: vec3<f32>,
+    tri_count: u32,
+    scale: vec3<f32>,
+    voxel_offset: u32,
+    dimensions: vec3<u32>,
+    flags: u32,
+    weld_margin: f32,
+    // flood offset in voxels
+    step: u32,
+    // 0 reads seeds_a and writes seeds_b, 1 the reverse
+    flip: u32,
+};
+
+let INSTANCE_FLAG_IGNORE_BACK_FACES: u32 = 1u;
+let INSTANCE_FLAG_INVERT: u32 = 2u;
+let INSTANCE_FLAG_SURFACE_MASK: u32 = 128u;
+let INSTANCE_FLAG_CHEBYSHEV: u32 = 256u;
+let INSTANCE_FLAG_MANHATTAN: u32 = 512u;
+
+@group(0) @binding(0)
+var<storage> tris: Tris;
+@group(0) @binding(1)
+var<storage, read_write> seeds_a: Seeds;
+@group(0) @binding(2)
+var<storage, read_write> seeds_b: Seeds;
+@group(0) @binding(3)
+#ifdef ATLAS_R16FLOAT
+var texture: texture_storage_3d<r16float, write>;
+#else
+var texture: texture_storage_3d<r32float, write>;
+#endif
+@group(0) @binding(4)
+var<uniform> params: JfaParams;
+
+fn closest_point_on_triangle(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> vec3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = dot(ab, ap);
+    let d2 = dot(ac, ap);
+    if (d1 <= 0.0 && d2 <= 0.0) {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = dot(ab, bp);
+    let d4 = dot(ac, bp);
+    if (d3 >= 0.0 && d4 <= d3) {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if (vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0) {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = dot(ab, cp);
+    let d6 = dot(ac, cp);
+    if (d6 >= 0.0 && d5 <= d6) {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if (vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0) {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if (va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0) {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    return a + ab * (vb * denom) + ac * (vc * denom);
+}
+
+// whether the triangle (relative to the box center) lies entirely on one side of the box along
+// the axis
+fn separated(axis: vec3<f32>, v0: vec3<f32>, v1: vec3<f32>, v2: vec3<f32>, half_size: vec3<f32>) -> bool {
+    let p = vec3<f32>(dot(axis, v0), dot(axis, v1), dot(axis, v2));
+    let r = dot(half_size, abs(axis));
+    return min(p.x, min(p.y, p.z)) > r || max(p.x, max(p.y, p.z)) < -r;
+}
+
+// the separating axes from the cross products of the triangle edge with the box axes
+fn edge_separated(edge: vec3<f32>, v0: vec3<f32>, v1: vec3<f32>, v2: vec3<f32>, half_size: vec3<f32>) -> bool {
+    return separated(vec3<f32>(0.0, -edge.z, edge.y), v0, v1, v2, half_size)
+        || separated(vec3<f32>(edge.z, 0.0, -edge.x), v0, v1, v2, half_size)
+        || separated(vec3<f32>(-edge.y, edge.x, 0.0), v0, v1, v2, half_size);
+}
+
+// whether the triangle passes through the box, by the separating axis test (akenine-moller)
+fn triangle_overlaps_box(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, center: vec3<f32>, half_size: vec3<f32>) -> bool {
+    let v0 = a - center;
+    let v1 = b - center;
+    let v2 = c - center;
+
+    // the box's face normals
+    if (any(min(min(v0, v1), v2) > half_size) || any(max(max(v0, v1), v2) < -half_size)) {
+        return false;
+    }
+
+    // the triangle's normal
+    let e0 = v1 - v0;
+    let e1 = v2 - v1;
+    let e2 = v0 - v2;
+    if (separated(cross(e0, e1), v0, v1, v2, half_size)) {
+        return false;
+    }
+
+    return !(edge_separated(e0, v0, v1, v2, half_size)
+        || edge_separated(e1, v0, v1, v2, half_size)
+        || edge_separated(e2, v0, v1, v2, half_size));
+}
+
+fn voxel_point(voxel: vec3<u32>) -> vec3<f32> {
+    return params.aabb_min + vec3<f32>(voxel) * params.scale;
+}
+
+fn voxel_index(voxel: vec3<u32>) -> u32 {
+    return params.voxel_offset + voxel.x + (voxel.y + voxel.z * params.dimensions.y) * params.dimensions.x;
+}
+
+fn seed_distance_squared(p: vec3<f32>, seed: u32) -> f32 {
+    let tri = tris.data[params.tri_start + seed - 1u];
+    let v = p - closest_point_on_triangle(p, tri.a, tri.b, tri.c);
+    return dot(v, v);
+}
+
+// conservatively voxelize the triangles: seed every voxel whose cell a triangle passes through,
+// however thin the geometry, and mark it as on the surface. one thread per triangle, racing writes
+// from triangles meeting in a voxel are fine as any of them is a valid starting point for the flood
+@compute
+@workgroup_size(64, 1, 1)
+fn seed(
+    @builtin(global_invocation_id) invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    // triangles may be spread over y, see `linear_workgroups` in compute.rs
+    let index = invocation_id.x + invocation_id.y * num_workgroups.x * 64u;
+    if (index >= params.tri_count) {
+        return;
+    }
+
+    let tri = tris.data[params.tri_start + index];
+    let lo = min(min(tri.a, tri.b), tri.c);
+    let hi = max(max(tri.a, tri.b), tri.c);
+    let max_voxel = vec3<f32>(params.dimensions - 1u);
+    let start = vec3<u32>(clamp(floor((lo - params.aabb_min) / params.scale), vec3<f32>(0.0), max_voxel));
+    let end = vec3<u32>(clamp(ceil((hi - params.aabb_min) / params.scale), vec3<f32>(0.0), max_voxel));
+
+    // each voxel's cell spans half a voxel either side of its point
+    let half_size = params.scale * 0.5;
+
+    for (var z = start.z; z <= end.z; z = z + 1u) {
+        for (var y = start.y; y <= end.y; y = y + 1u) {
+            for (var x = start.x; x <= end.x; x = x + 1u) {
+                let voxel = vec3<u32>(x, y, z);
+                if (triangle_overlaps_box(tri.a, tri.b, tri.c, voxel_point(voxel), half_size)) {
+                    seeds_a.data[voxel_index(voxel)] = (index + 1u) | SEED_SURFACE;
+                }
+            }
+        }
+    }
+}
+
+// one jump flood pass: take the nearest of the seeds found by the 26 neighbours `step` voxels away
+@compute
+@workgroup_size(8, 8, 8)
+fn flood(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let p = voxel_point(invocation_id);
+    let dimensions = vec3<i32>(params.dimensions);
+    let step = i32(params.step);
+
+    var best_seed = 0u;
+    var best_dist_sq = 3.4e38;
+    for (var z = -1; z <= 1; z = z + 1) {
+        for (var y = -1; y <= 1; y = y + 1) {
+            for (var x = -1; x <= 1; x = x + 1) {
+                let neighbour = vec3<i32>(invocation_id) + vec3<i32>(x, y, z) * step;
+                if (any(neighbour < vec3<i32>(0)) || any(neighbour >= dimensions)) {
+                    continue;
+                }
+
+                let index = voxel_index(vec3<u32>(neighbour));
+                var seed: u32;
+                if (params.flip == 0u) {
+                    seed = seeds_a.data[index];
+                } else {
+                    seed = seeds_b.data[index];
+                }
+                seed = seed & SEED_INDEX;
+                if (seed == 0u || seed == best_seed) {
+                    continue;
+                }
+
+                let dist_sq = seed_distance_squared(p, seed);
+                if (dist_sq < best_dist_sq) {
+                    best_dist_sq = dist_sq;
+                    best_seed = seed;
+                }
+            }
+        }
+    }
+
+    // keep the voxel's own surface mark
+    let index = voxel_index(invocation_id);
+    if (params.flip == 0u) {
+        seeds_b.data[index] = best_seed | (seeds_a.data[index] & SEED_SURFACE);
+    } else {
+        seeds_a.data[index] = best_seed | (seeds_b.data[index] & SEED_SURFACE);
+    }
+}
+
+// write the signed distance to each voxel's nearest triangle into the atlas
+@compute
+@workgroup_size(8, 8, 8)
+fn resolve(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let p = voxel_point(invocation_id);
+    let index = voxel_index(invocation_id);
+    var seed: u32;
+    if (params.flip == 0u) {
+        seed = seeds_a.data[index];
+    } else {
+        seed = seeds_b.data[index];
+    }
+    let surface = (seed & SEED_SURFACE) != 0u;
+    seed = seed & SEED_INDEX;
+
+    var dist = 3.4e38;
+    if (seed != 0u) {
+        let tri = tris.data[params.tri_start + seed - 1u];
+        let direction = p - closest_point_on_triangle(p, tri.a, tri.b, tri.c);
+        var outside = select(-1.0, 1.0, dot(direction, tri.plane.xyz) >= 0.0);
+        if ((params.flags & INSTANCE_FLAG_IGNORE_BACK_FACES) != 0u) {
+            outside = 1.0;
+        }
+        if ((params.flags & INSTANCE_FLAG_INVERT) != 0u) {
+            outside = -outside;
+        }
+        // the surface crosses the voxel's cell, so it's inside even when the voxel points either
+        // side of thin geometry are all outside, keeping a zero crossing there
+        if (surface && (params.flags & INSTANCE_FLAG_SURFACE_MASK) != 0u) {
+            outside = -1.0;
+        }
+        // under the instance's metric, must match `SdfMetric::apply` in lib.rs
+        var offset_length = length(direction);
+        let a = abs(direction);
+        if ((params.flags & INSTANCE_FLAG_CHEBYSHEV) != 0u) {
+            offset_length = max(a.x, max(a.y, a.z));
+        } else if ((params.flags & INSTANCE_FLAG_MANHATTAN) != 0u) {
+            offset_length = a.x + a.y + a.z;
+        }
+        dist = offset_length * outside - params.weld_margin;
+    }
+
+    textureStore(texture, vec3<i32>(params.write_position + invocation_id), vec4<f32>(dist, 0.0, 0.0, 1.0));
+}
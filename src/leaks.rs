@@ -0,0 +1,174 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+
+use crate::{query::SdfQuery, Sdf, SdfGlobalSettings};
+
+/// diagnostic for ambient occlusion light leaks. each sdf only describes its own entity, so two
+/// objects that should be sealed (e.g. a wall standing on a floor) but leave a gap wider than the
+/// sdf unit size let light through the seam. set `SdfLeakAnalysis::requested` to scan pairs of
+/// neighbouring static sdfs for such gaps.
+pub struct SdfLeakPlugin;
+
+impl Plugin for SdfLeakPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SdfLeakAnalysis>()
+            .add_system_to_stage(CoreStage::PostUpdate, analyse_leaks);
+    }
+}
+
+pub struct SdfLeakAnalysis {
+    // set to run the analysis on the next frame, cleared once it has run
+    pub requested: bool,
+    // gaps wider than this are treated as open space rather than leaks
+    pub max_gap: f32,
+    // sample spacing within the region between each pair. defaults to the global unit size
+    pub sample_spacing: Option<f32>,
+    // upper bound on samples per axis for each pair, the spacing is increased to fit
+    pub max_samples_per_axis: u32,
+    // results of the last analysis
+    pub leaks: Vec<SdfLeak>,
+}
+
+impl Default for SdfLeakAnalysis {
+    fn default() -> Self {
+        Self {
+            requested: false,
+            max_gap: 1.0,
+            sample_spacing: None,
+            max_samples_per_axis: 16,
+            leaks: Vec::new(),
+        }
+    }
+}
+
+/// a gap between two sdf entities
+#[derive(Debug, Clone)]
+pub struct SdfLeak {
+    pub entities: (Entity, Entity),
+    // world space position of the widest sample
+    pub position: Vec3,
+    // widest gap found between the two surfaces
+    pub gap: f32,
+    // number of leaking samples
+    pub samples: usize,
+}
+
+fn world_aabb(aabb: &Aabb, transform: &GlobalTransform) -> (Vec3, Vec3) {
+    let affine = transform.affine();
+    let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+    (0..8).fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(cur_min, cur_max), corner| {
+            let p = affine.transform_point3(Vec3::select(
+                BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                max,
+                min,
+            ));
+            (cur_min.min(p), cur_max.max(p))
+        },
+    )
+}
+
+// central difference gradient of an entity's distance field
+fn gradient(query: &SdfQuery, entity: Entity, point: Vec3, step: f32) -> Option<Vec3> {
+    let mut gradient = Vec3::ZERO;
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        let plus = query.entity_distance(entity, point + axis * step)?;
+        let minus = query.entity_distance(entity, point - axis * step)?;
+        gradient += axis * (plus - minus);
+    }
+    Some(gradient.normalize_or_zero())
+}
+
+fn analyse_leaks(
+    mut analysis: ResMut<SdfLeakAnalysis>,
+    settings: Res<SdfGlobalSettings>,
+    query: SdfQuery,
+    sdfs: Query<(Entity, &Sdf, &Aabb, &GlobalTransform)>,
+) {
+    if !analysis.requested {
+        return;
+    }
+    analysis.requested = false;
+    analysis.leaks.clear();
+
+    let unit_size = settings.unit_size;
+    let max_gap = analysis.max_gap;
+    let items = sdfs
+        .iter()
+        .filter(|(_, sdf, ..)| !sdf.skinned)
+        .map(|(ent, _, aabb, transform)| (ent, world_aabb(aabb, transform)))
+        .collect::<Vec<_>>();
+
+    for (i, (ent_a, (min_a, max_a))) in items.iter().enumerate() {
+        for (ent_b, (min_b, max_b)) in items.iter().skip(i + 1) {
+            // the region where a gap between the two could be
+            let min = min_a.max(*min_b) - max_gap;
+            let max = max_a.min(*max_b) + max_gap;
+            if min.cmpgt(max).any() {
+                continue;
+            }
+
+            let extent = max - min;
+            let spacing = analysis
+                .sample_spacing
+                .unwrap_or(unit_size)
+                .max(extent.max_element() / analysis.max_samples_per_axis as f32);
+            let counts = (extent / spacing).ceil().as_uvec3().max(UVec3::ONE);
+
+            let mut leak: Option<SdfLeak> = None;
+            for x in 0..counts.x {
+                for y in 0..counts.y {
+                    for z in 0..counts.z {
+                        let point = min + (UVec3::new(x, y, z).as_vec3() + 0.5) * spacing;
+                        let (Some(dist_a), Some(dist_b)) = (
+                            query.entity_distance(*ent_a, point),
+                            query.entity_distance(*ent_b, point),
+                        ) else {
+                            continue;
+                        };
+
+                        // outside both, with a gap too wide for the sdfs to close
+                        let gap = dist_a + dist_b;
+                        if dist_a <= 0.0 || dist_b <= 0.0 || gap <= unit_size || gap > max_gap {
+                            continue;
+                        }
+
+                        // and between them: the rays back to each surface run in opposite directions
+                        let step = spacing * 0.25;
+                        let (Some(grad_a), Some(grad_b)) = (
+                            gradient(&query, *ent_a, point, step),
+                            gradient(&query, *ent_b, point, step),
+                        ) else {
+                            continue;
+                        };
+                        if grad_a.dot(grad_b) > -0.5 {
+                            continue;
+                        }
+
+                        let leak = leak.get_or_insert(SdfLeak {
+                            entities: (*ent_a, *ent_b),
+                            position: point,
+                            gap,
+                            samples: 0,
+                        });
+                        leak.samples += 1;
+                        if gap > leak.gap {
+                            leak.gap = gap;
+                            leak.position = point;
+                        }
+                    }
+                }
+            }
+
+            if let Some(leak) = leak {
+                warn!(
+                    "possible light leak between {:?} and {:?}: gap of {} near {} ({} samples)",
+                    leak.entities.0, leak.entities.1, leak.gap, leak.position, leak.samples
+                );
+                analysis.leaks.push(leak);
+            }
+        }
+    }
+
+    info!("sdf leak analysis found {} leaking pairs", analysis.leaks.len());
+}
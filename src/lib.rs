@@ -1,31 +1,105 @@
 #![feature(let_else, slice_as_chunks)]
 pub mod animated_aabb;
+mod binning;
+mod bvh;
+pub mod compact;
+pub mod compress;
 pub mod compute;
 pub mod controller;
 pub mod cpu;
 pub mod debug_render;
+mod decimate;
+pub mod diagnostics;
+pub mod distance_grid;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flow;
+pub mod hierarchy;
+pub mod leaks;
+pub mod prebake;
+pub mod preprocessed;
+pub mod query;
+#[cfg(feature = "python")]
+mod python;
+mod pages;
+pub mod readback;
+pub mod snapshot;
 mod sdf_view_bindings;
+pub mod shadow_catcher;
+pub mod stats;
+pub mod timeline;
 pub mod utils;
 
+/// the commonly used types, `use mesh2sdf::prelude::*;`
+pub mod prelude {
+    pub use crate::{
+        compress::SdfCompressionPlugin,
+        compute::{SdfComputeBudget, SdfComputeGraphConfig, SdfComputeStarted},
+        debug_render::{
+            SdfDebugBundle, SdfDebugView, SdfMaterial, SdfRender, SdfRenderBounds, SdfRenderPlugin,
+        },
+        diagnostics::SdfDiagnosticsPlugin,
+        distance_grid::{SdfDistanceGrid, SdfDistanceGridPlugin, SdfDistanceGridSettings},
+        flow::{SdfFlowField, SdfFlowSettings},
+        hierarchy::SdfSceneRoot,
+        query::{CachedSdfQuery, SdfQuery, SdfQueryPlugin},
+        readback::{SdfReadback, SdfReadbackPlugin, SdfReadbackReady},
+        shadow_catcher::{
+            SdfShadowCatcherBundle, SdfShadowCatcherMaterial, SdfShadowCatcherPlugin,
+        },
+        snapshot::{
+            SdfAtlasSnapshot, SdfCaptureSnapshot, SdfSnapshotPlugin, SdfSnapshotReady,
+            SdfSnapshotRestore,
+        },
+        stats::{SdfAtlasStats, SdfEntryMetadata},
+        timeline::{SdfTimeline, SdfTimelinePlugin},
+        Sdf, SdfAoCombine, SdfAoSettings, SdfAtlas, SdfBackFaces, SdfComputeWorkgroup, SdfDelta,
+        SdfDeltaOp, SdfDeltas, SdfFailurePolicy, SdfGenMode, SdfGenerationState, SdfGlobalSettings,
+        SdfMethod, SdfMetric, SdfMirror, SdfMorphTargets, SdfOptions, SdfPinned, SdfPlugin,
+        SdfPriority, SdfQualityTier, SdfShadowCone, SdfShape, SdfSign, SdfStatus, SdfStreaming,
+    };
+}
+
 use animated_aabb::AnimatedAabbBuilder;
-use atlas3d::AtlasPage;
 use bevy::{
     asset::load_internal_asset,
+    ecs::schedule::ShouldRun,
+    math::Vec3A,
     pbr::{queue_mesh_view_bind_groups, PBR_AMBIENT_HANDLE},
     prelude::*,
+    reflect::TypeUuid,
     render::{
         extract_component::{ExtractComponent, ExtractComponentPlugin},
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         mesh::skinning::SkinnedMesh,
         primitives::Aabb,
+        render_resource::{TextureFormat, WgpuFeatures},
+        renderer::RenderDevice,
         view::VisibilitySystems::CheckVisibility,
         RenderApp, RenderStage,
     },
+    utils::{FloatOrd, HashMap, HashSet},
+};
+use compact::{compact_atlas, SdfAtlasMove};
+use compress::SdfCompressedSlot;
+use compute::{
+    dispatch_size, BlockBudget, SdfComputeBudget, SdfComputePlugin, SdfPipelineStatus, WORKGROUP_SIZE,
+};
+use hierarchy::{attach_scene_sdfs, SdfHierarchy};
+use pages::{SdfAtlasPages, SdfAtlasRegion};
+use prebake::{update_prebake_set, SdfPrebakeSet};
+use preprocessed::PreprocessedMesh;
+use query::SdfQueryPlugin;
+use stats::{record_entry_metadata, SdfEntryMetadata};
+use utils::{
+    atlas_texel_bytes, create_compressed_image, create_gradient_image, create_indirection_image,
+    create_sdf_image, mesh_content_hash,
 };
-use compute::{SdfComputePlugin, WORKGROUP_SIZE};
-use utils::create_sdf_image;
 
-use crate::sdf_view_bindings::queue_sdf_view_bindings;
+use crate::sdf_view_bindings::{
+    queue_sdf_view_bindings, record_written_entries, SdfWrittenEntries,
+};
+pub use crate::sdf_view_bindings::{SdfRenderResources, SDF_BINDINGS_WGSL};
 
 #[derive(Component, Clone)]
 pub struct Sdf {
@@ -67,17 +141,109 @@ impl ExtractComponent for Sdf {
     }
 }
 
-#[derive(Clone)]
+/// blend shapes applied to the source mesh before generation. the mesh's own attributes only
+/// describe the base pose, so per-vertex position deltas and the current weights are supplied
+/// here (e.g. by the app's morph animation system). entities are regenerated when this changes.
+/// targets with fewer deltas than the mesh has vertices are ignored with a warning
+#[derive(Component, Clone, Default)]
+pub struct SdfMorphTargets {
+    // per target, a position delta for every vertex of the mesh
+    pub deltas: Vec<Vec<Vec3>>,
+    // per target weight
+    pub weights: Vec<f32>,
+}
+
+impl SdfMorphTargets {
+    pub(crate) fn apply(&self, v: Vec3, index: usize) -> Vec3 {
+        self.deltas
+            .iter()
+            .zip(self.weights.iter())
+            .fold(v, |v, (deltas, weight)| v + deltas[index] * *weight)
+    }
+
+    // every target needs a delta per vertex of the mesh it's applied to
+    pub(crate) fn fits(&self, vertex_count: usize) -> bool {
+        self.deltas.iter().all(|deltas| deltas.len() >= vertex_count)
+    }
+
+    // upper bound on how far any vertex can move from its base position
+    pub(crate) fn max_offset(&self) -> f32 {
+        self.deltas
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(deltas, weight)| {
+                weight.abs() * deltas.iter().map(|d| d.length()).fold(0.0, f32::max)
+            })
+            .sum()
+    }
+}
+
+/// explicit generation priority, added to the automatic score (the entity's approximate projected
+/// size from the nearest 3d camera). e.g. give the player character a large value so it's never
+/// waiting behind distant props
+#[derive(Component, Clone, Copy, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdfPriority(pub f32);
+
+/// pins the entity's atlas entry while present, see `SdfAtlas::pin`. the entry is unpinned when
+/// the component is removed or the entity despawns, or when the entity moves to a different entry
+#[derive(Component, Clone, Copy, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdfPinned;
+
+#[derive(Clone, PartialEq)]
 pub enum SdfGenMode {
     // generate the sdf from the mesh attached to the owning entity
     FromPrimaryMesh,
-    // use a precomputed sdf texture
+    // use a precomputed R32Float 3d sdf texture (e.g. from `cpu::bake_all` or a readback), spanning
+    // the entity's aabb plus buffer with voxels at both corners. it is resampled to the atlas
+    // resolution if the sizes differ
     Precomputed(Handle<Image>),
     // use a custom mesh to generate the sdf (can be simplified, etc)
     FromCustomMesh(Handle<Mesh>),
+    // generate a single sdf from the meshes of the owning entity and all its descendants,
+    // in the owning entity's space. children are baked with their transforms at generation time
+    FromHierarchy,
+    // use geometry preprocessed ahead of time (e.g. loaded from a `.sdfmesh` file)
+    FromPreprocessed(Handle<PreprocessedMesh>),
+    // a precomputed base image (as for `Precomputed`) with the entity's `SdfDeltas` applied on
+    // top. each entity gets its own atlas entry. shapes added by deltas are clipped to the volume
+    Composite(Handle<Image>),
 }
 
-#[derive(Clone)]
+/// runtime modifications applied over the base of an `SdfGenMode::Composite` sdf, in order
+/// (e.g. brush strokes or attached analytic shapes), in the entity's space. the entry is
+/// regenerated when this changes
+#[derive(Component, Clone, Default)]
+pub struct SdfDeltas(pub Vec<SdfDelta>);
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdfDelta {
+    pub shape: SdfShape,
+    pub op: SdfDeltaOp,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfShape {
+    Sphere { center: Vec3, radius: f32 },
+    Box { center: Vec3, half_extents: Vec3 },
+    Capsule { start: Vec3, end: Vec3, radius: f32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfDeltaOp {
+    // add the shape to the surface
+    Union,
+    // carve the shape out of the surface
+    Subtract,
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
 pub struct SdfOptions {
     // specify the scale multiplier
     // by default, sdfs are generated with dimensions approximately matching the SdfPlugin::unit_size
@@ -85,18 +251,272 @@ pub struct SdfOptions {
     pub scale_multiplier: f32,
     // buffer size (defaults to global buffer_size)
     pub buffer_size: Option<f32>,
+    // how faces seen from behind affect the sign of the generated field
+    pub back_faces: SdfBackFaces,
+    // how the gpu brute force generator decides which side of the surface a voxel is on
+    pub sign: SdfSign,
+    // negate the generated field, so the empty space inside an enclosing mesh is treated as
+    // outside. use for architectural interiors where the camera is inside a closed shell
+    pub invert: bool,
+    // triangles with a smaller area (in mesh units) are skipped during preprocessing
+    pub min_triangle_area: f32,
+    // optionally simplify the mesh before generation. high-poly meshes don't need their full
+    // triangle count for a coarse sdf
+    pub decimation: Option<SdfDecimation>,
+    // what to do when the sdf can't be generated
+    pub failure_policy: SdfFailurePolicy,
+    // dilate the surface by this distance (in mesh units). a small margin on static geometry that
+    // meets other geometry (walls on floors) closes the bright seams left by the gap between
+    // separate per-object sdfs
+    pub weld_margin: f32,
+    // how the distance field is computed on the gpu. meshes with more features than a storage
+    // buffer binding holds on the device are split across dispatches and always computed by brute
+    // force, with a single sample and without thickening or gradients
+    pub method: SdfMethod,
+    // for meshes symmetric about the plane through their aabb center perpendicular to this axis
+    // (e.g. X for characters facing along z), only half the volume is computed and mirrored on
+    // write. ignored for animated entities, jump flooding and brick storage
+    pub mirror: Option<SdfMirror>,
+    // distance samples averaged per voxel, jittered within the voxel (1 samples only the voxel
+    // center). reduces aliasing of thin features at low resolutions, at this multiple of the
+    // generation cost. clamped to `MAX_SUPERSAMPLES`, ignored by jump flooding
+    pub supersample: u32,
+    // features thinner than this many voxels (fences, leaves, blades) are thickened to it, so
+    // they still occlude and shadow rather than vanishing between voxels. costs a second distance
+    // query for voxels near surfaces. 0 disables, ignored by jump flooding
+    pub min_thickness: f32,
+    // treat every voxel the surface may pass through as inside, so even geometry much thinner than
+    // a voxel always leaves a zero crossing at coarse resolutions. jump flooding marks the voxels
+    // its seed pass conservatively rasterizes the triangles into, and brute force voxels whose
+    // nearest feature is within half a voxel diagonal
+    pub surface_mask: bool,
+    // only points within this world space distance of the surface are occluded or shadowed by
+    // the sdf, and fragments further from its bounds skip it entirely. limits the cost of huge
+    // occluders (terrain, skyscrapers). keep it above `SdfGlobalSettings::ambient_distance` to
+    // avoid a visible edge in the occlusion
+    pub effect_range: Option<f32>,
+    // distance metric of the generated field, recorded in the view headers so shaders can adapt
+    pub metric: SdfMetric,
+    // store only the 8x8x8 voxel bricks near the surface, in the atlas region reserved by
+    // `SdfGlobalSettings::brick_region_depth`, with a texel per brick of the volume in the
+    // indirection texture pointing at its brick or holding a conservative distance for empty
+    // space. saves most of the atlas space of large, mostly empty volumes (buildings, terrain
+    // pieces). which bricks are needed is measured on the cpu while preprocessing. only for static
+    // brute force entries generated from meshes, others (and every entry without a brick region)
+    // are stored densely. brick entries have no mip levels and can't be read back
+    pub bricks: bool,
+    // once generated, move the entry into the BC4 compressed atlas (see
+    // `SdfGlobalSettings::compressed_atlas_size` and `SdfCompressionPlugin`), at a quarter of the
+    // memory of R16Float with 8 bit precision relative to each entry's largest distance. only for
+    // static entries stored densely. compressed entries have no mip levels and can't be read back
+    pub compress: bool,
+    // also write the direction away from the surface at each voxel into `SdfAtlas::gradient_image`,
+    // at the entry's slot, for effects that push things along the field. needs
+    // `SdfGlobalSettings::gradient_atlas`. only for brute force entries stored densely, and not
+    // kept for compressed entries
+    pub gradient: bool,
 }
 
+/// upper limit for `SdfOptions::supersample`
+pub const MAX_SUPERSAMPLES: u32 = 64;
+
 impl Default for SdfOptions {
     fn default() -> Self {
         Self {
             scale_multiplier: 1.0,
             buffer_size: None,
+            back_faces: SdfBackFaces::TwoSided,
+            sign: SdfSign::PseudoNormal,
+            invert: false,
+            min_triangle_area: 1e-8,
+            decimation: None,
+            failure_policy: SdfFailurePolicy::AabbOccluder,
+            weld_margin: 0.0,
+            method: SdfMethod::BruteForce,
+            mirror: None,
+            supersample: 1,
+            min_thickness: 0.0,
+            surface_mask: false,
+            effect_range: None,
+            metric: SdfMetric::Euclidean,
+            bricks: false,
+            compress: false,
+            gradient: false,
+        }
+    }
+}
+
+impl SdfOptions {
+    // options with relative decimation errors resolved against the voxel size of the target volume
+    pub(crate) fn for_voxel_size(&self, voxel_size: f32) -> Self {
+        let mut options = self.clone();
+        if let Some(SdfDecimation::UnitError(error)) = options.decimation {
+            options.decimation = Some(SdfDecimation::MaxError(error * voxel_size));
+        }
+        options
+    }
+
+    // the axis computed in halves, if any
+    pub(crate) fn mirror_axis(&self, animated: bool) -> Option<usize> {
+        match (self.mirror, self.method) {
+            (Some(mirror), SdfMethod::BruteForce) if !animated && !self.bricks => Some(mirror as usize),
+            _ => None,
+        }
+    }
+
+    // whether a static entry is stored as bricks, when the atlas has a brick region
+    pub(crate) fn stores_bricks(&self, mode: &SdfGenMode) -> bool {
+        self.bricks
+            && self.method == SdfMethod::BruteForce
+            && !matches!(mode, SdfGenMode::Precomputed(_) | SdfGenMode::Composite(_))
+    }
+
+    // distance samples per voxel
+    pub(crate) fn samples(&self) -> u32 {
+        self.supersample.clamp(1, MAX_SUPERSAMPLES)
+    }
+
+    // whether an entry generated with `previous` must be regenerated for these options. the
+    // effect range only applies when sampling, and the failure policy only when generation fails
+    pub(crate) fn regenerates(&self, previous: &Self) -> bool {
+        let previous = Self {
+            effect_range: self.effect_range,
+            failure_policy: self.failure_policy,
+            ..previous.clone()
+        };
+        *self != previous
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfMirror {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfMethod {
+    // every voxel tests every feature of the mesh. exact, but the cost grows with
+    // voxels * triangles (default)
+    BruteForce,
+    // seed the voxels touching each triangle then propagate the nearest triangle with jump flood
+    // passes. cost grows with voxels * log2(dimension), so it suits large volumes and dense
+    // meshes. distances are approximate away from the surface, and the sign comes from the nearest
+    // triangle's face normal so it can be wrong near sharp concave edges
+    JumpFlood,
+}
+
+/// threads per 8x8x8 block of the calc pass, which is specialized on the choice. smaller
+/// workgroups compute several voxels per thread
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfComputeWorkgroup {
+    // one thread per voxel (default)
+    Threads512,
+    // two voxels per thread, for devices limited to 256 invocations per workgroup
+    Threads256,
+    // four voxels per thread
+    Threads128,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfFailurePolicy {
+    // log a warning and don't generate anything
+    Warn,
+    // silently don't generate anything
+    Skip,
+    // when the sdf doesn't fit in the atlas, retry at successively halved resolutions down to
+    // `min_scale` times the requested resolution, then fall back to an aabb occluder
+    ReduceResolution { min_scale: f32 },
+    // log a warning and substitute an analytic box matching the entity's aabb until the real sdf
+    // is available (default)
+    AabbOccluder,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfDecimation {
+    // quadric error decimation down to (at most) this many triangles
+    TargetTriangles(usize),
+    // decimate while the surface moves less than this distance (in mesh units)
+    MaxError(f32),
+    // decimate while the surface moves less than this fraction of the sdf's voxel size
+    UnitError(f32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfBackFaces {
+    // points behind a face are inside the mesh and get negative distances (solid props)
+    TwoSided,
+    // back faces don't contribute to the sign, all distances are positive so the surface
+    // behaves as a thin shell. use for hollow shells (e.g. rooms modeled as a single surface)
+    // where points behind a face are empty space rather than solid interior
+    Ignore,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfSign {
+    // inside when the direction to the nearest feature opposes its (pseudo) normal. cheap, but
+    // meshes with holes or missing faces get wrongly signed regions behind the gaps (default)
+    PseudoNormal,
+    // inside when the generalized winding number (the solid angle of every triangle, summed) is
+    // over a half. robust to holes and overlapping parts, but every voxel visits every triangle,
+    // so it's best kept to low resolution or low triangle count entries. ignored by jump
+    // flooding and the cpu generator, which use the pseudo normal
+    WindingNumber,
+}
+
+/// how distances are measured, for stylized effects wanting boxy or diamond shaped falloff.
+/// alternative metrics are derived from the offset to the nearest euclidean surface point, which
+/// is exact for flat surfaces facing an axis and close elsewhere
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfMetric {
+    // straight line distance (default)
+    Euclidean,
+    // the largest axis of the offset, giving square contours. never more than the euclidean
+    // distance, so it's still safe to march by
+    Chebyshev,
+    // the sum of the axes of the offset, giving diamond contours. up to sqrt(3) times the
+    // euclidean distance, so marchers must divide their steps by that
+    Manhattan,
+}
+
+impl SdfMetric {
+    // signed distance under the metric, from the euclidean distance and the offset from the
+    // nearest surface point
+    pub(crate) fn apply(&self, distance: f32, offset: Vec3A) -> f32 {
+        match self {
+            SdfMetric::Euclidean => distance,
+            SdfMetric::Chebyshev => offset.abs().max_element().copysign(distance),
+            SdfMetric::Manhattan => (offset.x.abs() + offset.y.abs() + offset.z.abs()).copysign(distance),
         }
     }
 }
 
+/// distances from the nearest active 3d camera (to the entity's bounds) at which entries are
+/// evicted and regenerated, see `SdfGlobalSettings::streaming`
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdfStreaming {
+    // entities streamed out are regenerated once a camera is within this distance
+    pub load_distance: f32,
+    // entities further than this from every camera are evicted. keep it above `load_distance`
+    // so entities near the boundary aren't evicted and regenerated repeatedly
+    pub unload_distance: f32,
+}
+
+/// startup settings, insert before adding `SdfPlugin`. with the `serialize` feature this (and the
+/// other settings types) can be deserialized from a config file, missing fields take the defaults
 #[derive(Clone, ExtractResource)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
 pub struct SdfGlobalSettings {
     // size of the atlas used for storing all sdfs
     pub atlas_page_size: UVec3,
@@ -104,10 +524,76 @@ pub struct SdfGlobalSettings {
     // this should be as large as the ambient tap max distance and the maximum soft shadow cone radius
     // shadow cone radius depends on light range and cone angle/softness
     pub buffer_size: f32,
+    // derive each entity's buffer size from what samples it instead: the ambient distance, and
+    // the `SdfShadowCone` of any light in range of it. entities with their own
+    // `SdfOptions::buffer_size` keep it. recomputed every frame, and applied whenever the entity
+    // is (re)generated
+    pub auto_buffer_size: bool,
     // default sdf unit size
     pub unit_size: f32,
     // ambient occlusion distance
     pub ambient_distance: f32,
+    // resolution multiplier for first-time bakes of static sdfs. entries are generated at this
+    // coarse resolution first, then refined to full resolution over the following frames, so
+    // early frames already have plausible occlusion. 1.0 disables coarse-first generation
+    pub coarse_scale: f32,
+    // maximum number of coarse entries regenerated at full resolution each frame
+    pub refinements_per_frame: usize,
+    // share atlas entries between static meshes with identical geometry (e.g. cloned assets)
+    // by keying them on a hash of their contents instead of their handle
+    pub dedupe_meshes: bool,
+    // while an animated entity's pose stays inside its previous volume, recompute only the
+    // blocks near vertices whose joints moved, reusing the rest of its atlas slot
+    pub sparse_skinned_updates: bool,
+    // workgroup shape of the calc pass
+    pub compute_workgroup: SdfComputeWorkgroup,
+    // mip levels of the atlas image, each min-filtered from the one above as entries are written,
+    // so ray marchers can take larger steps far from surfaces. 1 disables the mip pass
+    pub atlas_mip_levels: u32,
+    // skip queueing and generating sdfs while no 3d camera is active, so headless and server
+    // builds pay nothing for the plugin. existing atlas entries are kept, and anything missing is
+    // generated once a camera becomes active. clear to generate regardless, e.g. for cpu queries
+    // or readback without a view
+    pub require_3d_camera: bool,
+    // atlas entries relocated per frame while compacting. compaction starts when an entry fails
+    // to fit and runs over as many frames as it takes to reallocate every resident entry once,
+    // so space freed by purges is joined up for larger entries. 0 disables compaction
+    pub compaction_moves_per_frame: usize,
+    // give animated entries two atlas slots, generating each pose into the slot not being
+    // sampled and switching the views to it the frame after it's written. the views never see a
+    // slot mid-update, at the cost of twice the atlas space and a frame of latency. disables
+    // `sparse_skinned_updates`, as the other slot holds an older pose
+    pub double_buffer_animated: bool,
+    // bytes of atlas texels the entries may occupy. when allocating past it, the least recently
+    // used entries which weren't used last frame are evicted, and regenerated if they're needed
+    // again. stale entries are also evicted to make room when an entry doesn't fit. none for no
+    // limit other than the atlas size
+    pub atlas_memory_budget: Option<usize>,
+    // texels at the far end of the atlas z axis reserved for animated entries, which are packed
+    // there and evicted oldest first when it's full. keeps the churn of entries regenerated every
+    // frame from fragmenting the space used by static entries. 0 packs everything together
+    pub animated_region_depth: u32,
+    // texels along the atlas z axis, in front of the animated region, holding the 8x8x8 bricks of
+    // entries with `SdfOptions::bricks`. rounded down to whole bricks, 0 stores every entry
+    // densely
+    pub brick_region_depth: u32,
+    // store the atlas as R16Float rather than R32Float, halving its memory. distances keep about
+    // three significant digits, plenty for occlusion and shadows. needs
+    // `WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` in the `WgpuSettings` for the
+    // compute shaders to write it, without it the atlas falls back to R32Float
+    pub half_precision_atlas: bool,
+    // allocate `SdfAtlas::gradient_image`, an Rgba8Snorm image the size of the atlas holding the
+    // gradients of entries with `SdfOptions::gradient`. without it the option is ignored
+    pub gradient_atlas: bool,
+    // evict the entries of entities far from every camera, regenerating them when a camera comes
+    // back within range, so large levels needn't keep every sdf resident. pinned entries are
+    // kept. none keeps entries of visible entities wherever they are
+    pub streaming: Option<SdfStreaming>,
+    // size of the BC4 compressed atlas holding entries with `SdfOptions::compress`, x and y in
+    // texels (rounded up to whole 4x4 blocks) and z in layers, a layer per z slice of each entry.
+    // needs `WgpuFeatures::TEXTURE_COMPRESSION_BC` in the `WgpuSettings` and
+    // `SdfCompressionPlugin`. zero (or a missing feature) stores every entry uncompressed
+    pub compressed_atlas_size: UVec3,
 }
 
 impl Default for SdfGlobalSettings {
@@ -116,15 +602,177 @@ impl Default for SdfGlobalSettings {
             // 32mb atlas page
             atlas_page_size: UVec3::splat(200),
             buffer_size: 1.0,
+            auto_buffer_size: false,
             unit_size: 1.0,
             ambient_distance: 1.0,
+            coarse_scale: 1.0,
+            refinements_per_frame: 4,
+            dedupe_meshes: false,
+            sparse_skinned_updates: true,
+            compute_workgroup: SdfComputeWorkgroup::Threads512,
+            atlas_mip_levels: 1,
+            require_3d_camera: true,
+            compaction_moves_per_frame: 4,
+            double_buffer_animated: false,
+            atlas_memory_budget: None,
+            animated_region_depth: 0,
+            brick_region_depth: 0,
+            half_precision_atlas: false,
+            gradient_atlas: false,
+            streaming: None,
+            compressed_atlas_size: UVec3::ZERO,
+        }
+    }
+}
+
+/// quality settings which can be switched at runtime, e.g. from a graphics settings menu.
+/// changing the resolution multiplier regenerates every sdf at the new resolution.
+#[derive(Clone, ExtractResource)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
+pub struct SdfQualityTier {
+    // multiplier on sdf resolution, on top of `SdfGlobalSettings::unit_size` and each entity's
+    // `scale_multiplier`
+    pub resolution_multiplier: f32,
+    // cones traced for diffuse ambient occlusion: 1 (along the normal only) or 5
+    pub ao_taps: u32,
+    // step count for materials which march sdf shadows, passed through the view uniform
+    pub shadow_steps: u32,
+    // overrides `SdfGlobalSettings::refinements_per_frame` when set
+    pub refinements_per_frame: Option<usize>,
+    // overrides `SdfComputeBudget::max_blocks_per_frame` when set
+    pub max_blocks_per_frame: Option<u32>,
+}
+
+impl SdfQualityTier {
+    pub fn low() -> Self {
+        Self {
+            resolution_multiplier: 0.5,
+            ao_taps: 1,
+            shadow_steps: 8,
+            refinements_per_frame: Some(1),
+            max_blocks_per_frame: Some(512),
+        }
+    }
+
+    pub fn medium() -> Self {
+        Self {
+            resolution_multiplier: 1.0,
+            ao_taps: 5,
+            shadow_steps: 16,
+            refinements_per_frame: Some(4),
+            max_blocks_per_frame: Some(4096),
+        }
+    }
+
+    pub fn high() -> Self {
+        Self {
+            resolution_multiplier: 2.0,
+            ao_taps: 5,
+            shadow_steps: 32,
+            refinements_per_frame: Some(8),
+            max_blocks_per_frame: None,
+        }
+    }
+}
+
+impl Default for SdfQualityTier {
+    // full resolution with no overrides of the global settings
+    fn default() -> Self {
+        Self {
+            resolution_multiplier: 1.0,
+            ao_taps: 5,
+            shadow_steps: 16,
+            refinements_per_frame: None,
+            max_blocks_per_frame: None,
+        }
+    }
+}
+
+/// how the occlusion from several overlapping sdfs is combined at each ambient tap
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfAoCombine {
+    // distance to the nearest surface over all sdfs, overlapping sdfs don't stack
+    Union,
+    // product of each sdf's visibility, so nearby sdfs each contribute, with the total occlusion
+    // of a tap limited to `max_occlusion` (0-1) to avoid double darkening
+    Product { max_occlusion: f32 },
+}
+
+#[derive(Clone, ExtractResource)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serialize", serde(default))]
+pub struct SdfAoSettings {
+    pub combine: SdfAoCombine,
+}
+
+impl Default for SdfAoSettings {
+    fn default() -> Self {
+        Self {
+            combine: SdfAoCombine::Union,
         }
     }
 }
 
+/// whether sdfs are being generated. while paused nothing is queued, preprocessed or dispatched
+/// and the atlas is left exactly as it is, so existing entries stay valid and in use. e.g. pause
+/// during cutscenes or loading to keep the frame cost fixed, entities that changed meanwhile are
+/// caught up on resuming
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SdfGenerationState {
+    Running,
+    Paused,
+}
+
+impl Default for SdfGenerationState {
+    fn default() -> Self {
+        Self::Running
+    }
+}
+
+// the queueing systems don't run at all while paused, so their change detection picks up
+// whatever changed in the meantime once resumed
+fn generation_running(state: Res<SdfGenerationState>) -> ShouldRun {
+    match *state {
+        SdfGenerationState::Running => ShouldRun::Yes,
+        SdfGenerationState::Paused => ShouldRun::No,
+    }
+}
+
+// drop the work queued before pausing, so it isn't dispatched again. likewise without a 3d camera
+// when one is required, as the compute node wouldn't run to write it
+fn clear_paused_queue(
+    state: Res<SdfGenerationState>,
+    settings: Res<SdfGlobalSettings>,
+    cameras: Query<&Camera, With<Camera3d>>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    let no_camera = settings.require_3d_camera && !cameras.iter().any(|camera| camera.is_active);
+    if *state == SdfGenerationState::Running && !no_camera {
+        return;
+    }
+    if !atlas.need_computing.is_empty() || !atlas.moves.is_empty() {
+        atlas.need_computing.clear();
+        atlas.sparse.clear();
+        atlas.moves.clear();
+    }
+}
+
 pub struct SdfPlugin;
 
+/// `mesh2sdf::sdf_material`, sdf occlusion and soft shadows for the fragment shaders of custom
+/// `Material`s. the sdf view bindings are added to the mesh view bind group by
+/// `SdfPlugin::add_view_bindings`, so materials sample them without any bindings of their own
+/// (their `AsBindGroup` is unchanged). import it after `bevy_pbr::mesh_view_bindings`,
+/// `bevy_pbr::mesh_bindings`, `bevy_pbr::utils`, `bevy_pbr::lighting` and `bevy_pbr::pbr_ambient`,
+/// see the soft_shadows example
+pub const SDF_MATERIAL_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 14629308374521187095);
+
 impl SdfPlugin {
+    /// add the sdf view bindings to the mesh view bind group. call before adding the default
+    /// plugins, as the bind group layout is fixed when `PbrPlugin` is built
     pub fn add_view_bindings(app: &mut App) {
         sdf_view_bindings::add_view_bindings(app)
     }
@@ -137,17 +785,106 @@ impl Plugin for SdfPlugin {
             .world
             .get_resource_or_insert_with(|| SdfGlobalSettings::default());
         let page_size = settings.atlas_page_size;
+        // down to a single texel along the smallest axis
+        let max_mip_levels = 32 - page_size.min_element().leading_zeros();
+        let mip_levels = settings.atlas_mip_levels.clamp(1, max_mip_levels);
+        let half_precision = settings.half_precision_atlas;
+        let gradients = settings.gradient_atlas;
+        let animated_region_depth = settings.animated_region_depth;
+        let brick_region_depth = settings.brick_region_depth;
+        let compressed_atlas_size = settings.compressed_atlas_size;
 
         // extract em
         app.add_plugin(ExtractResourcePlugin::<SdfGlobalSettings>::default());
 
+        // runtime quality
+        app.init_resource::<SdfQualityTier>();
+        app.init_resource::<SdfGenerationState>();
+        app.add_plugin(ExtractResourcePlugin::<SdfQualityTier>::default());
+        app.init_resource::<SdfAoSettings>();
+        app.add_plugin(ExtractResourcePlugin::<SdfAoSettings>::default());
+
+        // offline preprocessed geometry
+        app.add_asset::<PreprocessedMesh>();
+        #[cfg(feature = "serialize")]
+        app.init_asset_loader::<preprocessed::PreprocessedMeshLoader>();
+
         // create atlas resource
-        let image = create_sdf_image(page_size);
+        let format = match half_precision {
+            true if !app.world.get_resource::<RenderDevice>().map_or(true, |device| {
+                device
+                    .features()
+                    .contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            }) =>
+            {
+                warn!("adapter specific format features not enabled, using an R32Float sdf atlas. request `WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` in the `WgpuSettings`");
+                TextureFormat::R32Float
+            }
+            true => TextureFormat::R16Float,
+            false => TextureFormat::R32Float,
+        };
+        // whole 4x4 blocks, and at least two layers so the image is viewed as an array
+        let compressed_dim = match compressed_atlas_size.cmpeq(UVec3::ZERO).any() {
+            true => UVec3::ZERO,
+            false if !app.world.get_resource::<RenderDevice>().map_or(true, |device| {
+                device.features().contains(WgpuFeatures::TEXTURE_COMPRESSION_BC)
+            }) =>
+            {
+                warn!("bc texture compression not enabled, storing sdfs uncompressed. request `WgpuFeatures::TEXTURE_COMPRESSION_BC` in the `WgpuSettings`");
+                UVec3::ZERO
+            }
+            false => {
+                let layers = compressed_atlas_size.z.clamp(2, 256);
+                ((compressed_atlas_size.truncate() + 3) / 4 * 4).extend(layers)
+            }
+        };
+        let compressed_image =
+            create_compressed_image(compressed_dim, compressed_dim != UVec3::ZERO);
+        let compressed_image = app.world.resource_mut::<Assets<Image>>().add(compressed_image);
+
+        let page = SdfAtlasPages::new(
+            page_size,
+            animated_region_depth,
+            brick_region_depth,
+            compressed_dim,
+        );
+        let image = create_sdf_image(page_size, mip_levels, format);
         let image = app.world.resource_mut::<Assets<Image>>().add(image);
+        let indirection_image = create_indirection_image(page.indirection_dim());
+        let indirection_image = app.world.resource_mut::<Assets<Image>>().add(indirection_image);
+        let gradient_image = create_gradient_image(page_size, gradients);
+        let gradient_image = app.world.resource_mut::<Assets<Image>>().add(gradient_image);
         app.insert_resource(SdfAtlas {
-            page: AtlasPage::new(page_size),
+            page,
             image,
+            indirection_image,
+            gradient_image,
+            gradients,
+            mip_levels,
+            format,
             need_computing: Vec::new(),
+            sparse: HashSet::default(),
+            coarse: HashMap::default(),
+            reduced: HashMap::default(),
+            fallbacks: HashMap::default(),
+            pinned: HashSet::default(),
+            content_hashes: HashMap::default(),
+            moves: Vec::new(),
+            fragmented: false,
+            bricks_needed: 0,
+            buffered: HashMap::default(),
+            last_used: HashMap::default(),
+            entity_keys: HashMap::default(),
+            generated_with: HashMap::default(),
+            streamed_out: HashSet::default(),
+            frame: 0,
+            memory_budget: None,
+            metadata: HashMap::default(),
+            compressed_image,
+            compressed_dim,
+            compressed: HashMap::default(),
+            compressed_uploads: Vec::new(),
+            restored: HashMap::default(),
         });
 
         // and extract it
@@ -156,20 +893,67 @@ impl Plugin for SdfPlugin {
         // system to generate required sdfs
         app.add_system_to_stage(
             CoreStage::PostUpdate,
-            queue_sdfs.after(CheckVisibility).before("preprocess sdfs"),
+            queue_sdfs
+                .with_run_criteria(generation_running)
+                .after(CheckVisibility)
+                .before("preprocess sdfs"),
+        );
+
+        // relocate entries to join up free space once the atlas fragments
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            compact_atlas
+                .with_run_criteria(generation_running)
+                .after(queue_sdfs)
+                .before("preprocess sdfs"),
+        );
+
+        // queue nothing while paused or without a camera
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            clear_paused_queue
+                .after(queue_sdfs)
+                .after(compact_atlas)
+                .before("preprocess sdfs"),
+        );
+
+        // entities to generate ahead of becoming visible
+        app.init_resource::<SdfPrebakeSet>();
+        app.add_system_to_stage(CoreStage::PostUpdate, update_prebake_set.before(queue_sdfs));
+
+        // how each entry was generated, once its job is preprocessed
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            record_entry_metadata.after("preprocess sdfs"),
         );
 
+        // padding derived from the ambient distance and lights
+        app.add_system_to_stage(CoreStage::PostUpdate, update_auto_buffer_sizes.before(queue_sdfs));
+
+        // entries pinned by component
+        app.add_system_to_stage(CoreStage::PostUpdate, pin_marked_sdfs.before(queue_sdfs));
+
+        // single sdfs for whole glTF scenes
+        app.add_system_to_stage(CoreStage::PostUpdate, attach_scene_sdfs.before(queue_sdfs));
+
         // extract sdfs
         app.add_plugin(ExtractComponentPlugin::<Sdf>::default());
+        app.add_plugin(ExtractComponentPlugin::<SdfTransform>::default());
 
         // compute pass
         app.add_plugin(SdfComputePlugin);
 
-        // add view bindings
-        app.sub_app_mut(RenderApp).add_system_to_stage(
-            RenderStage::Queue,
-            queue_sdf_view_bindings.before(queue_mesh_view_bind_groups),
-        );
+        // cpu queries
+        app.add_plugin(SdfQueryPlugin);
+
+        // add view bindings, sampling only entries written in earlier frames
+        app.sub_app_mut(RenderApp)
+            .init_resource::<SdfWrittenEntries>()
+            .add_system_to_stage(
+                RenderStage::Queue,
+                queue_sdf_view_bindings.before(queue_mesh_view_bind_groups),
+            )
+            .add_system_to_stage(RenderStage::Cleanup, record_written_entries);
 
         // override occlusion function
         load_internal_asset!(
@@ -178,6 +962,14 @@ impl Plugin for SdfPlugin {
             "sdf_ambient.wgsl",
             Shader::from_wgsl
         );
+
+        // functions for custom materials
+        load_internal_asset!(
+            app,
+            SDF_MATERIAL_SHADER_HANDLE,
+            "sdf_material.wgsl",
+            Shader::from_wgsl
+        );
     }
 }
 
@@ -185,13 +977,364 @@ impl Plugin for SdfPlugin {
 pub enum SdfAtlasKey {
     Mesh(Handle<Mesh>),
     Image(Handle<Image>),
+    Hierarchy(Entity),
+    Preprocessed(Handle<PreprocessedMesh>),
+    // composite entries are unique to their entity
+    Composite(Entity),
+    // meshes with identical contents, when `SdfGlobalSettings::dedupe_meshes` is enabled
+    Content(u64),
+    // one of the two slots of an animated entity, when `SdfGlobalSettings::double_buffer_animated`
+    // is enabled
+    Buffered(Entity, bool),
+}
+
+/// the two atlas slots of a double buffered animated entity
+#[derive(Clone)]
+pub struct SdfBufferedEntry {
+    // the slot sampled by the views, holding the last completely written pose
+    pub front: bool,
+    // volumes of the poses in each slot, indexed by the slot
+    pub aabbs: [Aabb; 2],
+    // the other slot was queued last frame, it becomes the front unless the compute node skipped it
+    pub pending: bool,
+}
+
+impl SdfBufferedEntry {
+    /// the volume of the pose in the front slot
+    pub fn front_aabb(&self) -> &Aabb {
+        &self.aabbs[self.front as usize]
+    }
 }
 
+/// an entry's place in the atlas, in texels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SdfAtlasSlot {
+    pub position: UVec3,
+    pub size: UVec3,
+}
+
+/// the result of `SdfAtlas::insert`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfAtlasInsert {
+    // allocated a new slot, the entry must be generated into it
+    New(SdfAtlasSlot),
+    // the entry was already resident
+    Existing(SdfAtlasSlot),
+    // no space for the entry, even after evicting stale entries
+    NoFit,
+}
+
+/// the atlas of generated sdfs. entries are allocated by the plugin as sdf entities become
+/// visible, use the methods here to inspect or manage them rather than depending on the packing
+/// backend
 #[derive(Clone, ExtractResource)]
 pub struct SdfAtlas {
-    pub page: AtlasPage<SdfAtlasKey>,
+    // packing backend, use the wrappers on `SdfAtlas` outside the crate
+    pub(crate) page: SdfAtlasPages,
     pub image: Handle<Image>,
+    // Rgba32Float, a texel per 8x8x8 brick of each entry stored as bricks: the atlas texel of the
+    // brick's first voxel in xyz, or -1 in x for empty bricks, and the distance from the brick to
+    // the surface in w
+    pub indirection_image: Handle<Image>,
+    // Rgba8Snorm, the unit direction away from the surface at each texel of entries with
+    // `SdfOptions::gradient`, in the entry's local space. a single texel placeholder without
+    // `SdfGlobalSettings::gradient_atlas`
+    pub gradient_image: Handle<Image>,
+    // whether `gradient_image` covers the atlas
+    pub(crate) gradients: bool,
+    // mip levels of the atlas image, fixed when the plugin is built
+    pub mip_levels: u32,
+    // storage format of the atlas image, R32Float or R16Float (see
+    // `SdfGlobalSettings::half_precision_atlas`), fixed when the plugin is built.
+    // the compute shaders are specialized to write it. R16Float storage writes need
+    // `WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` and adapter support
+    pub format: TextureFormat,
     pub need_computing: Vec<(Entity, SdfAtlasKey, Aabb)>,
+    // animated entities in `need_computing` which are recomputed in their existing slot
+    pub sparse: HashSet<Entity>,
+    // entries currently baked at coarse resolution (with their atlas size), waiting for refinement
+    pub coarse: HashMap<SdfAtlasKey, UVec3>,
+    // entries generated at reduced resolution after failing to fit (with their atlas size)
+    pub reduced: HashMap<SdfAtlasKey, UVec3>,
+    // entities using an analytic box occluder (in local space) instead of an atlas entry
+    pub fallbacks: HashMap<Entity, Aabb>,
+    // entries which are always queued ahead of everything else and never evicted
+    pinned: HashSet<SdfAtlasKey>,
+    // content hashes of static meshes, when deduplicating
+    content_hashes: HashMap<Handle<Mesh>, u64>,
+    // entries relocated this frame, copied to their new slots by the compute node
+    pub moves: Vec<SdfAtlasMove>,
+    // an entry failed to fit, set until a compaction pass completes
+    fragmented: bool,
+    // bricks an entry stored as bricks failed to get, stale brick entries are evicted for them when
+    // the next one is allocated
+    pub(crate) bricks_needed: usize,
+    // slots of double buffered animated entities
+    pub buffered: HashMap<Entity, SdfBufferedEntry>,
+    // frame each entry was last allocated or kept, for eviction
+    last_used: HashMap<SdfAtlasKey, u64>,
+    // the entry each sdf entity used when last queued
+    entity_keys: HashMap<Entity, SdfAtlasKey>,
+    // entities whose entries were evicted by streaming
+    streamed_out: HashSet<Entity>,
+    // the mode and options each sdf entity was last queued with, to spot edits that need the
+    // entry regenerated
+    generated_with: HashMap<Entity, (SdfGenMode, SdfOptions)>,
+    frame: u64,
+    // copied from `SdfGlobalSettings::atlas_memory_budget` each frame
+    memory_budget: Option<usize>,
+    // how each entry was last generated
+    metadata: HashMap<SdfAtlasKey, SdfEntryMetadata>,
+    // BC4 layers holding entries with `SdfOptions::compress`, see
+    // `SdfGlobalSettings::compressed_atlas_size`. an R8Snorm placeholder without compression
+    pub compressed_image: Handle<Image>,
+    // size of the compressed atlas in texels, with layers in z
+    pub(crate) compressed_dim: UVec3,
+    // entries moved into the compressed atlas
+    pub(crate) compressed: HashMap<SdfAtlasKey, SdfCompressedSlot>,
+    // blocks to write into the compressed atlas this frame: first texel, size in blocks and data
+    pub(crate) compressed_uploads: Vec<(UVec3, UVec3, Vec<u8>)>,
+    // saved distances of entities restored from a snapshot, copied into their entries in place
+    // of generating them. see `SdfSnapshotPlugin`
+    pub(crate) restored: HashMap<Entity, Handle<Image>>,
+}
+
+impl SdfAtlas {
+    /// keep the entry resident regardless of memory pressure. pinned entries are allocated
+    /// before any others each frame, and are never evicted, relocated by compaction, streamed out
+    /// or purged while hidden. explicit `purge` and `purge_all` calls, changed options and shader
+    /// reloads still regenerate them. see also the `SdfPinned` component.
+    pub fn pin(&mut self, key: SdfAtlasKey) {
+        self.pinned.insert(key);
+    }
+
+    pub fn unpin(&mut self, key: &SdfAtlasKey) {
+        self.pinned.remove(key);
+    }
+
+    pub fn is_pinned(&self, key: &SdfAtlasKey) -> bool {
+        self.pinned.contains(key)
+    }
+
+    /// size of the atlas page in texels
+    pub fn dim(&self) -> UVec3 {
+        self.page.dim
+    }
+
+    /// whether the entry is stored as bricks (see `SdfOptions::bricks`). its slot is then in the
+    /// indirection texture, a texel per brick, with the size of the dense volume it maps
+    pub fn is_bricks(&self, key: &SdfAtlasKey) -> bool {
+        self.page.is_bricks(key)
+    }
+
+    /// the slot holding an entry, if it's resident
+    pub fn slot(&self, key: &SdfAtlasKey) -> Option<SdfAtlasSlot> {
+        self.page.get(key)
+    }
+
+    /// allocate a slot for an entry, or keep its existing one, evicting stale entries if needed.
+    /// new slots hold whatever was there before until generated
+    pub fn insert(&mut self, key: SdfAtlasKey, size: UVec3) -> SdfAtlasInsert {
+        self.make_room(&key, size);
+        self.page.insert(key, size)
+    }
+
+    /// free an entry's slot. it's regenerated if its entity is still visible
+    pub fn purge(&mut self, key: &SdfAtlasKey) {
+        self.page.purge(key);
+        self.purge_compressed(key);
+        self.last_used.remove(key);
+        self.coarse.remove(key);
+        self.reduced.remove(key);
+    }
+
+    /// free every slot, regenerating all visible sdfs
+    pub fn purge_all(&mut self) {
+        self.page.purge_all();
+        self.compressed.clear();
+        self.last_used.clear();
+        self.coarse.clear();
+        self.reduced.clear();
+    }
+
+    /// mark the entry used this frame ahead of inserting it into the page. if it isn't resident,
+    /// evict least recently used entries to keep within the memory budget, then while it
+    /// doesn't fit
+    pub fn make_room(&mut self, key: &SdfAtlasKey, size: UVec3) {
+        self.last_used.insert(key.clone(), self.frame);
+        if self.page.get(key).is_some() {
+            return;
+        }
+
+        let texel_bytes = atlas_texel_bytes(self.format);
+
+        // room is made in the entry's own region
+        let region = self.page.region(key);
+
+        if let Some(budget) = self.memory_budget {
+            let mut resident = self
+                .last_used
+                .keys()
+                .filter_map(|key| self.page.texels(key))
+                .sum::<usize>()
+                * texel_bytes;
+            // the bricks of an entry stored as bricks aren't known yet, so count its dense volume
+            let bytes = (size.x * size.y * size.z) as usize * texel_bytes;
+            while resident + bytes > budget {
+                let Some(evicted) = std::iter::once(region)
+                    .chain(SdfAtlasRegion::ALL)
+                    .find_map(|region| self.evict_lru(region)) else { break };
+                resident -= evicted * texel_bytes;
+            }
+        }
+
+        // a trial allocation, the caller inserts for real
+        while let SdfAtlasInsert::NoFit = self.page.insert(key.clone(), size) {
+            if self.evict_lru(region).is_none() {
+                return;
+            }
+        }
+        self.page.purge(key);
+
+        // bricks are only allocated while preprocessing, so room for them is made once an entry
+        // has failed to get its bricks
+        if region == SdfAtlasRegion::Bricks {
+            while self.page.free_bricks() < self.bricks_needed {
+                if self.evict_lru(SdfAtlasRegion::Bricks).is_none() {
+                    break;
+                }
+            }
+            self.bricks_needed = 0;
+        }
+    }
+
+    // purge an entry once no entity uses it
+    fn release(&mut self, key: &SdfAtlasKey) {
+        if self.entity_keys.values().any(|used| used == key) {
+            return;
+        }
+        self.page.release(key);
+        self.purge_compressed(key);
+        self.last_used.remove(key);
+        self.coarse.remove(key);
+        self.reduced.remove(key);
+    }
+
+    // purge the least recently used entry of a region that wasn't used this frame or last,
+    // returning the texels it occupied. the animated region is treated as a ring instead,
+    // evicting the oldest allocation not used this frame
+    fn evict_lru(&mut self, region: SdfAtlasRegion) -> Option<usize> {
+        self.last_used.retain(|key, _| self.page.get(key).is_some());
+        let key = match region {
+            SdfAtlasRegion::Animated => self.page.oldest_animated(|key| {
+                self.last_used.get(key).map_or(true, |frame| *frame < self.frame)
+                    && !self.pinned.contains(key)
+            })?,
+            SdfAtlasRegion::Static | SdfAtlasRegion::Bricks => {
+                let stale_before = self.frame.saturating_sub(1);
+                self.last_used
+                    .iter()
+                    .filter(|(key, frame)| {
+                        **frame < stale_before
+                            && !self.pinned.contains(*key)
+                            && self.page.region(key) == region
+                    })
+                    .min_by_key(|(_, frame)| **frame)
+                    .map(|(key, _)| key.clone())?
+            }
+        };
+
+        let texels = self.page.texels(&key)?;
+        self.page.purge(&key);
+        self.last_used.remove(&key);
+        self.coarse.remove(&key);
+        self.reduced.remove(&key);
+        Some(texels)
+    }
+
+    /// the atlas key used for an sdf entity. for double buffered entities, the key of the slot
+    /// currently sampled
+    pub fn key(&self, ent: Entity, sdf: &Sdf, maybe_mesh: Option<&Handle<Mesh>>) -> Option<SdfAtlasKey> {
+        if let Some(entry) = self.buffered.get(&ent) {
+            return Some(SdfAtlasKey::Buffered(ent, entry.front));
+        }
+        let key = SdfAtlasKey::try_from_sdf(ent, sdf, maybe_mesh)?;
+        if let SdfAtlasKey::Mesh(ref h) = key {
+            if let Some(hash) = self.content_hashes.get(h) {
+                return Some(SdfAtlasKey::Content(*hash));
+            }
+        }
+        Some(key)
+    }
+}
+
+// keep the entries of entities marked `SdfPinned` pinned, unpinning entries no marked entity uses
+// any more
+fn pin_marked_sdfs(
+    marked: Query<(Entity, &Sdf, Option<&Handle<Mesh>>), With<SdfPinned>>,
+    mut pinned: Local<HashSet<SdfAtlasKey>>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    let keys = marked
+        .iter()
+        .filter_map(|(ent, sdf, maybe_mesh)| atlas.key(ent, sdf, maybe_mesh))
+        .collect::<HashSet<_>>();
+
+    for key in pinned.difference(&keys) {
+        atlas.unpin(key);
+    }
+    for key in keys.iter() {
+        atlas.pin(key.clone());
+    }
+    *pinned = keys;
+}
+
+// queue the next pose of a double buffered entity into the slot the views aren't sampling, keeping
+// the front slot allocated
+fn queue_double_buffered(
+    atlas: &mut SdfAtlas,
+    budget: &mut BlockBudget,
+    ent: Entity,
+    sdf: &Sdf,
+    aabb: Aabb,
+    size: UVec3,
+) {
+    let entry = atlas.buffered.entry(ent).or_insert_with(|| SdfBufferedEntry {
+        front: false,
+        aabbs: [aabb; 2],
+        pending: false,
+    });
+    let front = SdfAtlasKey::Buffered(ent, entry.front);
+    let back = SdfAtlasKey::Buffered(ent, !entry.front);
+    let back_index = !entry.front as usize;
+    atlas.page.set_animated(&front);
+    atlas.page.set_animated(&back);
+
+    if let Some(front_size) = atlas.page.get(&front).map(|info| info.size) {
+        atlas.make_room(&front, front_size);
+        atlas.page.insert(front, front_size);
+    }
+
+    // keep showing the front pose while over budget
+    if !budget.fits(size) {
+        return;
+    }
+
+    atlas.page.purge(&back);
+    atlas.make_room(&back, size);
+    match atlas.page.insert(back.clone(), size) {
+        SdfAtlasInsert::New(_) => {
+            budget.take(dispatch_size(size, &sdf.options, true));
+            atlas.need_computing.push((ent, back, aabb));
+            let entry = atlas.buffered.get_mut(&ent).unwrap();
+            entry.aabbs[back_index] = aabb;
+            entry.pending = true;
+        }
+        _ => {
+            warn!("can't fit {} into atlas, keeping the previous pose", size);
+            atlas.page.purge(&back);
+        }
+    }
 }
 
 fn sdf_dim(aabb: &Aabb, unit_size: f32, buffer_size: f32) -> UVec3 {
@@ -202,7 +1345,11 @@ fn sdf_dim(aabb: &Aabb, unit_size: f32, buffer_size: f32) -> UVec3 {
 }
 
 impl SdfAtlasKey {
-    fn try_from_sdf(sdf: &Sdf, maybe_mesh: Option<&Handle<Mesh>>) -> Option<SdfAtlasKey> {
+    fn try_from_sdf(
+        ent: Entity,
+        sdf: &Sdf,
+        maybe_mesh: Option<&Handle<Mesh>>,
+    ) -> Option<SdfAtlasKey> {
         Some(match &sdf.mode {
             SdfGenMode::FromPrimaryMesh => match maybe_mesh {
                 Some(h) => Self::Mesh(h.clone_weak()),
@@ -210,70 +1357,729 @@ impl SdfAtlasKey {
             },
             SdfGenMode::Precomputed(h) => Self::Image(h.clone_weak()),
             SdfGenMode::FromCustomMesh(h) => Self::Mesh(h.clone_weak()),
+            SdfGenMode::FromHierarchy => Self::Hierarchy(ent),
+            SdfGenMode::FromPreprocessed(h) => Self::Preprocessed(h.clone_weak()),
+            SdfGenMode::Composite(_) => Self::Composite(ent),
         })
     }
 }
 
+// world transform of sdf entities, extracted for the view bindings (hierarchy roots don't have
+// a mesh uniform)
+#[derive(Component, Clone)]
+pub(crate) struct SdfTransform(pub Mat4);
+
+impl ExtractComponent for SdfTransform {
+    type Query = &'static GlobalTransform;
+    type Filter = With<Sdf>;
+
+    fn extract_component(item: bevy::ecs::query::QueryItem<Self::Query>) -> Self {
+        Self(item.compute_matrix())
+    }
+}
+
+/// the largest soft shadow cone radius (in world units) materials march towards this light, so
+/// `SdfGlobalSettings::auto_buffer_size` pads the sdfs it reaches far enough to contain the cones.
+/// add to point, spot or directional light entities
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SdfShadowCone {
+    pub max_radius: f32,
+}
+
+/// buffer size (in local space) derived for an sdf entity when
+/// `SdfGlobalSettings::auto_buffer_size` is set, inserted and updated by the plugin
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct SdfAutoBufferSize(pub f32);
+
+// padding added around an entity's volume: its own, the derived one, or the global default
+pub(crate) fn sdf_buffer_size(
+    options: &SdfOptions,
+    auto: Option<&SdfAutoBufferSize>,
+    settings: &SdfGlobalSettings,
+) -> f32 {
+    options
+        .buffer_size
+        .or(auto.filter(|_| settings.auto_buffer_size).map(|auto| auto.0))
+        .unwrap_or(settings.buffer_size)
+}
+
+// derive buffer sizes from the ambient distance and the shadow cones of the lights in range of
+// each entity's world bounds
+fn update_auto_buffer_sizes(
+    mut commands: Commands,
+    settings: Res<SdfGlobalSettings>,
+    sdfs: Query<(Entity, &Sdf, &GlobalTransform, Option<&Aabb>, Option<&SdfAutoBufferSize>)>,
+    point_lights: Query<(&PointLight, &GlobalTransform, &SdfShadowCone)>,
+    spot_lights: Query<(&SpotLight, &GlobalTransform, &SdfShadowCone)>,
+    directional_lights: Query<&SdfShadowCone, With<DirectionalLight>>,
+) {
+    if !settings.auto_buffer_size {
+        for (ent, .., maybe_auto) in sdfs.iter() {
+            if maybe_auto.is_some() {
+                commands.entity(ent).remove::<SdfAutoBufferSize>();
+            }
+        }
+        return;
+    }
+
+    let directional = directional_lights
+        .iter()
+        .map(|cone| cone.max_radius)
+        .fold(0.0, f32::max);
+    let positional = point_lights
+        .iter()
+        .map(|(light, transform, cone)| (transform.translation(), light.range, cone.max_radius))
+        .chain(
+            spot_lights
+                .iter()
+                .map(|(light, transform, cone)| (transform.translation(), light.range, cone.max_radius)),
+        )
+        .collect::<Vec<_>>();
+
+    for (ent, sdf, transform, maybe_aabb, maybe_auto) in sdfs.iter() {
+        let aabb = maybe_aabb.unwrap_or(&sdf.aabb);
+        let (scale, _, _) = transform.to_scale_rotation_translation();
+        let center = transform.transform_point(aabb.center.into());
+        let radius = aabb.half_extents.length() * scale.max_element();
+
+        let world_size = positional
+            .iter()
+            .filter(|(position, range, _)| position.distance(center) - radius < *range)
+            .map(|(_, _, cone_radius)| *cone_radius)
+            .fold(settings.ambient_distance.max(directional), f32::max);
+        // in local space, along the most shrunk axis
+        let auto = SdfAutoBufferSize(world_size / scale.min_element().max(1e-6));
+
+        if maybe_auto != Some(&auto) {
+            commands.entity(ent).insert(auto);
+        }
+    }
+}
+
+/// generation state of an sdf entity, inserted and updated by the plugin
+#[derive(Component, Clone, Debug, PartialEq)]
+pub enum SdfStatus {
+    // queued for generation this frame
+    Pending,
+    // generated at coarse resolution, waiting for refinement
+    Coarse,
+    // generated at full resolution
+    Full,
+    // generated at reduced resolution because the full resolution didn't fit
+    Reduced,
+    // generation failed, an analytic aabb occluder is used instead
+    Fallback,
+    // evicted while far from every camera, see `SdfGlobalSettings::streaming`
+    Streamed,
+    Failed(SdfFailReason),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdfFailReason {
+    // `FromPrimaryMesh` used on an entity without a mesh, or no meshes in the hierarchy
+    NoMesh,
+    // the source mesh asset isn't loaded (retried every frame)
+    MeshNotLoaded,
+    // the precomputed image isn't loaded (retried every frame)
+    ImageNotLoaded,
+    // the sdf doesn't fit in the atlas
+    NoFit,
+    // the mesh is too dense to split across dispatches at its resolution, as the partial
+    // distances of its voxels must fit a storage buffer binding on this device (see
+    // `max_storage_buffer_binding_size`). decimate it, use a simplified custom mesh or lower its
+    // resolution
+    TooManyFeatures,
+}
+
+pub(crate) fn set_status(
+    commands: &mut Commands,
+    ent: Entity,
+    current: Option<&SdfStatus>,
+    status: SdfStatus,
+) {
+    if current != Some(&status) {
+        commands.entity(ent).insert(status);
+    }
+}
+
+// handle a failed entity according to its failure policy
+pub(crate) fn apply_failure_policy(
+    commands: &mut Commands,
+    fallbacks: &mut HashMap<Entity, Aabb>,
+    ent: Entity,
+    sdf: &Sdf,
+    occluder: Option<Aabb>,
+    current: Option<&SdfStatus>,
+    reason: SdfFailReason,
+) {
+    // a box is a poor stand-in for inside-out geometry
+    let occluder = occluder.filter(|_| !sdf.options.invert);
+
+    match (sdf.options.failure_policy, occluder) {
+        (SdfFailurePolicy::AabbOccluder | SdfFailurePolicy::ReduceResolution { .. }, Some(aabb)) => {
+            // warn once as the entity falls back, not on every retry
+            if current != Some(&SdfStatus::Fallback) {
+                warn!("sdf generation failed for {:?}: {:?}, using an aabb occluder", ent, reason);
+            }
+            fallbacks.insert(ent, aabb);
+            set_status(commands, ent, current, SdfStatus::Fallback);
+            return;
+        }
+        (SdfFailurePolicy::Skip, _) => (),
+        _ => warn!("sdf generation failed for {:?}: {:?}", ent, reason),
+    }
+
+    set_status(commands, ent, current, SdfStatus::Failed(reason));
+}
+
 fn queue_sdfs(
+    mut commands: Commands,
     sdf_settings: Res<SdfGlobalSettings>,
     mut items: Query<(
         Entity,
         &mut Sdf,
         &GlobalTransform,
         &ComputedVisibility,
-        &Aabb,
+        Option<&Aabb>,
         Option<&SkinnedMesh>,
         Option<&Handle<Mesh>>,
+        Option<&SdfStatus>,
+        Option<(&SdfMorphTargets, ChangeTrackers<SdfMorphTargets>)>,
+        Option<ChangeTrackers<SdfDeltas>>,
+        Option<&SdfAutoBufferSize>,
     )>,
     aabb_builder: AnimatedAabbBuilder,
+    hierarchy: SdfHierarchy,
+    preprocessed: Res<Assets<PreprocessedMesh>>,
+    meshes: Res<Assets<Mesh>>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    removed_deltas: RemovedComponents<SdfDeltas>,
+    prebake: Res<SdfPrebakeSet>,
+    compute_budget: Res<SdfComputeBudget>,
+    tier: Res<SdfQualityTier>,
+    priorities: Query<&SdfPriority>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut tier_resolution: Local<Option<f32>>,
+    pipeline_status: Res<SdfPipelineStatus>,
     mut atlas: ResMut<SdfAtlas>,
 ) {
-    atlas.page.remove_all();
+    let mut budget = BlockBudget::new(
+        tier.max_blocks_per_frame
+            .or(compute_budget.max_blocks_per_frame),
+    );
+
+    // regenerate everything when the quality tier's resolution changes
+    if *tier_resolution != Some(tier.resolution_multiplier) {
+        if tier_resolution.is_some() {
+            atlas.purge_all();
+        }
+        *tier_resolution = Some(tier.resolution_multiplier);
+    }
+
+    // update content hashes for deduplication, and regenerate the entries of meshes edited in
+    // place, which would otherwise stay settled with the old geometry
+    for event in mesh_events.iter() {
+        match event {
+            AssetEvent::Modified { handle } => {
+                let mut stale = vec![SdfAtlasKey::Mesh(handle.clone_weak())];
+                stale.extend(atlas.content_hashes.remove(handle).map(SdfAtlasKey::Content));
+                for (ent, sdf, _, _, _, _, maybe_mesh, ..) in items.iter() {
+                    let uses_mesh = match sdf.mode {
+                        SdfGenMode::FromPrimaryMesh => maybe_mesh == Some(handle),
+                        SdfGenMode::FromCustomMesh(ref h) => h == handle,
+                        _ => false,
+                    };
+                    if uses_mesh {
+                        stale.extend(atlas.entity_keys.get(&ent).cloned());
+                    }
+                }
+                for key in stale {
+                    atlas.purge(&key);
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                atlas.content_hashes.remove(handle);
+            }
+            _ => (),
+        }
+    }
+
     atlas.need_computing.clear();
-    for (ent, mut sdf, _g_trans, vis, aabb, maybe_skin, maybe_mesh) in items.iter_mut() {
-        let Some(key) = SdfAtlasKey::try_from_sdf(&sdf, maybe_mesh) else {continue};
+    atlas.sparse.clear();
 
-        let mut use_aabb = aabb.clone();
-        sdf.skinned = maybe_skin.is_some();
+    // entities whose deltas were removed regenerate from the base image alone
+    let removed_deltas = removed_deltas.iter().collect::<HashSet<_>>();
 
-        if maybe_skin.is_some() {
-            // purge previous instance of animated items (no point in clogging up the atlas)
+    // nothing queued now would be dispatched, leave the atlas as it is and queue once the compute
+    // pipelines have compiled
+    if !pipeline_status.is_ready(sdf_settings.compute_workgroup) {
+        return;
+    }
+
+    // likewise without a 3d camera, as the compute node only runs as part of the 3d graph
+    if sdf_settings.require_3d_camera && !cameras.iter().any(|(camera, _)| camera.is_active) {
+        return;
+    }
+
+    let skipped = pipeline_status.take_skipped();
+
+    // double buffered entries switch to the slot written last frame. both slots are freed when
+    // the entity is gone, or double buffering is switched off
+    let double_buffer = sdf_settings.double_buffer_animated;
+    let mut released = Vec::new();
+    atlas.buffered.retain(|ent, entry| {
+        let exists = double_buffer && items.get(*ent).is_ok();
+        if !exists {
+            released.push(SdfAtlasKey::Buffered(*ent, entry.front));
+            released.push(SdfAtlasKey::Buffered(*ent, !entry.front));
+        }
+        exists
+    });
+    for (ent, entry) in atlas.buffered.iter_mut() {
+        if entry.pending && !skipped.contains(ent) {
+            entry.front = !entry.front;
+        }
+        entry.pending = false;
+    }
+
+    // entries the compute node had to skip are regenerated from scratch. a double buffered
+    // entity's back slot is regenerated anyway, and its front slot is still intact
+    for ent in skipped {
+        if atlas.buffered.contains_key(&ent) {
+            continue;
+        }
+        let Ok((_, sdf, _, _, _, _, maybe_mesh, ..)) = items.get(ent) else { continue };
+        if let Some(key) = atlas.key(ent, sdf, maybe_mesh) {
             atlas.page.purge(&key);
+        }
+    }
 
+    // entries stay allocated until the last entity using them loses its sdf, rather than being
+    // rebuilt every frame
+    atlas.entity_keys.retain(|ent, key| {
+        let exists = items.get(*ent).is_ok();
+        if !exists {
+            released.push(key.clone());
+        }
+        exists
+    });
+    for key in released {
+        atlas.release(&key);
+    }
+    atlas.generated_with.retain(|ent, _| items.get(*ent).is_ok());
+    atlas.streamed_out.retain(|ent| items.get(*ent).is_ok());
+
+    atlas.fallbacks.clear();
+    atlas.frame += 1;
+    atlas.memory_budget = sdf_settings.atlas_memory_budget;
+
+    if sdf_settings.dedupe_meshes {
+        for (ent, sdf, _, _, _, maybe_skin, maybe_mesh, ..) in items.iter() {
+            // animated meshes can't share
+            if maybe_skin.is_some() {
+                continue;
+            }
+            let Some(SdfAtlasKey::Mesh(handle)) = SdfAtlasKey::try_from_sdf(ent, sdf, maybe_mesh) else { continue };
+            if atlas.content_hashes.contains_key(&handle) {
+                continue;
+            }
+            let Some(mesh) = meshes.get(&handle) else { continue };
+            atlas.content_hashes.insert(handle, mesh_content_hash(mesh));
+        }
+    } else {
+        atlas.content_hashes.clear();
+    }
+
+    // coarse entries which could be refined this frame
+    let mut refine_candidates = Vec::new();
+
+    // pinned entries are queued first so they get space before anything else, then static entries
+    // ahead of animated ones, each by priority so the atlas space and compute budget go to the
+    // entries that matter most
+    let camera_positions = cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .map(|(_, transform)| transform.translation())
+        .collect::<Vec<_>>();
+    let mut items = items.iter_mut().collect::<Vec<_>>();
+    items.sort_by_cached_key(|(ent, sdf, transform, _, maybe_aabb, maybe_skin, maybe_mesh, ..)| {
+        let aabb = maybe_aabb.unwrap_or(&sdf.aabb);
+        let center = transform.transform_point(aabb.center.into());
+        let radius = aabb.half_extents.length() * transform.to_scale_rotation_translation().0.max_element();
+        // roughly the fraction of the view the entity covers from the nearest camera
+        let projected_size = camera_positions
+            .iter()
+            .map(|camera| radius / camera.distance(center).max(0.01))
+            .fold(0.0, f32::max);
+        let priority = priorities.get(*ent).map_or(0.0, |p| p.0);
+        (
+            !atlas.key(*ent, sdf, *maybe_mesh).map_or(false, |key| atlas.is_pinned(&key)),
+            maybe_skin.is_some(),
+            std::cmp::Reverse(FloatOrd(priority + projected_size)),
+        )
+    });
+
+    for (
+        ent,
+        mut sdf,
+        g_trans,
+        vis,
+        maybe_aabb,
+        maybe_skin,
+        maybe_mesh,
+        maybe_status,
+        maybe_morph,
+        maybe_deltas,
+        maybe_auto_buffer,
+    ) in items
+    {
+        let Some(key) = atlas.key(ent, &sdf, maybe_mesh) else {
+            apply_failure_policy(&mut commands, &mut atlas.fallbacks, ent, &sdf, maybe_aabb.cloned(), maybe_status, SdfFailReason::NoMesh);
+            continue;
+        };
+
+        // wait a frame for the derived buffer size rather than bake with the wrong one
+        if sdf_settings.auto_buffer_size && sdf.options.buffer_size.is_none() && maybe_auto_buffer.is_none() {
+            continue;
+        }
+
+        // edited options regenerate the entry. a new mode only does when the entity keeps its
+        // entry, a different entry is released below and the new one is valid as it is
+        if sdf.is_changed() {
+            let generation = (sdf.mode.clone(), sdf.options.clone());
+            if let Some((mode, options)) = atlas.generated_with.insert(ent, generation) {
+                let same_key = atlas.entity_keys.get(&ent) == Some(&key);
+                if sdf.options.regenerates(&options) || (mode != sdf.mode && same_key) {
+                    atlas.purge(&key);
+                }
+            }
+        }
+
+        // far from every camera, evict the entry and wait until a camera is back within range
+        let streaming = sdf_settings
+            .streaming
+            .filter(|_| !camera_positions.is_empty() && !atlas.is_pinned(&key));
+        if let Some(streaming) = streaming {
+            let aabb = maybe_aabb.unwrap_or(&sdf.aabb);
+            let center = g_trans.transform_point(aabb.center.into());
+            let radius = aabb.half_extents.length() * g_trans.to_scale_rotation_translation().0.max_element();
+            let distance = camera_positions
+                .iter()
+                .map(|camera| camera.distance(center) - radius)
+                .fold(f32::MAX, f32::min);
+            let streamed_out = atlas.streamed_out.contains(&ent);
+            let range = match streamed_out {
+                true => streaming.load_distance,
+                false => streaming.unload_distance.max(streaming.load_distance),
+            };
+            if distance > range {
+                if !streamed_out {
+                    atlas.streamed_out.insert(ent);
+                    if let Some(previous) = atlas.entity_keys.remove(&ent) {
+                        atlas.release(&previous);
+                    }
+                    if atlas.buffered.remove(&ent).is_some() {
+                        atlas.purge(&SdfAtlasKey::Buffered(ent, false));
+                        atlas.purge(&SdfAtlasKey::Buffered(ent, true));
+                    }
+                }
+                set_status(&mut commands, ent, maybe_status, SdfStatus::Streamed);
+                continue;
+            }
+        }
+        atlas.streamed_out.remove(&ent);
+
+        // static entries which are resident and unchanged need no atlas work at all
+        let settled = maybe_skin.is_none()
+            && !sdf.is_changed()
+            && !maybe_morph.map_or(false, |(_, changed)| changed.is_changed())
+            && !maybe_deltas.map_or(false, |deltas| deltas.is_changed())
+            && !removed_deltas.contains(&ent)
+            && matches!(maybe_status, Some(SdfStatus::Full | SdfStatus::Reduced))
+            && atlas.entity_keys.get(&ent) == Some(&key)
+            && (atlas.page.get(&key).is_some() || atlas.is_compressed(&key));
+        if settled {
             if vis.is_visible() {
+                atlas.last_used.insert(key, atlas.frame);
+            }
+            continue;
+        }
+
+        // double buffered slots are released with their entry in `buffered`
+        if maybe_skin.is_none() || !sdf_settings.double_buffer_animated {
+            if let Some(previous) = atlas.entity_keys.insert(ent, key.clone()) {
+                if previous != key {
+                    atlas.release(&previous);
+                }
+            }
+        }
+
+        let Some(mut use_aabb) = (match sdf.mode {
+            SdfGenMode::FromHierarchy => hierarchy.aabb(ent),
+            SdfGenMode::FromPreprocessed(ref h) => preprocessed.get(h).map(|p| p.aabb),
+            _ => maybe_aabb.cloned(),
+        }) else {
+            apply_failure_policy(&mut commands, &mut atlas.fallbacks, ent, &sdf, None, maybe_status, SdfFailReason::NoMesh);
+            continue;
+        };
+        sdf.skinned = maybe_skin.is_some();
+
+        // static entries can be stored as bricks, if there is a brick region. restored entries are
+        // copied in dense
+        let bricks = maybe_skin.is_none()
+            && sdf.options.stores_bricks(&sdf.mode)
+            && !atlas.restored.contains_key(&ent);
+        atlas.page.set_bricks(&key, bricks);
+
+        if maybe_skin.is_some() {
+            // animated entries are packed into their own region, if there is one
+            atlas.page.set_animated(&key);
+
+            if !vis.is_visible() && !atlas.is_pinned(&key) {
+                // purge previous instance of hidden animated items (no point in clogging up the atlas)
+                atlas.page.purge(&key);
+                if let Some(entry) = atlas.buffered.remove(&ent) {
+                    atlas.page.purge(&SdfAtlasKey::Buffered(ent, !entry.front));
+                }
+            } else {
                 // update animated item aabbs
                 use_aabb = match sdf.mode {
                     SdfGenMode::FromPrimaryMesh => aabb_builder.animated_aabb(ent).unwrap(),
-                    SdfGenMode::Precomputed(_) => {
+                    SdfGenMode::Precomputed(_) | SdfGenMode::Composite(_) => {
                         panic!("can't use precomputed sdf with animated meshes")
                     }
                     SdfGenMode::FromCustomMesh(ref h) => {
                         aabb_builder.animated_aabb_for_mesh(ent, h).unwrap()
                     }
+                    SdfGenMode::FromHierarchy => {
+                        panic!("can't use hierarchy sdf with animated meshes")
+                    }
+                    SdfGenMode::FromPreprocessed(_) => {
+                        panic!("can't use preprocessed sdf with animated meshes")
+                    }
                 };
             }
         }
 
-        let buffer_size = sdf.options.buffer_size.unwrap_or(sdf_settings.buffer_size);
+        if maybe_deltas.map_or(false, |deltas| deltas.is_changed())
+            || removed_deltas.contains(&ent)
+        {
+            // reapply the deltas over the base
+            atlas.purge(&key);
+        }
+
+        if let Some((morph, morph_changed)) = maybe_morph {
+            if morph_changed.is_changed() && maybe_skin.is_none() {
+                // regenerate with the new weights
+                atlas.purge(&key);
+            }
+            use_aabb.half_extents += morph.max_offset();
+        }
+
+        // compressed entries have no slot, and stay compressed until purged
+        if maybe_skin.is_none() && atlas.is_compressed(&key) {
+            let status = match atlas.reduced.contains_key(&key) {
+                true => SdfStatus::Reduced,
+                false => SdfStatus::Full,
+            };
+            set_status(&mut commands, ent, maybe_status, status);
+            continue;
+        }
+
+        // the dilated surface extends past the geometry
+        use_aabb.half_extents += sdf.options.weld_margin;
+
+        // the aabb before padding, for analytic fallbacks
+        let occluder_aabb = use_aabb;
+
+        let buffer_size = sdf_buffer_size(&sdf.options, maybe_auto_buffer, &sdf_settings);
         use_aabb.half_extents += buffer_size;
 
-        if vis.is_visible() {
-            let dims = sdf_dim(
-                &use_aabb,
-                sdf_settings.unit_size / sdf.options.scale_multiplier,
-                buffer_size,
-            );
-            let res = atlas.page.insert(key.clone(), dims + 1);
+        // static entities can also be generated ahead of time by prebake triggers
+        let prebaking = maybe_skin.is_none() && prebake.0.contains(&ent);
+        let animated = maybe_skin.is_some() || maybe_morph.is_some();
+
+        if vis.is_visible() || prebaking {
+            let unit_size = sdf_settings.unit_size / (sdf.options.scale_multiplier * tier.resolution_multiplier);
+            let dims = sdf_dim(&use_aabb, unit_size, buffer_size);
+
+            // entries waiting for refinement keep their coarse size, and reduced entries stay reduced
+            let insert_size = atlas
+                .coarse
+                .get(&key)
+                .or_else(|| atlas.reduced.get(&key))
+                .copied()
+                .unwrap_or(dims + 1);
+
+            if maybe_skin.is_some() && sdf_settings.double_buffer_animated {
+                queue_double_buffered(&mut atlas, &mut budget, ent, &sdf, use_aabb, insert_size);
+                sdf.aabb = use_aabb;
+                let has_front = atlas
+                    .key(ent, &sdf, maybe_mesh)
+                    .map_or(false, |key| atlas.page.get(&key).is_some());
+                let status = match has_front {
+                    true => SdfStatus::Full,
+                    false => SdfStatus::Pending,
+                };
+                set_status(&mut commands, ent, maybe_status, status);
+                continue;
+            }
+
+            // animated entries are regenerated every frame, keeping the previous pose while over budget
+            if maybe_skin.is_some() {
+                if !budget.fits(insert_size) {
+                    if let Some(size) = atlas.page.get(&key).map(|info| info.size) {
+                        atlas.make_room(&key, size);
+                        atlas.page.insert(key.clone(), size);
+                    }
+                    continue;
+                }
+
+                // a pose inside the previous volume can be written over the previous pose
+                let reuse = sdf_settings.sparse_skinned_updates
+                    && atlas.page.get(&key).map_or(false, |info| info.size == insert_size)
+                    && use_aabb.min().cmpge(sdf.aabb.min()).all()
+                    && use_aabb.max().cmple(sdf.aabb.max()).all();
+                if reuse {
+                    atlas.make_room(&key, insert_size);
+                    atlas.page.insert(key.clone(), insert_size);
+                    budget.take(insert_size);
+                    atlas.need_computing.push((ent, key.clone(), sdf.aabb));
+                    atlas.sparse.insert(ent);
+                    let status = match atlas.reduced.contains_key(&key) {
+                        true => SdfStatus::Reduced,
+                        false => SdfStatus::Full,
+                    };
+                    set_status(&mut commands, ent, maybe_status, status);
+                    continue;
+                }
+                atlas.page.purge(&key);
+            }
+
+            let mut size = insert_size;
+            atlas.make_room(&key, insert_size);
+            let mut res = atlas.page.insert(key.clone(), insert_size);
+
+            // static entries are baked coarse first
+            let coarse_first = maybe_skin.is_none() && sdf_settings.coarse_scale < 1.0;
+            if coarse_first && matches!(res, SdfAtlasInsert::New(_)) && !atlas.coarse.contains_key(&key) {
+                let coarse_size =
+                    sdf_dim(&use_aabb, unit_size / sdf_settings.coarse_scale, buffer_size) + 1;
+                if coarse_size != insert_size {
+                    atlas.page.purge(&key);
+                    size = coarse_size;
+                    atlas.make_room(&key, coarse_size);
+                    res = atlas.page.insert(key.clone(), coarse_size);
+                    atlas.coarse.insert(key.clone(), coarse_size);
+                }
+            }
 
             match res {
-                atlas3d::Slot::New(_) => {
+                SdfAtlasInsert::New(_) if !budget.take(dispatch_size(size, &sdf.options, animated)) => {
+                    // over this frame's compute budget, retry next frame
+                    atlas.page.purge(&key);
+                    atlas.coarse.remove(&key);
+                    set_status(&mut commands, ent, maybe_status, SdfStatus::Pending);
+                }
+                SdfAtlasInsert::New(_) => {
                     // println!("queue: {}", dims);
                     atlas.need_computing.push((ent, key, use_aabb.clone()));
                     sdf.aabb = use_aabb;
+                    set_status(&mut commands, ent, maybe_status, SdfStatus::Pending);
+                }
+                SdfAtlasInsert::NoFit => {
+                    // compaction only packs the dense static entries
+                    atlas.fragmented |= atlas.page.region(&key) == SdfAtlasRegion::Static;
+                    atlas.coarse.remove(&key);
+                    atlas.reduced.remove(&key);
+
+                    let mut reduced_size = None;
+                    if let SdfFailurePolicy::ReduceResolution { min_scale } = sdf.options.failure_policy {
+                        let mut scale = 0.5;
+                        while scale >= min_scale && reduced_size.is_none() {
+                            let size = sdf_dim(&use_aabb, unit_size / scale, buffer_size) + 1;
+                            atlas.make_room(&key, size);
+                            if let SdfAtlasInsert::New(_) = atlas.page.insert(key.clone(), size) {
+                                reduced_size = Some(size);
+                            }
+                            scale *= 0.5;
+                        }
+                    }
+
+                    match reduced_size {
+                        Some(size) if !budget.take(dispatch_size(size, &sdf.options, animated)) => {
+                            atlas.page.purge(&key);
+                            set_status(&mut commands, ent, maybe_status, SdfStatus::Pending);
+                        }
+                        Some(size) => {
+                            atlas.reduced.insert(key.clone(), size);
+                            atlas.need_computing.push((ent, key, use_aabb.clone()));
+                            sdf.aabb = use_aabb;
+                            set_status(&mut commands, ent, maybe_status, SdfStatus::Pending);
+                        }
+                        None => apply_failure_policy(
+                            &mut commands,
+                            &mut atlas.fallbacks,
+                            ent,
+                            &sdf,
+                            Some(occluder_aabb),
+                            maybe_status,
+                            SdfFailReason::NoFit,
+                        ),
+                    }
+                }
+                SdfAtlasInsert::Existing(_) => {
+                    if atlas.coarse.contains_key(&key) {
+                        set_status(&mut commands, ent, maybe_status, SdfStatus::Coarse);
+                        refine_candidates.push((ent, key, dims, use_aabb, sdf.options.clone()));
+                    } else if atlas.reduced.contains_key(&key) {
+                        set_status(&mut commands, ent, maybe_status, SdfStatus::Reduced);
+                    } else {
+                        set_status(&mut commands, ent, maybe_status, SdfStatus::Full);
+                    }
+                }
+            }
+        }
+    }
+
+    // refine pinned entries, then the largest coarse entries first, they contribute the most occlusion
+    refine_candidates.sort_by_key(|(_, key, _, aabb, _)| {
+        (
+            !atlas.is_pinned(key),
+            std::cmp::Reverse(FloatOrd(aabb.half_extents.x * aabb.half_extents.y * aabb.half_extents.z)),
+        )
+    });
+    for (ent, key, dims, aabb, options) in refine_candidates
+        .into_iter()
+        .take(tier.refinements_per_frame.unwrap_or(sdf_settings.refinements_per_frame))
+    {
+        // the coarse version stays in use until there's budget to replace it
+        // coarse entries are static
+        if !budget.fits(dispatch_size(dims + 1, &options, false)) {
+            break;
+        }
+
+        atlas.page.purge(&key);
+        atlas.make_room(&key, dims + 1);
+        match atlas.page.insert(key.clone(), dims + 1) {
+            SdfAtlasInsert::New(_) => {
+                atlas.coarse.remove(&key);
+                budget.take(dispatch_size(dims + 1, &options, false));
+            }
+            _ => {
+                // doesn't fit at full resolution, regenerate the coarse version and keep it as a
+                // reduced entry, so it settles instead of retrying every frame
+                warn!("can't fit {} into atlas, keeping coarse sdf", dims + 1);
+                let Some(coarse_size) = atlas.coarse.remove(&key) else { continue };
+                atlas.make_room(&key, coarse_size);
+                if let SdfAtlasInsert::NoFit = atlas.page.insert(key.clone(), coarse_size) {
+                    // queued again from scratch next frame
+                    continue;
                 }
-                atlas3d::Slot::NoFit => warn!("can't fit {} into atlas", dims + 1),
-                atlas3d::Slot::Existing(_) => (),
+                atlas.reduced.insert(key.clone(), coarse_size);
+                budget.take(dispatch_size(coarse_size, &options, false));
             }
         }
+        atlas.need_computing.push((ent, key, aabb));
     }
 }
@@ -0,0 +1,41 @@
+struct MipParams {
+    // region of the target level to write
+    position: vec3<u32>,
+    size: vec3<u32>,
+};
+
+@group(0) @binding(0)
+var source: texture_3d<f32>;
+@group(0) @binding(1)
+#ifdef ATLAS_R16FLOAT
+var texture: texture_storage_3d<r16float, write>;
+#else
+var texture: texture_storage_3d<r32float, write>;
+#endif
+@group(0) @binding(2)
+var<uniform> params: MipParams;
+
+// each texel takes the minimum of the 2x2x2 texels it covers in the level above, so a coarse
+// level never reports more clearance than the finer samples beneath it
+@compute @workgroup_size(4, 4, 4)
+fn downsample(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= params.size)) {
+        return;
+    }
+
+    let target_position = params.position + invocation_id;
+    // odd sized levels have a last texel covering a single source texel
+    let source_max = vec3<u32>(textureDimensions(source)) - 1u;
+
+    var distance = 3.40282347e+38;
+    for (var z = 0u; z < 2u; z = z + 1u) {
+        for (var y = 0u; y < 2u; y = y + 1u) {
+            for (var x = 0u; x < 2u; x = x + 1u) {
+                let source_position = min(target_position * 2u + vec3<u32>(x, y, z), source_max);
+                distance = min(distance, textureLoad(source, vec3<i32>(source_position), 0).r);
+            }
+        }
+    }
+
+    textureStore(texture, vec3<i32>(target_position), vec4<f32>(distance, 0.0, 0.0, 0.0));
+}
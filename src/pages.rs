@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+
+use atlas3d::AtlasPage;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{cpu::BRICK_SIZE, SdfAtlasInsert, SdfAtlasKey, SdfAtlasSlot};
+
+// the parts of the atlas entries are allocated from, each making room by evicting its own entries
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum SdfAtlasRegion {
+    // dense static entries
+    Static,
+    // entries stored as bricks, see `SdfOptions::bricks`
+    Bricks,
+    // animated entries, see `SdfGlobalSettings::animated_region_depth`
+    Animated,
+}
+
+impl SdfAtlasRegion {
+    pub const ALL: [SdfAtlasRegion; 3] = [Self::Static, Self::Bricks, Self::Animated];
+}
+
+// the bricks of entries stored sparsely, in a region of the atlas between the static and
+// animated regions. each entry also gets a slot in the indirection texture with a texel per brick
+// of its volume
+#[derive(Clone)]
+struct SdfBrickRegion {
+    // first texel of the region
+    origin: UVec3,
+    // bricks along each axis
+    dimensions: UVec3,
+    // unallocated bricks, lowest index last
+    free: Vec<u32>,
+    // bricks held by each entry
+    allocated: HashMap<SdfAtlasKey, Vec<u32>>,
+    // indirection slots, one texel per brick of the entry's volume
+    indirection: AtlasPage<SdfAtlasKey>,
+    indirection_dim: UVec3,
+    // keys stored as bricks
+    keys: HashSet<SdfAtlasKey>,
+}
+
+impl SdfBrickRegion {
+    fn brick_origin(&self, index: u32) -> UVec3 {
+        let d = self.dimensions;
+        self.origin + UVec3::new(index % d.x, (index / d.x) % d.y, index / (d.x * d.y)) * BRICK_SIZE
+    }
+
+    fn release_bricks(&mut self, key: &SdfAtlasKey) {
+        if let Some(bricks) = self.allocated.remove(key) {
+            self.free.extend(bricks);
+            self.free.sort_unstable_by(|a, b| b.cmp(a));
+        }
+    }
+}
+
+// the allocators behind `SdfAtlas`. static entries are packed into the main page, and with
+// `SdfGlobalSettings::animated_region_depth` set, animated entries are packed into a separate
+// region at the far end of the z axis, so their churn never fragments the static entries. with
+// `SdfGlobalSettings::brick_region_depth` set, the bricks of entries stored sparsely fill the
+// texels in front of the animated region. entries moved into the compressed atlas (see
+// `SdfOptions::compress`) are packed separately, in 4x4 blocks
+#[derive(Clone)]
+pub(crate) struct SdfAtlasPages {
+    statics: AtlasPage<SdfAtlasKey>,
+    bricks: Option<SdfBrickRegion>,
+    animated: Option<AtlasPage<SdfAtlasKey>>,
+    // first texel of the animated region
+    animated_origin: UVec3,
+    // keys allocated from the animated region
+    animated_keys: HashSet<SdfAtlasKey>,
+    // resident entries of the animated region, oldest allocation first
+    animated_order: VecDeque<SdfAtlasKey>,
+    // the compressed atlas in 4x4 blocks, with layers in z
+    compressed: Option<AtlasPage<SdfAtlasKey>>,
+    // size of the whole atlas in texels
+    pub dim: UVec3,
+}
+
+impl SdfAtlasPages {
+    pub fn new(dim: UVec3, animated_depth: u32, brick_depth: u32, compressed_dim: UVec3) -> Self {
+        let animated_depth = animated_depth.min(dim.z.saturating_sub(1));
+        let brick_depth =
+            brick_depth.min(dim.z.saturating_sub(1) - animated_depth) / BRICK_SIZE * BRICK_SIZE;
+        let static_dim = UVec3::new(dim.x, dim.y, dim.z - animated_depth - brick_depth);
+
+        let brick_dimensions = UVec3::new(dim.x, dim.y, brick_depth) / BRICK_SIZE;
+        let brick_count = brick_dimensions.x * brick_dimensions.y * brick_dimensions.z;
+        let indirection_dim = (dim / BRICK_SIZE).max(UVec3::ONE);
+        let bricks = (brick_count > 0).then(|| SdfBrickRegion {
+            origin: UVec3::new(0, 0, static_dim.z),
+            dimensions: brick_dimensions,
+            free: (0..brick_count).rev().collect(),
+            allocated: HashMap::default(),
+            indirection: AtlasPage::new(indirection_dim),
+            indirection_dim,
+            keys: HashSet::default(),
+        });
+
+        Self {
+            statics: AtlasPage::new(static_dim),
+            bricks,
+            animated: (animated_depth > 0)
+                .then(|| AtlasPage::new(UVec3::new(dim.x, dim.y, animated_depth))),
+            animated_origin: UVec3::new(0, 0, static_dim.z + brick_depth),
+            animated_keys: HashSet::default(),
+            animated_order: VecDeque::new(),
+            compressed: (compressed_dim != UVec3::ZERO)
+                .then(|| AtlasPage::new(compressed_dim / UVec3::new(4, 4, 1))),
+            dim,
+        }
+    }
+
+    // allocate the key from the animated region from now on, if there is one. a resident static
+    // entry is purged
+    pub fn set_animated(&mut self, key: &SdfAtlasKey) {
+        if self.animated.is_none() || self.animated_keys.contains(key) {
+            return;
+        }
+        self.statics.purge(key);
+        self.animated_keys.insert(key.clone());
+    }
+
+    pub fn is_animated(&self, key: &SdfAtlasKey) -> bool {
+        self.animated_keys.contains(key)
+    }
+
+    // store the key as bricks from now on, or densely again, if there is a brick region. a
+    // resident entry stored the other way is purged
+    pub fn set_bricks(&mut self, key: &SdfAtlasKey, bricks: bool) {
+        if self.bricks.is_none() || self.is_bricks(key) == bricks {
+            return;
+        }
+        self.purge(key);
+        let region = self.bricks.as_mut().unwrap();
+        match bricks {
+            true => region.keys.insert(key.clone()),
+            false => region.keys.remove(key),
+        };
+    }
+
+    pub fn is_bricks(&self, key: &SdfAtlasKey) -> bool {
+        self.bricks
+            .as_ref()
+            .map_or(false, |region| region.keys.contains(key))
+    }
+
+    pub fn region(&self, key: &SdfAtlasKey) -> SdfAtlasRegion {
+        if self.is_bricks(key) {
+            SdfAtlasRegion::Bricks
+        } else if self.is_animated(key) && self.animated.is_some() {
+            SdfAtlasRegion::Animated
+        } else {
+            SdfAtlasRegion::Static
+        }
+    }
+
+    // size of the indirection texture, a single texel without a brick region
+    pub fn indirection_dim(&self) -> UVec3 {
+        self.bricks
+            .as_ref()
+            .map_or(UVec3::ONE, |region| region.indirection_dim)
+    }
+
+    // texels the entry occupies, its bricks for entries stored as bricks
+    pub fn texels(&self, key: &SdfAtlasKey) -> Option<usize> {
+        let slot = self.get(key)?;
+        match self.bricks.as_ref().filter(|region| region.keys.contains(key)) {
+            Some(region) => {
+                let bricks = region.allocated.get(key).map_or(0, Vec::len);
+                Some(bricks * (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as usize)
+            }
+            None => Some((slot.size.x * slot.size.y * slot.size.z) as usize),
+        }
+    }
+
+    // (re)allocate the bricks of a resident entry stored as bricks, returning the first texel of
+    // each, or none if the region can't hold them all
+    pub fn allocate_bricks(&mut self, key: &SdfAtlasKey, count: usize) -> Option<Vec<UVec3>> {
+        let region = self.bricks.as_mut()?;
+        region.release_bricks(key);
+        if region.free.len() < count {
+            return None;
+        }
+        let bricks = region.free.split_off(region.free.len() - count);
+        let origins = bricks.iter().map(|index| region.brick_origin(*index)).collect();
+        region.allocated.insert(key.clone(), bricks);
+        Some(origins)
+    }
+
+    // bricks currently allocated to entries
+    pub fn allocated_bricks(&self) -> usize {
+        self.bricks
+            .as_ref()
+            .map_or(0, |region| region.allocated.values().map(Vec::len).sum())
+    }
+
+    pub fn free_bricks(&self) -> usize {
+        self.bricks.as_ref().map_or(0, |region| region.free.len())
+    }
+
+    // entries stored as bricks report their slot in the indirection texture, sized as the dense
+    // volume it maps
+    pub fn get(&self, key: &SdfAtlasKey) -> Option<SdfAtlasSlot> {
+        if let Some(region) = self.bricks.as_ref().filter(|region| region.keys.contains(key)) {
+            return region.indirection.get(key).map(|info| SdfAtlasSlot {
+                position: info.position,
+                size: info.size * BRICK_SIZE + 1,
+            });
+        }
+        match (self.is_animated(key), self.animated.as_ref()) {
+            (true, Some(animated)) => animated.get(key).map(|info| SdfAtlasSlot {
+                position: self.animated_origin + info.position,
+                size: info.size,
+            }),
+            _ => self.statics.get(key).map(|info| SdfAtlasSlot {
+                position: info.position,
+                size: info.size,
+            }),
+        }
+    }
+
+    pub fn insert(&mut self, key: SdfAtlasKey, size: UVec3) -> SdfAtlasInsert {
+        let animated = self.region(&key) == SdfAtlasRegion::Animated;
+        let bricks = self.bricks.as_mut().filter(|region| region.keys.contains(&key));
+        let new = match (bricks, animated, self.animated.as_mut()) {
+            // bricks are allocated once the occupied ones are known, see `allocate_bricks`
+            (Some(region), ..) => {
+                match region.indirection.insert(key.clone(), (size - 1) / BRICK_SIZE) {
+                    atlas3d::Slot::New(_) => true,
+                    atlas3d::Slot::Existing(_) => false,
+                    atlas3d::Slot::NoFit => return SdfAtlasInsert::NoFit,
+                }
+            }
+            (None, true, Some(page)) => match page.insert(key.clone(), size) {
+                atlas3d::Slot::New(_) => true,
+                atlas3d::Slot::Existing(_) => false,
+                atlas3d::Slot::NoFit => return SdfAtlasInsert::NoFit,
+            },
+            _ => match self.statics.insert(key.clone(), size) {
+                atlas3d::Slot::New(_) => true,
+                atlas3d::Slot::Existing(_) => false,
+                atlas3d::Slot::NoFit => return SdfAtlasInsert::NoFit,
+            },
+        };
+        let Some(slot) = self.get(&key) else { return SdfAtlasInsert::NoFit };
+        match new {
+            true => {
+                if animated {
+                    self.animated_order.push_back(key);
+                }
+                SdfAtlasInsert::New(slot)
+            }
+            false => SdfAtlasInsert::Existing(slot),
+        }
+    }
+
+    pub fn purge(&mut self, key: &SdfAtlasKey) {
+        if let Some(region) = self.bricks.as_mut().filter(|region| region.keys.contains(key)) {
+            region.indirection.purge(key);
+            region.release_bricks(key);
+            return;
+        }
+        match (self.is_animated(key), self.animated.as_mut()) {
+            (true, Some(page)) => {
+                page.purge(key);
+                self.animated_order.retain(|k| k != key);
+            }
+            _ => self.statics.purge(key),
+        }
+    }
+
+    pub fn purge_all(&mut self) {
+        self.statics.purge_all();
+        if let Some(page) = self.animated.as_mut() {
+            page.purge_all();
+        }
+        self.animated_order.clear();
+        if let Some(region) = self.bricks.as_mut() {
+            region.indirection.purge_all();
+            let keys = region.allocated.keys().cloned().collect::<Vec<_>>();
+            for key in keys {
+                region.release_bricks(&key);
+            }
+        }
+        if let Some(page) = self.compressed.as_mut() {
+            page.purge_all();
+        }
+    }
+
+    pub fn has_compressed(&self) -> bool {
+        self.compressed.is_some()
+    }
+
+    // allocate whole 4x4 blocks of the compressed atlas for an entry of `size` voxels, a layer per
+    // z slice. the slot is in texels, with the layer in z
+    pub fn insert_compressed(&mut self, key: SdfAtlasKey, size: UVec3) -> SdfAtlasInsert {
+        let Some(page) = self.compressed.as_mut() else { return SdfAtlasInsert::NoFit };
+        let blocks = (size + UVec3::new(3, 3, 0)) / UVec3::new(4, 4, 1);
+        let (new, info) = match page.insert(key, blocks) {
+            atlas3d::Slot::New(info) => (true, info),
+            atlas3d::Slot::Existing(info) => (false, info),
+            atlas3d::Slot::NoFit => return SdfAtlasInsert::NoFit,
+        };
+        let slot = SdfAtlasSlot {
+            position: info.position * UVec3::new(4, 4, 1),
+            size,
+        };
+        match new {
+            true => SdfAtlasInsert::New(slot),
+            false => SdfAtlasInsert::Existing(slot),
+        }
+    }
+
+    pub fn purge_compressed(&mut self, key: &SdfAtlasKey) {
+        if let Some(page) = self.compressed.as_mut() {
+            page.purge(key);
+        }
+    }
+
+    // purge the entry and forget which region it belongs to
+    pub fn release(&mut self, key: &SdfAtlasKey) {
+        self.purge(key);
+        self.animated_keys.remove(key);
+        if let Some(region) = self.bricks.as_mut() {
+            region.keys.remove(key);
+        }
+    }
+
+    // the oldest allocation in the animated region accepted by `evictable`
+    pub fn oldest_animated(&self, evictable: impl Fn(&SdfAtlasKey) -> bool) -> Option<SdfAtlasKey> {
+        self.animated_order.iter().find(|key| evictable(key)).cloned()
+    }
+}
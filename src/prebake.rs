@@ -0,0 +1,39 @@
+use bevy::{math::Vec3A, prelude::*, render::primitives::Aabb, utils::HashSet};
+
+/// a volume which, while a 3d camera is inside it, queues sdf generation for the listed entities
+/// even though they aren't visible yet. place them in doorways and corridors so the next room's
+/// sdfs are ready before it comes into view.
+#[derive(Component, Clone)]
+pub struct SdfPrebakeTrigger {
+    // the trigger volume, relative to the entity's transform
+    pub volume: Aabb,
+    // entities to generate while a camera is inside the volume
+    pub entities: Vec<Entity>,
+}
+
+/// entities to generate this frame regardless of visibility
+#[derive(Default)]
+pub struct SdfPrebakeSet(pub(crate) HashSet<Entity>);
+
+pub(crate) fn update_prebake_set(
+    mut prebake: ResMut<SdfPrebakeSet>,
+    triggers: Query<(&SdfPrebakeTrigger, &GlobalTransform)>,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+) {
+    prebake.0.clear();
+
+    for (trigger, transform) in triggers.iter() {
+        let to_local = transform.affine().inverse();
+        let triggered = cameras.iter().any(|(camera, camera_transform)| {
+            if !camera.is_active {
+                return false;
+            }
+            let local = to_local.transform_point3a(Vec3A::from(camera_transform.translation()));
+            (local - trigger.volume.center).abs().cmple(trigger.volume.half_extents).all()
+        });
+
+        if triggered {
+            prebake.0.extend(trigger.entities.iter().copied());
+        }
+    }
+}
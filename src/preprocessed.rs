@@ -0,0 +1,167 @@
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    reflect::TypeUuid,
+    render::primitives::{Aabb, Plane},
+};
+
+use crate::{
+    utils::{preprocess_mesh_for_sdf, PreprocessedMeshData, TriData},
+    SdfOptions,
+};
+
+/// preprocessed sdf geometry, for use with `SdfGenMode::FromPreprocessed`. can be built offline
+/// and loaded from `.sdfmesh` files with the `serialize` feature, to avoid preprocessing large
+/// meshes at runtime.
+#[derive(TypeUuid)]
+#[uuid = "5b7a0f6e-3c1d-4f0e-9a55-2c8e4b9d71a3"]
+pub struct PreprocessedMesh {
+    pub aabb: Aabb,
+    pub data: PreprocessedMeshData,
+}
+
+impl PreprocessedMesh {
+    pub fn new(data: PreprocessedMeshData) -> Self {
+        let (min, max) = data.vertices.iter().fold(
+            (Vec3A::splat(f32::MAX), Vec3A::splat(f32::MIN)),
+            |(cur_min, cur_max), (v, _)| (cur_min.min(*v), cur_max.max(*v)),
+        );
+
+        Self {
+            aabb: Aabb::from_min_max(Vec3::from(min), Vec3::from(max)),
+            data,
+        }
+    }
+
+    pub fn from_mesh(mesh: &Mesh, options: &SdfOptions) -> Self {
+        Self::new(preprocess_mesh_for_sdf(mesh, None, None, options))
+    }
+}
+
+#[cfg(feature = "serialize")]
+pub use serialize::*;
+
+#[cfg(feature = "serialize")]
+mod serialize {
+    use bevy::{
+        asset::{AssetLoader, LoadContext, LoadedAsset},
+        utils::BoxedFuture,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    // bump when the layout changes
+    const FORMAT_VERSION: u32 = 1;
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedMesh {
+        version: u32,
+        // position, normal
+        vertices: Vec<[f32; 6]>,
+        // v0, v1, normal
+        edges: Vec<[f32; 9]>,
+        // a, b, c, plane, inv_area
+        triangles: Vec<[f32; 14]>,
+    }
+
+    fn flatten<const N: usize>(parts: &[&[f32]]) -> [f32; N] {
+        let mut result = [0.0; N];
+        for (target, value) in result.iter_mut().zip(parts.iter().flat_map(|p| p.iter())) {
+            *target = *value;
+        }
+        result
+    }
+
+    impl PreprocessedMesh {
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let data = &self.data;
+            let serialized = SerializedMesh {
+                version: FORMAT_VERSION,
+                vertices: data
+                    .vertices
+                    .iter()
+                    .map(|(v, n)| flatten(&[&v.to_array(), &n.to_array()]))
+                    .collect(),
+                edges: data
+                    .edges
+                    .iter()
+                    .map(|((v0, v1), n)| flatten(&[&v0.to_array(), &v1.to_array(), &n.to_array()]))
+                    .collect(),
+                triangles: data
+                    .triangles
+                    .iter()
+                    .map(|tri| {
+                        flatten(&[
+                            &tri.a.to_array(),
+                            &tri.b.to_array(),
+                            &tri.c.to_array(),
+                            &tri.plane.normal_d().to_array(),
+                            &[tri.inv_area],
+                        ])
+                    })
+                    .collect(),
+            };
+
+            bincode::serialize(&serialized).unwrap()
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+            let serialized: SerializedMesh = bincode::deserialize(bytes)?;
+            if serialized.version != FORMAT_VERSION {
+                anyhow::bail!(
+                    "unsupported sdfmesh version {} (expected {})",
+                    serialized.version,
+                    FORMAT_VERSION
+                );
+            }
+
+            let v3 = |s: &[f32]| Vec3A::from_slice(s);
+            Ok(Self::new(PreprocessedMeshData {
+                vertices: serialized
+                    .vertices
+                    .iter()
+                    .map(|v| (v3(&v[0..3]), v3(&v[3..6])))
+                    .collect(),
+                edges: serialized
+                    .edges
+                    .iter()
+                    .map(|e| ((v3(&e[0..3]), v3(&e[3..6])), v3(&e[6..9])))
+                    .collect(),
+                triangles: serialized
+                    .triangles
+                    .iter()
+                    .map(|t| TriData {
+                        a: v3(&t[0..3]),
+                        b: v3(&t[3..6]),
+                        c: v3(&t[6..9]),
+                        plane: Plane::new(Vec4::from_slice(&t[9..13])),
+                        inv_area: t[13],
+                    })
+                    .collect(),
+            }))
+        }
+    }
+
+    /// loads `PreprocessedMesh` assets from `.sdfmesh` files written with `PreprocessedMesh::to_bytes`
+    #[derive(Default)]
+    pub struct PreprocessedMeshLoader;
+
+    impl AssetLoader for PreprocessedMeshLoader {
+        fn load<'a>(
+            &'a self,
+            bytes: &'a [u8],
+            load_context: &'a mut LoadContext,
+        ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+            Box::pin(async move {
+                let mesh = PreprocessedMesh::from_bytes(bytes)?;
+                load_context.set_default_asset(LoadedAsset::new(mesh));
+                Ok(())
+            })
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["sdfmesh"]
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! python bindings to the cpu generator and the `.sdfmesh` writer, for scripting batch bakes
+//! without a bevy app. build the extension module with
+//! `maturin build --release` (which enables the features listed in pyproject.toml), then:
+//!
+//! ```python
+//! import mesh2sdf
+//!
+//! # vertices as (x, y, z) tuples, three indices per triangle
+//! aabb_min, aabb_max, distances = mesh2sdf.bake(vertices, indices, (64, 64, 64), padding=0.5)
+//! mesh2sdf.write_sdfmesh("assets/rock.sdfmesh", vertices, indices)
+//! ```
+use bevy::{
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    cpu::create_sdf_from_triangles_cpu, preprocessed::PreprocessedMesh, SdfBackFaces, SdfOptions,
+};
+
+fn validate(vertices: &[[f32; 3]], indices: &[u32]) -> PyResult<()> {
+    if indices.is_empty() || indices.len() % 3 != 0 {
+        return Err(PyValueError::new_err(
+            "indices must hold at least one triangle, three indices each",
+        ));
+    }
+    if indices.iter().any(|&ix| ix as usize >= vertices.len()) {
+        return Err(PyValueError::new_err("index out of range of the vertices"));
+    }
+    Ok(())
+}
+
+fn options(ignore_back_faces: bool, invert: bool) -> SdfOptions {
+    SdfOptions {
+        back_faces: match ignore_back_faces {
+            true => SdfBackFaces::Ignore,
+            false => SdfBackFaces::TwoSided,
+        },
+        invert,
+        ..Default::default()
+    }
+}
+
+/// bake a signed distance volume, exactly as `cpu::create_sdf_from_mesh_cpu` would for the same
+/// triangles. the volume spans the mesh bounds expanded by `padding`, with voxels on both corners.
+/// returns `(aabb_min, aabb_max, distances)`, with distances x-fastest then y then z, negative
+/// inside. nothing is printed, so scripts can keep stdout for their own output.
+#[pyfunction(padding = "0.0", ignore_back_faces = "false", invert = "false")]
+fn bake(
+    py: Python,
+    vertices: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    dims: [u32; 3],
+    padding: f32,
+    ignore_back_faces: bool,
+    invert: bool,
+) -> PyResult<([f32; 3], [f32; 3], Vec<f32>)> {
+    validate(&vertices, &indices)?;
+    let dims = UVec3::from(dims);
+    if dims.cmplt(UVec3::splat(2)).any() {
+        return Err(PyValueError::new_err("dims must be at least 2 on each axis"));
+    }
+
+    let options = options(ignore_back_faces, invert);
+    // long bakes shouldn't block other python threads
+    let (aabb, distances) = py.allow_threads(|| {
+        create_sdf_from_triangles_cpu(vertices, indices, dims, padding, &options)
+    });
+    Ok((aabb.min().into(), aabb.max().into(), distances))
+}
+
+/// preprocess the triangles and write them to a `.sdfmesh` file, for `SdfGenMode::FromPreprocessed`
+#[pyfunction(ignore_back_faces = "false", invert = "false")]
+fn write_sdfmesh(
+    path: &str,
+    vertices: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    ignore_back_faces: bool,
+    invert: bool,
+) -> PyResult<()> {
+    validate(&vertices, &indices)?;
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    let bytes = PreprocessedMesh::from_mesh(&mesh, &options(ignore_back_faces, invert)).to_bytes();
+    std::fs::write(path, bytes).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))
+}
+
+#[pymodule]
+fn mesh2sdf(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(bake, m)?)?;
+    m.add_function(wrap_pyfunction!(write_sdfmesh, m)?)?;
+    Ok(())
+}
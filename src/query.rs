@@ -0,0 +1,310 @@
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3A,
+    prelude::*,
+    render::{mesh::PrimitiveTopology, primitives::Aabb},
+    tasks::ComputeTaskPool,
+    utils::HashMap,
+};
+
+use crate::{
+    cpu::compute_distance,
+    preprocessed::PreprocessedMesh,
+    utils::{preprocess_mesh_for_sdf, PreprocessedMeshData},
+    Sdf, SdfGenMode,
+};
+
+pub struct SdfQueryPlugin;
+
+impl Plugin for SdfQueryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SdfQueryMeshes>()
+            .init_resource::<SdfQueryCache>()
+            .add_system_to_stage(CoreStage::First, clear_query_cache)
+            .add_system_to_stage(CoreStage::PostUpdate, update_query_meshes);
+    }
+}
+
+/// cpu-side preprocessed geometry for the meshes used by static (non-skinned) sdf entities
+#[derive(Default)]
+pub struct SdfQueryMeshes {
+    meshes: HashMap<Handle<Mesh>, PreprocessedMeshData>,
+}
+
+fn query_mesh_handle<'a>(sdf: &'a Sdf, maybe_mesh: Option<&'a Handle<Mesh>>) -> Option<&'a Handle<Mesh>> {
+    match sdf.mode {
+        SdfGenMode::FromPrimaryMesh => maybe_mesh,
+        SdfGenMode::FromCustomMesh(ref h) => Some(h),
+        SdfGenMode::Precomputed(_)
+        | SdfGenMode::FromHierarchy
+        | SdfGenMode::FromPreprocessed(_)
+        | SdfGenMode::Composite(_) => None,
+    }
+}
+
+pub(crate) fn update_query_meshes(
+    mut events: EventReader<AssetEvent<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    sdfs: Query<(&Sdf, Option<&Handle<Mesh>>)>,
+    mut query_meshes: ResMut<SdfQueryMeshes>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Modified { handle } | AssetEvent::Removed { handle } => {
+                query_meshes.meshes.remove(handle);
+            }
+            AssetEvent::Created { .. } => (),
+        }
+    }
+
+    for (sdf, maybe_mesh) in sdfs.iter() {
+        if sdf.skinned {
+            continue;
+        }
+        let Some(handle) = query_mesh_handle(sdf, maybe_mesh) else { continue };
+        if query_meshes.meshes.contains_key(handle) {
+            continue;
+        }
+        let Some(mesh) = meshes.get(handle) else { continue };
+        if !matches!(mesh.primitive_topology(), PrimitiveTopology::TriangleList) {
+            continue;
+        }
+        query_meshes
+            .meshes
+            .insert(handle.clone_weak(), preprocess_mesh_for_sdf(mesh, None, None, &sdf.options));
+    }
+}
+
+/// cpu distance queries against the scene's (non-skinned) sdf entities
+///
+/// example usage:
+///
+/// fn keep_away(query: SdfQuery, agents: Query<&Transform, With<Agent>>) {
+///     for transform in agents.iter() {
+///         let distance = query.distance(transform.translation, 10.0);
+///     }
+/// }
+///
+#[derive(SystemParam)]
+pub struct SdfQuery<'w, 's> {
+    meshes: Res<'w, SdfQueryMeshes>,
+    preprocessed: Res<'w, Assets<PreprocessedMesh>>,
+    sdfs: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static Sdf,
+            &'static GlobalTransform,
+            &'static Aabb,
+            Option<&'static Handle<Mesh>>,
+        ),
+    >,
+}
+
+impl<'w, 's> SdfQuery<'w, 's> {
+    /// signed distance from the world space point to the nearest sdf geometry, or `max_distance`
+    /// if nothing is closer
+    pub fn distance(&self, point: Vec3, max_distance: f32) -> f32 {
+        self.distance_filtered(point, max_distance, |_| true)
+    }
+
+    /// as `distance`, over only the sdf entities accepted by `filter` (e.g. to ignore the player
+    /// character when querying from inside it)
+    pub fn distance_filtered(
+        &self,
+        point: Vec3,
+        max_distance: f32,
+        filter: impl Fn(Entity) -> bool,
+    ) -> f32 {
+        let mut best = max_distance;
+
+        for (ent, sdf, g_trans, aabb, maybe_mesh) in self.sdfs.iter() {
+            if !filter(ent) {
+                continue;
+            }
+            if let Some(distance) = self.item_distance(sdf, g_trans, aabb, maybe_mesh, point, best) {
+                best = best.min(distance);
+            }
+        }
+
+        best
+    }
+
+    /// direction of increasing distance at the point, by central differences over `epsilon`. zero
+    /// if nothing is within `max_distance`
+    pub fn normal(&self, point: Vec3, max_distance: f32, epsilon: f32) -> Vec3 {
+        let sample = |offset: Vec3| {
+            self.distance(point + offset, max_distance) - self.distance(point - offset, max_distance)
+        };
+        Vec3::new(
+            sample(Vec3::X * epsilon),
+            sample(Vec3::Y * epsilon),
+            sample(Vec3::Z * epsilon),
+        )
+        .normalize_or_zero()
+    }
+
+    /// the closest surface point to `point` and the surface normal there, if any sdf geometry is
+    /// within `max_distance`. e.g. for foot ik or snapping placed objects to the ground
+    pub fn project_to_surface(&self, point: Vec3, max_distance: f32) -> Option<SdfSurfacePoint> {
+        // the distance to each sdf's aabb bounds its distance from below, so add headroom to find
+        // surfaces right at the limit
+        let search = max_distance * 2.0;
+        let initial = self.distance(point, search);
+        if initial.abs() > max_distance {
+            return None;
+        }
+
+        // step along the gradient, refining where the distance field isn't exact
+        let epsilon = (initial.abs() * 0.01).max(1e-4);
+        let mut position = point;
+        let mut distance = initial;
+        let mut normal = Vec3::ZERO;
+        for _ in 0..PROJECTION_STEPS {
+            normal = self.normal(position, search, epsilon);
+            if normal == Vec3::ZERO {
+                return None;
+            }
+            position -= normal * distance;
+            distance = self.distance(position, search);
+            if distance.abs() <= epsilon {
+                break;
+            }
+        }
+
+        Some(SdfSurfacePoint {
+            position,
+            normal,
+            distance: initial,
+        })
+    }
+
+    /// `project_to_surface` for many points, spread over the compute task pool
+    pub fn project_to_surface_batch(
+        &self,
+        points: &[Vec3],
+        max_distance: f32,
+    ) -> Vec<Option<SdfSurfacePoint>> {
+        let chunk_size = (points.len() / ComputeTaskPool::get().thread_num()).max(16);
+        ComputeTaskPool::get()
+            .scope(|s| {
+                for chunk in points.chunks(chunk_size) {
+                    s.spawn(async move {
+                        chunk
+                            .iter()
+                            .map(|p| self.project_to_surface(*p, max_distance))
+                            .collect::<Vec<_>>()
+                    });
+                }
+            })
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// signed distance from the world space point to a single sdf entity's geometry, if it can be
+    /// queried
+    pub fn entity_distance(&self, entity: Entity, point: Vec3) -> Option<f32> {
+        let (_, sdf, g_trans, aabb, maybe_mesh) = self.sdfs.get(entity).ok()?;
+        self.item_distance(sdf, g_trans, aabb, maybe_mesh, point, f32::MAX)
+    }
+
+    // distance to the item, or none if it's not queryable or can't be closer than `max_distance`
+    fn item_distance(
+        &self,
+        sdf: &Sdf,
+        g_trans: &GlobalTransform,
+        aabb: &Aabb,
+        maybe_mesh: Option<&Handle<Mesh>>,
+        point: Vec3,
+        max_distance: f32,
+    ) -> Option<f32> {
+        if sdf.skinned {
+            return None;
+        }
+        let preprocessed = match sdf.mode {
+            SdfGenMode::FromPreprocessed(ref h) => self.preprocessed.get(h).map(|p| &p.data),
+            _ => query_mesh_handle(sdf, maybe_mesh).and_then(|h| self.meshes.meshes.get(h)),
+        }?;
+
+        let scale = g_trans.to_scale_rotation_translation().0.x;
+        let local = Vec3A::from(g_trans.affine().inverse().transform_point3(point));
+
+        // the distance to the aabb is a lower bound for the distance to the geometry
+        let nearest = local.clamp(aabb.min(), aabb.max());
+        if nearest.distance(local) * scale >= max_distance {
+            return None;
+        }
+
+        Some(compute_distance(preprocessed, &sdf.options, local, false) * scale)
+    }
+}
+
+// gradient steps taken when projecting onto the surface
+const PROJECTION_STEPS: usize = 4;
+
+/// a point on the sdf surface found by `SdfQuery::project_to_surface`
+#[derive(Clone, Copy, Debug)]
+pub struct SdfSurfacePoint {
+    pub position: Vec3,
+    // outward surface normal at the position
+    pub normal: Vec3,
+    // signed distance from the query point to the surface
+    pub distance: f32,
+}
+
+/// per-frame cache of query results, binned by cell
+pub struct SdfQueryCache {
+    // size of the cells used to bin cached results
+    pub cell_size: f32,
+    // max distance used for queries that populate the cache
+    pub max_distance: f32,
+    cells: HashMap<IVec3, f32>,
+}
+
+impl Default for SdfQueryCache {
+    fn default() -> Self {
+        Self {
+            cell_size: 0.25,
+            max_distance: 10.0,
+            cells: Default::default(),
+        }
+    }
+}
+
+fn clear_query_cache(mut cache: ResMut<SdfQueryCache>) {
+    cache.cells.clear();
+}
+
+/// `SdfQuery` with results cached per cell for the rest of the frame, for query-heavy systems
+/// (e.g. many ai agents). results are conservative: the distance at the cell center minus the
+/// distance from the point to the cell center.
+#[derive(SystemParam)]
+pub struct CachedSdfQuery<'w, 's> {
+    query: SdfQuery<'w, 's>,
+    cache: ResMut<'w, SdfQueryCache>,
+}
+
+impl<'w, 's> CachedSdfQuery<'w, 's> {
+    pub fn distance(&mut self, point: Vec3) -> f32 {
+        let cell_size = self.cache.cell_size;
+        let max_distance = self.cache.max_distance;
+        let cell = (point / cell_size).floor().as_ivec3();
+        let center = (cell.as_vec3() + 0.5) * cell_size;
+
+        let query = &self.query;
+        let center_distance = *self
+            .cache
+            .cells
+            .entry(cell)
+            .or_insert_with(|| query.distance(center, max_distance));
+
+        center_distance - point.distance(center)
+    }
+
+    /// uncached query
+    pub fn exact_distance(&self, point: Vec3, max_distance: f32) -> f32 {
+        self.query.distance(point, max_distance)
+    }
+}
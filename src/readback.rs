@@ -0,0 +1,382 @@
+use std::{
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        primitives::Aabb,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageCopyTexture,
+            ImageDataLayout, Maintain, MapMode, Origin3d, TextureAspect,
+        },
+        renderer::{RenderContext, RenderDevice},
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::{
+    compute::SdfComputeGraphConfig,
+    cpu::sdf_image,
+    utils::{atlas_texel_bytes, f16_to_f32},
+    Sdf, SdfAtlas, SdfStatus,
+};
+
+// texture to buffer copies must have rows padded to this
+const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
+/// opt-in copying of generated atlas entries back to the cpu, e.g. to save gpu-baked sdfs to
+/// disk or to run cpu queries against them. add `SdfReadback` to an sdf entity and read the
+/// `SdfReadbackReady` event.
+pub struct SdfReadbackPlugin;
+
+impl Plugin for SdfReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let results = SdfReadbackResults::default();
+
+        app.add_event::<SdfReadbackReady>()
+            .init_resource::<SdfReadbackRequests>()
+            .insert_resource(results.clone())
+            .add_plugin(ExtractResourcePlugin::<SdfReadbackRequests>::default())
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                request_readbacks.after("preprocess sdfs"),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, publish_readbacks);
+
+        let graph_config = app.world.resource::<SdfComputeGraphConfig>().clone();
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(results)
+            .init_resource::<SdfReadbackJobs>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_readbacks)
+            .add_system_to_stage(RenderStage::Cleanup, map_readbacks);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        // the compute plugin warns if the graph is missing
+        let Some(graph) = graph_config.graph(&mut render_graph) else { return };
+        graph.add_node("sdf_readback", SdfReadbackNode);
+        graph.add_node_edge("sdf_compute", "sdf_readback").unwrap();
+    }
+}
+
+/// request a copy of the entity's sdf once it has been generated at full (or reduced)
+/// resolution. removed when the copy is issued
+#[derive(Component, Clone, Copy, Default)]
+pub struct SdfReadback;
+
+/// sent when the data for a `SdfReadback` request arrives, usually a couple of frames later
+pub struct SdfReadbackReady {
+    pub entity: Entity,
+    pub grid: SdfGrid,
+    // the same data as an R32Float 3d image
+    pub image: Handle<Image>,
+}
+
+/// dense distance grid spanning an aabb, with voxels at both the min and max corners
+#[derive(Clone)]
+pub struct SdfGrid {
+    // in the entity's space (world space for skinned entities)
+    pub aabb: Aabb,
+    pub dimension: UVec3,
+    // x-major distances
+    pub data: Vec<f32>,
+}
+
+impl SdfGrid {
+    fn voxel_size(&self) -> Vec3A {
+        self.aabb.half_extents * 2.0 / (self.dimension - 1).as_vec3a()
+    }
+
+    /// distance stored for the given voxel (clamped to the volume)
+    pub fn voxel(&self, voxel: UVec3) -> f32 {
+        let voxel = voxel.min(self.dimension - 1);
+        self.data[(voxel.x
+            + voxel.y * self.dimension.x
+            + voxel.z * self.dimension.x * self.dimension.y) as usize]
+    }
+
+    /// trilinearly filtered distance at a point in the grid's space. points outside the aabb are
+    /// clamped to the boundary
+    pub fn sample(&self, point: Vec3A) -> f32 {
+        let coords = ((point - self.aabb.min()) / self.voxel_size())
+            .max(Vec3A::ZERO)
+            .min((self.dimension - 1).as_vec3a());
+        let base = coords.floor();
+        let t = coords - base;
+        let base = base.as_uvec3();
+
+        let weight = |offset: u32, t: f32| if offset == 1 { t } else { 1.0 - t };
+
+        let mut result = 0.0;
+        for corner in 0..8u32 {
+            let offset = UVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            result += self.voxel(base + offset)
+                * weight(offset.x, t.x)
+                * weight(offset.y, t.y)
+                * weight(offset.z, t.z);
+        }
+        result
+    }
+
+    pub fn to_image(&self) -> Image {
+        sdf_image(
+            self.dimension,
+            self.data.iter().flat_map(|d| d.to_le_bytes()).collect(),
+        )
+    }
+}
+
+#[derive(Clone)]
+struct ReadbackRequest {
+    entity: Entity,
+    aabb: Aabb,
+    atlas_position: UVec3,
+    dimension: UVec3,
+}
+
+// copies to issue this frame
+#[derive(Clone, Default, ExtractResource)]
+struct SdfReadbackRequests(Vec<ReadbackRequest>);
+
+// completed readbacks, shared between the render world and the main world
+#[derive(Clone, Default)]
+struct SdfReadbackResults(Arc<Mutex<Vec<(Entity, SdfGrid)>>>);
+
+fn request_readbacks(
+    mut commands: Commands,
+    mut requests: ResMut<SdfReadbackRequests>,
+    atlas: Res<SdfAtlas>,
+    sdfs: Query<(Entity, &Sdf, Option<&Handle<Mesh>>, Option<&SdfStatus>), With<SdfReadback>>,
+) {
+    if !requests.0.is_empty() {
+        requests.0.clear();
+    }
+
+    for (ent, sdf, maybe_mesh, maybe_status) in sdfs.iter() {
+        if !matches!(maybe_status, Some(SdfStatus::Full | SdfStatus::Reduced)) {
+            continue;
+        }
+
+        // wait for anything dispatched this frame
+        if atlas.need_computing.iter().any(|(e, ..)| *e == ent) {
+            continue;
+        }
+
+        let Some(key) = atlas.key(ent, sdf, maybe_mesh) else { continue };
+        if atlas.is_bricks(&key) {
+            warn!("sdf readback of {:?} skipped, entries stored as bricks can't be read back", ent);
+            commands.entity(ent).remove::<SdfReadback>();
+            continue;
+        }
+        if atlas.is_compressed(&key) {
+            warn!("sdf readback of {:?} skipped, compressed entries can't be read back", ent);
+            commands.entity(ent).remove::<SdfReadback>();
+            continue;
+        }
+        let Some(info) = atlas.slot(&key) else { continue };
+
+        requests.0.push(ReadbackRequest {
+            entity: ent,
+            aabb: sdf.aabb,
+            atlas_position: info.position,
+            dimension: info.size - 1,
+        });
+        commands.entity(ent).remove::<SdfReadback>();
+    }
+}
+
+fn publish_readbacks(
+    results: Res<SdfReadbackResults>,
+    mut images: ResMut<Assets<Image>>,
+    mut events: EventWriter<SdfReadbackReady>,
+) {
+    let results = std::mem::take(&mut *results.0.lock().unwrap());
+    for (entity, grid) in results {
+        let image = images.add(grid.to_image());
+        events.send(SdfReadbackReady {
+            entity,
+            grid,
+            image,
+        });
+    }
+}
+
+enum ReadbackState {
+    // waiting for the node to record the copy
+    Copy,
+    // copy submitted, waiting for the buffer to map
+    Mapping(Arc<AtomicBool>),
+}
+
+struct ReadbackJob {
+    request: ReadbackRequest,
+    buffer: Buffer,
+    // padded to the copy alignment
+    bytes_per_row: u32,
+    // 2 for an R16Float atlas, 4 for R32Float
+    texel_bytes: u32,
+    state: ReadbackState,
+}
+
+#[derive(Default)]
+struct SdfReadbackJobs(Vec<ReadbackJob>);
+
+fn prepare_readbacks(
+    requests: Res<SdfReadbackRequests>,
+    mut jobs: ResMut<SdfReadbackJobs>,
+    atlas: Res<SdfAtlas>,
+    render_device: Res<RenderDevice>,
+) {
+    // only new requests, the extracted resource stays around until it next changes
+    if !requests.is_changed() {
+        return;
+    }
+
+    let texel_bytes = atlas_texel_bytes(atlas.format) as u32;
+    for request in requests.0.iter() {
+        let unpadded = request.dimension.x * texel_bytes;
+        let bytes_per_row = (unpadded + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            / COPY_BYTES_PER_ROW_ALIGNMENT
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("sdf readback"),
+            size: (bytes_per_row * request.dimension.y * request.dimension.z) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        jobs.0.push(ReadbackJob {
+            request: request.clone(),
+            buffer,
+            bytes_per_row,
+            texel_bytes,
+            state: ReadbackState::Copy,
+        });
+    }
+}
+
+struct SdfReadbackNode;
+
+impl render_graph::Node for SdfReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let jobs = world.resource::<SdfReadbackJobs>();
+        if !jobs.0.iter().any(|job| matches!(job.state, ReadbackState::Copy)) {
+            return Ok(());
+        }
+
+        let atlas = world.resource::<SdfAtlas>();
+        let Some(gpu_image) = world.resource::<RenderAssets<Image>>().get(&atlas.image) else {
+            return Ok(());
+        };
+
+        for job in jobs.0.iter() {
+            if !matches!(job.state, ReadbackState::Copy) {
+                continue;
+            }
+
+            let position = job.request.atlas_position;
+            let dimension = job.request.dimension;
+            render_context.command_encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: &gpu_image.texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: position.x,
+                        y: position.y,
+                        z: position.z,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &job.buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(job.bytes_per_row),
+                        rows_per_image: NonZeroU32::new(dimension.y),
+                    },
+                },
+                Extent3d {
+                    width: dimension.x,
+                    height: dimension.y,
+                    depth_or_array_layers: dimension.z,
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// after the frame's commands are submitted: start mapping new copies, and collect finished ones
+fn map_readbacks(
+    mut jobs: ResMut<SdfReadbackJobs>,
+    results: Res<SdfReadbackResults>,
+    render_device: Res<RenderDevice>,
+) {
+    if jobs.0.is_empty() {
+        return;
+    }
+
+    for job in jobs.0.iter_mut() {
+        if let ReadbackState::Copy = job.state {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let flag = mapped.clone();
+            job.buffer.slice(..).map_async(MapMode::Read, move |res| {
+                if res.is_ok() {
+                    flag.store(true, Ordering::Release);
+                } else {
+                    warn!("failed to map sdf readback buffer");
+                }
+            });
+            job.state = ReadbackState::Mapping(mapped);
+        }
+    }
+
+    render_device.wgpu_device().poll(Maintain::Poll);
+
+    jobs.0.retain(|job| {
+        let ReadbackState::Mapping(ref mapped) = job.state else { return true };
+        if !mapped.load(Ordering::Acquire) {
+            return true;
+        }
+
+        let dimension = job.request.dimension;
+        let mut data = Vec::with_capacity((dimension.x * dimension.y * dimension.z) as usize);
+        {
+            let bytes = job.buffer.slice(..).get_mapped_range();
+            for row in bytes.chunks_exact(job.bytes_per_row as usize) {
+                let texels = row[..(dimension.x * job.texel_bytes) as usize]
+                    .chunks_exact(job.texel_bytes as usize);
+                match job.texel_bytes {
+                    2 => data.extend(texels.map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))),
+                    _ => data.extend(texels.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))),
+                }
+            }
+        }
+        job.buffer.unmap();
+
+        results.0.lock().unwrap().push((
+            job.request.entity,
+            SdfGrid {
+                aabb: job.request.aabb,
+                dimension,
+                data,
+            },
+        ));
+        false
+    });
+}
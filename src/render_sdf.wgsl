@@ -32,13 +32,14 @@ fn isnan(x: f32) -> bool {
 }
 
 fn sample_distance(pos: vec3<f32>) -> vec3<f32> {
-    let local_position = transpose(mesh.inverse_transpose_model) * vec4<f32>(pos, 1.0);
-    let local_position = local_position.xyz / local_position.w;
+    // the mesh is a unit cube stretched over the volume, back to the sdf's local space
+    let unit_position = transpose(mesh.inverse_transpose_model) * vec4<f32>(pos, 1.0);
+    let local_position = material.aabb_min + (unit_position.xyz / unit_position.w + 0.5) * material.aabb_extents;
     let nearest = clamp(local_position, material.aabb_min, material.aabb_min + material.aabb_extents);
 
     let coords = clamp((local_position - material.aabb_min) / material.aabb_extents, vec3<f32>(0.0), vec3<f32>(1.0)); // 0-1
     let atlas_coords = material.position + coords * material.size;
-    let inner_distance = textureSample(sdf_atlas, sdf_sampler, atlas_coords).r;
+    let inner_distance = textureSampleLevel(sdf_atlas, sdf_sampler, atlas_coords, 0.0).r;
 
     let offset = nearest - local_position;
     let distance_to_aabb_sq = dot(offset, offset);        
@@ -78,7 +79,8 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
 
     // todo use view depth for max dist
     // also use aabb center + half_extents?
-    let tl = mesh.model * vec4<f32>(material.aabb_min, 1.0);
+    // the volume's min corner
+    let tl = mesh.model * vec4<f32>(vec3<f32>(-0.5), 1.0);
     let tl = tl.xyz / tl.w;
     let max_distance = distance(origin, tl) + length(material.aabb_extents) * 2.0;
     let max_distance_sq = max_distance * max_distance;
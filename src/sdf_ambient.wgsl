@@ -1,61 +1,151 @@
 #define_import_path bevy_pbr::pbr_ambient
 
-fn sdf_item_distance(target_point: vec3<f32>, index: u32) -> f32 {
+// distance sampled from the given mip level (clamped to the entry's levels). coarser levels are
+// min-filtered, so they underestimate rather than overshoot, and suit large steps far from surfaces
+fn sdf_item_distance_level(target_point: vec3<f32>, index: u32, level: f32) -> f32 {
+    let distance = sdf_item_distance_unlimited(target_point, index, level);
+    // beyond the entry's effect range
+    if (distance >= sdf_headers.data[index].effect_range) {
+        return 999.0;
+    }
+    return distance;
+}
+
+// an entry can be skipped when its bounds are at least this far from the target point
+fn sdf_item_reach(index: u32, distance: f32) -> f32 {
+    return min(distance, sdf_headers.data[index].effect_range);
+}
+
+// how far a marcher can step in any direction from a point at the given distance from the entry
+// without passing through its surface. manhattan distances overestimate by up to sqrt(3), while
+// chebyshev distances never exceed the euclidean distance
+fn sdf_item_safe_step(index: u32, distance: f32) -> f32 {
+    if ((sdf_headers.data[index].flags & SDF_HEADER_FLAG_MANHATTAN) != 0u) {
+        return distance * 0.57735;
+    }
+    return distance;
+}
+
+// the distance within an entry stored as bricks, resolved through the indirection texture. bricks
+// have no mip levels, and samples are clamped within their brick so they never filter in texels of
+// another entry's brick
+fn sdf_brick_distance(sdf_header: SdfHeader, coords: vec3<f32>) -> f32 {
+    let voxel = coords * sdf_header.atlas_size;
+    let block_dimensions = (vec3<u32>(sdf_header.atlas_size) + 1u) / 8u;
+    let block = min(vec3<u32>(voxel / 8.0), block_dimensions - 1u);
+    let brick = textureLoad(sdf_indirection, vec3<i32>(vec3<u32>(sdf_header.atlas_position) + block), 0);
+    if (brick.x < 0.0) {
+        return brick.w;
+    }
+
+    let local = clamp(voxel - vec3<f32>(block * 8u), vec3<f32>(0.0), vec3<f32>(7.0));
+    let atlas_coords = (brick.xyz + local + 0.5) / vec3<f32>(textureDimensions(sdf_atlas));
+    return textureSampleLevel(sdf_atlas, sdf_sampler, atlas_coords, 0.0).r;
+}
+
+// the distance within a compressed entry, as a fraction of its range. layers are filtered between
+// by hand, and have no mip levels
+fn sdf_compressed_distance(sdf_header: SdfHeader, coords: vec3<f32>) -> f32 {
+    let voxel = coords * sdf_header.atlas_size;
+    let dimensions = vec2<f32>(textureDimensions(sdf_compressed));
+    let uv = (sdf_header.atlas_position.xy + voxel.xy + 0.5) / dimensions;
+    let last = max(u32(sdf_header.atlas_size.z), 1u);
+    let lower = min(u32(voxel.z), last - 1u);
+    let upper = min(lower + 1u, last);
+    let first = i32(sdf_header.atlas_position.z);
+    let a = textureSampleLevel(sdf_compressed, sdf_sampler, uv, first + i32(lower), 0.0).r;
+    let b = textureSampleLevel(sdf_compressed, sdf_sampler, uv, first + i32(upper), 0.0).r;
+    return mix(a, b, clamp(voxel.z - f32(lower), 0.0, 1.0));
+}
+
+// the sampled distance, ignoring the effect range
+fn sdf_item_distance_unlimited(target_point: vec3<f32>, index: u32, level: f32) -> f32 {
     let sdf_header = sdf_headers.data[index];
 
-    let local_position = sdf_header.transform * vec4<f32>(target_point, 1.0);
-    let local_position = local_position.xyz / local_position.w;
-    let nearest = clamp(local_position, sdf_header.aabb_min.xyz, sdf_header.aabb_min.xyz + sdf_header.aabb_size.xyz);
-
-    let coords = clamp((local_position - sdf_header.aabb_min.xyz) / sdf_header.aabb_size.xyz, vec3<f32>(0.0), vec3<f32>(1.0)); // 0-1
-    let atlas_coords = sdf_header.atlas_position.xyz + coords * sdf_header.atlas_size.xyz;
-    let inner_distance = textureSample(sdf_atlas, sdf_sampler, atlas_coords).r;
-
-    let offset = nearest - local_position;
-    let distance_to_aabb_sq = dot(offset, offset);        
-
-    if (distance_to_aabb_sq == 0.0) {
-        // inside the volume
-        // return vec3<f32>(inner_distance, 0.0, 0.0);
-        return inner_distance * sdf_header.scale;
-    } else {
-        // if (inner_distance < 0.0) {
-        //     return vec3<f32>(0.0, 1.0, 0.0);
-        //     // error, negative on edge
-        // }
-        // worst case assume right angle
-        // let inner_distance = max(inner_distance, 0.0);
-        // return vec3<f32>(sqrt(distance_to_aabb_sq + inner_distance * inner_distance), 0.0, 0.0);
-        // return sqrt(distance_to_aabb_sq + inner_distance * inner_distance);
+    // position within the aabb, 0-1 on each axis
+    let coords = vec4<f32>(target_point, 1.0) * sdf_header.transform;
+
+    if ((sdf_header.flags & SDF_HEADER_FLAG_BOX) != 0u) {
+        // analytic box occluder, atlas_size holds the aabb size
+        let q = (abs(coords - 0.5) - 0.5) * sdf_header.atlas_size;
+        return (length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0)) * sdf_header.scale;
+    }
+
+    if (any(coords != clamp(coords, vec3<f32>(0.0), vec3<f32>(1.0)))) {
+        // outside the volume
         return 999.0;
     }
+
+    if ((sdf_header.flags & SDF_HEADER_FLAG_BRICKS) != 0u) {
+        return sdf_brick_distance(sdf_header, coords) * sdf_header.scale;
+    }
+
+    if ((sdf_header.flags & SDF_HEADER_FLAG_COMPRESSED) != 0u) {
+        return sdf_compressed_distance(sdf_header, coords) * sdf_header.scale;
+    }
+
+    let atlas_coords = sdf_header.atlas_position + coords * sdf_header.atlas_size;
+    let level = min(level, f32(sdf_header.mip_count - 1u));
+    return textureSampleLevel(sdf_atlas, sdf_sampler, atlas_coords, level).r * sdf_header.scale;
 }
 
-fn sdf_distance(target_point: vec3<f32>, max_distance: f32) -> f32 {
+fn sdf_item_distance(target_point: vec3<f32>, index: u32) -> f32 {
+    return sdf_item_distance_level(target_point, index, 0.0);
+}
+
+// distance to the nearest surface that is safe to step by whatever the entries' metrics, see
+// `sdf_item_safe_step`
+fn sdf_distance_level(target_point: vec3<f32>, max_distance: f32, level: f32) -> f32 {
     var distance = max_distance;
 
     for (var i = 0u; i < arrayLength(&sdf_headers.data); i = i + 1u) {
-        let item_distance = sdf_item_distance(target_point, i);
+        // skip items which can't be closer than the current best (or the max tap distance)
+        let bounds = sdf_headers.data[i].bounds;
+        if (length(target_point - bounds.xyz) - bounds.w >= sdf_item_reach(i, distance)) {
+            continue;
+        }
+
+        let item_distance = sdf_item_safe_step(i, sdf_item_distance_level(target_point, i, level));
         distance = min(item_distance, distance);
     }
     return distance;
 }
 
+fn sdf_distance(target_point: vec3<f32>, max_distance: f32) -> f32 {
+    return sdf_distance_level(target_point, max_distance, 0.0);
+}
+
+// fraction of a cone tap left unoccluded, combining overlapping sdfs per `ao_combine`
+fn sdf_visibility(target_point: vec3<f32>, cone_radius: f32) -> f32 {
+    if (sdf_view.ao_combine == SDF_AO_COMBINE_PRODUCT) {
+        var visibility = 1.0;
+        for (var i = 0u; i < arrayLength(&sdf_headers.data); i = i + 1u) {
+            let bounds = sdf_headers.data[i].bounds;
+            if (length(target_point - bounds.xyz) - bounds.w >= sdf_item_reach(i, cone_radius)) {
+                continue;
+            }
+
+            let distance = sdf_item_safe_step(i, sdf_item_distance(target_point, i));
+            visibility = visibility * clamp(distance / cone_radius, 0.0, 1.0);
+        }
+        return max(visibility, 1.0 - sdf_view.ao_max_occlusion);
+    }
+
+    return clamp(sdf_distance(target_point, cone_radius) / cone_radius, 0.0, 1.0);
+}
+
 fn sdf_occlusion(world_position: vec4<f32>, world_normal: vec3<f32>, cone_scale: f32) -> f32 {
     let target_point = world_position.xyz + world_normal * sdf_view.ao_distances.x;
     let cone_radius = sdf_view.ao_distances.x * sdf_view.ao_sin_angle * cone_scale;
-    let target_point_distance = sdf_distance(target_point, cone_radius) / cone_radius;
-    let close = 1.0 - clamp(target_point_distance, 0.0, 1.0);
+    let close = 1.0 - sdf_visibility(target_point, cone_radius);
 
     let target_point = world_position.xyz + world_normal * sdf_view.ao_distances.y;
     let cone_radius = sdf_view.ao_distances.y * sdf_view.ao_sin_angle * cone_scale;
-    let target_point_distance = sdf_distance(target_point, cone_radius) / cone_radius;
-    let mid = 1.0 - clamp(target_point_distance, 0.0, 1.0);
+    let mid = 1.0 - sdf_visibility(target_point, cone_radius);
 
     let target_point = world_position.xyz + world_normal * sdf_view.ao_distances.z;
     let cone_radius = sdf_view.ao_distances.z * sdf_view.ao_sin_angle * cone_scale;
-    let target_point_distance = sdf_distance(target_point, cone_radius) / cone_radius;
-    let far = 1.0 - clamp(target_point_distance, 0.0, 1.0);
+    let far = 1.0 - sdf_visibility(target_point, cone_radius);
 
     return clamp(1.0 - close - 0.5 * mid - 0.25 * far, 0.0, 1.0);
 }
@@ -72,6 +162,11 @@ fn ambient_occlusion(world_position: vec4<f32>, world_normal: vec3<f32>) -> f32
     let up = vec3<f32>(1.0 + sign * fwd.x * fwd.x * a, sign * b, -sign * fwd.x);
     let right = vec3<f32>(-b, -sign - fwd.y * fwd.y * a, fwd.y);
 
+    // low quality: the normal cone only, with the weight of all five
+    if (sdf_view.ao_taps <= 1u) {
+        return 0.5 + sdf_occlusion(world_position, world_normal, 1.0) * 0.5;
+    }
+
     let side = 0.3;
     let ratio = sqrt(1.0 + 2.0*side*side);
     let fwd = fwd * ratio;
@@ -104,6 +199,10 @@ fn specular_occlusion(world_position: vec4<f32>, world_normal: vec3<f32>, view:
     return sdf_ao;
 }
 
+// `world_position` and `world_normal` are the interpolated fragment values, which the mesh vertex
+// shader has already skinned for skinned receivers, so occlusion follows the animated pose rather
+// than the rest pose. skinned occluders are regenerated in world space from the same frame's joint
+// transforms, so receiver and occluder stay in sync.
 fn ambient_light(
     world_position: vec4<f32>, 
     world_normal: vec3<f32>, 
@@ -0,0 +1,40 @@
+#define_import_path mesh2sdf::sdf_material
+
+// sdf occlusion and shadows for custom `Material` fragment shaders. the sdf view bindings are part
+// of the mesh view bind group, so a material needs no bindings of its own, only these imports in
+// this order:
+//
+// #import bevy_pbr::mesh_view_bindings
+// #import bevy_pbr::mesh_bindings
+// #import bevy_pbr::utils
+// #import bevy_pbr::lighting
+// #import bevy_pbr::pbr_ambient
+// #import mesh2sdf::sdf_material
+//
+// `bevy_pbr::pbr_ambient` brings in the lower level functions: `ambient_occlusion` and
+// `specular_occlusion` (1 when unoccluded), `sdf_distance` to the nearest sdf surface (safe to
+// march by, whatever the entries' metrics) and `sdf_view` with the current quality settings.
+
+// diffuse occlusion at a world space surface point, 0-1 with 1 unoccluded
+fn sdf_ambient_occlusion(world_position: vec4<f32>, world_normal: vec3<f32>) -> f32 {
+    return ambient_occlusion(world_position, normalize(world_normal));
+}
+
+// visibility of a light in `direction` (normalized, towards the light) from `origin`, 0-1. marches
+// towards the light tracking the narrowest cone around the ray that the scene leaves open, taking
+// its step count from the quality tier. larger `hardness` gives narrower penumbras, and the march
+// stops after `max_distance`. start the origin a little off the surface so the receiver doesn't
+// shadow itself
+fn sdf_soft_shadow(origin: vec3<f32>, direction: vec3<f32>, hardness: f32, max_distance: f32) -> f32 {
+    var visibility = 1.0;
+    var t = 0.05;
+    for (var i = 0u; i < sdf_view.shadow_steps; i = i + 1u) {
+        let distance = sdf_distance(origin + direction * t, max_distance);
+        visibility = min(visibility, hardness * distance / t);
+        if (visibility <= 0.0 || t >= max_distance) {
+            break;
+        }
+        t = t + max(distance, 0.02);
+    }
+    return clamp(visibility, 0.0, 1.0);
+}
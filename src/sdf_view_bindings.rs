@@ -1,44 +1,279 @@
 use bevy::{
     pbr::{
-        MeshUniform, UserViewBindGroupLayoutEntry, UserViewBindingsEntries, UserViewBindingsShader,
+        UserViewBindGroupLayoutEntry, UserViewBindingsEntries, UserViewBindingsShader,
         UserViewBindingsSpec,
     },
     prelude::*,
     render::{
+        render_asset::RenderAssets,
         render_resource::{
             encase::{StorageBuffer, UniformBuffer},
-            AddressMode, AsBindGroup, BindingType, BufferBindingType, BufferInitDescriptor,
-            BufferUsages, FilterMode, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
-            ShaderType,
+            AddressMode, AsBindGroup, BindGroup, BindGroupDescriptor, BindGroupEntry,
+            BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource,
+            BindingType, Buffer, BufferBindingType, BufferInitDescriptor, BufferUsages, FilterMode,
+            Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, ShaderType,
+            TextureSampleType, TextureView, TextureViewDimension,
         },
         renderer::RenderDevice,
     },
+    utils::HashMap,
 };
 
-use crate::{Sdf, SdfAtlas, SdfAtlasKey, SdfGlobalSettings};
+use crate::{
+    compute::SdfPipelineStatus, Sdf, SdfAoCombine, SdfAoSettings, SdfAtlas, SdfAtlasKey,
+    SdfGlobalSettings, SdfMetric, SdfQualityTier, SdfTransform,
+};
 
 #[derive(ShaderType, AsBindGroup)]
 struct SdfViewUniform {
     ao_distances: Vec3,
     ao_sin_angle: f32,
+    ao_taps: u32,
+    shadow_steps: u32,
+    ao_combine: u32,
+    ao_max_occlusion: f32,
 }
 
+// combination modes, must match sdf_view_bindings.wgsl
+const SDF_AO_COMBINE_UNION: u32 = 0;
+const SDF_AO_COMBINE_PRODUCT: u32 = 1;
+
 #[derive(ShaderType)]
 struct SdfHeader {
-    transform: Mat4,
-    aabb_min: Vec3,
-    aabb_size: Vec3,
+    // rows of the world space to 0-1 aabb coords affine
+    transform: [Vec4; 3],
+    // world space bounding sphere (center, radius) for culling before the transform
+    bounds: Vec4,
     atlas_position: Vec3,
-    atlas_size: Vec3,
     scale: f32,
+    // atlas extent, or the local aabb size for box occluders
+    atlas_size: Vec3,
+    flags: u32,
+    // atlas mip levels holding the entry, at least 1
+    mip_count: u32,
+    // world space distance from the surface the entry affects, f32::MAX when unlimited
+    effect_range: f32,
 }
 
+// header flags, must match sdf_view_bindings.wgsl
+const SDF_HEADER_FLAG_BOX: u32 = 1;
+const SDF_HEADER_FLAG_CHEBYSHEV: u32 = 2;
+const SDF_HEADER_FLAG_MANHATTAN: u32 = 4;
+const SDF_HEADER_FLAG_BRICKS: u32 = 8;
+const SDF_HEADER_FLAG_COMPRESSED: u32 = 16;
+
 #[derive(ShaderType)]
 struct SdfHeaders {
     #[size(runtime)]
     data: Vec<SdfHeader>,
 }
 
+// world space bounding sphere of a local space aabb
+fn world_bounds(world: Mat4, aabb_min: Vec3, aabb_size: Vec3) -> Vec4 {
+    let center = world.transform_point3(aabb_min + aabb_size * 0.5);
+    let max_scale = world
+        .x_axis
+        .truncate()
+        .length()
+        .max(world.y_axis.truncate().length())
+        .max(world.z_axis.truncate().length());
+    center.extend(aabb_size.length() * 0.5 * max_scale)
+}
+
+// mip levels keeping at least 2 texels of an entry along each axis
+fn entry_mip_count(size: UVec3, atlas_mip_levels: u32) -> u32 {
+    let min_size = size.min_element().max(2);
+    (32 - (min_size / 2).leading_zeros()).min(atlas_mip_levels)
+}
+
+// rows of the affine from world space to 0-1 coords within a local space aabb
+fn aabb_coords_transform(world: Mat4, aabb_min: Vec3, aabb_size: Vec3) -> [Vec4; 3] {
+    let to_coords = Mat4::from_scale(aabb_size.recip())
+        * Mat4::from_translation(-aabb_min)
+        * world.inverse();
+    [to_coords.row(0), to_coords.row(1), to_coords.row(2)]
+}
+
+// atlas slots (position, size) holding fully written entries. a slot is added once the frame
+// that dispatched it has been submitted, so views never sample an entry in the frame it is
+// (re)generated into a new slot, which may still hold another entry's data. entries recomputed
+// in place keep their slot and stay visible
+#[derive(Default)]
+pub(crate) struct SdfWrittenEntries(HashMap<SdfAtlasKey, (UVec3, UVec3)>);
+
+impl SdfWrittenEntries {
+    fn contains(&self, key: &SdfAtlasKey, position: UVec3, size: UVec3) -> bool {
+        self.0.get(key) == Some(&(position, size))
+    }
+
+    // a written entry relocated this frame, its data is copied to the new slot before the views
+    // are drawn
+    fn contains_moved(&self, atlas: &SdfAtlas, key: &SdfAtlasKey, position: UVec3, size: UVec3) -> bool {
+        atlas.moves.iter().any(|atlas_move| {
+            atlas_move.key == *key
+                && atlas_move.to == position
+                && self.contains(key, atlas_move.from, size)
+        })
+    }
+}
+
+pub(crate) fn record_written_entries(
+    atlas: Res<SdfAtlas>,
+    status: Res<SdfPipelineStatus>,
+    mut written: ResMut<SdfWrittenEntries>,
+) {
+    // follow entries relocated by compaction
+    for atlas_move in atlas.moves.iter() {
+        if written.contains(&atlas_move.key, atlas_move.from, atlas_move.size) {
+            written.0.insert(atlas_move.key.clone(), (atlas_move.to, atlas_move.size));
+        }
+    }
+
+    // forget slots that were freed or reallocated
+    written.0.retain(|key, slot| {
+        atlas
+            .slot(key)
+            .map_or(false, |info| (info.position, info.size) == *slot)
+    });
+
+    for (ent, key, _) in atlas.need_computing.iter() {
+        if status.was_skipped(*ent) {
+            continue;
+        }
+        if let Some(info) = atlas.slot(key) {
+            written.0.insert(key.clone(), (info.position, info.size));
+        }
+    }
+}
+
+/// wgsl declarations of the sdf bindings (at group 0, bindings 0-5) and header layout, matching
+/// `SdfRenderResources::layout`. replace the group index if binding elsewhere.
+pub const SDF_BINDINGS_WGSL: &str = include_str!("sdf_view_bindings.wgsl");
+
+/// render world handles to the sdf data for custom render nodes (e.g. a volumetrics pass), updated
+/// each frame in `RenderStage::Queue`. absent until the atlas image has been uploaded.
+///
+/// bind with `bind_group` against `layout` and declare the bindings with `SDF_BINDINGS_WGSL`:
+/// 0: view uniform, 1: sdf headers (one per sdf entity), 2: atlas texture, 3: atlas sampler,
+/// 4: brick indirection texture (see `SdfOptions::bricks`), 5: compressed atlas texture (see
+/// `SdfOptions::compress`).
+/// headers and uniform are recreated each frame, so don't keep bind groups across frames.
+pub struct SdfRenderResources {
+    pub view_uniform: Buffer,
+    pub headers: Buffer,
+    pub header_count: u32,
+    pub atlas_view: TextureView,
+    // atlas dimensions in texels
+    pub atlas_size: UVec3,
+    pub sampler: Sampler,
+    // read with `textureLoad` only
+    pub indirection_view: TextureView,
+    // 2d array, a layer per z slice of each compressed entry
+    pub compressed_view: TextureView,
+    // visible to vertex, fragment and compute stages
+    pub layout: BindGroupLayout,
+}
+
+impl SdfRenderResources {
+    pub fn bind_group(&self, render_device: &RenderDevice) -> BindGroup {
+        render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("sdf resources"),
+            layout: &self.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.view_uniform.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.headers.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&self.atlas_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&self.indirection_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&self.compressed_view),
+                },
+            ],
+        })
+    }
+}
+
+fn create_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    let visibility = ShaderStages::VERTEX | ShaderStages::FRAGMENT | ShaderStages::COMPUTE;
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("sdf resources layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SdfViewUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SdfHeaders::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
 pub(crate) fn add_view_bindings(app: &mut App) {
     let mut user_bindings = app
         .world
@@ -86,28 +321,65 @@ pub(crate) fn add_view_bindings(app: &mut App) {
                 ty: BindingType::Sampler(SamplerBindingType::Filtering),
             },
         ),
+        (
+            "sdf_indirection",
+            UserViewBindGroupLayoutEntry {
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+            },
+        ),
+        (
+            "sdf_compressed",
+            UserViewBindGroupLayoutEntry {
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+            },
+        ),
     ]);
 
     user_bindings.binding_shaders.push(UserViewBindingsShader {
         shader: String::from(include_str!("sdf_view_bindings.wgsl")),
-        num_bindings: 5,
+        num_bindings: 7,
     });
 }
 
 pub(crate) fn queue_sdf_view_bindings(
     settings: Res<SdfGlobalSettings>,
+    tier: Res<SdfQualityTier>,
+    ao_settings: Res<SdfAoSettings>,
+    mut commands: Commands,
     mut view_bindings: ResMut<UserViewBindingsEntries>,
     atlas: Res<SdfAtlas>,
+    written: Res<SdfWrittenEntries>,
+    gpu_images: Res<RenderAssets<Image>>,
     render_device: Res<RenderDevice>,
-    sdfs: Query<(&Sdf, Option<&Handle<Mesh>>, &MeshUniform)>,
+    sdfs: Query<(Entity, &Sdf, Option<&Handle<Mesh>>, &SdfTransform)>,
     mut frame: Local<u32>,
     mut sampler: Local<Option<Sampler>>,
+    mut layout: Local<Option<BindGroupLayout>>,
 ) {
     *frame = (*frame + 1) % 1000;
 
+    let (ao_combine, ao_max_occlusion) = match ao_settings.combine {
+        SdfAoCombine::Union => (SDF_AO_COMBINE_UNION, 1.0),
+        SdfAoCombine::Product { max_occlusion } => (SDF_AO_COMBINE_PRODUCT, max_occlusion),
+    };
+
     let view_uniform = SdfViewUniform {
         ao_distances: Vec3::new(settings.ambient_distance / 3.0, settings.ambient_distance * 2.0 / 3.0, settings.ambient_distance),
         ao_sin_angle: 0.5,
+        ao_taps: tier.ao_taps,
+        shadow_steps: tier.shadow_steps,
+        ao_combine,
+        ao_max_occlusion,
     };
 
     let byte_buffer = Vec::with_capacity(SdfViewUniform::min_size().get() as usize);
@@ -120,28 +392,102 @@ pub(crate) fn queue_sdf_view_bindings(
         contents: buffer.as_ref(),
     });
 
-    let sdf_headers = sdfs.iter().filter_map(|(sdf, maybe_mesh, mesh_uniform)| {
-        SdfAtlasKey::try_from_sdf(sdf, maybe_mesh)
-            .and_then(|key| atlas.page.get(&key))
-            .and_then(|info| {
-                let (scale, transform) = match sdf.skinned {
-                    true => (1.0, Mat4::IDENTITY),
-                    false => (Transform::from_matrix(mesh_uniform.transform).scale.x, mesh_uniform.inverse_transpose_model.transpose()),
-                };
-                Some(SdfHeader {
-                    transform,
-                    aabb_min: sdf.aabb.min().into(),
-                    aabb_size: (sdf.aabb.half_extents * 2.0).into(),
-                    atlas_position: info.position.as_vec3() / atlas.page.dim.as_vec3(),
-                    atlas_size: (info.size - 1).as_vec3() / atlas.page.dim.as_vec3(),
-                    scale,
-                })
-            })
+    let sdf_headers = sdfs.iter().filter_map(|(ent, sdf, maybe_mesh, sdf_transform)| {
+        // skinned sdfs are generated in world space
+        let world = match sdf.skinned {
+            true => Mat4::IDENTITY,
+            false => sdf_transform.0,
+        };
+        let scale = Transform::from_matrix(world).scale.x;
+        let effect_range = sdf.options.effect_range.unwrap_or(f32::MAX);
+
+        let key = atlas.key(ent, sdf, maybe_mesh);
+
+        // compressed entries are found by layer, in voxels, with distances as a fraction of their
+        // range
+        if let Some(slot) = key.as_ref().and_then(|key| atlas.compressed.get(key)) {
+            let aabb_min = sdf.aabb.min().into();
+            let aabb_size = (sdf.aabb.half_extents * 2.0).into();
+            let flags = match sdf.options.metric {
+                SdfMetric::Euclidean => 0,
+                SdfMetric::Chebyshev => SDF_HEADER_FLAG_CHEBYSHEV,
+                SdfMetric::Manhattan => SDF_HEADER_FLAG_MANHATTAN,
+            };
+            return Some(SdfHeader {
+                transform: aabb_coords_transform(world, aabb_min, aabb_size),
+                bounds: world_bounds(world, aabb_min, aabb_size),
+                atlas_position: slot.position.as_vec3(),
+                scale: scale * slot.range,
+                atlas_size: (slot.size - 1).as_vec3(),
+                flags: flags | SDF_HEADER_FLAG_COMPRESSED,
+                mip_count: 1,
+                effect_range,
+            });
+        }
+
+        let written_info = key.and_then(|key| {
+            let info = atlas.slot(&key)?;
+            let is_written = written.contains(&key, info.position, info.size)
+                || written.contains_moved(&atlas, &key, info.position, info.size);
+            is_written.then_some((key, info))
+        });
+        if let Some((key, info)) = written_info {
+            // double buffered entities show the pose in their front slot
+            let aabb = atlas.buffered.get(&ent).map_or(&sdf.aabb, |entry| entry.front_aabb());
+            let aabb_min = aabb.min().into();
+            let aabb_size = (aabb.half_extents * 2.0).into();
+            let flags = match sdf.options.metric {
+                SdfMetric::Euclidean => 0,
+                SdfMetric::Chebyshev => SDF_HEADER_FLAG_CHEBYSHEV,
+                SdfMetric::Manhattan => SDF_HEADER_FLAG_MANHATTAN,
+            };
+            // brick entries are found through their indirection texels, in voxels
+            let (atlas_position, atlas_size, flags, mip_count) = match atlas.is_bricks(&key) {
+                true => (
+                    info.position.as_vec3(),
+                    (info.size - 2).as_vec3(),
+                    flags | SDF_HEADER_FLAG_BRICKS,
+                    1,
+                ),
+                false => (
+                    info.position.as_vec3() / atlas.dim().as_vec3(),
+                    (info.size - 1).as_vec3() / atlas.dim().as_vec3(),
+                    flags,
+                    entry_mip_count(info.size - 1, atlas.mip_levels),
+                ),
+            };
+            return Some(SdfHeader {
+                transform: aabb_coords_transform(world, aabb_min, aabb_size),
+                bounds: world_bounds(world, aabb_min, aabb_size),
+                atlas_position,
+                scale,
+                atlas_size,
+                flags,
+                mip_count,
+                effect_range,
+            });
+        }
+
+        atlas.fallbacks.get(&ent).map(|aabb| {
+            let aabb_min = aabb.min().into();
+            // flat meshes give flat aabbs
+            let aabb_size = Vec3::from(aabb.half_extents * 2.0).max(Vec3::splat(1e-6));
+            SdfHeader {
+                transform: aabb_coords_transform(world, aabb_min, aabb_size),
+                bounds: world_bounds(world, aabb_min, aabb_size),
+                atlas_position: Vec3::ZERO,
+                scale,
+                atlas_size: aabb_size,
+                flags: SDF_HEADER_FLAG_BOX,
+                mip_count: 1,
+                effect_range,
+            }
+        })
     });
 
     // if let Some((sdf, maybe_mesh, mesh_uniform)) = sdfs.iter().nth(4) {
     //     if let Some(key) = SdfAtlasKey::try_from_sdf(sdf, maybe_mesh) {
-    //         if let Some(info) = atlas.page.get(&key) {
+    //         if let Some(info) = atlas.slot(&key) {
     //             println!(
     //                 "sdf 4 is {} @ {}",
     //                 info.size - 1,
@@ -154,6 +500,7 @@ pub(crate) fn queue_sdf_view_bindings(
     let sdf_headers = SdfHeaders {
         data: sdf_headers.collect(),
     };
+    let header_count = sdf_headers.data.len() as u32;
 
     // println!("{}", sdf_headers.data.len());
 
@@ -180,6 +527,26 @@ pub(crate) fn queue_sdf_view_bindings(
         })
     });
 
+    if let (Some(gpu_image), Some(indirection), Some(compressed)) = (
+        gpu_images.get(&atlas.image),
+        gpu_images.get(&atlas.indirection_image),
+        gpu_images.get(&atlas.compressed_image),
+    ) {
+        commands.insert_resource(SdfRenderResources {
+            view_uniform: view_uniform_buffer.clone(),
+            headers: view_sdf_headers_buffer.clone(),
+            header_count,
+            atlas_view: gpu_image.texture_view.clone(),
+            atlas_size: atlas.dim(),
+            sampler: sampler.clone(),
+            indirection_view: indirection.texture_view.clone(),
+            compressed_view: compressed.texture_view.clone(),
+            layout: layout
+                .get_or_insert_with(|| create_layout(&render_device))
+                .clone(),
+        });
+    }
+
     view_bindings
         .entries
         .insert("sdf_uniform", Box::new(view_uniform_buffer));
@@ -192,4 +559,10 @@ pub(crate) fn queue_sdf_view_bindings(
     view_bindings
         .entries
         .insert("sdf_sampler", Box::new(sampler.clone()));
+    view_bindings
+        .entries
+        .insert("sdf_indirection", Box::new(atlas.indirection_image.clone()));
+    view_bindings
+        .entries
+        .insert("sdf_compressed", Box::new(atlas.compressed_image.clone()));
 }
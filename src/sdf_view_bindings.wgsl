@@ -3,17 +3,51 @@ struct SdfUniform {
     ao_distances: vec3<f32>,
     // cone angle (opp/adj)
     ao_sin_angle: f32,
+    // cones traced for diffuse occlusion, 1 or 5
+    ao_taps: u32,
+    // for materials marching sdf shadows
+    shadow_steps: u32,
+    // how overlapping sdfs combine
+    ao_combine: u32,
+    // limit on a tap's occlusion when combining by product
+    ao_max_occlusion: f32,
 };
 
+// combination modes, must match sdf_view_bindings.rs
+let SDF_AO_COMBINE_UNION: u32 = 0u;
+let SDF_AO_COMBINE_PRODUCT: u32 = 1u;
+
 struct SdfHeader {
-    transform: mat4x4<f32>,
-    aabb_min: vec3<f32>,
-    aabb_size: vec3<f32>,
+    // world space to 0-1 coords within the aabb, as a transposed 3x4 affine (use `v * transform`)
+    transform: mat3x4<f32>,
+    // world space bounding sphere (center, radius)
+    bounds: vec4<f32>,
     atlas_position: vec3<f32>,
-    atlas_size: vec3<f32>,
+    // local to world distance scale
     scale: f32,
+    // atlas extent, or the local aabb size for box occluders
+    atlas_size: vec3<f32>,
+    flags: u32,
+    // atlas mip levels holding the entry, at least 1. levels are min-filtered, so marchers far
+    // from surfaces can sample coarser levels
+    mip_count: u32,
+    // world space distance from the surface the entry affects, points further away should
+    // ignore it
+    effect_range: f32,
 };
 
+// header flags, must match sdf_view_bindings.rs
+let SDF_HEADER_FLAG_BOX: u32 = 1u;
+// the entry was generated with `SdfMetric::Chebyshev` or `SdfMetric::Manhattan`
+let SDF_HEADER_FLAG_CHEBYSHEV: u32 = 2u;
+let SDF_HEADER_FLAG_MANHATTAN: u32 = 4u;
+// the entry is stored as 8x8x8 bricks. atlas_position holds its first texel in sdf_indirection
+// and atlas_size its extent in voxels
+let SDF_HEADER_FLAG_BRICKS: u32 = 8u;
+// the entry is in sdf_compressed, a layer per z slice. atlas_position holds its first texel with
+// the layer in z, atlas_size its extent in voxels, and scale includes its distance range
+let SDF_HEADER_FLAG_COMPRESSED: u32 = 16u;
+
 struct SdfHeaders {
     data: array<SdfHeader>,
 };
@@ -36,3 +70,10 @@ var<storage> sdf_headers: SdfHeaders;
 var sdf_atlas: texture_3d<f32>;
 @group(0) @binding(3)
 var sdf_sampler: sampler;
+// a texel per brick of entries stored as bricks: the atlas texel of the brick's first voxel in
+// xyz, or -1 in x for empty bricks, and a lower bound on the distance to the surface in w
+@group(0) @binding(4)
+var sdf_indirection: texture_3d<f32>;
+// BC4 layers holding entries with `SdfOptions::compress`, sampled with sdf_sampler
+@group(0) @binding(5)
+var sdf_compressed: texture_2d_array<f32>;
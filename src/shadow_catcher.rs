@@ -0,0 +1,78 @@
+use bevy::{
+    asset::load_internal_asset,
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{AsBindGroup, ShaderRef},
+};
+
+pub const SHADOW_CATCHER_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 6120937460381514529);
+
+/// adds `SdfShadowCatcherMaterial`, for surfaces which are invisible except for the sdf
+/// occlusion and shadows they receive, e.g. the ground plane of a product viewer. add after
+/// `SdfPlugin`, with the sdf view bindings added.
+pub struct SdfShadowCatcherPlugin;
+
+impl Plugin for SdfShadowCatcherPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADOW_CATCHER_SHADER_HANDLE,
+            "shadow_catcher.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugin(MaterialPlugin::<SdfShadowCatcherMaterial>::default());
+    }
+}
+
+/// draws `color` blended over the scene in proportion to how occluded and shadowed the surface
+/// is, and nothing where it's unoccluded. leave the catcher itself without an `Sdf`, or it
+/// occludes itself
+#[derive(Clone, TypeUuid, AsBindGroup)]
+#[uuid = "3e5d2a7c-91b4-4f08-b6c1-58d0e2f7a934"]
+pub struct SdfShadowCatcherMaterial {
+    // the shadow color, its alpha is the opacity of a fully occluded point
+    #[uniform(0)]
+    pub color: Color,
+    // towards the light casting the shadows
+    #[uniform(0)]
+    pub light_direction: Vec3,
+    // larger values give narrower penumbras
+    #[uniform(0)]
+    pub hardness: f32,
+    // shadow rays stop after this distance
+    #[uniform(0)]
+    pub max_distance: f32,
+    // how much ambient occlusion darkens the surface, 0-1
+    #[uniform(0)]
+    pub ao_strength: f32,
+    // how much shadows darken the surface, 0-1. 0 skips the shadow march
+    #[uniform(0)]
+    pub shadow_strength: f32,
+}
+
+impl Default for SdfShadowCatcherMaterial {
+    fn default() -> Self {
+        Self {
+            color: Color::rgba(0.0, 0.0, 0.0, 0.8),
+            light_direction: Vec3::Y,
+            hardness: 8.0,
+            max_distance: 2.0,
+            ao_strength: 1.0,
+            shadow_strength: 1.0,
+        }
+    }
+}
+
+impl Material for SdfShadowCatcherMaterial {
+    fn fragment_shader() -> ShaderRef {
+        SHADOW_CATCHER_SHADER_HANDLE.typed().into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+/// a shadow catcher mesh, e.g. a `shape::Plane` under a model
+pub type SdfShadowCatcherBundle = MaterialMeshBundle<SdfShadowCatcherMaterial>;
@@ -0,0 +1,46 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::utils
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import mesh2sdf::sdf_material
+
+struct ShadowCatcherMaterial {
+    color: vec4<f32>,
+    // towards the light
+    light_direction: vec3<f32>,
+    hardness: f32,
+    max_distance: f32,
+    ao_strength: f32,
+    shadow_strength: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: ShadowCatcherMaterial;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    var normal = normalize(in.world_normal);
+    if (!in.is_front) {
+        normal = -normal;
+    }
+
+    // the normal cone only, unoccluded surfaces should be fully transparent
+    let ao = sdf_occlusion(in.world_position, normal, 1.0);
+
+    var shadow = 1.0;
+    let light = normalize(material.light_direction);
+    if (material.shadow_strength > 0.0 && dot(normal, light) > 0.0) {
+        let origin = in.world_position.xyz + normal * 0.05;
+        shadow = sdf_soft_shadow(origin, light, material.hardness, material.max_distance);
+    }
+
+    let visibility =
+        mix(1.0, ao, material.ao_strength) * mix(1.0, shadow, material.shadow_strength);
+    return vec4<f32>(material.color.rgb, material.color.a * (1.0 - visibility));
+}
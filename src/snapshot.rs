@@ -0,0 +1,324 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::mesh::skinning::SkinnedMesh,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    queue_sdfs,
+    readback::{SdfGrid, SdfReadback, SdfReadbackReady},
+    Sdf, SdfAtlas, SdfGenMode, SdfStatus,
+};
+
+/// saving and restoring the whole atlas, so a level's sdfs can be baked at build time and
+/// restored at runtime without generating anything. entities are matched by their `Name`, so
+/// give every sdf entity in the level a unique one. add after `SdfPlugin` and
+/// `SdfReadbackPlugin`.
+///
+/// send `SdfCaptureSnapshot` once the level's sdfs are generated, and write out the
+/// `SdfAtlasSnapshot` from `SdfSnapshotReady` (with `to_bytes` and the `serialize` feature, as a
+/// `.sdfatlas` file). to restore, set `SdfSnapshotRestore` to the loaded snapshot: the entries of
+/// named entities in it are filled by copying the saved distances rather than generating them.
+/// the entities' `Sdf`s are left as they are. distances are copied as they are while the
+/// entity's volume and the atlas resolution match the capture, and resampled otherwise.
+///
+/// the snapshot holds each entity's distances, not the atlas allocation table or keys, as keys
+/// name asset handles and entities which don't survive a restart. restoring allocates each entry
+/// as usual and copies into it, so it costs an atlas insert and a blit per entity, and the
+/// restored layout generally differs from the captured one.
+pub struct SdfSnapshotPlugin;
+
+impl Plugin for SdfSnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<SdfAtlasSnapshot>()
+            .add_event::<SdfCaptureSnapshot>()
+            .add_event::<SdfSnapshotReady>()
+            .init_resource::<SdfSnapshotRestore>()
+            .init_resource::<SdfSnapshotCapture>()
+            .add_system_to_stage(CoreStage::PostUpdate, start_capture.after(queue_sdfs))
+            .add_system_to_stage(CoreStage::PreUpdate, collect_capture)
+            .add_system_to_stage(CoreStage::PostUpdate, restore_snapshot.before(queue_sdfs));
+
+        #[cfg(feature = "serialize")]
+        app.init_asset_loader::<SdfAtlasSnapshotLoader>();
+    }
+}
+
+/// the generated sdfs of a level's named static entities
+#[derive(TypeUuid, Clone, Default)]
+#[uuid = "d41c6b0e-8f2a-4b67-a3c5-7e19f05b2d84"]
+pub struct SdfAtlasSnapshot {
+    pub entries: Vec<SdfSnapshotEntry>,
+}
+
+/// one entity's sdf in an `SdfAtlasSnapshot`
+#[derive(Clone)]
+pub struct SdfSnapshotEntry {
+    // the entity's `Name`
+    pub name: String,
+    pub grid: SdfGrid,
+}
+
+/// send to read back the sdfs of every named static entity whose sdf is generated, completing
+/// with `SdfSnapshotReady`. entities stored as bricks or compressed and skinned entities are left
+/// out
+pub struct SdfCaptureSnapshot;
+
+/// sent when every readback of a capture has arrived
+pub struct SdfSnapshotReady {
+    pub snapshot: SdfAtlasSnapshot,
+}
+
+/// the snapshot to restore named entities from, none to generate as usual. entities spawned
+/// after it's set are restored too, and their restored entries are kept if it's cleared
+#[derive(Default)]
+pub struct SdfSnapshotRestore {
+    pub snapshot: Option<Handle<SdfAtlasSnapshot>>,
+    // images of the loaded snapshot's entries by name
+    images: HashMap<String, Handle<Image>>,
+    loaded: Option<Handle<SdfAtlasSnapshot>>,
+}
+
+// the capture in progress: entities still waiting for their readback, and the entries so far
+#[derive(Default)]
+struct SdfSnapshotCapture {
+    pending: HashSet<Entity>,
+    entries: Vec<SdfSnapshotEntry>,
+    active: bool,
+}
+
+#[allow(clippy::type_complexity)]
+fn start_capture(
+    mut commands: Commands,
+    mut requests: EventReader<SdfCaptureSnapshot>,
+    sdfs: Query<(Entity, &Sdf, &SdfStatus, Option<&Handle<Mesh>>), With<Name>>,
+    atlas: Res<SdfAtlas>,
+    mut capture: ResMut<SdfSnapshotCapture>,
+) {
+    if requests.iter().count() == 0 {
+        return;
+    }
+    if capture.active {
+        warn!("sdf snapshot requested while a capture is in progress, ignoring");
+        return;
+    }
+
+    capture.active = true;
+    capture.entries.clear();
+    capture.pending = sdfs
+        .iter()
+        .filter(|(ent, sdf, status, maybe_mesh)| {
+            // evicted and streamed out entries keep their status, but have nothing to read back
+            !sdf.skinned
+                && matches!(status, SdfStatus::Full | SdfStatus::Reduced)
+                && atlas.key(*ent, sdf, *maybe_mesh).map_or(false, |key| {
+                    atlas.slot(&key).is_some()
+                        && !atlas.is_bricks(&key)
+                        && !atlas.is_compressed(&key)
+                })
+        })
+        .map(|(ent, ..)| ent)
+        .collect();
+    for ent in capture.pending.iter() {
+        commands.entity(*ent).insert(SdfReadback);
+    }
+}
+
+fn collect_capture(
+    mut readbacks: EventReader<SdfReadbackReady>,
+    names: Query<&Name, With<Sdf>>,
+    sdfs: Query<(&Sdf, Option<&Handle<Mesh>>)>,
+    atlas: Res<SdfAtlas>,
+    mut capture: ResMut<SdfSnapshotCapture>,
+    mut ready: EventWriter<SdfSnapshotReady>,
+) {
+    if !capture.active {
+        return;
+    }
+
+    for event in readbacks.iter() {
+        if !capture.pending.remove(&event.entity) {
+            continue;
+        }
+        if let Ok(name) = names.get(event.entity) {
+            capture.entries.push(SdfSnapshotEntry {
+                name: name.as_str().to_owned(),
+                grid: event.grid.clone(),
+            });
+        }
+    }
+
+    // despawned entities, and entries evicted or streamed out before their readback was issued,
+    // never arrive
+    capture.pending.retain(|ent| {
+        let Ok((sdf, maybe_mesh)) = sdfs.get(*ent) else { return false };
+        names.get(*ent).is_ok()
+            && atlas
+                .key(*ent, sdf, maybe_mesh)
+                .map_or(false, |key| atlas.slot(&key).is_some())
+    });
+    if !capture.pending.is_empty() {
+        return;
+    }
+
+    capture.active = false;
+    capture.pending.clear();
+    let entries = std::mem::take(&mut capture.entries);
+    ready.send(SdfSnapshotReady {
+        snapshot: SdfAtlasSnapshot { entries },
+    });
+}
+
+// point the atlas at the saved distances of named entities in the snapshot. entries which were
+// already generated, or restored from another snapshot, are purged so they're copied in again
+fn restore_snapshot(
+    mut restore: ResMut<SdfSnapshotRestore>,
+    snapshots: Res<Assets<SdfAtlasSnapshot>>,
+    mut images: ResMut<Assets<Image>>,
+    sdfs: Query<(Entity, &Name, &Sdf, Option<&Handle<Mesh>>), Without<SkinnedMesh>>,
+    mut atlas: ResMut<SdfAtlas>,
+) {
+    let restore = &mut *restore;
+    let Some(handle) = restore.snapshot.as_ref() else {
+        restore.images.clear();
+        restore.loaded = None;
+        atlas.restored.clear();
+        return;
+    };
+
+    if restore.loaded.as_ref() != Some(handle) {
+        let Some(snapshot) = snapshots.get(handle) else { return };
+        restore.images = snapshot
+            .entries
+            .iter()
+            .map(|entry| (entry.name.clone(), images.add(entry.grid.to_image())))
+            .collect();
+        restore.loaded = Some(handle.clone());
+    }
+
+    let mut restored = HashMap::default();
+    for (ent, name, sdf, maybe_mesh) in sdfs.iter() {
+        // entities copying an image of their own already skip generation
+        if matches!(sdf.mode, SdfGenMode::Precomputed(_) | SdfGenMode::Composite(_)) {
+            continue;
+        }
+        let Some(image) = restore.images.get(name.as_str()) else { continue };
+        if atlas.restored.get(&ent) != Some(image) {
+            if let Some(key) = atlas.key(ent, sdf, maybe_mesh) {
+                atlas.purge(&key);
+            }
+        }
+        restored.insert(ent, image.clone_weak());
+    }
+    atlas.restored = restored;
+}
+
+#[cfg(feature = "serialize")]
+pub use serialize::*;
+
+#[cfg(feature = "serialize")]
+mod serialize {
+    use bevy::{
+        asset::{AssetLoader, LoadContext, LoadedAsset},
+        render::primitives::Aabb,
+        utils::BoxedFuture,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    // bump when the layout changes
+    const FORMAT_VERSION: u32 = 1;
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedSnapshot {
+        version: u32,
+        entries: Vec<SerializedEntry>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedEntry {
+        name: String,
+        min: [f32; 3],
+        max: [f32; 3],
+        dimension: [u32; 3],
+        // x-major distances
+        data: Vec<f32>,
+    }
+
+    impl SdfAtlasSnapshot {
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let serialized = SerializedSnapshot {
+                version: FORMAT_VERSION,
+                entries: self
+                    .entries
+                    .iter()
+                    .map(|entry| SerializedEntry {
+                        name: entry.name.clone(),
+                        min: Vec3::from(entry.grid.aabb.min()).to_array(),
+                        max: Vec3::from(entry.grid.aabb.max()).to_array(),
+                        dimension: entry.grid.dimension.to_array(),
+                        data: entry.grid.data.clone(),
+                    })
+                    .collect(),
+            };
+
+            bincode::serialize(&serialized).unwrap()
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+            let serialized: SerializedSnapshot = bincode::deserialize(bytes)?;
+            if serialized.version != FORMAT_VERSION {
+                anyhow::bail!(
+                    "unsupported sdfatlas version {} (expected {})",
+                    serialized.version,
+                    FORMAT_VERSION
+                );
+            }
+
+            let mut entries = Vec::with_capacity(serialized.entries.len());
+            for entry in serialized.entries {
+                let dimension = UVec3::from(entry.dimension);
+                if entry.data.len() != (dimension.x * dimension.y * dimension.z) as usize {
+                    anyhow::bail!(
+                        "sdfatlas entry {} has the wrong number of distances",
+                        entry.name
+                    );
+                }
+                entries.push(SdfSnapshotEntry {
+                    name: entry.name,
+                    grid: SdfGrid {
+                        aabb: Aabb::from_min_max(entry.min.into(), entry.max.into()),
+                        dimension,
+                        data: entry.data,
+                    },
+                });
+            }
+            Ok(Self { entries })
+        }
+    }
+
+    /// loads `SdfAtlasSnapshot` assets from `.sdfatlas` files written with
+    /// `SdfAtlasSnapshot::to_bytes`
+    #[derive(Default)]
+    pub struct SdfAtlasSnapshotLoader;
+
+    impl AssetLoader for SdfAtlasSnapshotLoader {
+        fn load<'a>(
+            &'a self,
+            bytes: &'a [u8],
+            load_context: &'a mut LoadContext,
+        ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+            Box::pin(async move {
+                let snapshot = SdfAtlasSnapshot::from_bytes(bytes)?;
+                load_context.set_default_asset(LoadedAsset::new(snapshot));
+                Ok(())
+            })
+        }
+
+        fn extensions(&self) -> &[&str] {
+            &["sdfatlas"]
+        }
+    }
+}
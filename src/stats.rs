@@ -0,0 +1,227 @@
+use bevy::{prelude::*, render::primitives::Aabb};
+
+use crate::{
+    compute::SdfData, cpu::BRICK_SIZE, Sdf, SdfAtlas, SdfAtlasKey, SdfGenMode, SdfMethod,
+};
+
+// granularity of the occupancy grid used to measure the free space
+const STATS_CELL_SIZE: u32 = 8;
+
+// the lower neighbours a free cube ending at a cell grows from
+const CUBE_NEIGHBOURS: [UVec3; 7] = [
+    UVec3::new(1, 0, 0),
+    UVec3::new(0, 1, 0),
+    UVec3::new(0, 0, 1),
+    UVec3::new(1, 1, 0),
+    UVec3::new(1, 0, 1),
+    UVec3::new(0, 1, 1),
+    UVec3::new(1, 1, 1),
+];
+
+/// a resident atlas entry
+#[derive(Clone)]
+pub struct SdfAtlasEntryInfo {
+    pub key: SdfAtlasKey,
+    // first texel of the entry
+    pub position: UVec3,
+    // texels along each axis
+    pub size: UVec3,
+}
+
+/// how and when a resident atlas entry was last generated, from `SdfAtlas::metadata`
+#[derive(Clone)]
+pub struct SdfEntryMetadata {
+    pub key: SdfAtlasKey,
+    // the entity the entry was generated for (one of them for shared entries)
+    pub entity: Entity,
+    // `SdfAtlas` frame the entry was queued on, see `SdfAtlas::frame`
+    pub frame: u64,
+    // voxels along each axis, lower than the entity's full resolution while coarse or reduced
+    pub resolution: UVec3,
+    // world space bounds of the volume when it was generated
+    pub world_aabb: Aabb,
+    // none for entries copied from precomputed images or composited from other entries
+    pub method: Option<SdfMethod>,
+    pub bricks: bool,
+}
+
+/// a summary of how the atlas is used, from `SdfAtlas::stats`
+#[derive(Clone, Debug, Default)]
+pub struct SdfAtlasStats {
+    pub entry_count: usize,
+    // texels in the atlas page
+    pub total_texels: u64,
+    // texels covered by entries
+    pub used_texels: u64,
+    // bricks held by entries stored as bricks, included in `used_texels`
+    pub brick_count: usize,
+    // fraction of the page covered by entries, 0-1
+    pub occupancy: f32,
+    // texels along each axis of the largest empty cube the page could still take, measured in
+    // whole cells of 8 texels
+    pub largest_free_cube: u32,
+    // how scattered the free space is, 0-1: 0 when the largest free cube covers all of it, near 1
+    // when it's split into many small gaps. compaction brings it down
+    pub fragmentation: f32,
+}
+
+impl SdfAtlas {
+    /// the entries currently resident in the atlas. entries stored as bricks are left out, their
+    /// slots are in the indirection texture
+    pub fn entries(&self) -> impl Iterator<Item = SdfAtlasEntryInfo> + '_ {
+        self.last_used.keys().filter(|key| !self.is_bricks(key)).filter_map(|key| {
+            let info = self.slot(key)?;
+            Some(SdfAtlasEntryInfo {
+                key: key.clone(),
+                position: info.position,
+                size: info.size,
+            })
+        })
+    }
+
+    /// metadata of a resident entry
+    pub fn metadata(&self, key: &SdfAtlasKey) -> Option<&SdfEntryMetadata> {
+        self.metadata.get(key).filter(|_| self.slot(key).is_some())
+    }
+
+    /// metadata of every resident entry, including entries stored as bricks
+    pub fn all_metadata(&self) -> impl Iterator<Item = &SdfEntryMetadata> + '_ {
+        self.metadata
+            .iter()
+            .filter(|(key, _)| self.slot(key).is_some())
+            .map(|(_, metadata)| metadata)
+    }
+
+    /// frames counted by the atlas, incremented each time sdfs are queued
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// occupancy and fragmentation of the atlas. walks every entry and a coarse grid of the page,
+    /// so call it when needed rather than for every entity
+    pub fn stats(&self) -> SdfAtlasStats {
+        let dim = self.dim();
+        let cells = (dim + STATS_CELL_SIZE - 1) / STATS_CELL_SIZE;
+        let cell_index = |c: UVec3| (c.x + c.y * cells.x + c.z * cells.x * cells.y) as usize;
+
+        // cells touched by any entry count as used
+        let mut occupied = vec![false; (cells.x * cells.y * cells.z) as usize];
+        let mut stats = SdfAtlasStats {
+            total_texels: dim.x as u64 * dim.y as u64 * dim.z as u64,
+            ..Default::default()
+        };
+        for entry in self.entries() {
+            stats.entry_count += 1;
+            stats.used_texels += entry.size.x as u64 * entry.size.y as u64 * entry.size.z as u64;
+
+            let min = entry.position / STATS_CELL_SIZE;
+            let max = ((entry.position + entry.size - 1) / STATS_CELL_SIZE).min(cells - 1);
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        occupied[cell_index(UVec3::new(x, y, z))] = true;
+                    }
+                }
+            }
+        }
+        stats.brick_count = self.page.allocated_bricks();
+        stats.used_texels += stats.brick_count as u64 * (BRICK_SIZE * BRICK_SIZE * BRICK_SIZE) as u64;
+        stats.occupancy = stats.used_texels as f32 / stats.total_texels.max(1) as f32;
+
+        // side of the largest empty cube ending at each cell, from its lower neighbours
+        let mut cube = vec![0u32; occupied.len()];
+        let mut largest = 0;
+        let mut free_cells = 0u64;
+        for z in 0..cells.z {
+            for y in 0..cells.y {
+                for x in 0..cells.x {
+                    let index = cell_index(UVec3::new(x, y, z));
+                    if occupied[index] {
+                        continue;
+                    }
+                    free_cells += 1;
+                    let cell = UVec3::new(x, y, z);
+                    let size = match cell.cmpeq(UVec3::ZERO).any() {
+                        true => 1,
+                        false => {
+                            1 + CUBE_NEIGHBOURS
+                                .iter()
+                                .map(|offset| cube[cell_index(cell - *offset)])
+                                .min()
+                                .unwrap()
+                        }
+                    };
+                    cube[index] = size;
+                    largest = largest.max(size);
+                }
+            }
+        }
+
+        stats.largest_free_cube = largest * STATS_CELL_SIZE;
+        stats.fragmentation = match free_cells {
+            0 => 0.0,
+            _ => 1.0 - (largest as u64).pow(3) as f32 / free_cells as f32,
+        };
+        stats
+    }
+}
+
+// note the entries preprocessed this frame, which are written by the compute node
+pub(crate) fn record_entry_metadata(
+    mut atlas: ResMut<SdfAtlas>,
+    sdf_data: Res<SdfData>,
+    sdfs: Query<(&Sdf, &GlobalTransform)>,
+) {
+    let atlas = &mut *atlas;
+    // compressed entries keep the metadata of their generation
+    atlas
+        .metadata
+        .retain(|key, _| atlas.page.get(key).is_some() || atlas.compressed.contains_key(key));
+
+    for (ent, key, aabb) in atlas.need_computing.iter() {
+        if !sdf_data.entities.contains(ent) {
+            continue;
+        }
+        let (Ok((sdf, transform)), Some(slot)) = (sdfs.get(*ent), atlas.page.get(key)) else {
+            continue;
+        };
+
+        // skinned entries are generated in world space
+        let world_aabb = match sdf.skinned {
+            true => *aabb,
+            false => {
+                let affine = transform.affine();
+                let (min, max) = (Vec3::from(aabb.min()), Vec3::from(aabb.max()));
+                let (min, max) = (0..8).fold(
+                    (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                    |(cur_min, cur_max), corner| {
+                        let p = affine.transform_point3(Vec3::select(
+                            BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                            max,
+                            min,
+                        ));
+                        (cur_min.min(p), cur_max.max(p))
+                    },
+                );
+                Aabb::from_min_max(min, max)
+            }
+        };
+        let method = match sdf.mode {
+            SdfGenMode::Precomputed(_) | SdfGenMode::Composite(_) => None,
+            _ => Some(sdf.options.method),
+        };
+
+        atlas.metadata.insert(
+            key.clone(),
+            SdfEntryMetadata {
+                key: key.clone(),
+                entity: *ent,
+                frame: atlas.frame,
+                resolution: slot.size - 1,
+                world_aabb,
+                method,
+                bricks: atlas.page.is_bricks(key),
+            },
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{self, RenderGraph},
+        renderer::RenderContext,
+        RenderApp,
+    },
+};
+
+use crate::{
+    compute::{SdfComputeGraphConfig, SdfData, SdfPipelineStatus},
+    queue_sdfs, SdfAtlas, SdfStatus,
+};
+
+/// opt-in record of the sdf work done each frame: which entities were queued, preprocessed,
+/// dispatched to the gpu or failed. useful for tracking down why an entity's sdf never appears,
+/// either by reading `SdfTimeline::history` or by setting `SdfTimeline::log` to print each frame
+/// with any activity. add after `SdfPlugin`.
+pub struct SdfTimelinePlugin;
+
+impl Plugin for SdfTimelinePlugin {
+    fn build(&self, app: &mut App) {
+        let dispatched = SdfTimelineDispatched::default();
+
+        app.init_resource::<SdfTimeline>()
+            .insert_resource(dispatched.clone())
+            .add_system_to_stage(CoreStage::PostUpdate, record_queued.after(queue_sdfs))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                record_preprocessed.after("preprocess sdfs"),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, record_dispatched);
+
+        let graph_config = app.world.resource::<SdfComputeGraphConfig>().clone();
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.insert_resource(dispatched);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        // the compute plugin warns if the graph is missing
+        let Some(graph) = graph_config.graph(&mut render_graph) else { return };
+        graph.add_node("sdf_timeline", SdfTimelineNode);
+        graph.add_node_edge("sdf_compute", "sdf_timeline").unwrap();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SdfTimelineStage {
+    // allocated an atlas slot and added to `SdfAtlas::need_computing`
+    Queued,
+    // geometry gathered (or a precomputed image found) and uploaded for generation
+    Preprocessed,
+    // generated or copied into the atlas by the compute node, usable from the following frame
+    Dispatched,
+    // generation failed or fell back to an aabb occluder, see the entity's `SdfStatus`
+    Failed,
+}
+
+/// the sdf work of one frame
+#[derive(Clone, Default, Debug)]
+pub struct SdfTimelineFrame {
+    pub frame: u64,
+    pub queued: Vec<Entity>,
+    pub preprocessed: Vec<Entity>,
+    pub dispatched: Vec<Entity>,
+    pub failed: Vec<(Entity, SdfStatus)>,
+}
+
+impl SdfTimelineFrame {
+    fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+            && self.preprocessed.is_empty()
+            && self.dispatched.is_empty()
+            && self.failed.is_empty()
+    }
+
+    fn stages(&self, entity: Entity) -> impl Iterator<Item = SdfTimelineStage> + '_ {
+        let queued = self.queued.contains(&entity).then_some(SdfTimelineStage::Queued);
+        let preprocessed = self
+            .preprocessed
+            .contains(&entity)
+            .then_some(SdfTimelineStage::Preprocessed);
+        let dispatched = self
+            .dispatched
+            .contains(&entity)
+            .then_some(SdfTimelineStage::Dispatched);
+        let failed = self
+            .failed
+            .iter()
+            .any(|(e, _)| *e == entity)
+            .then_some(SdfTimelineStage::Failed);
+        [queued, preprocessed, dispatched, failed].into_iter().flatten()
+    }
+}
+
+pub struct SdfTimeline {
+    // info! a line for each frame with any sdf activity
+    pub log: bool,
+    // frames kept in `frames`
+    pub max_frames: usize,
+    // most recent last
+    pub frames: VecDeque<SdfTimelineFrame>,
+    frame: u64,
+}
+
+impl Default for SdfTimeline {
+    fn default() -> Self {
+        Self {
+            log: false,
+            max_frames: 120,
+            frames: VecDeque::new(),
+            frame: 0,
+        }
+    }
+}
+
+impl SdfTimeline {
+    /// stages the entity passed through within the recorded frames, oldest first
+    pub fn history(&self, entity: Entity) -> Vec<(u64, SdfTimelineStage)> {
+        self.frames
+            .iter()
+            .flat_map(|frame| frame.stages(entity).map(|stage| (frame.frame, stage)))
+            .collect()
+    }
+
+    fn log_frame(&self) {
+        let Some(frame) = self.frames.back() else { return };
+        if !self.log || frame.is_empty() {
+            return;
+        }
+        info!(
+            "sdf frame {}: queued {:?}, preprocessed {:?}, dispatched {:?}, failed {:?}",
+            frame.frame, frame.queued, frame.preprocessed, frame.dispatched, frame.failed
+        );
+    }
+}
+
+// entities the compute node ran for, written by the render world
+#[derive(Clone, Default)]
+struct SdfTimelineDispatched(Arc<Mutex<Vec<Entity>>>);
+
+fn record_queued(mut timeline: ResMut<SdfTimeline>, atlas: Res<SdfAtlas>) {
+    timeline.frame += 1;
+    let frame = SdfTimelineFrame {
+        frame: timeline.frame,
+        queued: atlas.need_computing.iter().map(|(ent, ..)| *ent).collect(),
+        ..Default::default()
+    };
+
+    timeline.frames.push_back(frame);
+    while timeline.frames.len() > timeline.max_frames.max(1) {
+        timeline.frames.pop_front();
+    }
+}
+
+fn record_preprocessed(mut timeline: ResMut<SdfTimeline>, sdf_data: Res<SdfData>) {
+    if let Some(frame) = timeline.frames.back_mut() {
+        frame.preprocessed = sdf_data.entities.clone();
+    }
+}
+
+// the previous frame's dispatches and failures only show up once the render world has run and
+// the status commands have been applied
+fn record_dispatched(
+    mut timeline: ResMut<SdfTimeline>,
+    dispatched: Res<SdfTimelineDispatched>,
+    statuses: Query<(Entity, &SdfStatus), Changed<SdfStatus>>,
+) {
+    let dispatched = std::mem::take(&mut *dispatched.0.lock().unwrap());
+    let Some(frame) = timeline.frames.back_mut() else { return };
+
+    frame.dispatched = dispatched;
+    frame.failed = statuses
+        .iter()
+        .filter(|(_, status)| matches!(status, SdfStatus::Fallback | SdfStatus::Failed(_)))
+        .map(|(ent, status)| (ent, status.clone()))
+        .collect();
+
+    timeline.log_frame();
+}
+
+struct SdfTimelineNode;
+
+impl render_graph::Node for SdfTimelineNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        _render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        // runs once per 3d camera, so overwrite rather than append. entries the compute node
+        // skipped or requeued aren't dispatched
+        let sdf_data = world.resource::<SdfData>();
+        if !sdf_data.entities.is_empty() {
+            let dispatched = world.resource::<SdfTimelineDispatched>();
+            *dispatched.0.lock().unwrap() = world.resource::<SdfPipelineStatus>().written();
+        }
+        Ok(())
+    }
+}
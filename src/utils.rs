@@ -1,4 +1,7 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    hash::{Hash, Hasher},
+};
 
 use bevy::{
     math::Vec3A,
@@ -7,13 +10,16 @@ use bevy::{
         mesh::VertexAttributeValues,
         primitives::Plane,
         render_resource::{
-            AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureUsages,
+            AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat,
+            TextureUsages,
         },
         texture::ImageSampler,
     },
-    utils::FloatOrd,
+    utils::{FloatOrd, HashMap},
 };
 
+use crate::{decimate::decimate, SdfDecimation, SdfMorphTargets, SdfOptions};
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 struct OrderedVec(Vec3A);
 
@@ -33,7 +39,7 @@ impl PartialOrd for OrderedVec {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TriData {
     pub a: Vec3A,
     pub b: Vec3A,
@@ -42,6 +48,30 @@ pub struct TriData {
     pub plane: Plane,
 }
 
+// accumulated face normals for an edge
+#[derive(Default, Clone, Copy)]
+struct EdgeNormal {
+    normal: Vec3A,
+    valence: u32,
+}
+
+impl EdgeNormal {
+    fn add(&mut self, face_normal: Vec3A) {
+        self.normal += face_normal;
+        self.valence += 1;
+    }
+
+    // boundary edges use their single face normal and manifold edges the sum of both faces.
+    // with more than two faces the sum says nothing about which side is inside, so
+    // non-manifold edges get a zero normal and points nearest to them are treated as outside.
+    fn pseudo_normal(&self) -> Vec3A {
+        match self.valence {
+            1 | 2 => self.normal,
+            _ => Vec3A::ZERO,
+        }
+    }
+}
+
 pub struct PreprocessedMeshData {
     // pub aabb: Aabb,
     pub vertices: Vec<(Vec3A, Vec3A)>,
@@ -49,96 +79,267 @@ pub struct PreprocessedMeshData {
     pub triangles: Vec<TriData>,
 }
 
-pub fn preprocess_mesh_for_sdf(mesh: &Mesh, joints: Option<&[Mat4]>) -> PreprocessedMeshData {
-    let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
-        panic!("bad mesh");
+impl PreprocessedMeshData {
+    /// canonical text dump for snapshot tests: positions and normalized normals rounded to 4
+    /// decimals, triangle corners rotated to start at the smallest, and every section sorted, so
+    /// the output doesn't depend on input order or float noise
+    pub fn snapshot(&self) -> String {
+        fn round(f: f32) -> String {
+            let s = format!("{:.4}", f);
+            if s == "-0.0000" {
+                "0.0000".to_string()
+            } else {
+                s
+            }
+        }
+        fn fmt(v: Vec3A) -> String {
+            format!("({}, {}, {})", round(v.x), round(v.y), round(v.z))
+        }
+
+        let mut vertices = self
+            .vertices
+            .iter()
+            .map(|(v, n)| format!("{} n {}", fmt(*v), fmt(n.normalize_or_zero())))
+            .collect::<Vec<_>>();
+        vertices.sort();
+
+        let mut edges = self
+            .edges
+            .iter()
+            .map(|((v0, v1), n)| {
+                let (v0, v1) = (OrderedVec(*v0), OrderedVec(*v1));
+                let (v0, v1) = (v0.min(v1), v0.max(v1));
+                format!("{} {} n {}", fmt(v0.0), fmt(v1.0), fmt(n.normalize_or_zero()))
+            })
+            .collect::<Vec<_>>();
+        edges.sort();
+
+        let mut triangles = self
+            .triangles
+            .iter()
+            .map(|tri| {
+                let mut corners = [tri.a, tri.b, tri.c];
+                let first = (0..3).min_by_key(|i| OrderedVec(corners[*i])).unwrap();
+                corners.rotate_left(first);
+                let plane = tri.plane.normal_d();
+                format!(
+                    "{} {} {} n {} d {}",
+                    fmt(corners[0]),
+                    fmt(corners[1]),
+                    fmt(corners[2]),
+                    fmt(tri.plane.normal()),
+                    round(plane.w),
+                )
+            })
+            .collect::<Vec<_>>();
+        triangles.sort();
+
+        let mut dump = String::new();
+        for (name, lines) in [("vertices", vertices), ("edges", edges), ("triangles", triangles)] {
+            dump.push_str(&format!("{} {}\n", name, lines.len()));
+            for line in lines {
+                dump.push_str(&format!("  {}\n", line));
+            }
+        }
+        dump
+    }
+}
+
+pub fn preprocess_mesh_for_sdf(
+    mesh: &Mesh,
+    joints: Option<&[Mat4]>,
+    morph: Option<&SdfMorphTargets>,
+    options: &SdfOptions,
+) -> PreprocessedMeshData {
+    preprocess_triangles_for_sdf(mesh_triangles(mesh, joints, morph), options)
+}
+
+/// preprocess several meshes into a single combined sdf, each mesh is transformed by its
+/// matrix (e.g. into the space of a hierarchy root) first
+pub fn preprocess_meshes_for_sdf(
+    meshes: &[(&Mesh, Mat4)],
+    options: &SdfOptions,
+) -> PreprocessedMeshData {
+    let values = meshes
+        .iter()
+        .flat_map(|(mesh, transform)| {
+            mesh_triangles(mesh, None, None)
+                .into_iter()
+                .map(|v| transform.transform_point3(v))
+        })
+        .collect();
+    preprocess_triangles_for_sdf(values, options)
+}
+
+/// joint index attribute access for any of the unsigned integer layouts (u8, u16 or u32)
+pub(crate) struct JointIndexes<'a>(&'a VertexAttributeValues);
+
+impl<'a> JointIndexes<'a> {
+    pub fn new(values: &'a VertexAttributeValues) -> Option<Self> {
+        match values {
+            VertexAttributeValues::Uint8x4(_)
+            | VertexAttributeValues::Uint16x4(_)
+            | VertexAttributeValues::Uint32x4(_) => Some(Self(values)),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> [usize; 4] {
+        match self.0 {
+            VertexAttributeValues::Uint8x4(v) => v[index].map(|j| j as usize),
+            VertexAttributeValues::Uint16x4(v) => v[index].map(|j| j as usize),
+            VertexAttributeValues::Uint32x4(v) => v[index].map(|j| j as usize),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// position of a source vertex, morphed and skinned if provided
+fn vertex_position(
+    mesh: &Mesh,
+    positions: &[[f32; 3]],
+    joints: Option<&[Mat4]>,
+    morph: Option<&SdfMorphTargets>,
+    index: usize,
+) -> Vec3 {
+    let v = Vec3::from(positions[index]);
+    let v = match morph {
+        Some(morph) => morph.apply(v, index),
+        None => v,
     };
 
-    let weight_with_joints = |v: Vec3, index: usize| -> Vec3 {
-        let Some(VertexAttributeValues::Float32x4(joint_weights)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) else {panic!("bad joint weights!")};
-        let Some(VertexAttributeValues::Uint16x4(joint_indexes)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX) else {panic!("bad joint indexes!")};
-        let joints = joints.unwrap();
-        let indexes = joint_indexes[index];
-        let weights = joint_weights[index];
-        let mat = joints[indexes[0] as usize] * weights[0]
-            + joints[indexes[1] as usize] * weights[1]
-            + joints[indexes[2] as usize] * weights[2]
-            + joints[indexes[3] as usize] * weights[3];
-        let res = mat * v.extend(1.0);
-        res.truncate() / res.w
+    let Some(joints) = joints else {
+        return v;
     };
 
-    let weight = |v: Vec3, index: usize| -> Vec3 {
-        if joints.is_some() {
-            weight_with_joints(v, index)
-        } else {
-            v
-        }
+    let Some(VertexAttributeValues::Float32x4(joint_weights)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) else {panic!("bad joint weights!")};
+    let Some(joint_indexes) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX).and_then(JointIndexes::new) else {panic!("bad joint indexes!")};
+    let indexes = joint_indexes.get(index);
+    let weights = joint_weights[index];
+    let mat = joints[indexes[0]] * weights[0]
+        + joints[indexes[1]] * weights[1]
+        + joints[indexes[2]] * weights[2]
+        + joints[indexes[3]] * weights[3];
+    let res = mat * v.extend(1.0);
+    res.truncate() / res.w
+}
+
+fn mesh_positions(mesh: &Mesh) -> &[[f32; 3]] {
+    let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+        panic!("bad mesh");
     };
+    values
+}
 
-    let values: Vec<Vec3> = match mesh.indices() {
-        Some(ix) => ix
-            .iter()
-            .map(|ix| weight(Vec3::from(values[ix]), ix))
-            .collect(),
-        None => values
-            .iter()
-            .enumerate()
-            .map(|(ix, v)| weight(Vec3::from(*v), ix))
-            .collect(),
+// source vertex index for each triangle corner
+fn mesh_corners(mesh: &Mesh) -> Vec<usize> {
+    match mesh.indices() {
+        Some(ix) => ix.iter().collect(),
+        None => (0..mesh_positions(mesh).len()).collect(),
+    }
+}
+
+/// hash of the geometry used for sdf generation, so identical meshes behind different handles
+/// can share an atlas entry
+pub(crate) fn mesh_content_hash(mesh: &Mesh) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for position in mesh_positions(mesh) {
+        position.map(f32::to_bits).hash(&mut hasher);
+    }
+    mesh_corners(mesh).hash(&mut hasher);
+    hasher.finish()
+}
+
+// triangle list positions for the mesh, morphed and skinned if provided
+fn mesh_triangles(
+    mesh: &Mesh,
+    joints: Option<&[Mat4]>,
+    morph: Option<&SdfMorphTargets>,
+) -> Vec<Vec3> {
+    let positions = mesh_positions(mesh);
+    mesh_corners(mesh)
+        .into_iter()
+        .map(|ix| vertex_position(mesh, positions, joints, morph, ix))
+        .collect()
+}
+
+// triangle data and corner angles, or none for zero-area and sliver triangles (which give nan
+// normals and infinite inv_area)
+fn triangle_data(a: Vec3A, b: Vec3A, c: Vec3A, options: &SdfOptions) -> Option<(TriData, [f32; 3])> {
+    let cross = (b - a).cross(c - b);
+    let area = cross.length() * 0.5;
+    if !(area > options.min_triangle_area) || !area.is_finite() {
+        return None;
+    }
+
+    let normal = cross / (area * 2.0);
+
+    let ab_len = (b - a).length();
+    let ac_len = (c - a).length();
+    let bc_len = (c - b).length();
+
+    let a_angle = tri_angle(bc_len, ab_len, ac_len);
+    let b_angle = tri_angle(ac_len, ab_len, bc_len);
+    let c_angle = tri_angle(ab_len, ac_len, bc_len);
+
+    let plane = Plane::new(normal.extend(-a.dot(normal)));
+    let inv_area = (b - a).cross(c - a).dot(plane.normal()).recip();
+    if !inv_area.is_finite() || !(a_angle + b_angle + c_angle).is_finite() {
+        return None;
+    }
+
+    fn tri_angle(opp: f32, a: f32, b: f32) -> f32 {
+        ((a * a + b * b - opp * opp) / (2.0 * a * b)).clamp(-1.0, 1.0).acos()
+    }
+
+    Some((
+        TriData {
+            a,
+            b,
+            c,
+            inv_area,
+            plane,
+        },
+        [a_angle, b_angle, c_angle],
+    ))
+}
+
+fn preprocess_triangles_for_sdf(values: Vec<Vec3>, options: &SdfOptions) -> PreprocessedMeshData {
+    let values = match options.decimation {
+        Some(SdfDecimation::TargetTriangles(count)) => decimate(&values, count, f32::MAX),
+        Some(SdfDecimation::MaxError(error)) => decimate(&values, 0, error),
+        // only meaningful once resolved against a voxel size
+        Some(SdfDecimation::UnitError(_)) | None => values,
     };
 
     let mut vertices = BTreeMap::<OrderedVec, Vec3A>::new();
-    let mut edges = BTreeMap::<(OrderedVec, OrderedVec), Vec3A>::new();
+    let mut edges = BTreeMap::<(OrderedVec, OrderedVec), EdgeNormal>::new();
     let mut triangles = Vec::<TriData>::new();
+    let mut degenerate_count = 0;
 
     for tri in values.chunks_exact(3) {
-        let a = OrderedVec(tri[0].into());
-        let b = OrderedVec(tri[1].into());
-        let c = OrderedVec(tri[2].into());
-
-        let normal = (b.0 - a.0).cross(c.0 - b.0).normalize();
-
-        // sort
-        let mut sorted = vec![a, b, c];
-        sorted.sort();
-        let mut iter = sorted.into_iter();
-        let (a, b, c) = (
-            iter.next().unwrap(),
-            iter.next().unwrap(),
-            iter.next().unwrap(),
-        );
-
-        let ab_len = (b.0 - a.0).length();
-        let ac_len = (c.0 - a.0).length();
-        let bc_len = (c.0 - b.0).length();
-
-        let a_angle = tri_angle(bc_len, ab_len, ac_len);
-        let b_angle = tri_angle(ac_len, ab_len, bc_len);
-        let c_angle = tri_angle(ab_len, ac_len, bc_len);
-
-        *vertices.entry(a).or_default() += normal * a_angle;
-        *vertices.entry(b).or_default() += normal * b_angle;
-        *vertices.entry(c).or_default() += normal * c_angle;
-
-        *edges.entry((a, b)).or_default() += normal;
-        *edges.entry((a, c)).or_default() += normal;
-        *edges.entry((b, c)).or_default() += normal;
-
-        let plane = Plane::new(normal.extend(-(a.0).dot(normal)));
-        let inv_area = (b.0 - a.0).cross(c.0 - a.0).dot(plane.normal()).recip();
-
-        triangles.push(TriData {
-            a: a.0,
-            b: b.0,
-            c: c.0,
-            inv_area,
-            plane,
-        });
+        let Some((tri_data, angles)) = triangle_data(tri[0].into(), tri[1].into(), tri[2].into(), options) else {
+            degenerate_count += 1;
+            continue;
+        };
+
+        let normal = tri_data.plane.normal();
+        let corners = [tri_data.a, tri_data.b, tri_data.c].map(OrderedVec);
+
+        for (corner, angle) in corners.iter().zip(angles) {
+            *vertices.entry(*corner).or_default() += normal * angle;
+        }
+
+        for (v0, v1) in [(0, 1), (0, 2), (1, 2)] {
+            let (v0, v1) = (corners[v0], corners[v1]);
+            edges.entry((v0.min(v1), v0.max(v1))).or_default().add(normal);
+        }
+
+        triangles.push(tri_data);
     }
 
-    fn tri_angle(opp: f32, a: f32, b: f32) -> f32 {
-        ((a * a + b * b - opp * opp) / (2.0 * a * b)).acos()
+    if degenerate_count > 0 {
+        warn!("skipped {} degenerate triangles", degenerate_count);
     }
 
     // let (min, max) = vertices.keys().fold((Vec3A::splat(f32::MAX), Vec3A::splat(f32::MIN)), |(cur_min, cur_max), v| {
@@ -150,13 +351,199 @@ pub fn preprocess_mesh_for_sdf(mesh: &Mesh, joints: Option<&[Mat4]>) -> Preproce
         vertices: vertices.into_iter().map(|(ov, n)| (ov.0, n)).collect(),
         edges: edges
             .into_iter()
-            .map(|((ov0, ov1), n)| ((ov0.0, ov1.0), n))
+            .map(|((ov0, ov1), n)| ((ov0.0, ov1.0), n.pseudo_normal()))
             .collect(),
         triangles,
     }
 }
 
-pub fn create_sdf_image(dimension: UVec3) -> Image {
+/// the index topology of a mesh (welded vertices, shared edges and triangles), which doesn't
+/// change when the mesh is skinned or morphed. built once per mesh so animated entities only
+/// re-run the position dependent parts of preprocessing each frame.
+pub struct MeshTopology {
+    // a representative source vertex index for each welded vertex
+    vertices: Vec<usize>,
+    // welded vertex pairs
+    edges: Vec<(u32, u32)>,
+    // welded vertex indices and edge indices for each triangle
+    triangles: Vec<([u32; 3], [u32; 3])>,
+}
+
+impl MeshTopology {
+    pub fn new(mesh: &Mesh) -> Self {
+        let positions = mesh_positions(mesh);
+
+        // weld coincident rest pose vertices
+        let mut vertex_lookup = HashMap::<[u32; 3], u32>::default();
+        let mut vertices = Vec::new();
+        let welded = mesh_corners(mesh)
+            .into_iter()
+            .map(|ix| {
+                *vertex_lookup
+                    .entry(positions[ix].map(f32::to_bits))
+                    .or_insert_with(|| {
+                        vertices.push(ix);
+                        vertices.len() as u32 - 1
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let mut edge_lookup = HashMap::<(u32, u32), u32>::default();
+        let mut edges = Vec::new();
+        let mut triangles = Vec::new();
+        for tri in welded.chunks_exact(3) {
+            let tri = [tri[0], tri[1], tri[2]];
+            if tri[0] == tri[1] || tri[1] == tri[2] || tri[0] == tri[2] {
+                continue;
+            }
+
+            let tri_edges = [(0, 1), (0, 2), (1, 2)].map(|(v0, v1)| {
+                let key = (tri[v0].min(tri[v1]), tri[v0].max(tri[v1]));
+                *edge_lookup.entry(key).or_insert_with(|| {
+                    edges.push(key);
+                    edges.len() as u32 - 1
+                })
+            });
+
+            triangles.push((tri, tri_edges));
+        }
+
+        Self {
+            vertices,
+            edges,
+            triangles,
+        }
+    }
+}
+
+/// preprocess a mesh using its cached topology. decimation is not applied.
+pub fn preprocess_topology_for_sdf(
+    topology: &MeshTopology,
+    mesh: &Mesh,
+    joints: Option<&[Mat4]>,
+    morph: Option<&SdfMorphTargets>,
+    options: &SdfOptions,
+) -> PreprocessedMeshData {
+    let positions = mesh_positions(mesh);
+    let positions = topology
+        .vertices
+        .iter()
+        .map(|&ix| Vec3A::from(vertex_position(mesh, positions, joints, morph, ix)))
+        .collect::<Vec<_>>();
+
+    preprocess_topology(topology, positions, options).0
+}
+
+/// preprocess a skinned mesh in its rest pose for skinning on the gpu. also returns the source
+/// vertex indices of each feature: one per vertex, then two per edge, then three per triangle.
+pub fn preprocess_rest_pose_for_sdf(
+    topology: &MeshTopology,
+    mesh: &Mesh,
+    options: &SdfOptions,
+) -> (PreprocessedMeshData, Vec<u32>) {
+    let positions = mesh_positions(mesh);
+    let positions = topology
+        .vertices
+        .iter()
+        .map(|&ix| Vec3A::from(positions[ix]))
+        .collect::<Vec<_>>();
+
+    preprocess_topology(topology, positions, options)
+}
+
+/// rest pose position, joint indexes and joint weights of each source vertex of a skinned mesh
+pub fn skin_vertices(mesh: &Mesh) -> Option<Vec<(Vec3, [u32; 4], [f32; 4])>> {
+    let positions = mesh_positions(mesh);
+    let Some(VertexAttributeValues::Float32x4(joint_weights)) = mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) else { return None };
+    let joint_indexes = JointIndexes::new(mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX)?)?;
+
+    Some(
+        (0..positions.len())
+            .map(|ix| {
+                (
+                    Vec3::from(positions[ix]),
+                    joint_indexes.get(ix).map(|j| j as u32),
+                    joint_weights[ix],
+                )
+            })
+            .collect(),
+    )
+}
+
+// preprocess welded vertex positions, returning the features and their source vertex indices
+fn preprocess_topology(
+    topology: &MeshTopology,
+    positions: Vec<Vec3A>,
+    options: &SdfOptions,
+) -> (PreprocessedMeshData, Vec<u32>) {
+    // normals stay none for vertices and edges only used by degenerate triangles
+    let mut vertex_normals = vec![None; positions.len()];
+    let mut edge_normals = vec![None::<EdgeNormal>; topology.edges.len()];
+    let mut triangles = Vec::with_capacity(topology.triangles.len());
+    let mut triangle_sources = Vec::with_capacity(topology.triangles.len() * 3);
+    let mut degenerate_count = 0;
+
+    let source = |v: u32| topology.vertices[v as usize] as u32;
+
+    for (tri, tri_edges) in topology.triangles.iter() {
+        let [a, b, c] = tri.map(|v| positions[v as usize]);
+        let Some((tri_data, angles)) = triangle_data(a, b, c, options) else {
+            degenerate_count += 1;
+            continue;
+        };
+
+        let normal = tri_data.plane.normal();
+        for (v, angle) in tri.iter().zip(angles) {
+            *vertex_normals[*v as usize].get_or_insert(Vec3A::ZERO) += normal * angle;
+        }
+        for e in tri_edges.iter() {
+            edge_normals[*e as usize].get_or_insert_with(Default::default).add(normal);
+        }
+
+        triangles.push(tri_data);
+        triangle_sources.extend(tri.map(source));
+    }
+
+    if degenerate_count > 0 {
+        warn!("skipped {} degenerate triangles", degenerate_count);
+    }
+
+    let mut sources = Vec::new();
+    let vertices = positions
+        .iter()
+        .zip(vertex_normals)
+        .enumerate()
+        .filter_map(|(ix, (v, n))| {
+            let n = n?;
+            sources.push(source(ix as u32));
+            Some((*v, n))
+        })
+        .collect();
+    let edges = topology
+        .edges
+        .iter()
+        .zip(edge_normals)
+        .filter_map(|((v0, v1), n)| {
+            let n = n?;
+            sources.extend([source(*v0), source(*v1)]);
+            Some(((positions[*v0 as usize], positions[*v1 as usize]), n.pseudo_normal()))
+        })
+        .collect();
+    sources.extend(triangle_sources);
+
+    (
+        PreprocessedMeshData {
+            vertices,
+            edges,
+            triangles,
+        },
+        sources,
+    )
+}
+
+/// the atlas image, with zeroed space for `mip_levels` levels. `format` is R32Float or R16Float
+pub fn create_sdf_image(dimension: UVec3, mip_levels: u32, format: TextureFormat) -> Image {
+    let texel_bytes = atlas_texel_bytes(format);
     let mut image = Image::new_fill(
         Extent3d {
             width: dimension.x,
@@ -164,8 +551,8 @@ pub fn create_sdf_image(dimension: UVec3) -> Image {
             depth_or_array_layers: dimension.z,
         },
         TextureDimension::D3,
-        &[0; 4],
-        bevy::render::render_resource::TextureFormat::R32Float,
+        &vec![0; texel_bytes],
+        format,
     );
 
     image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
@@ -179,7 +566,162 @@ pub fn create_sdf_image(dimension: UVec3) -> Image {
     });
 
     image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+        TextureUsages::COPY_DST
+            | TextureUsages::COPY_SRC
+            | TextureUsages::STORAGE_BINDING
+            | TextureUsages::TEXTURE_BINDING;
+
+    // initial data covers every level
+    image.texture_descriptor.mip_level_count = mip_levels;
+    let texels = (0..mip_levels)
+        .map(|level| {
+            let size = (dimension >> level).max(UVec3::ONE);
+            (size.x * size.y * size.z) as usize
+        })
+        .sum::<usize>();
+    image.data.resize(texels * texel_bytes, 0);
 
     image
 }
+
+/// the indirection image of entries stored as bricks, see `SdfAtlas::indirection_image`. only
+/// read with `textureLoad`, so it needs no sampler
+pub fn create_indirection_image(dimension: UVec3) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: dimension.x,
+            height: dimension.y,
+            depth_or_array_layers: dimension.z,
+        },
+        TextureDimension::D3,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// the gradient image, see `SdfGlobalSettings::gradient_atlas`. Rgba8Snorm the size of the atlas,
+/// or a single texel placeholder without gradients
+pub fn create_gradient_image(dimension: UVec3, gradients: bool) -> Image {
+    let size = match gradients {
+        true => dimension,
+        false => UVec3::ONE,
+    };
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: size.z,
+        },
+        TextureDimension::D3,
+        &[0; 4],
+        TextureFormat::Rgba8Snorm,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+    image
+}
+
+/// the compressed atlas image, see `SdfGlobalSettings::compressed_atlas_size`. BC4 with a layer
+/// per z slice of its entries, as compressed formats can't be 3d. without compression it's an
+/// R8Snorm placeholder. always at least two layers, so it's viewed as an array
+pub fn create_compressed_image(dimension: UVec3, compressed: bool) -> Image {
+    let (size, format) = match compressed {
+        true => (dimension, TextureFormat::Bc4RSnorm),
+        false => (UVec3::new(1, 1, 2), TextureFormat::R8Snorm),
+    };
+    let size = size.max(UVec3::new(1, 1, 2));
+    let bytes = match compressed {
+        // 8 bytes per 4x4 block
+        true => (size.x / 4 * size.y / 4 * size.z * 8) as usize,
+        false => (size.x * size.y * size.z) as usize,
+    };
+
+    let mut image = Image {
+        data: vec![0; bytes],
+        ..Default::default()
+    };
+    image.texture_descriptor.size = Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: size.z,
+    };
+    image.texture_descriptor.dimension = TextureDimension::D2;
+    image.texture_descriptor.format = format;
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..Default::default()
+    });
+    image
+}
+
+// BC4 blocks of an x-major grid of distances, a layer per z slice, with each layer's edge voxels
+// repeated out to whole 4x4 blocks. distances are multiplied by `scale` into the snorm range
+pub(crate) fn encode_bc4(data: &[f32], dimension: UVec3, scale: f32) -> Vec<u8> {
+    let blocks = (dimension + UVec3::new(3, 3, 0)) / UVec3::new(4, 4, 1);
+    let mut bytes = Vec::with_capacity((blocks.x * blocks.y * blocks.z * 8) as usize);
+    for z in 0..blocks.z {
+        for block_y in 0..blocks.y {
+            for block_x in 0..blocks.x {
+                let mut values = [0i32; 16];
+                for (i, value) in values.iter_mut().enumerate() {
+                    let x = (block_x * 4 + i as u32 % 4).min(dimension.x - 1);
+                    let y = (block_y * 4 + i as u32 / 4).min(dimension.y - 1);
+                    let index = x + y * dimension.x + z * dimension.x * dimension.y;
+                    let distance = data[index as usize];
+                    *value = ((distance * scale).clamp(-1.0, 1.0) * 127.0).round() as i32;
+                }
+
+                // endpoints at the extremes, with 6 values interpolated between them. index 0
+                // is red0, 1 is red1, and 2-7 step from red0 towards red1
+                let red0 = *values.iter().max().unwrap();
+                let red1 = *values.iter().min().unwrap();
+                let mut indices = 0u64;
+                if red0 > red1 {
+                    for (i, value) in values.iter().enumerate() {
+                        let step = (red0 - value) as f32 * 7.0 / (red0 - red1) as f32;
+                        let step = step.round() as u64;
+                        let index = match step {
+                            0 => 0,
+                            7 => 1,
+                            step => step + 1,
+                        };
+                        indices |= index << (3 * i);
+                    }
+                }
+                bytes.push(red0 as i8 as u8);
+                bytes.push(red1 as i8 as u8);
+                bytes.extend_from_slice(&indices.to_le_bytes()[..6]);
+            }
+        }
+    }
+    bytes
+}
+
+// bytes per texel of an atlas image
+pub(crate) fn atlas_texel_bytes(format: TextureFormat) -> usize {
+    match format {
+        TextureFormat::R16Float => 2,
+        _ => 4,
+    }
+}
+
+// an R16Float texel
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
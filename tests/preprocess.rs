@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use bevy::{
+    math::Vec3A,
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+use mesh2sdf::{
+    utils::{preprocess_mesh_for_sdf, PreprocessedMeshData},
+    SdfOptions,
+};
+
+// compare against `tests/snapshots/<name>.snap`. set `UPDATE_SNAPSHOTS=1` to write new snapshots,
+// or overwrite existing ones after an intended change
+fn assert_snapshot(name: &str, data: &PreprocessedMeshData) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(format!("{}.snap", name));
+    let dump = data.snapshot();
+
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &dump).unwrap();
+        return;
+    }
+    if !path.exists() {
+        panic!(
+            "missing snapshot {:?}, run with `UPDATE_SNAPSHOTS=1` to write it:\n{}",
+            path, dump
+        );
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        expected, dump,
+        "preprocessed output for {} differs from {:?}",
+        name, path
+    );
+}
+
+fn triangle_mesh(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn cube() -> Mesh {
+    Mesh::from(shape::Cube { size: 2.0 })
+}
+
+// regular tetrahedron centered on the origin, wound outwards
+fn tetrahedron() -> Mesh {
+    let positions = vec![[1.0, 1.0, 1.0], [1.0, -1.0, -1.0], [-1.0, 1.0, -1.0], [-1.0, -1.0, 1.0]];
+    let mut indices = Vec::new();
+    for [a, b, c] in [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]] {
+        let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(positions[i as usize]));
+        let outward = (pb - pa).cross(pc - pb).dot(pa + pb + pc) > 0.0;
+        indices.extend(if outward { [a, b, c] } else { [a, c, b] });
+    }
+    triangle_mesh(positions, indices)
+}
+
+// the same triangles in reverse order, with each triangle's corners rotated
+fn shuffled(mesh: &Mesh) -> Mesh {
+    let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { panic!() };
+    let indices = mesh.indices().unwrap().iter().map(|i| i as u32).collect::<Vec<_>>();
+    let indices = indices
+        .chunks_exact(3)
+        .rev()
+        .flat_map(|tri| [tri[1], tri[2], tri[0]])
+        .collect();
+    triangle_mesh(positions.clone(), indices)
+}
+
+fn preprocess(mesh: &Mesh) -> PreprocessedMeshData {
+    preprocess_mesh_for_sdf(mesh, None, None, &SdfOptions::default())
+}
+
+// for convex shapes centered on the origin every pseudo-normal points away from the origin
+fn assert_outward_normals(data: &PreprocessedMeshData) {
+    for (v, n) in data.vertices.iter() {
+        assert!(n.dot(*v) > 0.0, "vertex {} has inward normal {}", v, n);
+    }
+    for ((v0, v1), n) in data.edges.iter() {
+        let mid = (*v0 + *v1) * 0.5;
+        assert!(n.dot(mid) > 0.0, "edge {}-{} has inward normal {}", v0, v1, n);
+    }
+    for tri in data.triangles.iter() {
+        let centroid = (tri.a + tri.b + tri.c) / 3.0;
+        assert!(tri.plane.normal().dot(centroid) > 0.0, "triangle {:?} faces inwards", tri);
+    }
+}
+
+#[test]
+fn cube_features() {
+    let data = preprocess(&cube());
+
+    // 8 corners, 12 edges plus a diagonal per face, 2 triangles per face
+    assert_eq!(data.vertices.len(), 8);
+    assert_eq!(data.edges.len(), 18);
+    assert_eq!(data.triangles.len(), 12);
+    assert_outward_normals(&data);
+
+    // angle weighted corner normals point along the diagonal
+    for (v, n) in data.vertices.iter() {
+        assert!(n.normalize().abs_diff_eq(v.normalize(), 1e-5), "corner {} normal {}", v, n);
+    }
+
+    // face diagonals lie between coplanar triangles, so their normal is the face normal
+    for ((v0, v1), n) in data.edges.iter() {
+        let n = n.normalize();
+        let axis_count = (*v1 - *v0).abs().cmpgt(Vec3A::splat(1e-5)).bitmask().count_ones();
+        if axis_count == 2 {
+            assert!((n.abs().max_element() - 1.0).abs() < 1e-5, "diagonal {}-{} normal {}", v0, v1, n);
+        } else {
+            // cube edges average the two adjacent faces
+            assert!((n.abs().max_element() - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-5);
+        }
+    }
+
+    // faces are 1 unit from the center
+    for tri in data.triangles.iter() {
+        assert!((tri.plane.normal_d().w + 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn tetrahedron_features() {
+    let data = preprocess(&tetrahedron());
+
+    assert_eq!(data.vertices.len(), 4);
+    assert_eq!(data.edges.len(), 6);
+    assert_eq!(data.triangles.len(), 4);
+    assert_outward_normals(&data);
+
+    // by symmetry each corner normal points back through the center
+    for (v, n) in data.vertices.iter() {
+        assert!(n.normalize().abs_diff_eq(v.normalize(), 1e-5), "corner {} normal {}", v, n);
+    }
+}
+
+#[test]
+fn snapshot_is_order_independent() {
+    for mesh in [cube(), tetrahedron()] {
+        assert_eq!(preprocess(&mesh).snapshot(), preprocess(&shuffled(&mesh)).snapshot());
+    }
+}
+
+#[test]
+fn cube_snapshot() {
+    assert_snapshot("cube", &preprocess(&cube()));
+}
+
+#[test]
+fn tetrahedron_snapshot() {
+    assert_snapshot("tetrahedron", &preprocess(&tetrahedron()));
+}